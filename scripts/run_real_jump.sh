@@ -4,4 +4,7 @@ spmspm_pim analyze new-analysis configs/real_jump_same_bank-1-64.toml
 
 spmspm_pim analyze new-analysis configs/real_jump_weighted-1-16.toml 
 spmspm_pim analyze new-analysis configs/real_jump_weighted-1-32.toml 
-spmspm_pim analyze new-analysis configs/real_jump_weighted-1-64.toml 
\ No newline at end of file
+spmspm_pim analyze new-analysis configs/real_jump_weighted-1-64.toml 
+
+# or run all the combinations above from one config
+# spmspm_pim sweep configs/real_jump_sweep.toml
@@ -4,6 +4,7 @@
 //! Description: this module contains some SPMV algorithms
 pub mod bfs;
 pub mod page_rank;
+pub mod replay;
 pub mod spmm;
 // sssp is identical to bfs now
 // pub mod sssp;
@@ -25,4 +26,9 @@ pub trait SpmvAlgorithm {
     /// ## Date: 2023-05-18
     /// Description: move to the next frontier
     fn next_frontier(&mut self) -> Option<FrontierType>;
+
+    /// whether the last frontier finished an iteration of the algorithm, each frontier is an iteration by default
+    fn iteration_finished(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,535 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-19
+//! Description: replay a recorded access trace instead of running an algorithm on the graph.
+//! the trace is a text file with the `.access` extension(it can be compressed by gzip or zstd, see
+//! [`file_server::file_reader`]), each line is one of:
+//! - `hardware <mapping> <channels> <banks> <subarrays> <columns>`: the hardware the trace was recorded with, see
+//!   [`TraceHardware`]
+//! - `shape <rows> <cols>`: the shape of matrix b, without it the matrix is square and large enough for all ids
+//! - `b <row> <cols...>`: the non-zeros of a row of matrix b
+//! - `a <round> <rows...>`: the rows of matrix b read in a round, `*` means all rows. the consecutive lines of
+//!   the same round are merged, so an external tool can write one access per line
+//!
+//! empty lines and the lines starting with `#` are ignored. the ids are used as they are, a trace recorded by the
+//! real jump simulations already contains the translated graph, see [`AccessTraceWriter`].
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use eyre::{bail, eyre, Context};
+use sprs::{num_kinds::Pattern, CsMatI, CsMatViewI, CsVecI, TriMatI};
+
+use super::{FrontierType, SpmvAlgorithm};
+use crate::{
+    pim::configv2::ConfigV3,
+    tools::{file_server, graph_reader},
+};
+
+/// the extension of the access traces
+pub const ACCESS_TRACE_EXTENSION: &str = "access";
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-19
+/// Description: whether an entry of `graph_path` is an access trace, the compression extension is skipped
+pub fn is_access_trace(path: &str) -> bool {
+    let path = Path::new(path);
+    let path = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz" | "zst") => Path::new(path.file_stem().unwrap_or_default()),
+        _ => path,
+    };
+    path.extension()
+        .map_or(false, |ext| ext == ACCESS_TRACE_EXTENSION)
+}
+
+/// the rows of matrix b read in a round
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessRound {
+    All,
+    /// sorted and without duplicates
+    Rows(Vec<u32>),
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-19
+/// Description: the mapping and the hardware a trace was recorded with. matrix b of a recorded trace is translated
+/// for them, so it can only be replayed on the same ones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceHardware {
+    pub mapping: String,
+    pub channels: usize,
+    pub banks: usize,
+    pub subarrays: usize,
+    pub columns: usize,
+}
+
+impl TraceHardware {
+    pub fn from_config(config: &ConfigV3) -> Self {
+        Self {
+            mapping: format!("{:?}", config.mapping),
+            channels: config.channels.num,
+            banks: config.banks.num,
+            subarrays: config.subarrays,
+            columns: config.columns,
+        }
+    }
+
+    fn parse<'a>(mut tokens: impl Iterator<Item = &'a str>, line_no: usize) -> eyre::Result<Self> {
+        let usage = || {
+            eyre!(
+                "line {}: hardware should be `hardware <mapping> <channels> <banks> <subarrays> <columns>`",
+                line_no
+            )
+        };
+        let mapping = tokens.next().ok_or_else(usage)?.to_string();
+        let mut next_num = || -> eyre::Result<usize> {
+            Ok(parse_id(tokens.next().ok_or_else(usage)?, line_no)? as usize)
+        };
+        Ok(Self {
+            mapping,
+            channels: next_num()?,
+            banks: next_num()?,
+            subarrays: next_num()?,
+            columns: next_num()?,
+        })
+    }
+}
+
+impl Display for TraceHardware {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.mapping, self.channels, self.banks, self.subarrays, self.columns
+        )
+    }
+}
+
+/// a parsed access trace
+#[derive(Debug, Clone)]
+pub struct AccessTrace {
+    /// `None` for the traces without the hardware line, such as the ones written by external tools
+    hardware: Option<TraceHardware>,
+    matrix_b: CsMatI<Pattern, u32>,
+    rounds: Vec<AccessRound>,
+}
+
+fn parse_id(token: &str, line_no: usize) -> eyre::Result<u32> {
+    token
+        .parse()
+        .wrap_err(format!("line {}: invalid id {:?}", line_no, token))
+}
+
+impl AccessTrace {
+    /// read the trace from a file
+    pub fn read(path: &str) -> eyre::Result<Self> {
+        let reader = file_server::file_reader(path)
+            .wrap_err(format!("fail to open access trace {}", path))?;
+        Self::parse(reader).wrap_err(format!("fail to parse access trace {}", path))
+    }
+
+    /// ## rust function
+    /// ## Author: Jiangqiu Shen
+    /// ## Date: 2023-06-19
+    /// Description: parse the trace, see the module doc for the format
+    pub fn parse(reader: impl BufRead) -> eyre::Result<Self> {
+        let mut hardware = None;
+        let mut shape = None;
+        let mut b_rows: Vec<(u32, Vec<u32>)> = vec![];
+        let mut rounds: Vec<(u32, AccessRound)> = vec![];
+        // the largest id seen, used when there is no shape
+        let mut max_id = None;
+        for (line_no, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line_no = line_no + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let kind = tokens.next().unwrap();
+            match kind {
+                "hardware" => hardware = Some(TraceHardware::parse(tokens, line_no)?),
+                "shape" => {
+                    let rows = tokens.next().map(|t| parse_id(t, line_no)).transpose()?;
+                    let cols = tokens.next().map(|t| parse_id(t, line_no)).transpose()?;
+                    let (Some(rows), Some(cols)) = (rows, cols) else {
+                        bail!("line {}: shape should be `shape <rows> <cols>`", line_no);
+                    };
+                    shape = Some((rows as usize, cols as usize));
+                }
+                "b" => {
+                    let row = tokens
+                        .next()
+                        .ok_or_else(|| eyre!("line {}: missing the row of matrix b", line_no))?;
+                    let row = parse_id(row, line_no)?;
+                    let cols = tokens
+                        .map(|t| parse_id(t, line_no))
+                        .collect::<eyre::Result<Vec<_>>>()?;
+                    max_id = cols.iter().chain([&row]).copied().max().max(max_id);
+                    b_rows.push((row, cols));
+                }
+                "a" => {
+                    let round = tokens
+                        .next()
+                        .ok_or_else(|| eyre!("line {}: missing the round", line_no))?;
+                    let round = parse_id(round, line_no)?;
+                    let tokens = tokens.collect::<Vec<_>>();
+                    let access = if tokens.contains(&"*") {
+                        AccessRound::All
+                    } else {
+                        let rows = tokens
+                            .into_iter()
+                            .map(|t| parse_id(t, line_no))
+                            .collect::<eyre::Result<Vec<_>>>()?;
+                        max_id = rows.iter().copied().max().max(max_id);
+                        AccessRound::Rows(rows)
+                    };
+                    match rounds.last_mut() {
+                        Some((last, last_access)) if *last == round => {
+                            match (last_access, access) {
+                                (AccessRound::Rows(last_rows), AccessRound::Rows(rows)) => {
+                                    last_rows.extend(rows)
+                                }
+                                (last_access, _) => *last_access = AccessRound::All,
+                            }
+                        }
+                        _ => rounds.push((round, access)),
+                    }
+                }
+                _ => bail!("line {}: unknown line type {:?}", line_no, kind),
+            }
+        }
+        let (rows, cols) = match shape {
+            Some(shape) => shape,
+            None => {
+                let size = max_id.map_or(0, |id| id as usize + 1);
+                (size, size)
+            }
+        };
+        let mut matrix_b = TriMatI::new((rows, cols));
+        for (row, mut b_cols) in b_rows {
+            if row as usize >= rows {
+                bail!("the row {} of matrix b is out of {} rows", row, rows);
+            }
+            b_cols.sort_unstable();
+            b_cols.dedup();
+            for col in b_cols {
+                if col as usize >= cols {
+                    bail!("the col {} of matrix b is out of {} cols", col, cols);
+                }
+                matrix_b.add_triplet(row as usize, col as usize, Pattern);
+            }
+        }
+        let rounds = rounds
+            .into_iter()
+            .map(|(_, access)| match access {
+                AccessRound::All => Ok(AccessRound::All),
+                AccessRound::Rows(mut access_rows) => {
+                    access_rows.sort_unstable();
+                    access_rows.dedup();
+                    if let Some(&row) = access_rows.last().filter(|&&row| row as usize >= rows) {
+                        bail!("the accessed row {} is out of {} rows", row, rows);
+                    }
+                    Ok(AccessRound::Rows(access_rows))
+                }
+            })
+            .collect::<eyre::Result<Vec<_>>>()?;
+        Ok(Self {
+            hardware,
+            matrix_b: matrix_b.to_csr(),
+            rounds,
+        })
+    }
+
+    pub fn hardware(&self) -> Option<&TraceHardware> {
+        self.hardware.as_ref()
+    }
+
+    pub fn matrix_b(&self) -> CsMatViewI<'_, Pattern, u32> {
+        self.matrix_b.view()
+    }
+
+    pub fn rounds(&self) -> &[AccessRound] {
+        &self.rounds
+    }
+
+    /// ## rust function
+    /// ## Author: Jiangqiu Shen
+    /// ## Date: 2023-06-19
+    /// Description: the rounds as matrix a of the gearbox simulators, each row is a round
+    pub fn matrix_a(&self) -> CsMatI<Pattern, u32> {
+        let rows = self.matrix_b.rows();
+        let mut matrix_a = TriMatI::new((self.rounds.len(), rows));
+        for (round, access) in self.rounds.iter().enumerate() {
+            match access {
+                AccessRound::All => {
+                    (0..rows).for_each(|row| matrix_a.add_triplet(round, row, Pattern))
+                }
+                AccessRound::Rows(access_rows) => access_rows
+                    .iter()
+                    .for_each(|&row| matrix_a.add_triplet(round, row as usize, Pattern)),
+            }
+        }
+        matrix_a.to_csr()
+    }
+
+    /// (matrix a, matrix b) of the gearbox simulators
+    pub fn into_matrices(self) -> (CsMatI<Pattern, u32>, CsMatI<Pattern, u32>) {
+        (self.matrix_a(), self.matrix_b)
+    }
+
+    /// replay the rounds as a [`SpmvAlgorithm`]
+    pub fn replay(&self) -> Replay<'_> {
+        Replay {
+            rounds: self.rounds.iter(),
+            rows: self.matrix_b.rows(),
+        }
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-19
+/// Description: the frontiers of a recorded trace, one round at a time
+pub struct Replay<'a> {
+    rounds: std::slice::Iter<'a, AccessRound>,
+    rows: usize,
+}
+
+impl<'a> SpmvAlgorithm for Replay<'a> {
+    fn next_frontier(&mut self) -> Option<FrontierType> {
+        self.rounds.next().map(|access| match access {
+            AccessRound::All => FrontierType::All,
+            AccessRound::Rows(rows) => FrontierType::Some(CsVecI::new(
+                self.rows,
+                rows.clone(),
+                vec![Pattern; rows.len()],
+            )),
+        })
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-19
+/// Description: record the frontiers of a simulation, matrix b is written before the first round
+pub struct AccessTraceWriter {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    /// written before matrix b
+    hardware: Option<TraceHardware>,
+    matrix_written: bool,
+    rounds: usize,
+}
+
+impl AccessTraceWriter {
+    pub fn create(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .wrap_err(format!("fail to create dir: {:?}", parent))?;
+        }
+        let file =
+            File::create(&path).wrap_err(format!("fail to create access trace: {:?}", path))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            path,
+            hardware: None,
+            matrix_written: false,
+            rounds: 0,
+        })
+    }
+
+    /// create the writer at `{dir}/{graph}_{mapping}_{name}.access`,
+    /// when `resume` is set, the rounds of the existing file are kept
+    pub fn for_graph(
+        dir: impl AsRef<Path>,
+        graph: &str,
+        hardware: TraceHardware,
+        name: &str,
+        resume: bool,
+    ) -> eyre::Result<Self> {
+        let graph_name = graph_reader::entry_file_stem(graph);
+        let file_name = format!(
+            "{graph_name}_{}_{name}.{ACCESS_TRACE_EXTENSION}",
+            hardware.mapping
+        );
+        let path = dir.as_ref().join(file_name);
+        let mut writer = if resume && path.exists() {
+            Self::rewrite(path, usize::MAX)?
+        } else {
+            Self::create(path)?
+        };
+        writer.hardware = Some(hardware);
+        Ok(writer)
+    }
+
+    /// rewrite the existing trace with its first `rounds` rounds, the trace is written line by line so it can't be
+    /// appended in place
+    fn rewrite(path: impl AsRef<Path>, rounds: usize) -> eyre::Result<Self> {
+        let trace = AccessTrace::read(&path.as_ref().to_string_lossy())?;
+        let mut writer = Self::create(path)?;
+        writer.hardware = trace.hardware;
+        if trace.matrix_b.rows() > 0 {
+            writer.write_matrix(trace.matrix_b())?;
+        }
+        for access in trace.rounds.iter().take(rounds) {
+            match access {
+                AccessRound::All => writer.write_round(None)?,
+                AccessRound::Rows(rows) => writer.write_round(Some(rows))?,
+            }
+        }
+        Ok(writer)
+    }
+
+    /// drop all rounds after `next_round`, those rounds were written after the last checkpoint
+    /// and will be simulated again
+    pub fn truncate_to(&mut self, next_round: usize) -> eyre::Result<()> {
+        self.writer.flush()?;
+        let hardware = self.hardware.take();
+        *self = Self::rewrite(&self.path, next_round)?;
+        self.hardware = self.hardware.take().or(hardware);
+        Ok(())
+    }
+
+    /// write matrix b, only the first call writes it
+    pub fn write_matrix(&mut self, matrix_b: CsMatViewI<Pattern, u32>) -> eyre::Result<()> {
+        if self.matrix_written {
+            return Ok(());
+        }
+        if let Some(hardware) = &self.hardware {
+            writeln!(self.writer, "hardware {}", hardware)?;
+        }
+        writeln!(self.writer, "shape {} {}", matrix_b.rows(), matrix_b.cols())?;
+        for (row, cols) in matrix_b.outer_iterator().enumerate() {
+            if cols.nnz() == 0 {
+                continue;
+            }
+            write!(self.writer, "b {}", row)?;
+            for col in cols.indices() {
+                write!(self.writer, " {}", col)?;
+            }
+            writeln!(self.writer)?;
+        }
+        self.matrix_written = true;
+        Ok(())
+    }
+
+    /// write the rows read in the next round, `None` means all rows
+    pub fn write_round(&mut self, rows: Option<&[u32]>) -> eyre::Result<()> {
+        write!(self.writer, "a {}", self.rounds)?;
+        match rows {
+            Some(rows) => {
+                for row in rows {
+                    write!(self.writer, " {}", row)?;
+                }
+            }
+            None => write!(self.writer, " *")?,
+        }
+        writeln!(self.writer).wrap_err(format!("fail to write access trace: {:?}", self.path))?;
+        self.rounds += 1;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_trace() -> eyre::Result<()> {
+        let content =
+            "# a test trace\nhardware SameBank 1 2 4 256\nshape 4 4\nb 0 1 2\nb 1 3\nb 3 0\n\na 0 1 0\na 0 1\na 1 *\na 2 3\n";
+        let trace = AccessTrace::parse(content.as_bytes())?;
+        assert_eq!(
+            trace.hardware(),
+            Some(&TraceHardware {
+                mapping: "SameBank".to_string(),
+                channels: 1,
+                banks: 2,
+                subarrays: 4,
+                columns: 256,
+            })
+        );
+        assert_eq!(trace.matrix_b().shape(), (4, 4));
+        assert_eq!(trace.matrix_b().nnz(), 4);
+        assert_eq!(
+            trace.rounds(),
+            &[
+                AccessRound::Rows(vec![0, 1]),
+                AccessRound::All,
+                AccessRound::Rows(vec![3])
+            ]
+        );
+        let matrix_a = trace.matrix_a();
+        assert_eq!(matrix_a.shape(), (3, 4));
+        assert_eq!(matrix_a.nnz(), 7);
+
+        let mut replay = trace.replay();
+        assert_eq!(
+            replay.next_frontier(),
+            Some(FrontierType::Some(CsVecI::new(
+                4,
+                vec![0, 1],
+                vec![Pattern; 2]
+            )))
+        );
+        assert_eq!(replay.next_frontier(), Some(FrontierType::All));
+        assert!(replay.next_frontier().is_some());
+        assert_eq!(replay.next_frontier(), None);
+
+        // without the shape, the matrix covers all ids
+        let trace = AccessTrace::parse("b 0 5\na 0 2\n".as_bytes())?;
+        assert_eq!(trace.matrix_b().shape(), (6, 6));
+        assert_eq!(trace.hardware(), None);
+        assert!(AccessTrace::parse("hardware SameBank 1 2\n".as_bytes()).is_err());
+        assert!(AccessTrace::parse("shape 2 2\na 0 3\n".as_bytes()).is_err());
+        assert!(AccessTrace::parse("c 0 1\n".as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_and_replay() -> eyre::Result<()> {
+        assert!(is_access_trace("output/test_SameBank_spmm.access"));
+        assert!(is_access_trace("output/test_SameBank_spmm.access.gz"));
+        assert!(!is_access_trace("test_mtx/test.mtx.gz"));
+
+        let matrix_b = CsMatI::new((3, 3), vec![0, 2, 2, 3], vec![0, 2, 1], vec![Pattern; 3]);
+        let dir = std::env::temp_dir().join("spmspm_access_trace_test");
+        let hardware = TraceHardware::from_config(&ConfigV3 {
+            mapping: crate::pim::configv2::MappingType::SameBank,
+            ..Default::default()
+        });
+        let mut writer =
+            AccessTraceWriter::for_graph(&dir, "graphs/test.mtx", hardware.clone(), "spmm", false)?;
+        let path = writer.path().to_path_buf();
+        assert!(path.ends_with("test_SameBank_spmm.access"));
+        writer.write_matrix(matrix_b.view())?;
+        writer.write_matrix(matrix_b.view())?;
+        writer.write_round(Some(&[0, 2][..]))?;
+        writer.write_round(None)?;
+        writer.write_round(Some(&[][..]))?;
+        writer.truncate_to(2)?;
+        drop(writer);
+
+        let trace = AccessTrace::read(path.to_str().unwrap())?;
+        assert_eq!(trace.hardware(), Some(&hardware));
+        assert_eq!(trace.matrix_b(), matrix_b.view());
+        assert_eq!(
+            trace.rounds(),
+            &[AccessRound::Rows(vec![0, 2]), AccessRound::All]
+        );
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
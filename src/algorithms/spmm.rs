@@ -32,6 +32,11 @@ impl<'a> SpmvAlgorithm for Spmm<'a> {
             None
         }
     }
+
+    /// the product is a single iteration, it's finished by the last row
+    fn iteration_finished(&self) -> bool {
+        self.current_frontier >= self.matrix.outer_dims()
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -51,10 +56,12 @@ mod tests {
             spmm.next_frontier(),
             Some(FrontierType::Some(CsVecI::new(3, vec![1], vec![Pattern])))
         );
+        assert!(!spmm.iteration_finished());
         assert_eq!(
             spmm.next_frontier(),
             Some(FrontierType::Some(CsVecI::new(3, vec![2], vec![Pattern])))
         );
+        assert!(spmm.iteration_finished());
         assert_eq!(spmm.next_frontier(), None);
     }
 }
@@ -0,0 +1,321 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-15
+//! Description: run the analysis jobs listed in a manifest, and keep the state of each job in a status file so an
+//! interrupted campaign can be restarted.
+//! the manifest is a toml file:
+//! ```toml
+//! # optional, default to the manifest path with the extension `status.json`
+//! status_path = "output/batch_status.json"
+//! [[jobs]]
+//! config = "configs/real_jump_same_bank.toml"
+//! analysis = "new-analysis"
+//! [[jobs]]
+//! config = "configs/gearbox_001_v2.toml"
+//! analysis = "gearbox-origin-all-v2"
+//! ```
+//! the `analysis` is the same name used by the `analyze` subcommand. on restart, the `done` jobs are skipped, the
+//! other jobs are run again and continue from their checkpoints. a job stopped by the `stop` command or a deadline
+//! is `stopped` instead of `done`, so it's resumed on restart. the jobs run in parallel, each one acquires its
+//! memory from [`crate::tools::memory_scheduler`] before loading the graphs, so they wait for each other instead of
+//! running out of memory.
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::BufWriter,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use clap::ValueEnum;
+use eyre::{bail, eyre, Context};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::{cli::AnalyzeType, tools::cancel};
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    status_path: Option<PathBuf>,
+    jobs: Vec<ManifestJob>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestJob {
+    config: PathBuf,
+    analysis: String,
+}
+
+/// a job of the batch
+#[derive(Debug, Clone)]
+pub struct BatchJob {
+    /// the key in the status file, like `new-analysis:configs/real_jump.toml`
+    pub key: String,
+    pub config: PathBuf,
+    pub analysis: AnalyzeType,
+}
+
+/// the state of a job in the status file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Stopped,
+    Done,
+    Failed { error: String },
+}
+
+/// the status of all jobs, keyed by [`BatchJob::key`]
+pub type BatchStatus = BTreeMap<String, JobStatus>;
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-15
+/// Description: parse the manifest, return the jobs and the path of the status file
+pub fn parse_manifest(
+    content: &str,
+    manifest_path: &Path,
+) -> eyre::Result<(Vec<BatchJob>, PathBuf)> {
+    let manifest: Manifest = toml::from_str(content).wrap_err("the manifest is not valid")?;
+    let status_path = manifest
+        .status_path
+        .unwrap_or_else(|| manifest_path.with_extension("status.json"));
+    let mut jobs: Vec<BatchJob> = vec![];
+    for (i, job) in manifest.jobs.into_iter().enumerate() {
+        let analysis = AnalyzeType::from_str(&job.analysis, true)
+            .map_err(|e| eyre!("jobs[{}].analysis: {}", i, e))?;
+        let key = format!("{}:{}", job.analysis, job.config.display());
+        if jobs.iter().any(|other| other.key == key) {
+            bail!("jobs[{}]: the job {} is listed twice", i, key);
+        }
+        jobs.push(BatchJob {
+            key,
+            config: job.config,
+            analysis,
+        });
+    }
+    Ok((jobs, status_path))
+}
+
+/// read the status file, a missing file means no job has started
+pub fn load_status(path: &Path) -> eyre::Result<BatchStatus> {
+    if !path.exists() {
+        return Ok(BatchStatus::new());
+    }
+    let file = File::open(path).wrap_err(format!("fail to open status file {:?}", path))?;
+    serde_json::from_reader(file).wrap_err(format!("fail to parse status file {:?}", path))
+}
+
+/// write the status to a temporary file and rename it, so a crash never leaves a broken status file
+pub fn save_status(path: &Path, status: &BatchStatus) -> eyre::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let temp_path = path.with_extension("tmp");
+    serde_json::to_writer_pretty(BufWriter::new(File::create(&temp_path)?), status)?;
+    std::fs::rename(&temp_path, path).wrap_err(format!("fail to write status file {:?}", path))?;
+    Ok(())
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-15
+/// Description: the jobs that should run, with whether they should resume from their checkpoints. the `done` jobs
+/// are skipped, the `running`, `stopped` and `failed` ones were interrupted and resume, the others start over
+pub fn plan_jobs(jobs: &[BatchJob], status: &BatchStatus) -> Vec<(BatchJob, bool)> {
+    jobs.iter()
+        .filter_map(|job| match status.get(&job.key) {
+            Some(JobStatus::Done) => None,
+            Some(JobStatus::Running)
+            | Some(JobStatus::Stopped)
+            | Some(JobStatus::Failed { .. }) => Some((job.clone(), true)),
+            Some(JobStatus::Pending) | None => Some((job.clone(), false)),
+        })
+        .collect()
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// update the state of a job and write the status file
+fn update_status(
+    status: &Mutex<BatchStatus>,
+    path: &Path,
+    key: &str,
+    job_status: JobStatus,
+) -> eyre::Result<()> {
+    let mut status = status.lock().unwrap();
+    status.insert(key.to_string(), job_status);
+    save_status(path, &status)
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-15
+/// Description: run the unfinished jobs of the manifest, a failed or panicked job is recorded and does not stop the
+/// others, return an error listing the failed jobs at the end
+pub fn run_batch(manifest_path: impl AsRef<Path>) -> eyre::Result<()> {
+    let current_time = std::time::Instant::now();
+    let manifest_path = manifest_path.as_ref();
+    let content = std::fs::read_to_string(manifest_path)
+        .wrap_err(format!("fail to read manifest {:?}", manifest_path))?;
+    let (jobs, status_path) = parse_manifest(&content, manifest_path)
+        .wrap_err(format!("fail to load manifest {:?}", manifest_path))?;
+    let mut status = load_status(&status_path)?;
+    let planned = plan_jobs(&jobs, &status);
+    info!(
+        "batch: {} jobs, {} finished, {} to run",
+        jobs.len(),
+        jobs.len() - planned.len(),
+        planned.len()
+    );
+    for (job, _) in &planned {
+        status.entry(job.key.clone()).or_insert(JobStatus::Pending);
+    }
+    save_status(&status_path, &status)?;
+    let status = Mutex::new(status);
+
+    planned
+        .into_par_iter()
+        .map(|(job, resume)| {
+            // the analyses without checkpoints start over
+            let resume = resume && job.analysis.supports_resume();
+            info!("start job {}, resume: {}", job.key, resume);
+            update_status(&status, &status_path, &job.key, JobStatus::Running)?;
+            // the jobs running at the same time share the counter, so a job can be marked `stopped` by another
+            // one, it then resumes from its finished checkpoints
+            let partial_runs = cancel::partial_runs();
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                super::run_analysis(job.analysis.clone(), job.config.clone(), resume)
+            }));
+            let stopped = cancel::root().is_cancelled() || cancel::partial_runs() != partial_runs;
+            let job_status = match result {
+                Ok(Ok(())) if stopped => {
+                    info!("job {} stopped, it will resume on restart", job.key);
+                    JobStatus::Stopped
+                }
+                Ok(Ok(())) => {
+                    info!("job {} done", job.key);
+                    JobStatus::Done
+                }
+                Ok(Err(e)) => {
+                    error!("job {} failed: {:?}", job.key, e);
+                    JobStatus::Failed {
+                        error: format!("{:?}", e),
+                    }
+                }
+                Err(payload) => {
+                    let message = panic_message(payload);
+                    error!("job {} panicked: {}", job.key, message);
+                    JobStatus::Failed {
+                        error: format!("panicked: {}", message),
+                    }
+                }
+            };
+            update_status(&status, &status_path, &job.key, job_status)
+        })
+        .collect::<eyre::Result<()>>()?;
+
+    let status = status.into_inner().unwrap();
+    let failed = jobs
+        .iter()
+        .filter(|job| matches!(status.get(&job.key), Some(JobStatus::Failed { .. })))
+        .map(|job| format!("  - {}", job.key))
+        .collect::<Vec<_>>();
+    info!(
+        "batch finished in {:?}, the status is written to {:?}",
+        current_time.elapsed(),
+        status_path
+    );
+    if !failed.is_empty() {
+        bail!(
+            "{} job(s) failed, see {:?}:\n{}",
+            failed.len(),
+            status_path,
+            failed.join("\n")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_status() -> eyre::Result<()> {
+        let manifest = r#"
+[[jobs]]
+config = "configs/a.toml"
+analysis = "new-analysis"
+[[jobs]]
+config = "configs/b.toml"
+analysis = "gearbox-origin-all-v2"
+[[jobs]]
+config = "configs/c.toml"
+analysis = "new-analysis"
+[[jobs]]
+config = "configs/d.toml"
+analysis = "new-analysis"
+[[jobs]]
+config = "configs/e.toml"
+analysis = "new-analysis"
+"#;
+        let (jobs, status_path) = parse_manifest(manifest, Path::new("batch/manifest.toml"))?;
+        assert_eq!(status_path, Path::new("batch/manifest.status.json"));
+        assert_eq!(jobs.len(), 5);
+        assert_eq!(jobs[0].key, "new-analysis:configs/a.toml");
+        assert!(matches!(jobs[1].analysis, AnalyzeType::GearboxOriginAllV2));
+
+        let mut status = BatchStatus::new();
+        status.insert(jobs[0].key.clone(), JobStatus::Done);
+        status.insert(jobs[1].key.clone(), JobStatus::Running);
+        status.insert(
+            jobs[2].key.clone(),
+            JobStatus::Failed {
+                error: "oom".into(),
+            },
+        );
+        status.insert(jobs[4].key.clone(), JobStatus::Stopped);
+        let planned = plan_jobs(&jobs, &status)
+            .into_iter()
+            .map(|(job, resume)| (job.key, resume))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            planned,
+            vec![
+                ("gearbox-origin-all-v2:configs/b.toml".to_string(), true),
+                ("new-analysis:configs/c.toml".to_string(), true),
+                ("new-analysis:configs/d.toml".to_string(), false),
+                ("new-analysis:configs/e.toml".to_string(), true),
+            ]
+        );
+
+        let dir = std::env::temp_dir().join("spmspm_batch_test");
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("status.json");
+        save_status(&path, &status)?;
+        assert_eq!(load_status(&path)?, status);
+        std::fs::remove_file(&path)?;
+        assert!(load_status(&path)?.is_empty());
+
+        let wrong = "[[jobs]]\nconfig = \"a.toml\"\nanalysis = \"not-an-analysis\"\n";
+        assert!(parse_manifest(wrong, Path::new("m.toml")).is_err());
+        let twice = format!(
+            "{}{}",
+            manifest, "[[jobs]]\nconfig = \"configs/a.toml\"\nanalysis = \"new-analysis\"\n"
+        );
+        assert!(parse_manifest(&twice, Path::new("m.toml")).is_err());
+        Ok(())
+    }
+}
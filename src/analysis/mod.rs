@@ -4,26 +4,34 @@ pub mod evil_filter;
 pub mod remap_analyze;
 pub mod translate_mapping;
 
+pub mod batch;
 pub mod results;
 pub mod stats;
+pub mod sweep;
 pub mod traits;
-use crate::cli::LogType;
-use crate::pim::configv2::{ConfigV2, ConfigV3};
+use crate::cli::{BatchArgs, ConvertArgs, GenerateArgs, LogType, SweepArgs};
+use crate::pim::config_validation::{load_validated, ValidationTarget};
+use crate::pim::configv2::ConfigV2;
 use crate::{cli, pim::config::Config};
-use crate::{draw, init_logger, RunArgs, Simulator};
+use crate::{
+    draw, init_logger,
+    tools::{graph_generator, matrix_cache, memory_profile::MemoryReport},
+    RunArgs, Simulator,
+};
 use crate::{init_logger_stderr, AnalyzeArgs};
 use serde::Serialize;
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::BufWriter;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::AtomicUsize;
 use std::sync::RwLock;
-use tracing::info;
 use tracing::metadata::LevelFilter;
+use tracing::{info, warn};
 pub mod mapping;
 use self::remap_analyze::row_cycle::*;
 use self::three_stages::analyze_refined_dispatcher_overflow;
+use self::traits::AnalyzeTool;
 pub mod old;
 pub use old::*;
 pub mod three_stages;
@@ -81,7 +89,7 @@ pub fn do_analyze(
     match cli.subcmd {
         cli::Operation::Run(RunArgs { config }) => {
             println!("run with config: {:?}", config);
-            let config = Config::new(config);
+            let config: Config = load_validated(config, ValidationTarget::Simulator)?.into();
             info!("building simulator");
             let mut simulator = Simulator::new(&config);
             info!("start running simulator");
@@ -89,265 +97,313 @@ pub fn do_analyze(
             simulator.run(&config);
         }
 
-        cli::Operation::Analyze(AnalyzeArgs { analyze, config }) => {
-            match analyze {
-                cli::AnalyzeType::All => {
-                    println!("analyze with config: {:?}", config);
-                    let config = Config::new(config);
-                    print_all_stats(&config);
-                }
-                cli::AnalyzeType::Overlap => todo!(),
-                cli::AnalyzeType::Sequential => todo!(),
-                cli::AnalyzeType::Window => todo!(),
-                cli::AnalyzeType::SplitSpmm => {
-                    let current_time = std::time::Instant::now();
-                    println!("analyze with config: {:?}", config);
-                    let config = Config::new(config);
+        cli::Operation::Analyze(AnalyzeArgs {
+            analyze,
+            config,
+            resume,
+        }) => run_analysis(analyze, config, resume)?,
+        cli::Operation::Draw(draw_args) => draw::draw_with_type(draw_args.subcmd)?,
+        cli::Operation::Convert(ConvertArgs { config, force }) => {
+            info!("convert the graphs in config: {:?}", config);
+            matrix_cache::convert_config(config, force)?;
+        }
+        cli::Operation::Generate(GenerateArgs { graphs, output }) => {
+            let paths = graph_generator::write_generated(&graphs, output)?;
+            info!("generated graphs: {:?}", paths);
+        }
+        cli::Operation::Sweep(SweepArgs { config, resume }) => {
+            info!("sweep with config: {:?}", config);
+            sweep::run_sweep(config, resume)?;
+        }
+        cli::Operation::Batch(BatchArgs { manifest }) => {
+            info!("batch with manifest: {:?}", manifest);
+            batch::run_batch(manifest)?;
+        }
+    };
+    Ok(())
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-15
+/// Description: run the analysis on the config, shared by the `analyze` and `batch` subcommands
+pub fn run_analysis(analyze: cli::AnalyzeType, config: PathBuf, resume: bool) -> eyre::Result<()> {
+    if resume && !analyze.supports_resume() {
+        eyre::bail!(
+            "the {:?} analysis does not save checkpoints, it can not run with `--resume`",
+            analyze
+        );
+    }
+    match analyze {
+        cli::AnalyzeType::All => {
+            println!("analyze with config: {:?}", config);
+            let config: Config = load_validated(config, ValidationTarget::Analysis)?.into();
+            print_all_stats(&config);
+        }
+        cli::AnalyzeType::Overlap => todo!(),
+        cli::AnalyzeType::Sequential => todo!(),
+        cli::AnalyzeType::Window => todo!(),
+        cli::AnalyzeType::SplitSpmm => {
+            let current_time = std::time::Instant::now();
+            println!("analyze with config: {:?}", config);
+            let config: Config = load_validated(config, ValidationTarget::Analysis)?.into();
 
-                    let stem = config.output_path.file_stem().unwrap();
-                    let externsion = config.output_path.extension().unwrap();
-                    let new_file_name = format!(
-                        "{}_split_spmm.{}",
-                        stem.to_str().unwrap(),
-                        externsion.to_str().unwrap()
-                    );
-                    let dir_name = config.output_path.parent().unwrap();
-                    let new_path = dir_name.join(new_file_name);
-                    info!("the result will be written to {:?}", new_path);
+            let stem = config.output_path.file_stem().unwrap();
+            let externsion = config.output_path.extension().unwrap();
+            let new_file_name = format!(
+                "{}_split_spmm.{}",
+                stem.to_str().unwrap(),
+                externsion.to_str().unwrap()
+            );
+            let dir_name = config.output_path.parent().unwrap();
+            let new_path = dir_name.join(new_file_name);
+            info!("the result will be written to {:?}", new_path);
 
-                    let split_result = analyze_split_spmm::analyze_split_spmm(&config);
-                    split_result.show_results();
+            let split_result = analyze_split_spmm::analyze_split_spmm(&config);
+            split_result.show_results();
 
-                    serde_json::to_writer(BufWriter::new(File::create(new_path)?), &split_result)?;
-                    info!("time elapsed: {:?}", current_time.elapsed());
-                }
-                cli::AnalyzeType::Gearbox => {
-                    let current_time = std::time::Instant::now();
-                    info!("analyze with config: {:?}", config);
-                    let config = Config::new(config);
+            serde_json::to_writer(BufWriter::new(File::create(new_path)?), &split_result)?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::Gearbox => {
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config);
+            let config: Config = load_validated(config, ValidationTarget::Analysis)?.into();
 
-                    let stem = config.output_path.file_stem().unwrap();
-                    let externsion = config.output_path.extension().unwrap();
-                    let new_file_name = format!(
-                        "{}_gearbox.{}",
-                        stem.to_str().unwrap(),
-                        externsion.to_str().unwrap()
-                    );
-                    let dir_name = config.output_path.parent().unwrap();
-                    let new_path = dir_name.join(new_file_name);
-                    info!("the result will be written to {:?}", new_path);
+            let stem = config.output_path.file_stem().unwrap();
+            let externsion = config.output_path.extension().unwrap();
+            let new_file_name = format!(
+                "{}_gearbox.{}",
+                stem.to_str().unwrap(),
+                externsion.to_str().unwrap()
+            );
+            let dir_name = config.output_path.parent().unwrap();
+            let new_path = dir_name.join(new_file_name);
+            info!("the result will be written to {:?}", new_path);
 
-                    let gearbox_result = analyze_gearbox::analyze_gearbox(&config);
-                    serde_json::to_writer(
-                        BufWriter::new(File::create(new_path)?),
-                        &gearbox_result,
-                    )?;
-                    info!("time elapsed: {:?}", current_time.elapsed());
-                }
-                cli::AnalyzeType::Nnz => {
-                    let current_time = std::time::Instant::now();
-                    info!("analyze with config: {:?}", config);
-                    let config = Config::new(config);
-                    let nnz_result = analyze_nnz::analyze_nnz_spmm(&config);
-                    nnz_result.show_results();
+            let gearbox_result = analyze_gearbox::analyze_gearbox(&config);
+            serde_json::to_writer(BufWriter::new(File::create(new_path)?), &gearbox_result)?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::Nnz => {
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config);
+            let config: Config = load_validated(config, ValidationTarget::Analysis)?.into();
+            let nnz_result = analyze_nnz::analyze_nnz_spmm(&config);
+            nnz_result.show_results();
 
-                    serde_json::to_writer(
-                        BufWriter::new(File::create(config.output_path)?),
-                        &nnz_result,
-                    )?;
-                    info!("time elapsed: {:?}", current_time.elapsed());
-                }
-                cli::AnalyzeType::NnzNative => {
-                    let current_time = std::time::Instant::now();
-                    info!("analyze with config: {:?}", config);
-                    let config = Config::new(config);
-                    let nnz_result = analyze_nnz_native::analyze_nnz_spmm(&config);
-                    nnz_result.show_results();
-                    serde_json::to_writer(
-                        BufWriter::new(File::create(config.output_path)?),
-                        &nnz_result,
-                    )?;
-                    info!("time elapsed: {:?}", current_time.elapsed());
-                }
-                cli::AnalyzeType::GearboxParallel => {
-                    let current_time = std::time::Instant::now();
-                    info!("analyze with config: {:?}", config);
-                    let config = Config::new(config);
+            serde_json::to_writer(
+                BufWriter::new(File::create(config.output_path)?),
+                &nnz_result,
+            )?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::NnzNative => {
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config);
+            let config: Config = load_validated(config, ValidationTarget::Analysis)?.into();
+            let nnz_result = analyze_nnz_native::analyze_nnz_spmm(&config);
+            nnz_result.show_results();
+            serde_json::to_writer(
+                BufWriter::new(File::create(config.output_path)?),
+                &nnz_result,
+            )?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::GearboxParallel => {
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config);
+            let config: Config = load_validated(config, ValidationTarget::Analysis)?.into();
 
-                    let stem = config.output_path.file_stem().unwrap();
-                    let externsion = config.output_path.extension().unwrap();
-                    let new_file_name = format!(
-                        "{}_gearbox.{}",
-                        stem.to_str().unwrap(),
-                        externsion.to_str().unwrap()
-                    );
-                    let dir_name = config.output_path.parent().unwrap();
-                    let new_path = dir_name.join(new_file_name);
-                    info!("the result will be written to {:?}", new_path);
+            let stem = config.output_path.file_stem().unwrap();
+            let externsion = config.output_path.extension().unwrap();
+            let new_file_name = format!(
+                "{}_gearbox.{}",
+                stem.to_str().unwrap(),
+                externsion.to_str().unwrap()
+            );
+            let dir_name = config.output_path.parent().unwrap();
+            let new_path = dir_name.join(new_file_name);
+            info!("the result will be written to {:?}", new_path);
 
-                    let gearbox_result = analyze_gearbox_parallel::analyze_gearbox(&config);
-                    serde_json::to_writer(
-                        BufWriter::new(File::create(new_path)?),
-                        &gearbox_result,
-                    )?;
-                    info!("time elapsed: {:?}", current_time.elapsed());
-                }
-                cli::AnalyzeType::NnzDraw => {
-                    let current_time = std::time::Instant::now();
-                    info!("analyze with config: {:?}", config);
-                    let config = Config::new(config);
+            let gearbox_result = analyze_gearbox_parallel::analyze_gearbox(&config);
+            serde_json::to_writer(BufWriter::new(File::create(new_path)?), &gearbox_result)?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::NnzDraw => {
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config);
+            let config: Config = load_validated(config, ValidationTarget::Analysis)?.into();
 
-                    let stem = config.output_path.file_stem().unwrap();
-                    let externsion = config.output_path.extension().unwrap();
-                    let new_file_name = format!(
-                        "{}_gearbox.{}",
-                        stem.to_str().unwrap(),
-                        externsion.to_str().unwrap()
-                    );
-                    let dir_name = config.output_path.parent().unwrap();
-                    let new_path = dir_name.join(new_file_name);
-                    info!("the result will be written to {:?}", new_path);
+            let stem = config.output_path.file_stem().unwrap();
+            let externsion = config.output_path.extension().unwrap();
+            let new_file_name = format!(
+                "{}_gearbox.{}",
+                stem.to_str().unwrap(),
+                externsion.to_str().unwrap()
+            );
+            let dir_name = config.output_path.parent().unwrap();
+            let new_path = dir_name.join(new_file_name);
+            info!("the result will be written to {:?}", new_path);
 
-                    analyze_nnz_gearbox::analyze_nnz_spmm(&config);
-                    info!("time elapsed: {:?}", current_time.elapsed());
-                }
-                cli::AnalyzeType::GearboxOrigin => {
-                    let current_time = std::time::Instant::now();
-                    info!("analyze with config: {:?}", config);
-                    let config = Config::new(config);
+            analyze_nnz_gearbox::analyze_nnz_spmm(&config);
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::GearboxOrigin => {
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config);
+            let config: Config = load_validated(config, ValidationTarget::Analysis)?.into();
 
-                    let stem = config.output_path.file_stem().unwrap();
-                    let externsion = config.output_path.extension().unwrap();
-                    let new_file_name = format!(
-                        "{}_gearbox_origin.{}",
-                        stem.to_str().unwrap(),
-                        externsion.to_str().unwrap()
-                    );
-                    let dir_name = config.output_path.parent().unwrap();
-                    let new_path = dir_name.join(new_file_name);
-                    info!("the result will be written to {:?}", new_path);
+            let stem = config.output_path.file_stem().unwrap();
+            let externsion = config.output_path.extension().unwrap();
+            let new_file_name = format!(
+                "{}_gearbox_origin.{}",
+                stem.to_str().unwrap(),
+                externsion.to_str().unwrap()
+            );
+            let dir_name = config.output_path.parent().unwrap();
+            let new_path = dir_name.join(new_file_name);
+            info!("the result will be written to {:?}", new_path);
 
-                    let gearbox_result = analyze_gearbox_origin::analyze_gearbox(&config);
-                    serde_json::to_writer(
-                        BufWriter::new(File::create(new_path)?),
-                        &gearbox_result,
-                    )?;
-                    info!("time elapsed: {:?}", current_time.elapsed());
-                }
-                cli::AnalyzeType::GearboxOriginAll => {
-                    let current_time = std::time::Instant::now();
-                    info!("analyze with config: {:?}", config);
-                    let config = Config::new(config);
+            let gearbox_result = analyze_gearbox_origin::analyze_gearbox(&config);
+            serde_json::to_writer(BufWriter::new(File::create(new_path)?), &gearbox_result)?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::GearboxOriginAll => {
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config);
+            let config: Config = load_validated(config, ValidationTarget::Analysis)?.into();
 
-                    let stem = config.output_path.file_stem().unwrap();
-                    let externsion = config.output_path.extension().unwrap();
-                    let new_file_name = format!(
-                        "{}_gearbox_origin_all.{}",
-                        stem.to_str().unwrap(),
-                        externsion.to_str().unwrap()
-                    );
-                    let dir_name = config.output_path.parent().unwrap();
-                    let new_path = dir_name.join(new_file_name);
-                    info!("the result will be written to {:?}", new_path);
+            let stem = config.output_path.file_stem().unwrap();
+            let externsion = config.output_path.extension().unwrap();
+            let new_file_name = format!(
+                "{}_gearbox_origin_all.{}",
+                stem.to_str().unwrap(),
+                externsion.to_str().unwrap()
+            );
+            let dir_name = config.output_path.parent().unwrap();
+            let new_path = dir_name.join(new_file_name);
+            info!("the result will be written to {:?}", new_path);
 
-                    let gearbox_result = analyze_gearbox_origin_all::analyze_gearbox(&config);
-                    serde_json::to_writer(
-                        BufWriter::new(File::create(new_path)?),
-                        &gearbox_result,
-                    )?;
-                    info!("time elapsed: {:?}", current_time.elapsed());
-                }
-                cli::AnalyzeType::GearboxOriginAllV2 => {
-                    let config_v2 = ConfigV2::new(config);
-                    do_analyze_by_batch_and_topk(
-                        &config_v2,
-                        &config_v2.output_path,
-                        analyze_gearbox_origin_all_v2::analyze_gearbox,
-                    )?;
-                }
-                cli::AnalyzeType::GearboxOriginAllV2OverFlow => {
-                    let config_v2 = ConfigV2::new(config);
-                    do_analyze_by_batch_and_topk(
-                        &config_v2,
-                        &config_v2.output_path,
-                        analyze_gearbox_origin_all_v2_overflow::analyze_gearbox,
-                    )?;
-                }
-                cli::AnalyzeType::GearboxOverflowTraffic => {
-                    let config_v2 = ConfigV2::new(config);
-                    do_analyze_by_batch_and_topk(
-                        &config_v2,
-                        &config_v2.output_path,
-                        analyze_gearbox_overflow_and_traffic::analyze_gearbox,
-                    )?;
-                }
-                cli::AnalyzeType::AnalyzeChannel => {
-                    let config_v2 = ConfigV2::new(config);
-                    do_analyze_by_batch_and_topk(
-                        &config_v2,
-                        &config_v2.output_path,
-                        analyze_channel::analyze_gearbox,
-                    )?;
-                }
-                cli::AnalyzeType::AnalyzeRefinedGearbox => {
-                    let config_v2 = ConfigV2::new(config);
-                    do_analyze_by_batch_and_topk(
-                        &config_v2,
-                        &config_v2.output_path,
-                        three_stages::analyze_refined_gearbox::analyze_gearbox,
-                    )?;
-                }
-                cli::AnalyzeType::AnalyzeRefinedGearboxDispatchOverflow => {
-                    let config_v2 = ConfigV2::new(config);
-                    do_analyze_by_batch_and_topk(
-                        &config_v2,
-                        &config_v2.output_path,
-                        analyze_refined_dispatcher_overflow::analyze_gearbox,
-                    )?;
-                }
-                cli::AnalyzeType::AnalyzeRefinedDistribution => {
-                    todo!()
-                }
-                cli::AnalyzeType::AnalyzeRefinedNewMapping => {
-                    todo!()
-                }
-                cli::AnalyzeType::AnalyzeBankTrace => {
-                    todo!()
-                }
-                cli::AnalyzeType::AnalyzeBankTraceAll => {
-                    let config_v2 = ConfigV2::new(config);
-                    do_analyze_by_batch_and_topk(
-                        &config_v2,
-                        &config_v2.output_path,
-                        three_stages::analyze_refined_bank_trace_all::analyze_gearbox,
-                    )?;
-                }
-                cli::AnalyzeType::AnalyzeRefinedNewMappingCycle => {
-                    let config_v2 = ConfigV2::new(config);
-                    do_analyze_by_batch_and_topk(
-                        &config_v2,
-                        &config_v2.output_path,
-                        three_stages::analyze_refined_cycle::analyze_gearbox,
-                    )?;
-                }
-                cli::AnalyzeType::AnalyzeRealOneHotJump => {
-                    // let config_v2 = ConfigV2::new(config);
-                    // do_analyze_by_batch_and_topk(&config_v2, &config_v2.output_path, todo!())?;
-                }
-                cli::AnalyzeType::NewAnalysis => {
-                    let config_v2 = ConfigV3::new(config);
-                    remap_analyze::run_simulation(config_v2)?;
-                }
-            }
+            let gearbox_result = analyze_gearbox_origin_all::analyze_gearbox(&config)?;
+            serde_json::to_writer(BufWriter::new(File::create(new_path)?), &gearbox_result)?;
+            info!("time elapsed: {:?}", current_time.elapsed());
         }
-        cli::Operation::Draw(draw_args) => draw::draw_with_type(draw_args.subcmd)?,
-    };
+        cli::AnalyzeType::GearboxOriginAllV2 => {
+            let config_v2: ConfigV2 = load_validated(config, ValidationTarget::Gearbox)?.into();
+            warn_without_checkpoint(&config_v2);
+            do_analyze_by_batch_and_topk(
+                &config_v2,
+                &config_v2.output_path,
+                analyze_gearbox_origin_all_v2::analyze_gearbox,
+            )?;
+        }
+        cli::AnalyzeType::GearboxOriginAllV2OverFlow => {
+            let config_v2: ConfigV2 = load_validated(config, ValidationTarget::Gearbox)?.into();
+            warn_without_checkpoint(&config_v2);
+            do_analyze_by_batch_and_topk(
+                &config_v2,
+                &config_v2.output_path,
+                analyze_gearbox_origin_all_v2_overflow::analyze_gearbox,
+            )?;
+        }
+        cli::AnalyzeType::GearboxOverflowTraffic => {
+            let config_v2: ConfigV2 = load_validated(config, ValidationTarget::Gearbox)?.into();
+            warn_without_checkpoint(&config_v2);
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config_v2);
+            let results = analyze_gearbox_overflow_and_traffic::analyze_gearbox(&config_v2)?;
+            write_by_batch_and_topk(&config_v2.output_path, results)?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::AnalyzeChannel => {
+            let config_v2: ConfigV2 = load_validated(config, ValidationTarget::Gearbox)?.into();
+            warn_without_checkpoint(&config_v2);
+            do_analyze_by_batch_and_topk(
+                &config_v2,
+                &config_v2.output_path,
+                analyze_channel::analyze_gearbox,
+            )?;
+        }
+        cli::AnalyzeType::AnalyzeRefinedGearbox => {
+            let config_v2: ConfigV2 = load_validated(config, ValidationTarget::Gearbox)?.into();
+            warn_without_checkpoint(&config_v2);
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config_v2);
+            let results = three_stages::analyze_refined_gearbox::analyze_gearbox(&config_v2)?;
+            write_by_batch_and_topk(&config_v2.output_path, results)?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::AnalyzeRefinedGearboxDispatchOverflow => {
+            let config_v2: ConfigV2 = load_validated(config, ValidationTarget::Gearbox)?.into();
+            warn_without_checkpoint(&config_v2);
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config_v2);
+            let results = analyze_refined_dispatcher_overflow::analyze_gearbox(&config_v2)?;
+            write_by_batch_and_topk(&config_v2.output_path, results)?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::AnalyzeRefinedDistribution => {
+            todo!()
+        }
+        cli::AnalyzeType::AnalyzeRefinedNewMapping => {
+            todo!()
+        }
+        cli::AnalyzeType::AnalyzeBankTrace => {
+            let mut config_v2: ConfigV2 = load_validated(config, ValidationTarget::Gearbox)?.into();
+            config_v2.resume = resume;
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config_v2);
+            let output = stats::evil_bank_trace::AnalyzeBankTrace::analyze_gearbox(&config_v2)?;
+            write_by_batch_and_topk(&config_v2.output_path, output.results)?;
+            write_memory_reports(&config_v2.output_path, &output.memory)?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::AnalyzeBankTraceAll => {
+            let config_v2: ConfigV2 = load_validated(config, ValidationTarget::Gearbox)?.into();
+            warn_without_checkpoint(&config_v2);
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config_v2);
+            let results =
+                three_stages::analyze_refined_bank_trace_all::analyze_gearbox(&config_v2)?;
+            write_by_batch_and_topk(&config_v2.output_path, results)?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::AnalyzeRefinedNewMappingCycle => {
+            let config_v2: ConfigV2 = load_validated(config, ValidationTarget::Gearbox)?.into();
+            warn_without_checkpoint(&config_v2);
+            let current_time = std::time::Instant::now();
+            info!("analyze with config: {:?}", config_v2);
+            let results = three_stages::analyze_refined_cycle::analyze_gearbox(&config_v2)?;
+            write_by_batch_and_topk(&config_v2.output_path, results)?;
+            info!("time elapsed: {:?}", current_time.elapsed());
+        }
+        cli::AnalyzeType::AnalyzeRealOneHotJump => {
+            // let config_v2 = ConfigV2::new(config)?;
+            // do_analyze_by_batch_and_topk(&config_v2, &config_v2.output_path, todo!())?;
+        }
+        cli::AnalyzeType::NewAnalysis => {
+            let mut config_v2 = load_validated(config, ValidationTarget::RealJump)?;
+            config_v2.resume = resume;
+            remap_analyze::run_simulation(config_v2)?;
+        }
+    }
     Ok(())
 }
 
+/// the analyses without checkpoints run from the start, tell the user instead of ignoring `checkpoint_dir` silently
+fn warn_without_checkpoint(config: &ConfigV2) {
+    if let Some(dir) = &config.checkpoint_dir {
+        warn!(
+            "this analysis does not save checkpoints, the checkpoint_dir {:?} is ignored",
+            dir
+        );
+    }
+}
+
 fn do_analyze_by_batch_and_topk<
     C: Debug,
-    F: Fn(&C) -> Vec<((usize, f32), Vec<R>)>,
+    F: Fn(&C) -> eyre::Result<Vec<((usize, f32), Vec<R>)>>,
     R: Serialize,
 >(
     config: &C,
@@ -356,10 +412,19 @@ fn do_analyze_by_batch_and_topk<
 ) -> Result<(), eyre::ErrReport> {
     let current_time = std::time::Instant::now();
     info!("analyze with config: {:?}", config);
+    write_by_batch_and_topk(output_path, f(config)?)?;
+    info!("time elapsed: {:?}", current_time.elapsed());
+    Ok(())
+}
+
+/// write the result of each (batch, topk) to `{stem}_gearbox_origin_all_{batch}_{topk}.{ext}` next to `output_path`
+fn write_by_batch_and_topk<R: Serialize>(
+    output_path: &Path,
+    gearbox_result: Vec<((usize, f32), Vec<R>)>,
+) -> eyre::Result<()> {
     let stem = output_path.file_stem().unwrap();
     let externsion = output_path.extension().unwrap();
     let dir_name = output_path.parent().unwrap();
-    let gearbox_result = f(config);
     for ((batch, topk), result) in gearbox_result {
         let new_file_name = format!(
             "{}_gearbox_origin_all_{batch}_{topk}.{}",
@@ -370,6 +435,23 @@ fn do_analyze_by_batch_and_topk<
         info!("the result will be written to {:?}", new_path);
         serde_json::to_writer(BufWriter::new(File::create(new_path)?), &result)?;
     }
-    info!("time elapsed: {:?}", current_time.elapsed());
+    Ok(())
+}
+
+/// write the memory of each graph to `{stem}_memory.{ext}` next to `output_path`
+pub(crate) fn write_memory_reports(
+    output_path: &Path,
+    memory: &[MemoryReport],
+) -> eyre::Result<()> {
+    let stem = output_path.file_stem().unwrap();
+    let externsion = output_path.extension().unwrap();
+    let new_file_name = format!(
+        "{}_memory.{}",
+        stem.to_str().unwrap(),
+        externsion.to_str().unwrap()
+    );
+    let new_path = output_path.parent().unwrap().join(new_file_name);
+    info!("the memory reports will be written to {:?}", new_path);
+    serde_json::to_writer(BufWriter::new(File::create(new_path)?), memory)?;
     Ok(())
 }
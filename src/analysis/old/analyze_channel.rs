@@ -8,11 +8,7 @@
 //!  w
 use crate::{
     analysis::{remap_analyze::row_cycle::*, RingTask, RingTasksInAllBanks},
-    tools::{
-        crossbare_simulator::CrossBareSimulator,
-        crossbare_simulator_no_conflic::CrossBareSimulatorNoConflict,
-        ring_simulator::RingSimulator, CrossBarPacket, Direction, FlatInterleaveTrait, IcntPacket,
-    },
+    tools::{graph_reader, matrix_cache, FlatInterleaveTrait},
 };
 use std::{
     cmp::Reverse,
@@ -31,7 +27,7 @@ use itertools::Itertools;
 use plotters::{coord::Shift, prelude::*};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use sprs::{io::MatrixHead, num_kinds::Pattern, CsMatI, TriMatI};
+use sprs::{num_kinds::Pattern, CsMatI};
 use tracing::{debug, info};
 
 use crate::{
@@ -103,7 +99,9 @@ impl Display for GearboxResult {
 }
 
 /// analyze the split spmm
-pub(crate) fn analyze_gearbox(config: &ConfigV2) -> Vec<((usize, f32), Vec<SingleResult>)> {
+pub(crate) fn analyze_gearbox(
+    config: &ConfigV2,
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>> {
     match config.dram_type {
         DramType::DDR3 => unimplemented!(),
         DramType::DDR4 => {
@@ -480,15 +478,6 @@ struct TsvReport {
     pub max_use_valid: usize,
     pub real_use: usize,
 }
-#[allow(unused)]
-#[derive(Debug, Default)]
-struct TsvReportV2 {
-    pub cycle_normal: usize,
-    pub cycle_no_conflict: usize,
-    pub max_use: usize,
-    pub max_use_valid: usize,
-    pub real_use: usize,
-}
 
 #[allow(dead_code)]
 fn compute_result(mut tsv_traffic: Vec<VecDeque<&RingTask>>) -> TsvReport {
@@ -554,92 +543,7 @@ fn get_ring_interleave(rings_tasks: Vec<&RingTasksInAllBanks>) -> Vec<Vec<VecDeq
         })
         .collect()
 }
-#[allow(unused)]
-struct RingTraffic<T> {
-    from: usize,
-    to: usize,
-    direction: Direction,
-    traffic: T,
-}
-impl<T> RingTraffic<T> {
-    #[allow(dead_code)]
-    fn new(from: usize, to: usize, direction: Direction, traffic: T) -> Self {
-        Self {
-            from,
-            to,
-            direction,
-            traffic,
-        }
-    }
-}
-impl<T> IcntPacket for RingTraffic<T> {
-    fn get_source(&self) -> usize {
-        self.from
-    }
-
-    fn get_next_hop(&self) -> usize {
-        self.to
-    }
-
-    fn get_direction(&self) -> Direction {
-        self.direction
-    }
-}
-
-#[allow(dead_code)]
-struct CrossBarTraffic<T> {
-    from: usize,
-    to: usize,
-    traffic: T,
-}
-impl<T> CrossBarTraffic<T> {
-    #[allow(dead_code)]
-    fn new(from: usize, to: usize, traffic: T) -> Self {
-        Self { from, to, traffic }
-    }
-}
-impl<T> CrossBarPacket for CrossBarTraffic<T> {
-    fn get_source(&self) -> usize {
-        self.from
-    }
-
-    fn get_dest(&self) -> usize {
-        self.to
-    }
-}
-
-trait CrossBarCommon<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T>;
-    fn pop(&mut self, node: usize) -> Option<T>;
-    fn cycle(&mut self);
-}
-
-impl<T: CrossBarPacket> CrossBarCommon<T> for CrossBareSimulator<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T> {
-        self.add(node, traffic)
-    }
-
-    fn pop(&mut self, node: usize) -> Option<T> {
-        self.pop(node)
-    }
-
-    fn cycle(&mut self) {
-        self.cycle()
-    }
-}
-impl<T: CrossBarPacket> CrossBarCommon<T> for CrossBareSimulatorNoConflict<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T> {
-        self.add(node, traffic)
-    }
-
-    fn pop(&mut self, node: usize) -> Option<T> {
-        self.pop(node)
-    }
 
-    fn cycle(&mut self) {
-        self.cycle()
-    }
-}
 impl Hardware {
     fn new(
         dense_dim: usize,
@@ -700,171 +604,6 @@ impl Hardware {
         [input_traffic, output_traffic]
     }
 
-    /// # situation
-    /// - when a round is finished, all traffic from each input port are ready to route to the other port
-    /// - the traffic is routed by a crossbar network in the base layer
-    /// # inbanlance reason
-    /// - the input traffic is not balanced
-    /// - the output traffic is not balanced
-    /// # solution
-    /// - need a score to represent the reason why usage is low
-    /// - the input reason
-    /// - the output reason
-    /// # TODO
-    /// - redesign the buffer to store the traffic from each subarray. then use the [flat_interleave] to
-    ///  route the traffic
-    #[allow(dead_code)]
-    fn calculate_tsv_traffic(&self) -> TsvReportV2 {
-        // the input traffic from each bank in the layer
-
-        // the old method is deprecated
-        // `compute_result(self.get_tsv_interleave())`
-
-        // first get the traffic for each bank and each ring
-        let rings_tasks = self.ring.iter().map(|r| &r.tasks).collect_vec();
-        let ring_bank_traffic: Vec<Vec<VecDeque<&RingTask>>> = get_ring_interleave(rings_tasks);
-        // second, build the hardware:
-        // - the ring simulator for each ring
-        let ports = self.config.channels.num;
-        let cycle_normal = {
-            self.calculate_icnt(
-                ring_bank_traffic.clone(),
-                CrossBareSimulator::new(ports, 16),
-            )
-        };
-        let cycle_no_conflict = {
-            self.calculate_icnt(
-                ring_bank_traffic,
-                CrossBareSimulatorNoConflict::new(ports, 16),
-            )
-        };
-        // println!("cycle: {}", cycle);
-        tracing::debug!("cycle: {cycle_normal} {cycle_no_conflict}");
-        TsvReportV2 {
-            cycle_normal,
-            cycle_no_conflict,
-            max_use: 0,
-            max_use_valid: 0,
-            real_use: 0,
-        }
-    }
-
-    fn calculate_icnt<'a, Cross>(
-        &'a self,
-        mut ring_bank_traffic: Vec<Vec<VecDeque<&'a RingTask>>>,
-        mut crossbar_simulator: Cross,
-    ) -> usize
-    where
-        Cross: CrossBarCommon<CrossBarTraffic<&'a RingTask>>,
-    {
-        // - the corssbar simulator for the base layer
-        let mut ring_simulators = (0..self.config.channels.num)
-            .map(|_ring_id| RingSimulator::new(self.config.banks.num, 128))
-            .collect_vec();
-
-        let mut tsv_buffer = (0..self.config.channels.num)
-            .map(|_| VecDeque::with_capacity(16))
-            .collect_vec();
-
-        let mut total_remain_traffic = 0;
-        let mut cycle = 0;
-        loop {
-            for (ring_id, (ring_sim, ring_traffic)) in ring_simulators
-                .iter_mut()
-                .zip(ring_bank_traffic.iter_mut())
-                .enumerate()
-            {
-                for (bank_id, bank_traffic) in ring_traffic.iter_mut().enumerate() {
-                    // first add the traffic to the input of the ring
-
-                    if let Some(traffic) = bank_traffic.pop_front() {
-                        // add the traffic to ring port  `bank_id`
-                        let from = traffic.0 .0 as usize;
-                        let to = traffic.1 .0 as usize;
-                        let nodes = self.config.banks.num;
-                        let right_distance = (to + nodes - from) % nodes;
-                        let left_distance = (from + nodes - to) % nodes;
-                        let direction = if right_distance < left_distance {
-                            Direction::Right
-                        } else {
-                            Direction::Left
-                        };
-                        match ring_sim.add(bank_id, RingTraffic::new(from, to, direction, traffic))
-                        {
-                            Ok(_) => {
-                                total_remain_traffic += 1;
-                            }
-                            Err(RingTraffic { traffic, .. }) => {
-                                bank_traffic.push_front(traffic);
-                            }
-                        }
-                    }
-                    // then pop the traffic from the output of the ring
-
-                    if let Some(RingTraffic { traffic, .. }) = ring_sim.front(bank_id) {
-                        if bank_id == 0 {
-                            // the tsv port
-                            // test if the traffic should go to other ring
-                            let target_ring_id = traffic.2 .0 .0;
-                            if ring_id == target_ring_id {
-                                // no!
-                                // do nothing and pop the traffic
-                                ring_sim.pop(bank_id);
-                                total_remain_traffic -= 1;
-                            } else {
-                                // yes, go to the target ring
-                                // push it to the tsv buffer
-                                if tsv_buffer[ring_id].len() < 16 {
-                                    let traffic = ring_sim.pop(bank_id).unwrap();
-                                    tsv_buffer[ring_id].push_back(traffic);
-                                    // do not need to decrease the total_remain_traffic because we will do it on pop of the crossbar
-                                } else {
-                                    // the buffer is full, do nothing
-                                }
-                            }
-                        } else {
-                            // just pop the traffic
-                            ring_sim.pop(bank_id).unwrap();
-                            total_remain_traffic -= 1;
-                        }
-                    }
-                }
-            }
-
-            // handle the tsv buffer traffic
-            for (tsv_id, tsv) in tsv_buffer.iter_mut().enumerate() {
-                if let Some(traffic) = tsv.pop_front() {
-                    let crossbare_packet =
-                        CrossBarTraffic::new(tsv_id, traffic.traffic.2 .0 .0, traffic.traffic);
-                    match crossbar_simulator.add(tsv_id, crossbare_packet) {
-                        Ok(_) => {
-                            // do nothing
-                        }
-                        Err(_) => {
-                            tsv.push_front(traffic);
-                        }
-                    }
-                }
-                // handle the crossbar traffic out
-                if let Some(_p) = crossbar_simulator.pop(tsv_id) {
-                    // the packet is finished
-                    total_remain_traffic -= 1;
-                }
-            }
-            // cycle the simulators
-            for ring_sim in ring_simulators.iter_mut() {
-                ring_sim.cycle();
-            }
-            crossbar_simulator.cycle();
-
-            cycle += 1;
-            if total_remain_traffic == 0 {
-                break;
-            }
-        }
-        cycle
-    }
-
     #[allow(unused)]
     fn subarrays(&self) -> usize {
         self.sub_array.len()
@@ -1393,7 +1132,7 @@ impl DrawFn for DistributionDrawer {
     }
 }
 
-fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
+fn compute_gearbox(config: &ConfigV2, path: &str) -> eyre::Result<Vec<SingleResult>> {
     // for hbm config, they should be 1!
     assert!(config.ranks.num == 1);
     assert!(config.chips.num == 1);
@@ -1408,7 +1147,7 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
     info!(?partitions, "compute gearbox");
     info!("reading mtx file: {}", path);
     let read_time = std::time::Instant::now();
-    let matrix_head: MatrixHead<Pattern, u32> = sprs::io::read_matrix_market_head(path).unwrap();
+    let matrix_head = graph_reader::read_graph_head(path).unwrap();
     let matrix_size = matrix_head.ind_ptr_size() + matrix_head.ind_size() + matrix_head.data_size();
     // two csr csc matrix during runtime
     let matrix_size = matrix_size * 2;
@@ -1421,19 +1160,18 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
         "info there will be {} bytes,start acquire the space",
         sim_size + temp_size
     );
-    let mut _guard = crate::acquire_memory_sections([sim_size as u64, temp_size as u64]);
+    let mut _guard = crate::try_acquire_memory_sections([sim_size as u64, temp_size as u64])?;
     let _guard_temp = _guard.pop().unwrap();
     let _guard_sim = _guard.pop().unwrap();
 
-    let tri_mat: TriMatI<Pattern, u32> = sprs::io::read_matrix_market(path).unwrap();
+    let matrix = matrix_cache::load_or_build(path).unwrap();
     info!(
         "finished read the matrix: time:{:.2} secs",
         read_time.elapsed().as_secs_f32()
     );
     let (matrix_a, matrix_b): (CsMatI<Pattern, u32>, CsMatI<Pattern, u32>) =
-        (tri_mat.to_csr(), tri_mat.transpose_view().to_csr());
+        (matrix.csr, matrix.csc.transpose_into());
 
-    drop(tri_mat);
     drop(_guard_temp);
 
     info!(
@@ -1501,7 +1239,7 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
     drop(matrix_a);
     drop(matrix_b);
     drop(_guard_sim);
-    results
+    Ok(results)
 }
 pub fn transpose2<T>(v: Vec<Vec<T>>) -> Vec<Vec<T>> {
     assert!(!v.is_empty());
@@ -1523,7 +1261,7 @@ static TOTAL_TASKS: RwLock<usize> = RwLock::new(0);
 fn analyze_gearbox_inner<LevelType: LevelTrait>(
     config: &ConfigV2,
     _total_size: &LevelType::Storage,
-) -> Vec<((usize, f32), Vec<SingleResult>)>
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>>
 where
     LevelType::Storage: Debug + Sync,
     LevelType::Mapping: Debug,
@@ -1542,7 +1280,7 @@ where
             info!("analyzing graph {}/{}", index + 1, total_graphs);
             compute_gearbox(config, path)
         })
-        .collect();
+        .collect::<eyre::Result<_>>()?;
     let results = transpose2(results);
     let configs = config
         .gearbox_config
@@ -1553,7 +1291,7 @@ where
         .collect_vec();
     info!(?configs, "configs");
     assert_eq!(configs.len(), results.len());
-    configs.into_iter().zip(results).collect()
+    Ok(configs.into_iter().zip(results).collect())
     // GearboxResult { results }
 }
 
@@ -99,6 +99,11 @@ pub struct GearboxConfig {
 pub struct GearboxConfigV2 {
     pub batch: Vec<usize>,
     pub topk: Vec<f32>,
+    /// only used when converting to the v1 config
+    #[serde(default)]
+    pub stacks: usize,
+    #[serde(default)]
+    pub layers: usize,
     // fix bugs here, we should not rely on the stacks and layers because they are already set in channels
 }
 
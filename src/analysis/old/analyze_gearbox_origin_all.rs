@@ -14,7 +14,7 @@ use itertools::Itertools;
 use plotters::{coord::Shift, prelude::*};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use sprs::{io::MatrixHead, num_kinds::Pattern, CsMatI, TriMatI};
+use sprs::{num_kinds::Pattern, CsMatI};
 use tracing::{debug, info};
 
 use crate::pim::{
@@ -22,7 +22,13 @@ use crate::pim::{
     level::{ddr4, LevelTrait},
 };
 use crate::TIME_TO_LOG;
-use crate::{draw::DrawFn, tools::stop_signal};
+use crate::{
+    draw::DrawFn,
+    tools::{
+        cancel::{self, CancellationToken},
+        graph_reader, matrix_cache,
+    },
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct TotalResult {
@@ -70,7 +76,7 @@ impl Display for GearboxResult {
 }
 
 /// analyze the split spmm
-pub(crate) fn analyze_gearbox(config: &Config) -> GearboxResult {
+pub(crate) fn analyze_gearbox(config: &Config) -> eyre::Result<GearboxResult> {
     match config.dram_type {
         crate::pim::config::DramType::DDR3 => unimplemented!(),
         crate::pim::config::DramType::DDR4 => {
@@ -747,7 +753,7 @@ impl GearboxSim {
     }
 
     /// distribute the task to components
-    fn run(&mut self, input_vec: &CsMatI<Pattern, u32>) -> TotalResult {
+    fn run(&mut self, input_vec: &CsMatI<Pattern, u32>, cancel: &CancellationToken) -> TotalResult {
         let mut global_max_acc_cycle = 0;
         let mut global_max_acc_cycle_remote = 0;
         let mut gloabl_max_acc_ring = 0;
@@ -773,7 +779,8 @@ impl GearboxSim {
                 info!("{target_id} of {total_rows} rows processed, time eclips: {min:.2}, estimate remaining time:{min_r:.2},speed: {speed} rows per min");
                 next_print_percent = target_id + total_rows / 100;
                 next_print_time = now.elapsed().as_secs() + TIME_TO_LOG;
-                if stop_signal::read() {
+                if cancel.is_cancelled() {
+                    cancel::record_partial_run();
                     break;
                 }
             }
@@ -933,7 +940,11 @@ impl DrawFn for DistributionDrawer {
     }
 }
 
-fn compute_gearbox(config: &Config, path: &str) -> SingleResult {
+fn compute_gearbox(
+    config: &Config,
+    path: &str,
+    cancel: &CancellationToken,
+) -> eyre::Result<SingleResult> {
     let partitions = config.channels.num
         * config.ranks.num
         * config.chips.num
@@ -943,21 +954,21 @@ fn compute_gearbox(config: &Config, path: &str) -> SingleResult {
     info!(?partitions, "compute gearbox");
     info!("reading mtx file: {}", path);
     let read_time = std::time::Instant::now();
-    let matrix_head: MatrixHead<Pattern, u32> = sprs::io::read_matrix_market_head(path).unwrap();
+    let matrix_head = graph_reader::read_graph_head(path).unwrap();
     let total_size = matrix_head.ind_ptr_size() + matrix_head.ind_size() + matrix_head.data_size();
     let total_size = total_size * 3;
     info!(
         "info there will be {} bytes,start acquire the space",
         total_size
     );
-    let _guard = crate::acquire_memory(total_size);
-    let matrix_a: TriMatI<Pattern, u32> = sprs::io::read_matrix_market(path).unwrap();
+    let _guard = crate::try_acquire_memory_sections([total_size])?;
+    let matrix = matrix_cache::load_or_build(path).unwrap();
     info!(
         "finished read the matrix: time:{:.2} secs",
         read_time.elapsed().as_secs_f32()
     );
     let (matrix_a, matrix_b): (CsMatI<Pattern, u32>, CsMatI<Pattern, u32>) =
-        (matrix_a.to_csr(), matrix_a.transpose_view().to_csr());
+        (matrix.csr, matrix.csc.transpose_into());
     info!(
         "finished transpose the matrix: time:{:.2} secs",
         read_time.elapsed().as_secs_f32()
@@ -1010,21 +1021,22 @@ fn compute_gearbox(config: &Config, path: &str) -> SingleResult {
         config,
     );
     info!("start running the sim");
-    let total_result = gearbox.run(&matrix_a);
+    let total_result = gearbox.run(&matrix_a, cancel);
     info!("finished running the sim");
 
-    gearbox.report(path.to_string(), total_result)
+    Ok(gearbox.report(path.to_string(), total_result))
 }
 
 fn analyze_gearbox_inner<LevelType: LevelTrait>(
     config: &Config,
     _total_size: &LevelType::Storage,
-) -> GearboxResult
+) -> eyre::Result<GearboxResult>
 where
     LevelType::Storage: Debug + Sync,
     LevelType::Mapping: Debug,
 {
     let total_graphs = config.graph_path.len();
+    let config_cancel = cancel::root().child();
     let results = config
         .graph_path
         .par_iter()
@@ -1032,11 +1044,12 @@ where
         .map(|(index, path)| {
             info!("analyzing graph {}/{}", index + 1, total_graphs);
             let _span = tracing::info_span!("compute_gearbox", index).entered();
-            compute_gearbox(config, path)
+            let graph_cancel = config_cancel.child();
+            compute_gearbox(config, path, &graph_cancel)
         })
-        .collect();
+        .collect::<eyre::Result<_>>()?;
 
-    GearboxResult { results }
+    Ok(GearboxResult { results })
 }
 
 /// the stat result of the seq spmm
@@ -8,6 +8,7 @@ use std::error::Error;
 use std::mem::size_of;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::RwLock;
+use std::time::Duration;
 use std::{
     collections::BTreeMap,
     fmt::{Debug, Display},
@@ -19,15 +20,17 @@ use plotters::coord::Shift;
 use plotters::prelude::*;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use sprs::io::MatrixHead;
 use sprs::num_kinds::Pattern;
-use sprs::{CsMatI, TriMatI};
+use sprs::CsMatI;
 use tracing::{debug, info};
 
 use crate::draw::DrawFn;
 use crate::pim::configv2::{ConfigV2, DramType};
 use crate::pim::level::{ddr4, LevelTrait};
-use crate::tools::stop_signal;
+use crate::tools::{
+    cancel::{self, CancellationToken},
+    graph_reader, matrix_cache,
+};
 use crate::TIME_TO_LOG;
 
 #[derive(Serialize, Deserialize)]
@@ -84,7 +87,9 @@ impl Display for GearboxResult {
 }
 
 /// analyze the split spmm
-pub(crate) fn analyze_gearbox(config: &ConfigV2) -> Vec<((usize, f32), Vec<SingleResult>)> {
+pub(crate) fn analyze_gearbox(
+    config: &ConfigV2,
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>> {
     match config.dram_type {
         DramType::DDR3 => unimplemented!(),
         DramType::DDR4 => {
@@ -777,6 +782,7 @@ impl<'a> GearboxSim<'a> {
         input_vec: &CsMatI<Pattern, u32>,
         current_batch: usize,
         _current_topk: f32,
+        cancel: &CancellationToken,
     ) -> TotalResult {
         let mut global_max_acc_cycle = 0;
         let mut global_max_acc_cycle_remote = 0;
@@ -811,7 +817,8 @@ impl<'a> GearboxSim<'a> {
                 tracing::trace!("{target_id} of {total_rows} rows processed, time eclips: {min:.2}, estimate remaining time:{min_r:.2},speed: {speed} rows per min");
                 next_print_percent = target_id + total_rows / 100;
                 next_print_time = now.elapsed().as_secs() + TIME_TO_LOG;
-                if stop_signal::read() {
+                if cancel.is_cancelled() {
+                    cancel::record_partial_run();
                     break;
                 }
             }
@@ -1013,7 +1020,11 @@ impl DrawFn for DistributionDrawer {
     }
 }
 
-fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
+fn compute_gearbox(
+    config: &ConfigV2,
+    path: &str,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<SingleResult>> {
     // for hbm config, they should be 1!
     assert!(config.ranks.num == 1);
     assert!(config.chips.num == 1);
@@ -1028,7 +1039,7 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
     info!(?partitions, "compute gearbox");
     info!("reading mtx file: {}", path);
     let read_time = std::time::Instant::now();
-    let matrix_head: MatrixHead<Pattern, u32> = sprs::io::read_matrix_market_head(path).unwrap();
+    let matrix_head = graph_reader::read_graph_head(path).unwrap();
     let matrix_size = matrix_head.ind_ptr_size() + matrix_head.ind_size() + matrix_head.data_size();
     // two csr csc matrix during runtime
     let matrix_size = matrix_size * 2;
@@ -1041,19 +1052,18 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
         "info there will be {} bytes,start acquire the space",
         sim_size + temp_size
     );
-    let mut _guard = crate::acquire_memory_sections([sim_size, temp_size]);
+    let mut _guard = crate::try_acquire_memory_sections([sim_size, temp_size])?;
     let _guard_temp = _guard.pop().unwrap();
     let _guard_sim = _guard.pop().unwrap();
 
-    let tri_mat: TriMatI<Pattern, u32> = sprs::io::read_matrix_market(path).unwrap();
+    let matrix = matrix_cache::load_or_build(path).unwrap();
     info!(
         "finished read the matrix: time:{:.2} secs",
         read_time.elapsed().as_secs_f32()
     );
     let (matrix_a, matrix_b): (CsMatI<Pattern, u32>, CsMatI<Pattern, u32>) =
-        (tri_mat.to_csr(), tri_mat.transpose_view().to_csr());
+        (matrix.csr, matrix.csc.transpose_into());
 
-    drop(tri_mat);
     drop(_guard_temp);
 
     info!(
@@ -1108,7 +1118,7 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
                 config,
             );
             info!("start running the sim");
-            let result = gearbox.run(&matrix_a, batch, top_k);
+            let result = gearbox.run(&matrix_a, batch, top_k, cancel);
             TOTAL_FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
             info!(
                 "finished task: {}/{}",
@@ -1121,7 +1131,7 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
     drop(matrix_a);
     drop(matrix_b);
     drop(_guard_sim);
-    results
+    Ok(results)
 }
 pub fn transpose2<T>(v: Vec<Vec<T>>) -> Vec<Vec<T>> {
     assert!(!v.is_empty());
@@ -1143,7 +1153,7 @@ static TOTAL_TASKS: RwLock<usize> = RwLock::new(0);
 fn analyze_gearbox_inner<LevelType: LevelTrait>(
     config: &ConfigV2,
     _total_size: &LevelType::Storage,
-) -> Vec<((usize, f32), Vec<SingleResult>)>
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>>
 where
     LevelType::Storage: Debug + Sync,
     LevelType::Mapping: Debug,
@@ -1153,6 +1163,8 @@ where
     let total_tasks = total_graphs * total_configs;
     info!(?total_graphs, ?total_configs, ?total_tasks, "total tasks");
     *TOTAL_TASKS.write().unwrap() = total_tasks;
+    let config_cancel =
+        cancel::root().child_with_timeout(config.deadline_secs.map(Duration::from_secs));
 
     let results: Vec<_> = config
         .graph_path
@@ -1161,9 +1173,11 @@ where
         .map(|(index, path)| {
             info!("analyzing graph {}/{}", index + 1, total_graphs);
             let span = tracing::info_span!("compute_gearbox", index);
-            span.in_scope(|| compute_gearbox(config, path))
+            let graph_cancel = config_cancel
+                .child_with_timeout(config.graph_deadline_secs.map(Duration::from_secs));
+            span.in_scope(|| compute_gearbox(config, path, &graph_cancel))
         })
-        .collect();
+        .collect::<eyre::Result<_>>()?;
     let results = transpose2(results);
     let configs = config
         .gearbox_config
@@ -1174,7 +1188,7 @@ where
         .collect_vec();
     info!(?configs, "configs");
     assert_eq!(configs.len(), results.len());
-    configs.into_iter().zip(results).collect()
+    Ok(configs.into_iter().zip(results).collect())
     // GearboxResult { results }
 }
 
@@ -9,10 +9,13 @@
 use crate::{
     analysis::{remap_analyze::row_cycle::*, RingTask, RingTasksInAllBanks},
     tools::{
-        crossbare_simulator::CrossBareSimulator,
+        cancel::{self, CancellationToken},
         crossbare_simulator_no_conflic::CrossBareSimulatorNoConflict,
-        ring_simulator::RingSimulator, stop_signal, CrossBarPacket, Direction, FlatInterleaveTrait,
-        IcntPacket,
+        graph_reader,
+        interconnect::{build_interconnect, drive_networks, packet_words, Interconnect},
+        matrix_cache,
+        ring_simulator::RingSimulator,
+        CrossBarPacket, Direction, FlatInterleaveTrait, IcntPacket,
     },
 };
 use std::{
@@ -25,6 +28,7 @@ use std::{
         atomic::{AtomicUsize, Ordering},
         RwLock,
     },
+    time::Duration,
 };
 
 use hashbrown::HashSet;
@@ -32,7 +36,7 @@ use itertools::Itertools;
 use plotters::{coord::Shift, prelude::*};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use sprs::{io::MatrixHead, num_kinds::Pattern, CsMatI, TriMatI};
+use sprs::{num_kinds::Pattern, CsMatI};
 use tracing::{debug, info};
 
 use crate::{
@@ -84,7 +88,9 @@ impl Display for GearboxResult {
 }
 
 /// analyze the split spmm
-pub(crate) fn analyze_gearbox(config: &ConfigV2) -> Vec<((usize, f32), Vec<SingleResult>)> {
+pub(crate) fn analyze_gearbox(
+    config: &ConfigV2,
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>> {
     match config.dram_type {
         DramType::DDR3 => unimplemented!(),
         DramType::DDR4 => {
@@ -538,14 +544,17 @@ struct RingTraffic<T> {
     from: usize,
     to: usize,
     direction: Direction,
+    /// the words of the packet, see [`packet_words`]
+    size: usize,
     traffic: T,
 }
 impl<T> RingTraffic<T> {
-    fn new(from: usize, to: usize, direction: Direction, traffic: T) -> Self {
+    fn new(from: usize, to: usize, direction: Direction, size: usize, traffic: T) -> Self {
         Self {
             from,
             to,
             direction,
+            size,
             traffic,
         }
     }
@@ -562,6 +571,10 @@ impl<T> IcntPacket for RingTraffic<T> {
     fn get_direction(&self) -> Direction {
         self.direction
     }
+
+    fn get_size(&self) -> usize {
+        self.size
+    }
 }
 
 #[allow(dead_code)]
@@ -585,39 +598,6 @@ impl<T> CrossBarPacket for CrossBarTraffic<T> {
     }
 }
 
-trait CrossBarCommon<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T>;
-    fn pop(&mut self, node: usize) -> Option<T>;
-    fn cycle(&mut self);
-}
-
-impl<T: CrossBarPacket> CrossBarCommon<T> for CrossBareSimulator<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T> {
-        self.add(node, traffic)
-    }
-
-    fn pop(&mut self, node: usize) -> Option<T> {
-        self.pop(node)
-    }
-
-    fn cycle(&mut self) {
-        self.cycle()
-    }
-}
-impl<T: CrossBarPacket> CrossBarCommon<T> for CrossBareSimulatorNoConflict<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T> {
-        self.add(node, traffic)
-    }
-
-    fn pop(&mut self, node: usize) -> Option<T> {
-        self.pop(node)
-    }
-
-    fn cycle(&mut self) {
-        self.cycle()
-    }
-}
-
 impl Hardware {
     fn new(
         dense_dim: usize,
@@ -668,7 +648,7 @@ impl Hardware {
     /// # TODO
     /// - redesign the buffer to store the traffic from each subarray. then use the [flat_interleave] to
     ///  route the traffic
-    fn calculate_tsv_traffic(&self) -> TsvReportV2 {
+    fn calculate_tsv_traffic(&self) -> eyre::Result<TsvReportV2> {
         // the input traffic from each bank in the layer
 
         // the old method is deprecated
@@ -683,46 +663,53 @@ impl Hardware {
         let cycle_normal = {
             self.calculate_icnt(
                 ring_bank_traffic.clone(),
-                CrossBareSimulator::new(ports, 16),
-            )
+                build_interconnect(
+                    self.config.interconnect,
+                    ports,
+                    self.config.network_buffer_size,
+                ),
+            )?
         };
         let cycle_no_conflict = {
             self.calculate_icnt(
                 ring_bank_traffic,
-                CrossBareSimulatorNoConflict::new(ports, 16),
-            )
+                CrossBareSimulatorNoConflict::new(ports, self.config.network_buffer_size),
+            )?
         };
         // println!("cycle: {}", cycle);
         tracing::debug!("cycle: {cycle_normal} {cycle_no_conflict}");
-        TsvReportV2 {
+        Ok(TsvReportV2 {
             cycle_normal,
             cycle_no_conflict,
             max_use: 0,
             max_use_valid: 0,
             real_use: 0,
-        }
+        })
     }
 
     fn calculate_icnt<'a, Cross>(
         &'a self,
         mut ring_bank_traffic: Vec<Vec<VecDeque<&'a RingTask>>>,
         mut crossbar_simulator: Cross,
-    ) -> usize
+    ) -> eyre::Result<usize>
     where
-        Cross: CrossBarCommon<CrossBarTraffic<&'a RingTask>>,
+        Cross: Interconnect<CrossBarTraffic<&'a RingTask>>,
     {
         // - the corssbar simulator for the base layer
         let mut ring_simulators = (0..self.config.channels.num)
-            .map(|_ring_id| RingSimulator::new(self.config.banks.num, 128))
+            .map(|_ring_id| {
+                RingSimulator::with_config(self.config.banks.num, 128, self.config.ring)
+            })
             .collect_vec();
 
         let mut tsv_buffer = (0..self.config.channels.num)
             .map(|_| VecDeque::with_capacity(16))
             .collect_vec();
-
+        // an u32 index and the value
+        let packet_size = packet_words(size_of::<u32>(), self.config.packet_value_bytes);
         let mut total_remain_traffic = 0;
-        let mut cycle = 0;
-        loop {
+        // stop with the state of the networks instead of hanging when they stall
+        drive_networks(self.config.network_watchdog_cycles, |clock| {
             for (ring_id, (ring_sim, ring_traffic)) in ring_simulators
                 .iter_mut()
                 .zip(ring_bank_traffic.iter_mut())
@@ -743,8 +730,10 @@ impl Hardware {
                         } else {
                             Direction::Left
                         };
-                        match ring_sim.add(bank_id, RingTraffic::new(from, to, direction, traffic))
-                        {
+                        match ring_sim.add(
+                            bank_id,
+                            RingTraffic::new(from, to, direction, packet_size, traffic),
+                        ) {
                             Ok(_) => {
                                 total_remain_traffic += 1;
                             }
@@ -790,7 +779,7 @@ impl Hardware {
                 if let Some(traffic) = tsv.pop_front() {
                     let crossbare_packet =
                         CrossBarTraffic::new(tsv_id, traffic.traffic.2 .0 .0, traffic.traffic);
-                    match crossbar_simulator.add(tsv_id, crossbare_packet) {
+                    match crossbar_simulator.inject(tsv_id, crossbare_packet) {
                         Ok(_) => {
                             // do nothing
                         }
@@ -800,23 +789,22 @@ impl Hardware {
                     }
                 }
                 // handle the crossbar traffic out
-                if let Some(_p) = crossbar_simulator.pop(tsv_id) {
+                if let Some(_p) = crossbar_simulator.eject(tsv_id) {
                     // the packet is finished
                     total_remain_traffic -= 1;
                 }
             }
             // cycle the simulators
-            for ring_sim in ring_simulators.iter_mut() {
-                ring_sim.cycle();
+            for (ring_id, ring_sim) in ring_simulators.iter_mut().enumerate() {
+                clock.cycle(ring_sim, || {
+                    format!("the ring of channel {} is stalled", ring_id)
+                })?;
             }
-            crossbar_simulator.cycle();
-
-            cycle += 1;
-            if total_remain_traffic == 0 {
-                break;
-            }
-        }
-        cycle
+            clock.cycle(&mut crossbar_simulator, || {
+                "the base layer network is stalled".to_string()
+            })?;
+            Ok(total_remain_traffic)
+        })
     }
 
     #[allow(unused)]
@@ -1144,7 +1132,8 @@ impl<'a> GearboxSim<'a> {
         input_vec: &CsMatI<Pattern, u32>,
         current_batch: usize,
         _current_topk: f32,
-    ) -> GlobalStatV2 {
+        cancel: &CancellationToken,
+    ) -> eyre::Result<GlobalStatV2> {
         let mut global_stats = GlobalStatV2::default();
         let now = std::time::Instant::now();
         debug!("run gearbox sim");
@@ -1172,7 +1161,8 @@ impl<'a> GearboxSim<'a> {
                 if next_print_time > 300 {
                     break;
                 }
-                if stop_signal::read() {
+                if cancel.is_cancelled() {
+                    cancel::record_partial_run();
                     info!("received stop signal, start write results");
                     break;
                 }
@@ -1242,12 +1232,12 @@ impl<'a> GearboxSim<'a> {
             // reduce the tasks and clear the tasks
             // the cycle of this round
             if (target_id + 1) % current_batch == 0 {
-                update_stats(&mut self.hardware, &mut global_stats);
+                update_stats(&mut self.hardware, &mut global_stats)?;
                 // the data for overflow:
             }
             // add the result to the total result and continue to the next line
         }
-        global_stats
+        Ok(global_stats)
     }
 
     /// reduce the result and return the result
@@ -1315,9 +1305,9 @@ pub struct GlobalStatV2 {
     pub global_max_ring_buffer: usize,
 }
 
-fn update_stats(hardware: &mut Hardware, global_stats: &mut GlobalStatV2) {
+fn update_stats(hardware: &mut Hardware, global_stats: &mut GlobalStatV2) -> eyre::Result<()> {
     // test if the size is overflow!
-    let tsv_report_base = hardware.calculate_tsv_traffic();
+    let tsv_report_base = hardware.calculate_tsv_traffic()?;
     global_stats.global_tsv_base_total += tsv_report_base.max_use;
     global_stats.global_tsv_base_real += tsv_report_base.real_use;
     global_stats.global_tsv_base_cycle_normal += tsv_report_base.cycle_normal;
@@ -1388,6 +1378,7 @@ fn update_stats(hardware: &mut Hardware, global_stats: &mut GlobalStatV2) {
     global_stats.global_max_acc_tsv += tsv_max_cycle;
     global_stats.global_max_real_local += max_real_local_cycle;
     global_stats.global_max_ring_buffer += ring_buffer_max;
+    Ok(())
 }
 
 struct DistributionDrawer;
@@ -1423,7 +1414,11 @@ impl DrawFn for DistributionDrawer {
     }
 }
 
-fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
+fn compute_gearbox(
+    config: &ConfigV2,
+    path: &str,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<SingleResult>> {
     // for hbm config, they should be 1!
     assert!(config.ranks.num == 1);
     assert!(config.chips.num == 1);
@@ -1438,7 +1433,7 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
     info!(?partitions, "compute gearbox");
     info!("reading mtx file: {}", path);
     let read_time = std::time::Instant::now();
-    let matrix_head: MatrixHead<Pattern, u32> = sprs::io::read_matrix_market_head(path).unwrap();
+    let matrix_head = graph_reader::read_graph_head(path)?;
     let matrix_size = matrix_head.ind_ptr_size() + matrix_head.ind_size() + matrix_head.data_size();
     // two csr csc matrix during runtime
     let matrix_size = matrix_size * 2;
@@ -1451,19 +1446,18 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
         "info there will be {} bytes,start acquire the space",
         sim_size + temp_size
     );
-    let mut _guard = crate::acquire_memory_sections([sim_size, temp_size]);
+    let mut _guard = crate::try_acquire_memory_sections([sim_size, temp_size])?;
     let _guard_temp = _guard.pop().unwrap();
     let _guard_sim = _guard.pop().unwrap();
 
-    let tri_mat: TriMatI<Pattern, u32> = sprs::io::read_matrix_market(path).unwrap();
+    let matrix = matrix_cache::load_or_build(path)?;
     info!(
         "finished read the matrix: time:{:.2} secs",
         read_time.elapsed().as_secs_f32()
     );
     let (matrix_a, matrix_b): (CsMatI<Pattern, u32>, CsMatI<Pattern, u32>) =
-        (tri_mat.to_csr(), tri_mat.transpose_view().to_csr());
+        (matrix.csr, matrix.csc.transpose_into());
 
-    drop(tri_mat);
     drop(_guard_temp);
 
     info!(
@@ -1518,20 +1512,20 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
                 config,
             );
             info!("start running the sim");
-            let result = gearbox.run(&matrix_a, batch, top_k);
+            let result = gearbox.run(&matrix_a, batch, top_k, cancel)?;
             TOTAL_FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
             info!(
                 "finished task: {}/{}",
                 TOTAL_FINISHED_TASKS.load(Ordering::Relaxed),
                 *TOTAL_TASKS.read().unwrap()
             );
-            gearbox.report(path.to_string(), result, batch, top_k)
+            Ok(gearbox.report(path.to_string(), result, batch, top_k))
         })
-        .collect();
+        .collect::<eyre::Result<_>>()?;
     drop(matrix_a);
     drop(matrix_b);
     drop(_guard_sim);
-    results
+    Ok(results)
 }
 pub fn transpose2<T>(v: Vec<Vec<T>>) -> Vec<Vec<T>> {
     assert!(!v.is_empty());
@@ -1553,7 +1547,7 @@ static TOTAL_TASKS: RwLock<usize> = RwLock::new(0);
 fn analyze_gearbox_inner<LevelType: LevelTrait>(
     config: &ConfigV2,
     _total_size: &LevelType::Storage,
-) -> Vec<((usize, f32), Vec<SingleResult>)>
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>>
 where
     LevelType::Storage: Debug + Sync,
     LevelType::Mapping: Debug,
@@ -1563,6 +1557,8 @@ where
     let total_tasks = total_graphs * total_configs;
     info!(?total_graphs, ?total_configs, ?total_tasks, "total tasks");
     *TOTAL_TASKS.write().unwrap() = total_tasks;
+    let config_cancel =
+        cancel::root().child_with_timeout(config.deadline_secs.map(Duration::from_secs));
 
     let results: Vec<_> = config
         .graph_path
@@ -1570,9 +1566,11 @@ where
         .enumerate()
         .map(|(index, path)| {
             info!("analyzing graph {}/{}", index + 1, total_graphs);
-            compute_gearbox(config, path)
+            let graph_cancel = config_cancel
+                .child_with_timeout(config.graph_deadline_secs.map(Duration::from_secs));
+            compute_gearbox(config, path, &graph_cancel)
         })
-        .collect();
+        .collect::<eyre::Result<_>>()?;
     let results = transpose2(results);
     let configs = config
         .gearbox_config
@@ -1583,7 +1581,7 @@ where
         .collect_vec();
     info!(?configs, "configs");
     assert_eq!(configs.len(), results.len());
-    configs.into_iter().zip(results).collect()
+    Ok(configs.into_iter().zip(results).collect())
     // GearboxResult { results }
 }
 
@@ -2,13 +2,17 @@ pub mod action;
 pub mod jump;
 pub mod real_jump;
 pub mod remote_updator;
-// pub mod real_jump_iterative;
+pub mod real_jump_iterative;
+pub mod replay;
 pub mod row_cycle;
+pub mod time_series;
 use serde::{Deserialize, Serialize};
 use sprs::{num_kinds::Pattern, CsMatViewI};
 use tracing::info;
 
-use crate::{algorithms::SpmvAlgorithm, pim::configv2::ConfigV3};
+use crate::{
+    algorithms::SpmvAlgorithm, pim::configv2::ConfigV3, tools::cancel::CancellationToken,
+};
 
 use super::translate_mapping::TranslateMapping;
 
@@ -16,6 +20,10 @@ use super::translate_mapping::TranslateMapping;
 pub enum SimulationType {
     #[default]
     RealJump,
+    /// real jump with one simulator for all iterations of bfs, page rank and spmm
+    RealJumpIterative,
+    /// replay the access traces in `graph_path` on the real jump simulator, see [`replay`]
+    Replay,
 }
 pub fn run_simulation(config: ConfigV3) -> eyre::Result<()> {
     let current_time = std::time::Instant::now();
@@ -25,11 +33,29 @@ pub fn run_simulation(config: ConfigV3) -> eyre::Result<()> {
         SimulationType::RealJump => {
             real_jump::run_simulation(config)?;
         }
+        SimulationType::RealJumpIterative => {
+            real_jump_iterative::run_simulation(config)?;
+        }
+        SimulationType::Replay => {
+            replay::run_simulation(config)?;
+        }
     }
     info!("analyze finished in {:?}", current_time.elapsed());
     Ok(())
 }
 
+/// run the simulation of the config and return the results of all graphs in json, used when the results of
+/// several configs are collected together, like the sweeps
+pub fn simulate(config: &ConfigV3) -> eyre::Result<serde_json::Value> {
+    match config.analysis {
+        SimulationType::RealJump => real_jump::simulate_json(config),
+        SimulationType::RealJumpIterative => real_jump_iterative::simulate_json(config),
+        SimulationType::Replay => replay::simulate_json(config),
+    }
+}
+
+/// the simulators stop early when `cancel` is cancelled,
+/// the result of the finished rounds is still returned
 pub trait Simulator {
     type R;
     fn run(
@@ -38,9 +64,12 @@ pub trait Simulator {
         matrix_tri_translated: CsMatViewI<Pattern, u32>,
         algorithm: impl SpmvAlgorithm,
         max_rounds: Option<usize>,
+        cancel: &CancellationToken,
     ) -> eyre::Result<Self::R>;
 }
 
+/// a simulator that keep its status across the iterations of the algorithm,
+/// calling `run` again on the same simulator continues from the last status
 pub trait IterativeSimulator {
     type R;
     fn run(
@@ -48,5 +77,6 @@ pub trait IterativeSimulator {
         mapping: &impl TranslateMapping,
         matrix_tri_translated: CsMatViewI<Pattern, u32>,
         algorithm: &mut impl SpmvAlgorithm,
+        cancel: &CancellationToken,
     ) -> eyre::Result<Self::R>;
 }
@@ -2,11 +2,15 @@
 //! ## Author: Jiangqiu Shen
 //! ## Date: 2023-05-11
 //! Description: perform the real jump simulation, a real_jump means the one-hot-encoded value should jump to target when current != target
+//! the rows and the columns of a graph share one remapping, so only the square graphs are simulated, the config
+//! validation rejects the others before they are loaded. a rectangular `A*B` would need separate row and column
+//! mappings and frontiers taken from `A` instead of the graph itself (bfs, page rank and spmm all walk the graph),
+//! it's not supported yet, such products are simulated by the stream merger simulator
 use eyre::Context;
 use itertools::Itertools;
 use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
-use sprs::{io::MatrixHead, num_kinds::Pattern, CsMatI, CsMatViewI, TriMatI};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sprs::{num_kinds::Pattern, CsMatI, CsMatViewI, TriMatI};
 use std::{
     collections::{BTreeMap, BTreeSet},
     fs::File,
@@ -15,23 +19,48 @@ use std::{
     mem::size_of,
     time::{Duration, Instant},
 };
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
 use crate::{
-    algorithms::{bfs::Bfs, page_rank::PageRank, spmm::Spmm, FrontierType, SpmvAlgorithm},
+    algorithms::{
+        bfs::Bfs,
+        page_rank::PageRank,
+        replay::{AccessTraceWriter, TraceHardware},
+        spmm::Spmm,
+        FrontierType, SpmvAlgorithm,
+    },
     analysis::{
         remap_analyze::{
             action::{ReduceAction, TotalAction, UpdateAction, UpdateBatchAction},
             row_cycle::*,
+            time_series::{RoundStats, TimeSeriesRecorder},
             Simulator,
         },
         translate_mapping::{
-            self, same_bank::SameBankMapping, weighted::SameBankWeightedMapping, TranslateMapping,
+            self, same_bank::SameBankMapping, weighted::SameBankWeightedMapping,
+            CachedTranslateMapping, TranslateMapping,
+        },
+        write_memory_reports, EVIL_RATE,
+    },
+    pim::{configv2::ConfigV3, level::ddr4},
+    tools::{
+        self,
+        cancel::{self, CancelReason, CancellationToken},
+        checkpoint::{Checkpoint, Checkpointer},
+        control::{self, TaskProgress},
+        dram_trace::{DramTrace, TraceOp},
+        graph_reader,
+        hierarchical_network::{HierarchicalNetwork, HierarchicalNetworkConfig, HierarchyReport},
+        interconnect::{
+            build_interconnect_with_ring, drain_traffic, packet_words, InterconnectType, Packet,
+            DEFAULT_BUFFER_SIZE, DEFAULT_PACKET_VALUE_BYTES, DEFAULT_WATCHDOG_CYCLES,
         },
-        EVIL_RATE,
+        mapping_cache::{MappingCache, MappingKey},
+        matrix_cache,
+        memory_profile::{self, MemoryReport},
+        ring_simulator::RingConfig,
+        FlatInterleaveTrait,
     },
-    pim::configv2::ConfigV3,
-    tools::{self, file_server, FlatInterleaveTrait},
 };
 /// ## rust function
 /// ## Author: Jiangqiu Shen
@@ -42,7 +71,7 @@ struct WriteTasks {
     tasks_from_sources: BTreeMap<SubarrayId, Vec<RowIdWordId>>,
 }
 
-struct RealJumpSimulator {
+pub(crate) struct RealJumpSimulator {
     /// the local read of evil row
     evil_row_status: Vec<RowIdWordId>,
     evil_row_cycles: Vec<AllJumpCycles>,
@@ -59,12 +88,139 @@ struct RealJumpSimulator {
     subarray_bits: usize,
     /// the (sending,receiving) status of each bank
     dispatcher_status: Vec<(usize, usize)>,
+    /// the network connecting the banks of a channel, `None` only counts the packets of each dispatcher
+    bank_network: Option<InterconnectType>,
+    /// the packets each buffer of the bank network holds
+    network_buffer_size: usize,
+    /// the link width and hop latency when the bank network is a ring
+    network_ring: RingConfig,
+    /// the words of a remote packet, see [`packet_words`]
+    packet_size: usize,
+    /// the cycles without progress before the bank network is reported as stalled
+    network_watchdog_cycles: usize,
+    /// the number of banks in a channel
+    bank_size: usize,
+    /// the (source bank, target bank) of the remote packets in this round, only recorded when `bank_network` is set
+    network_packets: Vec<(usize, usize)>,
+    /// the end to end network of the remote writes
+    hierarchy: Option<HierarchicalNetwork>,
+    /// the trace of the reads and writes of the subarrays
+    dram_trace: Option<DramTrace>,
+    /// the frontiers of each round, can be replayed later
+    access_trace: Option<AccessTraceWriter>,
 
     /// the cycle of each remap calculation
     remap_cycle: usize,
 
     /// write tasks
     write_tasks: BTreeMap<SubarrayId, WriteTasks>,
+
+    /// the number of finished rounds
+    round: usize,
+    /// record the stats of each round
+    time_series: Option<TimeSeriesRecorder>,
+    /// save the status periodically
+    checkpointer: Option<Checkpointer>,
+    /// the name shown in the status of the control server
+    task_name: String,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-06
+/// Description: the recorders attached to the simulation of one graph
+#[derive(Default)]
+pub struct SimulationRecorders {
+    pub time_series: Option<TimeSeriesRecorder>,
+    pub checkpointer: Option<Checkpointer>,
+    /// the name shown in the status of the control server
+    pub task_name: Option<String>,
+    pub dram_trace: Option<DramTrace>,
+    pub access_trace: Option<AccessTraceWriter>,
+}
+
+impl SimulationRecorders {
+    /// build the recorders enabled in the config, `name` is the name of the algorithm
+    pub fn for_graph(config: &ConfigV3, graph: &str, name: &str) -> eyre::Result<Self> {
+        let time_series = config
+            .time_series_dir
+            .as_ref()
+            .map(|dir| {
+                TimeSeriesRecorder::for_graph(dir, graph, &config.mapping, name, config.resume)
+            })
+            .transpose()?;
+        let checkpointer = config.checkpoint_dir.as_ref().map(|dir| {
+            Checkpointer::for_graph(
+                dir,
+                graph,
+                &format!("{:?}_{}", config.mapping, name),
+                Duration::from_secs(config.checkpoint_interval),
+                config.resume,
+            )
+            .with_fingerprint(config.checkpoint_fingerprint())
+        });
+        let dram_trace = config
+            .dram_trace
+            .as_ref()
+            .map(|trace_config| {
+                let total_size = ddr4::Storage::new(
+                    config.channels.num,
+                    config.ranks.num,
+                    config.chips.num,
+                    config.bank_groups.num,
+                    config.banks.num,
+                    config.subarrays,
+                    config.rows,
+                    config.columns,
+                );
+                DramTrace::for_graph(
+                    trace_config,
+                    total_size,
+                    graph,
+                    &format!("{:?}_{}", config.mapping, name),
+                )
+            })
+            .transpose()?;
+        let access_trace = config
+            .access_trace_dir
+            .as_ref()
+            .map(|dir| {
+                AccessTraceWriter::for_graph(
+                    dir,
+                    graph,
+                    TraceHardware::from_config(config),
+                    name,
+                    config.resume,
+                )
+            })
+            .transpose()?;
+        Ok(Self {
+            time_series,
+            checkpointer,
+            task_name: Some(format!("{}_{:?}_{}", graph, config.mapping, name)),
+            dram_trace,
+            access_trace,
+        })
+    }
+}
+
+/// the status that should be kept between rounds, the cycles are reset after each round so they are not saved
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct RealJumpSimulatorState {
+    evil_row_status: Vec<(usize, usize)>,
+    non_evil_status: Vec<(usize, usize)>,
+    col_status_remote: Vec<(usize, usize)>,
+    col_status_local: Vec<(usize, usize)>,
+    round: usize,
+}
+fn save_status(status: &[RowIdWordId]) -> Vec<(usize, usize)> {
+    status.iter().map(|s| (s.row_id.0, s.word_id.0)).collect()
+}
+fn restore_status(status: &[(usize, usize)]) -> Vec<RowIdWordId> {
+    status
+        .iter()
+        .map(|&(row_id, word_id)| RowIdWordId::new(PhysicRowId(row_id), WordId(word_id)))
+        .collect()
 }
 struct WriteDenseInfo<'a> {
     source_subarray_id: SubarrayId,
@@ -106,6 +262,16 @@ impl RealJumpSimulator {
                 global_subarray_size
             ],
             dispatcher_status: vec![(0, 0); global_bank_size],
+            bank_network: None,
+            network_buffer_size: DEFAULT_BUFFER_SIZE,
+            network_ring: RingConfig::default(),
+            packet_size: packet_words(size_of::<u32>(), DEFAULT_PACKET_VALUE_BYTES),
+            network_watchdog_cycles: DEFAULT_WATCHDOG_CYCLES,
+            bank_size,
+            network_packets: vec![],
+            hierarchy: None,
+            dram_trace: None,
+            access_trace: None,
             evil_row_cycles: vec![Default::default(); global_subarray_size],
             evil_row_status: vec![
                 RowIdWordId {
@@ -124,10 +290,194 @@ impl RealJumpSimulator {
             ],
             remap_cycle,
             write_tasks: Default::default(),
+            round: 0,
+            time_series: None,
+            checkpointer: None,
+            task_name: "real_jump".to_string(),
+        }
+    }
+
+    /// record the stats of each round to the time series and save the checkpoints
+    pub fn with_recorders(mut self, recorders: SimulationRecorders) -> Self {
+        self.time_series = recorders.time_series;
+        self.checkpointer = recorders.checkpointer;
+        self.dram_trace = recorders.dram_trace;
+        self.access_trace = recorders.access_trace;
+        if let Some(task_name) = recorders.task_name {
+            self.task_name = task_name;
+        }
+        self
+    }
+
+    /// deliver the remote packets of each round through the `bank_interconnect` of the config, see
+    /// [`RealJumpResult::dispatcher_network_cycle`]. a network that delivers no packet for `network_watchdog_cycles`
+    /// cycles stops the simulation with an error
+    pub fn with_bank_network(mut self, config: &ConfigV3) -> Self {
+        self.bank_network = config.bank_interconnect;
+        self.network_buffer_size = config.network_buffer_size;
+        self.network_ring = config.ring;
+        self.packet_size = packet_words(size_of::<u32>(), config.packet_value_bytes);
+        self.network_watchdog_cycles = config.network_watchdog_cycles;
+        self
+    }
+
+    /// route the remote writes through the hierarchical network, see [`RealJumpResult::hierarchy`]
+    pub fn with_hierarchy(mut self, config: Option<HierarchicalNetworkConfig>) -> Self {
+        let channels = self.dispatcher_status.len() / self.bank_size;
+        self.hierarchy =
+            config.map(|config| HierarchicalNetwork::new(config, channels, self.bank_size));
+        self
+    }
+
+    /// register the simulation to the control server
+    pub(crate) fn register_progress(
+        &self,
+        total: usize,
+        cancel: &CancellationToken,
+    ) -> TaskProgress {
+        TaskProgress::register(self.task_name.clone(), total, cancel)
+    }
+
+    fn save_state(&self) -> RealJumpSimulatorState {
+        RealJumpSimulatorState {
+            evil_row_status: save_status(&self.evil_row_status),
+            non_evil_status: save_status(&self.non_evil_status),
+            col_status_remote: save_status(&self.col_status_remote),
+            col_status_local: save_status(&self.col_status_local),
+            round: self.round,
+        }
+    }
+
+    fn restore_state(&mut self, state: RealJumpSimulatorState) -> eyre::Result<()> {
+        if state.evil_row_status.len() != self.evil_row_status.len() {
+            eyre::bail!(
+                "the checkpoint has {} subarrays, but the config has {}",
+                state.evil_row_status.len(),
+                self.evil_row_status.len()
+            );
+        }
+        self.evil_row_status = restore_status(&state.evil_row_status);
+        self.non_evil_status = restore_status(&state.non_evil_status);
+        self.col_status_remote = restore_status(&state.col_status_remote);
+        self.col_status_local = restore_status(&state.col_status_local);
+        self.round = state.round;
+        Ok(())
+    }
+
+    /// ## rust function
+    /// ## Author: Jiangqiu Shen
+    /// ## Date: 2023-06-06
+    /// Description: load the checkpoint and restore the status, return the next target row and the saved result.
+    /// the time series and the access trace are truncated to the rounds before the checkpoint
+    pub(crate) fn load_checkpoint<R: DeserializeOwned>(
+        &mut self,
+    ) -> eyre::Result<Option<Checkpoint<R>>> {
+        let Some(checkpointer) = &self.checkpointer else {
+            return Ok(None);
+        };
+        let checkpoint: Option<Checkpoint<(RealJumpSimulatorState, R)>> = checkpointer.load()?;
+        let checkpoint = match checkpoint {
+            Some(Checkpoint {
+                target_id,
+                finished,
+                fingerprint,
+                state: (state, result),
+            }) => {
+                self.restore_state(state)?;
+                Some(Checkpoint {
+                    target_id,
+                    finished,
+                    fingerprint,
+                    state: result,
+                })
+            }
+            None => None,
+        };
+        if let Some(time_series) = &mut self.time_series {
+            time_series.truncate_to(self.round)?;
+        }
+        if let Some(access_trace) = &mut self.access_trace {
+            access_trace.truncate_to(self.round)?;
+        }
+        Ok(checkpoint)
+    }
+
+    /// save the checkpoint if the interval has passed, `target_id` is the next row to be processed
+    pub(crate) fn checkpoint_if_due<R: Serialize>(
+        &mut self,
+        target_id: usize,
+        result: &R,
+    ) -> eyre::Result<()> {
+        if self.checkpointer.as_ref().map_or(false, |c| c.is_due()) {
+            let state = (self.save_state(), result);
+            self.checkpointer
+                .as_mut()
+                .unwrap()
+                .save(target_id, false, &state)?;
+        }
+        Ok(())
+    }
+
+    /// save the checkpoint immediately, used when the simulation is cancelled
+    pub(crate) fn checkpoint_now<R: Serialize>(
+        &mut self,
+        target_id: usize,
+        result: &R,
+    ) -> eyre::Result<()> {
+        if self.checkpointer.is_some() {
+            let state = (self.save_state(), result);
+            self.checkpointer
+                .as_mut()
+                .unwrap()
+                .save(target_id, false, &state)?;
+        }
+        Ok(())
+    }
+
+    /// save the final result, a resumed run will return it directly
+    pub(crate) fn checkpoint_finished<R: Serialize>(
+        &mut self,
+        target_id: usize,
+        result: &R,
+    ) -> eyre::Result<()> {
+        if self.checkpointer.is_some() {
+            let state = (self.save_state(), result);
+            self.checkpointer
+                .as_mut()
+                .unwrap()
+                .save(target_id, true, &state)?;
+        }
+        Ok(())
+    }
+
+    /// issue the accesses of `words` words from the location to the dram trace
+    fn trace_access(&mut self, location: &RowLocation, words: usize, op: TraceOp) {
+        if let Some(trace) = &mut self.dram_trace {
+            let RowIdWordId { row_id, word_id } = location.row_id_word_id;
+            for word in word_id.0..word_id.0 + words {
+                let path = trace
+                    .mapping()
+                    .subarray_path(location.subarray_id.0, row_id.0, word);
+                trace.issue(&path, op);
+            }
         }
     }
 
+    /// record the rows of matrix b read in this round, `None` means all rows
+    fn record_round(
+        &mut self,
+        csr_translated: CsMatViewI<Pattern, u32>,
+        rows: Option<&[u32]>,
+    ) -> eyre::Result<()> {
+        if let Some(access_trace) = &mut self.access_trace {
+            access_trace.write_matrix(csr_translated)?;
+            access_trace.write_round(rows)?;
+        }
+        Ok(())
+    }
+
     fn read_local_evil(&mut self, location: &RowLocation, size: WordId) {
+        self.trace_access(location, size.0, TraceOp::Read);
         let current_status = &self.evil_row_status[location.subarray_id.0];
 
         debug!(
@@ -214,6 +564,14 @@ impl RealJumpSimulator {
         debug!(?status);
     }
     fn write_dense_remote(&mut self, source_subarray_id: SubarrayId, col_location: &RowLocation) {
+        self.trace_access(col_location, 1, TraceOp::Write);
+        if self.hierarchy.is_some() {
+            let source_bank = self.bank_id_from_subarray_id(source_subarray_id);
+            let target_bank = self.bank_id_from_subarray_id(col_location.subarray_id);
+            if let Some(hierarchy) = &mut self.hierarchy {
+                hierarchy.add(source_bank, target_bank);
+            }
+        }
         let current_status = self
             .col_status_remote
             .get_mut(col_location.subarray_id.0)
@@ -238,6 +596,7 @@ impl RealJumpSimulator {
         Self::write_dense_lazy(write_dense_info);
     }
     fn write_dense_local(&mut self, source_subarray_id: SubarrayId, col_location: &RowLocation) {
+        self.trace_access(col_location, 1, TraceOp::Write);
         let current_status = self
             .col_status_local
             .get_mut(col_location.subarray_id.0)
@@ -263,6 +622,7 @@ impl RealJumpSimulator {
     }
 
     fn read_local(&mut self, location: &RowLocation, word_size: WordId) {
+        self.trace_access(location, word_size.0, TraceOp::Read);
         let current_status = &self.non_evil_status[location.subarray_id.0];
         debug!(
             ?current_status,
@@ -290,12 +650,77 @@ impl RealJumpSimulator {
         self.dispatcher_status[bank_id].1 += 1;
     }
 
+    /// a remote packet from the dispatcher of the source bank to the dispatcher of the target bank
+    fn dispatch_remote(&mut self, source: SubarrayId, target: SubarrayId) {
+        self.write_tsv_sending(source);
+        self.write_tsv_reading(target);
+        if self.bank_network.is_some() {
+            let packet = (
+                self.bank_id_from_subarray_id(source),
+                self.bank_id_from_subarray_id(target),
+            );
+            self.network_packets.push(packet);
+        }
+    }
+
     fn bank_id_from_subarray_id(&self, subarray_id: SubarrayId) -> usize {
         subarray_id.0 >> self.subarray_bits
     }
 
+    /// ## rust function
+    /// ## Author: Jiangqiu Shen
+    /// ## Date: 2023-06-17
+    /// Description: the cycles to deliver the remote packets of this round through the bank network of each channel,
+    /// the channels run in parallel. a packet to another channel goes to the tsv at bank 0 of its channel, and leaves
+    /// the tsv at bank 0 of the target channel
+    fn network_cycle(&self) -> eyre::Result<usize> {
+        let Some(kind) = self.bank_network else {
+            return Ok(0);
+        };
+        let bank_size = self.bank_size;
+        let buffer_size = self.network_buffer_size;
+        let ring = self.network_ring;
+        let size = self.packet_size;
+        let watchdog_cycles = self.network_watchdog_cycles;
+        let channels = self.dispatcher_status.len() / bank_size;
+        let mut channel_packets = vec![vec![]; channels];
+        for &(source, target) in &self.network_packets {
+            let (source_channel, source_bank) = (source / bank_size, source % bank_size);
+            let (target_channel, target_bank) = (target / bank_size, target % bank_size);
+            if source_channel == target_channel {
+                channel_packets[source_channel].push(Packet {
+                    source: source_bank,
+                    dest: target_bank,
+                    size,
+                });
+            } else {
+                channel_packets[source_channel].push(Packet {
+                    source: source_bank,
+                    dest: 0,
+                    size,
+                });
+                channel_packets[target_channel].push(Packet {
+                    source: 0,
+                    dest: target_bank,
+                    size,
+                });
+            }
+        }
+        channel_packets
+            .into_par_iter()
+            .map(|packets| {
+                let mut network = build_interconnect_with_ring(kind, bank_size, buffer_size, ring);
+                drain_traffic(&mut network, packets, watchdog_cycles)
+            })
+            .try_reduce(|| 0, |a, b| Ok(a.max(b)))
+    }
+
     ///[normal, ideal, from_source, my, smart]
-    fn update_result(&mut self, result: &mut RealJumpResult) {
+    fn update_result(
+        &mut self,
+        result: &mut RealJumpResult,
+        frontier_size: usize,
+    ) -> eyre::Result<()> {
         // first reduce the write tasks
         let current_tasks = std::mem::take(&mut self.write_tasks);
         for (target_subarray, tasks) in current_tasks.into_iter() {
@@ -350,7 +775,6 @@ impl RealJumpSimulator {
         update_row_cycle(&self.col_cycles_remote, &mut result.remote_dense_col_cycles);
         #[cfg(debug_assertions)]
         {
-            use tracing::error;
             let no_select = result.remote_dense_col_cycles.normal_jump_cycle_32;
             let select = result
                 .remote_dense_col_cycles
@@ -360,7 +784,8 @@ impl RealJumpSimulator {
             }
         }
 
-        update_row_cycle(&self.evil_row_cycles, &mut result.evil_row_cycles);
+        let round_evil_row_cycles =
+            update_row_cycle(&self.evil_row_cycles, &mut result.evil_row_cycles);
         update_row_cycle(&self.non_evil_row_cycles, &mut result.row_cycles);
 
         let subarrays = self.non_evil_row_cycles.len() / self.dispatcher_status.len();
@@ -411,8 +836,33 @@ impl RealJumpSimulator {
         let max_receive_cycle = self.dispatcher_status.iter().map(|x| x.1).max().unwrap();
         result.dispatcher_sending_cycle += max_sending_cycle;
         result.dispatcher_reading_cycle += max_receive_cycle;
+        let network_cycle = self.network_cycle()?;
+        result.dispatcher_network_cycle += network_cycle;
+        if let Some(hierarchy) = &mut self.hierarchy {
+            let report = hierarchy.run_round(self.network_watchdog_cycles)?;
+            result
+                .hierarchy
+                .get_or_insert_with(Default::default)
+                .add(&report);
+        }
 
         assert_eq!(result.real_local_cycle.len(), local_max.len());
+        if let Some(trace) = &mut self.dram_trace {
+            // the next round starts after the slowest subarray of the first jump type
+            trace.end_round(local_max[0] as u64)?;
+        }
+        if let Some(time_series) = &mut self.time_series {
+            time_series.record(&RoundStats {
+                round: self.round,
+                frontier_size,
+                max_local_cycle: local_max.clone(),
+                dispatcher_sending_cycle: max_sending_cycle,
+                dispatcher_reading_cycle: max_receive_cycle,
+                dispatcher_network_cycle: network_cycle,
+                evil_row_cycles: round_evil_row_cycles.to_vec(),
+            })?;
+        }
+        self.round += 1;
         result
             .real_local_cycle
             .iter_mut()
@@ -427,16 +877,19 @@ impl RealJumpSimulator {
         self.non_evil_row_cycles = vec![Default::default(); self.non_evil_row_cycles.len()];
 
         self.dispatcher_status = vec![(0, 0); self.dispatcher_status.len()];
+        self.network_packets.clear();
+        Ok(())
     }
 
-    fn process_one_row(
+    pub(crate) fn process_one_row(
         &mut self,
         target_row: sprs::CsVecBase<&[u32], &[Pattern], Pattern, u32>,
         csr_translated: CsMatViewI<Pattern, u32>,
         mapping: &impl TranslateMapping,
         target_id: usize,
         result: &mut RealJumpResult,
-    ) {
+    ) -> eyre::Result<()> {
+        self.record_round(csr_translated, Some(target_row.indices()))?;
         let mut evil_col_handler = EvilColHandler::new();
         // this is a single task
         for &matrix_b_row_id in target_row.indices() {
@@ -457,14 +910,13 @@ impl RealJumpSimulator {
                     self.write_dense_local(subarray_id, &col_location);
                 } else {
                     // first send to the remote dispacher, ring and tsv,
-                    self.write_tsv_sending(subarray_id);
-                    self.write_tsv_reading(col_location.subarray_id);
+                    self.dispatch_remote(subarray_id, col_location.subarray_id);
                     self.write_dense_remote(subarray_id, &col_location);
                 }
             }
         }
         // after each target_id, update the result and clear current status.
-        self.update_result(result);
+        self.update_result(result, target_row.nnz())
     }
     fn update_one_column(
         &mut self,
@@ -516,8 +968,7 @@ impl RealJumpSimulator {
                     self.write_dense_local(location.subarray_id, &dense_location);
                 } else {
                     // first send to the remote dispacher, ring and tsv,
-                    self.write_tsv_sending(location.subarray_id);
-                    self.write_tsv_reading(dense_location.subarray_id);
+                    self.dispatch_remote(location.subarray_id, dense_location.subarray_id);
                     self.write_dense_remote(location.subarray_id, &dense_location);
                     // then send to the subarray
                 }
@@ -525,13 +976,14 @@ impl RealJumpSimulator {
         }
     }
 
-    fn process_one_fullrow(
+    pub(crate) fn process_one_fullrow(
         &mut self,
         csr_translated: CsMatViewI<Pattern, u32>,
         mapping: &impl TranslateMapping,
         target_id: usize,
         result: &mut RealJumpResult,
-    ) {
+    ) -> eyre::Result<()> {
+        self.record_round(csr_translated, None)?;
         let mut evil_col_handler = EvilColHandler::new();
         // this is a single task
         for matrix_b_row_id in 0..csr_translated.rows() as u32 {
@@ -552,14 +1004,13 @@ impl RealJumpSimulator {
                     self.write_dense_local(subarray_id, &col_location);
                 } else {
                     // first send to the remote dispacher, ring and tsv,
-                    self.write_tsv_sending(subarray_id);
-                    self.write_tsv_reading(col_location.subarray_id);
+                    self.dispatch_remote(subarray_id, col_location.subarray_id);
                     self.write_dense_remote(subarray_id, &col_location);
                 }
             }
         }
         // after each target_id, update the result and clear current status.
-        self.update_result(result);
+        self.update_result(result, csr_translated.rows())
     }
 }
 
@@ -612,6 +1063,27 @@ pub fn run_with_mapping(
     matrix_csr: CsMatViewI<Pattern, u32>,
     algorithm: impl SpmvAlgorithm,
     max_rounds: Option<usize>,
+) -> eyre::Result<RealJumpResult> {
+    run_with_mapping_recorded(
+        mapping,
+        config,
+        matrix_csr,
+        algorithm,
+        max_rounds,
+        Default::default(),
+        cancel::root(),
+    )
+}
+
+/// same as [`run_with_mapping`], but the recorders are attached to the simulator
+pub fn run_with_mapping_recorded(
+    mapping: &impl TranslateMapping,
+    config: &ConfigV3,
+    matrix_csr: CsMatViewI<Pattern, u32>,
+    algorithm: impl SpmvAlgorithm,
+    max_rounds: Option<usize>,
+    recorders: SimulationRecorders,
+    cancel: &CancellationToken,
 ) -> eyre::Result<RealJumpResult> {
     let remap_cycle = config.remap_cycle;
     info!("remap cycle: {}", remap_cycle);
@@ -620,25 +1092,61 @@ pub fn run_with_mapping(
         config.banks.num,
         config.channels.num,
         remap_cycle,
-    );
+    )
+    .with_bank_network(config)
+    .with_hierarchy(config.hierarchical_network.clone())
+    .with_recorders(recorders);
     info!("start to run simulator");
-    simulator.run(mapping, matrix_csr, algorithm, max_rounds)
+    simulator.run(mapping, matrix_csr, algorithm, max_rounds, cancel)
 }
-pub fn build_same_bank_mapping(
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-09
+/// Description: build the mapping through the mapping cache in `config.mapping_cache_dir`, `build` is called directly
+/// when the cache is disabled
+pub fn build_with_cache<M: CachedTranslateMapping>(
     config: &ConfigV3,
-    matrix_tri: &TriMatI<Pattern, u32>,
     matrix_csr: &CsMatI<Pattern, u32>,
-) -> (SameBankMapping, CsMatI<Pattern, u32>) {
-    let row_evil_threshold = (matrix_tri.rows() as f32 * EVIL_RATE) as usize;
-    translate_mapping::same_bank::SameBankMapping::new(
-        config.banks.num,
+    row_evil_threshold: usize,
+    build: impl FnOnce() -> (M, CsMatI<Pattern, u32>),
+) -> (M, CsMatI<Pattern, u32>) {
+    let Some(cache_dir) = &config.mapping_cache_dir else {
+        return build();
+    };
+    let key = match MappingKey::new(
+        M::KIND,
+        matrix_csr.view(),
         config.channels.num,
+        config.banks.num,
         config.subarrays,
         row_evil_threshold,
         config.columns,
-        matrix_tri,
-        matrix_csr,
-    )
+    ) {
+        Ok(key) => key,
+        Err(e) => {
+            error!("fail to hash the graph for the mapping cache: {:?}", e);
+            return build();
+        }
+    };
+    translate_mapping::build_cached(&MappingCache::new(cache_dir), &key, build)
+}
+pub fn build_same_bank_mapping(
+    config: &ConfigV3,
+    matrix_tri: &TriMatI<Pattern, u32>,
+    matrix_csr: &CsMatI<Pattern, u32>,
+) -> (SameBankMapping, CsMatI<Pattern, u32>) {
+    let row_evil_threshold = (matrix_tri.rows() as f32 * EVIL_RATE) as usize;
+    build_with_cache(config, matrix_csr, row_evil_threshold, || {
+        translate_mapping::same_bank::SameBankMapping::new(
+            config.banks.num,
+            config.channels.num,
+            config.subarrays,
+            row_evil_threshold,
+            config.columns,
+            matrix_tri,
+            matrix_csr,
+        )
+    })
 }
 pub fn build_weighted_mapping(
     config: &ConfigV3,
@@ -646,58 +1154,159 @@ pub fn build_weighted_mapping(
     matrix_csr: &CsMatI<Pattern, u32>,
 ) -> (SameBankWeightedMapping, CsMatI<Pattern, u32>) {
     let row_evil_threshold = (matrix_tri.rows() as f32 * EVIL_RATE) as usize;
-    translate_mapping::weighted::SameBankWeightedMapping::new(
-        config.banks.num,
-        config.channels.num,
-        config.subarrays,
-        row_evil_threshold,
-        config.columns,
-        matrix_tri,
-        matrix_csr,
-    )
+    build_with_cache(config, matrix_csr, row_evil_threshold, || {
+        translate_mapping::weighted::SameBankWeightedMapping::new(
+            config.banks.num,
+            config.channels.num,
+            config.subarrays,
+            row_evil_threshold,
+            config.columns,
+            matrix_tri,
+            matrix_csr,
+        )
+    })
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-02
+/// Description: the simulation that run on an already translated graph, it's used by [`run_simulation_with`] so that
+/// different simulators can share the graph reading and mapping building
+pub(crate) trait TranslatedGraphRunner: Sync {
+    type R: Serialize + Send;
+    fn run_translated(
+        &self,
+        graph: &str,
+        mapping: &impl TranslateMapping,
+        config: &ConfigV3,
+        translated_csr: CsMatViewI<Pattern, u32>,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<Self::R>;
+}
+
+/// run the spmm for at most [`MAX_RUN_ROUNDS`] rows
+struct SpmmRunner;
+impl TranslatedGraphRunner for SpmmRunner {
+    type R = RealJumpResult;
+    fn run_translated(
+        &self,
+        graph: &str,
+        mapping: &impl TranslateMapping,
+        config: &ConfigV3,
+        translated_csr: CsMatViewI<Pattern, u32>,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<Self::R> {
+        run_with_mapping_recorded(
+            mapping,
+            config,
+            translated_csr.view(),
+            Spmm::new(translated_csr.view()),
+            Some(MAX_RUN_ROUNDS),
+            SimulationRecorders::for_graph(config, graph, "spmm")?,
+            cancel,
+        )
+    }
+}
+
+/// the memory needed to simulate a graph with `rows` rows and `nnz` non-zeros, see [`crate::try_acquire_memory_sections`]
+pub(crate) fn memory_sections(config: &ConfigV3, rows: usize, nnz: usize) -> Vec<u64> {
+    // allocate he hardware guard, for each subarray, there need at least 128 bytes to save the statistics
+    let hardware_size = config.channels.num
+        * config.bank_groups.num
+        * config.banks.num
+        * (size_of::<(usize, usize)>()
+            + (config.subarrays
+                * (size_of::<AllJumpCycles>() * 3 + size_of::<(usize, usize)>() * 3)))
+        * 2;
+    let csr_matrix_size = rows * size_of::<usize>() + nnz * size_of::<u32>();
+    let tri_matrix_size = nnz * size_of::<u32>() * 2;
+    let row_evil_threshold = ((rows as f32 * EVIL_RATE) as usize).max(1);
+    // for each subarray, it should keep a subgraph, the ind size is rows*size_of::<usize>(), the data size is nnz*size_of::<u32>()
+    let subarray_matrix_size = 2
+        * config.channels.num
+        * config.bank_groups.num
+        * config.banks.num
+        * config.subarrays
+        * row_evil_threshold
+        * size_of::<usize>()
+        + nnz * size_of::<u32>();
+
+    // there will be 4 copy of matrics during initialization
+    let mut memory_sections = vec![hardware_size as u64, subarray_matrix_size as u64];
+    memory_sections.extend([csr_matrix_size as u64; 2]);
+    memory_sections.extend([tri_matrix_size as u64; 2]);
+    memory_sections
 }
 
 pub(crate) fn run_simulation(config: ConfigV3) -> eyre::Result<()> {
+    run_simulation_with(config, &SpmmRunner)
+}
+
+/// the results of all graphs, in json
+pub(crate) fn simulate_json(config: &ConfigV3) -> eyre::Result<serde_json::Value> {
+    Ok(serde_json::to_value(
+        simulate_graphs(config, &SpmmRunner)?.results,
+    )?)
+}
+
+/// run the `runner` on all graphs of the config and write the results to the `output_path`, the memory of each
+/// graph is written next to it, see [`crate::analysis::write_memory_reports`]
+pub(crate) fn run_simulation_with(
+    config: ConfigV3,
+    runner: &impl TranslatedGraphRunner,
+) -> eyre::Result<()> {
+    let total_graph_results = simulate_graphs(&config, runner)?;
+
+    // let mut simualtor = RealJumpSimulator;
+    // let result = simualtor.run(matrix_tri_translated, filter)?;
+    serde_json::to_writer_pretty(
+        BufWriter::new(File::create(&config.output_path)?),
+        &total_graph_results.results,
+    )?;
+    write_memory_reports(&config.output_path, &total_graph_results.memory)?;
+
+    Ok(())
+}
+
+/// the results of all graphs, with the estimated and measured memory of each graph
+#[derive(Debug)]
+pub(crate) struct SimulationOutput<R> {
+    pub results: Vec<R>,
+    pub memory: Vec<MemoryReport>,
+}
+
+/// read each graph in the config, build the mapping and run the `runner` on the translated graph
+pub(crate) fn simulate_graphs<T: TranslatedGraphRunner>(
+    config: &ConfigV3,
+    runner: &T,
+) -> eyre::Result<SimulationOutput<T::R>> {
     info!("start simulation");
-    let total_graph_results: Vec<eyre::Result<RealJumpResult>> = config
+    let config_cancel =
+        cancel::root().child_with_timeout(config.deadline_secs.map(Duration::from_secs));
+    let total_graph_results: Vec<eyre::Result<_>> = config
         .graph_path
         .par_iter()
         .map(|graph| {
             info!("run graph: {}", graph);
+            let graph_cancel = config_cancel
+                .child_with_timeout(config.graph_deadline_secs.map(Duration::from_secs));
             // first allocate the memory quota
-            let matrix_head: MatrixHead<Pattern, u32> =
-                sprs::io::read_matrix_market_from_bufread_head(&mut file_server::file_reader(
-                    graph,
-                )?)?;
+            let matrix_head = graph_reader::read_graph_head(graph)?;
             let rows = matrix_head.rows;
             let nnz = matrix_head.nnz;
+            // the same remapping is applied to the rows and the columns, the `A*B` entries are simulated by the
+            // stream merger simulator. checked before the memory is acquired and the graph is loaded
+            if rows != matrix_head.cols {
+                eyre::bail!(
+                    "the real jump simulation needs a square graph, got {}x{} for graph {}",
+                    rows,
+                    matrix_head.cols,
+                    graph
+                );
+            }
 
-            // allocate he hardware guard, for each subarray, there need at least 128 bytes to save the statistics
-            let hardware_size = config.channels.num
-                * config.bank_groups.num
-                * config.banks.num
-                * (size_of::<(usize, usize)>()
-                    + (config.subarrays
-                        * (size_of::<AllJumpCycles>() * 3 + size_of::<(usize, usize)>() * 3)))
-                * 2;
-            let csr_matrix_size = rows * size_of::<usize>() + nnz * size_of::<u32>();
-            let tri_matrix_size = nnz * size_of::<u32>() * 2;
-            let row_evil_threshold = (rows as f32 * EVIL_RATE) as usize;
-            let row_evil_threshold = row_evil_threshold.max(1);
-            // for each subarray, it should keep a subgraph, the ind size is rows*size_of::<usize>(), the data size is nnz*size_of::<u32>()
-            let subarray_matrix_size = 2
-                * config.channels.num
-                * config.bank_groups.num
-                * config.banks.num
-                * config.subarrays
-                * row_evil_threshold
-                * size_of::<usize>()
-                + nnz * size_of::<u32>();
-
-            // there will be 4 copy of matrics during initialization
-            let mut memory_sections = vec![hardware_size as u64, subarray_matrix_size as u64];
-            memory_sections.extend([csr_matrix_size as u64; 2]);
-            memory_sections.extend([tri_matrix_size as u64; 2]);
+            let row_evil_threshold = ((rows as f32 * EVIL_RATE) as usize).max(1);
+            let memory_sections = memory_sections(config, rows, nnz);
             info!("memory sections: {:?}", memory_sections);
             let total_memory = memory_sections.iter().sum::<u64>();
             let kb = total_memory / 1024;
@@ -717,65 +1326,71 @@ pub(crate) fn run_simulation(config: ConfigV3) -> eyre::Result<()> {
 
             info!("total memory: {}{}{} KB for graph:{}", gb, mb, kb, graph);
 
-            let mut matrix_guard = crate::acquire_memory_sections(memory_sections);
-            info!("Memory allocation succeed for graph: {}", graph);
-            let matrix_tri: TriMatI<Pattern, u32> = sprs::io::read_matrix_market_from_bufread(
-                &mut file_server::file_reader(graph)
-                    .wrap_err(format!("fail to read path:{}", graph))?,
+            let mut matrix_guard = crate::try_acquire_memory_sections_with_priority(
+                memory_sections,
+                config.memory_priority,
             )
-            .wrap_err(format!("fail to parse mtx format in file {}", graph))?;
-            let rows = matrix_tri.rows();
-            let cols = matrix_tri.cols();
-            assert_eq!(rows, cols);
+            .wrap_err(format!("fail to acquire the memory for graph {}", graph))?;
+            info!("Memory allocation succeed for graph: {}", graph);
+            let memory_profile =
+                memory_profile::start_job(graph, total_memory, matrix_guard[0].waited());
+            let matrix_csr = matrix_cache::load_csr_or_build(graph)?;
+            let matrix_tri = matrix_cache::csr_to_triplet(matrix_csr.view());
 
             let result = match config.mapping {
                 crate::pim::configv2::MappingType::SameSubarray => todo!(),
                 crate::pim::configv2::MappingType::SameBank => {
                     let (mapping, translated_csr) =
-                        translate_mapping::same_bank::SameBankMapping::new(
-                            config.banks.num,
-                            config.channels.num,
-                            config.subarrays,
-                            row_evil_threshold,
-                            config.columns,
-                            &matrix_tri,
-                            &matrix_tri.to_csr(),
-                        );
+                        build_with_cache(config, &matrix_csr, row_evil_threshold, || {
+                            translate_mapping::same_bank::SameBankMapping::new(
+                                config.banks.num,
+                                config.channels.num,
+                                config.subarrays,
+                                row_evil_threshold,
+                                config.columns,
+                                &matrix_tri,
+                                &matrix_csr,
+                            )
+                        });
                     // after created the mapping, there will be 2 copy of the matrix remained
+                    drop(matrix_csr);
                     matrix_guard.pop().unwrap();
                     matrix_guard.pop().unwrap();
                     matrix_guard.pop().unwrap();
 
-                    run_with_mapping(
+                    runner.run_translated(
+                        graph,
                         &mapping,
-                        &config,
+                        config,
                         translated_csr.view(),
-                        Spmm::new(translated_csr.view()),
-                        Some(MAX_RUN_ROUNDS),
+                        &graph_cancel,
                     )?
                     // free the hardware guard here, it's automatically dropped
                 }
                 crate::pim::configv2::MappingType::SameBankWeightedMapping => {
                     let (mapping, translated_csr) =
-                        translate_mapping::weighted::SameBankWeightedMapping::new(
-                            config.banks.num,
-                            config.channels.num,
-                            config.subarrays,
-                            row_evil_threshold,
-                            config.columns,
-                            &matrix_tri,
-                            &matrix_tri.to_csr(),
-                        );
+                        build_with_cache(config, &matrix_csr, row_evil_threshold, || {
+                            translate_mapping::weighted::SameBankWeightedMapping::new(
+                                config.banks.num,
+                                config.channels.num,
+                                config.subarrays,
+                                row_evil_threshold,
+                                config.columns,
+                                &matrix_tri,
+                                &matrix_csr,
+                            )
+                        });
                     // after created the mapping, there will be 2 copy of the matrix remained
+                    drop(matrix_csr);
                     matrix_guard.pop().unwrap();
                     matrix_guard.pop().unwrap();
                     matrix_guard.pop().unwrap();
-                    run_with_mapping(
+                    runner.run_translated(
+                        graph,
                         &mapping,
-                        &config,
+                        config,
                         translated_csr.view(),
-                        Spmm::new(translated_csr.view()),
-                        Some(MAX_RUN_ROUNDS),
+                        &graph_cancel,
                     )?
                 }
             };
@@ -785,22 +1400,16 @@ pub(crate) fn run_simulation(config: ConfigV3) -> eyre::Result<()> {
             // free the matrix guard
             drop(matrix_guard);
             info!("finish graph: {}", graph);
-            Ok(result)
+            Ok((result, memory_profile.finish()))
         })
         .collect();
-    let total_graph_results = total_graph_results
+    let (results, memory) = total_graph_results
         .into_iter()
-        .map(|r| r.wrap_err("fail to run experiemnt").unwrap())
-        .collect_vec();
-
-    // let mut simualtor = RealJumpSimulator;
-    // let result = simualtor.run(matrix_tri_translated, filter)?;
-    serde_json::to_writer_pretty(
-        BufWriter::new(File::create(&config.output_path)?),
-        &total_graph_results,
-    )?;
-
-    Ok(())
+        .map(|r| r.wrap_err("fail to run experiemnt"))
+        .collect::<eyre::Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    Ok(SimulationOutput { results, memory })
 }
 #[derive(Default)]
 struct EvilColHandler {
@@ -833,10 +1442,67 @@ pub struct RealJumpResult {
     pub row_cycles: AllJumpCycles,
     pub dispatcher_sending_cycle: usize,
     pub dispatcher_reading_cycle: usize,
+    /// the cycles to deliver the remote packets through `bank_interconnect` in the config, 0 when it's not set
+    #[serde(default)]
+    pub dispatcher_network_cycle: usize,
+    /// the cycles and the congestion of each level of `hierarchical_network` in the config
+    #[serde(default)]
+    pub hierarchy: Option<HierarchyReport>,
     // pub real_cycle: [usize; 7],
     pub real_local_cycle: [usize; NUM_JUMP_CYCLES],
+    /// why the simulation stopped before the last row, `None` when it finished
+    #[serde(default)]
+    pub stopped: Option<CancelReason>,
+}
+impl RealJumpResult {
+    /// accumulate the result of another run into this one
+    pub fn add(&mut self, other: &RealJumpResult) {
+        update_row_cycle(
+            std::slice::from_ref(&other.local_dense_col_cycles),
+            &mut self.local_dense_col_cycles,
+        );
+        update_row_cycle(
+            std::slice::from_ref(&other.remote_dense_col_cycles),
+            &mut self.remote_dense_col_cycles,
+        );
+        update_row_cycle(
+            std::slice::from_ref(&other.evil_row_cycles),
+            &mut self.evil_row_cycles,
+        );
+        update_row_cycle(
+            std::slice::from_ref(&other.row_cycles),
+            &mut self.row_cycles,
+        );
+        self.dispatcher_sending_cycle += other.dispatcher_sending_cycle;
+        self.dispatcher_reading_cycle += other.dispatcher_reading_cycle;
+        self.dispatcher_network_cycle += other.dispatcher_network_cycle;
+        if let Some(hierarchy) = &other.hierarchy {
+            self.hierarchy
+                .get_or_insert_with(Default::default)
+                .add(hierarchy);
+        }
+        self.real_local_cycle
+            .iter_mut()
+            .zip(other.real_local_cycle)
+            .for_each(|(r, o)| *r += o);
+        self.stopped = self.stopped.or(other.stopped);
+    }
 }
 pub const MAX_RUN_ROUNDS: usize = 10000;
+
+/// report the progress to the control server and write the partial result when it's requested,
+/// a failed dump should not stop the simulation
+pub(crate) fn report_progress<R: Serialize>(
+    progress: &mut TaskProgress,
+    processed: usize,
+    result: &R,
+) {
+    if let Some(path) = progress.update(processed) {
+        if let Err(e) = control::write_partial(&path, result) {
+            error!("failed to write partial result: {}", e);
+        }
+    }
+}
 impl super::Simulator for RealJumpSimulator {
     type R = RealJumpResult;
     fn run(
@@ -845,13 +1511,38 @@ impl super::Simulator for RealJumpSimulator {
         csr_translated: CsMatViewI<Pattern, u32>,
         mut algorithm: impl SpmvAlgorithm,
         max_rounds: Option<usize>,
+        cancel: &CancellationToken,
     ) -> eyre::Result<Self::R> {
         let start_time = Instant::now();
         let mut next_print_time = Duration::from_secs(60);
         let mut result = RealJumpResult::default();
         let total_rows = csr_translated.rows();
         let mut target_id = 0;
+        if let Some(checkpoint) = self.load_checkpoint()? {
+            if checkpoint.finished {
+                return Ok(checkpoint.state);
+            }
+            result = checkpoint.state;
+            result.stopped = None;
+            // the algorithm is deterministic, replay it to the saved frontier
+            for _ in 0..checkpoint.target_id {
+                algorithm.next_frontier();
+            }
+            target_id = checkpoint.target_id;
+        }
+        let mut progress = self.register_progress(max_rounds.unwrap_or(total_rows), cancel);
         while let Some(target_row) = algorithm.next_frontier() {
+            report_progress(&mut progress, target_id, &result);
+            if let Some(reason) = cancel.cancel_reason() {
+                info!(
+                    "{:?} after {} rows, return the partial result",
+                    reason, target_id
+                );
+                result.stopped = Some(reason);
+                cancel::record_partial_run();
+                self.checkpoint_now(target_id, &result)?;
+                return Ok(result);
+            }
             if (target_id + 1) % 1000 == 0 {
                 let elapsed = start_time.elapsed();
                 let remaining =
@@ -874,20 +1565,25 @@ impl super::Simulator for RealJumpSimulator {
                 }
             }
             match target_row {
-                FrontierType::All => {
-                    self.process_one_fullrow(csr_translated.view(), mapping, target_id, &mut result)
-                }
+                FrontierType::All => self.process_one_fullrow(
+                    csr_translated.view(),
+                    mapping,
+                    target_id,
+                    &mut result,
+                )?,
                 FrontierType::Some(target_row) => self.process_one_row(
                     target_row.view(),
                     csr_translated.view(),
                     mapping,
                     target_id,
                     &mut result,
-                ),
+                )?,
             };
 
             target_id += 1;
+            self.checkpoint_if_due(target_id, &result)?;
         }
+        self.checkpoint_finished(target_id, &result)?;
 
         Ok(result)
     }
@@ -0,0 +1,250 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-19
+//! Description: drive the real jump simulator with the access traces in `graph_path` instead of running the
+//! algorithms, see [`crate::algorithms::replay`]. the ids of the trace are used as they are, so the mapping is
+//! built on matrix b of the trace without translating it again. a trace recorded by `access_trace_dir` gives the
+//! same result as the recorded run, it can only be replayed with the recorded mapping, channels, banks, subarrays
+//! and columns because its ids are translated for them.
+use std::{fs::File, io::BufWriter, mem::size_of, time::Duration};
+
+use eyre::{bail, Context};
+use rayon::prelude::*;
+use sprs::{num_kinds::Pattern, CsMatViewI};
+use tracing::info;
+
+use crate::{
+    algorithms::replay::{AccessRound, AccessTrace, TraceHardware},
+    analysis::{
+        remap_analyze::real_jump::{
+            memory_sections, run_with_mapping_recorded, RealJumpResult, SimulationOutput,
+            SimulationRecorders,
+        },
+        translate_mapping::{
+            same_bank::SameBankMapping, weighted::SameBankWeightedMapping, AverageMapping,
+            CachedTranslateMapping,
+        },
+        write_memory_reports, EVIL_RATE,
+    },
+    pim::configv2::{ConfigV3, MappingType},
+    tools::{
+        cancel::{self, CancellationToken},
+        memory_profile,
+    },
+};
+
+/// build the mapping on the already translated matrix b and replay the trace
+fn replay_with<M: CachedTranslateMapping>(
+    config: &ConfigV3,
+    graph: &str,
+    trace: &AccessTrace,
+    matrix_b: CsMatViewI<Pattern, u32>,
+    cancel: &CancellationToken,
+) -> eyre::Result<RealJumpResult> {
+    let row_evil_threshold = ((matrix_b.rows() as f32 * EVIL_RATE) as usize).max(1);
+    let total_subarrays = config.channels.num * config.banks.num * config.subarrays;
+    let mapping = M::from_row_sub_mapping(
+        AverageMapping::new(
+            matrix_b,
+            row_evil_threshold,
+            total_subarrays,
+            config.columns,
+        ),
+        row_evil_threshold,
+    );
+    run_with_mapping_recorded(
+        &mapping,
+        config,
+        matrix_b,
+        trace.replay(),
+        None,
+        SimulationRecorders::for_graph(config, graph, "replay")?,
+        cancel,
+    )
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-19
+/// Description: replay a single trace
+pub fn replay_trace(
+    config: &ConfigV3,
+    graph: &str,
+    cancel: &CancellationToken,
+) -> eyre::Result<RealJumpResult> {
+    replay_access_trace(config, graph, &AccessTrace::read(graph)?, cancel)
+}
+
+/// replay the parsed trace of `graph`
+fn replay_access_trace(
+    config: &ConfigV3,
+    graph: &str,
+    trace: &AccessTrace,
+    cancel: &CancellationToken,
+) -> eyre::Result<RealJumpResult> {
+    if let Some(recorded) = trace.hardware() {
+        let hardware = TraceHardware::from_config(config);
+        if *recorded != hardware {
+            bail!(
+                "the trace {} is recorded with `{}` but replayed with `{}`, the ids of the trace are translated for \
+                 the recorded mapping, channels, banks, subarrays and columns",
+                graph,
+                recorded,
+                hardware
+            );
+        }
+    }
+    let matrix_b = trace.matrix_b();
+    if matrix_b.rows() != matrix_b.cols() {
+        bail!(
+            "matrix b of the trace should be square, got {}x{}",
+            matrix_b.rows(),
+            matrix_b.cols()
+        );
+    }
+    info!("replay {} rounds of trace: {}", trace.rounds().len(), graph);
+    match config.mapping {
+        MappingType::SameSubarray => {
+            bail!("SameSubarray is not supported by the real jump simulation")
+        }
+        MappingType::SameBank => {
+            replay_with::<SameBankMapping>(config, graph, trace, matrix_b, cancel)
+        }
+        MappingType::SameBankWeightedMapping => {
+            replay_with::<SameBankWeightedMapping>(config, graph, trace, matrix_b, cancel)
+        }
+    }
+}
+
+/// replay all traces of the config
+pub(crate) fn simulate_traces(config: &ConfigV3) -> eyre::Result<SimulationOutput<RealJumpResult>> {
+    info!("start replay");
+    let config_cancel =
+        cancel::root().child_with_timeout(config.deadline_secs.map(Duration::from_secs));
+    let total_graph_results: Vec<eyre::Result<_>> = config
+        .graph_path
+        .par_iter()
+        .map(|graph| {
+            let graph_cancel = config_cancel
+                .child_with_timeout(config.graph_deadline_secs.map(Duration::from_secs));
+            // the size of the file says nothing about a compressed trace, so the memory is estimated from the
+            // parsed trace, which is kept with the simulator until the replay finishes
+            let trace = AccessTrace::read(graph)?;
+            let matrix_b = trace.matrix_b();
+            let round_size = trace
+                .rounds()
+                .iter()
+                .map(|access| match access {
+                    AccessRound::All => 0,
+                    AccessRound::Rows(rows) => rows.len() * size_of::<u32>(),
+                })
+                .sum::<usize>() as u64;
+            let sections = memory_sections(config, matrix_b.rows(), matrix_b.nnz());
+            let estimated_bytes = sections.iter().sum::<u64>() + round_size;
+            let _guard = crate::try_acquire_memory_sections_with_priority(
+                [estimated_bytes],
+                config.memory_priority,
+            )
+            .wrap_err(format!("fail to acquire the memory for trace {}", graph))?;
+            let memory_profile =
+                memory_profile::start_job(graph, estimated_bytes, _guard[0].waited());
+            let result = replay_access_trace(config, graph, &trace, &graph_cancel)?;
+            info!("finish trace: {}", graph);
+            Ok((result, memory_profile.finish()))
+        })
+        .collect();
+    let (results, memory) = total_graph_results
+        .into_iter()
+        .map(|r| r.wrap_err("fail to replay trace"))
+        .collect::<eyre::Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    Ok(SimulationOutput { results, memory })
+}
+
+pub(crate) fn run_simulation(config: ConfigV3) -> eyre::Result<()> {
+    let output = simulate_traces(&config)?;
+    serde_json::to_writer_pretty(
+        BufWriter::new(File::create(&config.output_path)?),
+        &output.results,
+    )?;
+    write_memory_reports(&config.output_path, &output.memory)?;
+    Ok(())
+}
+
+/// the results of all traces, in json
+pub(crate) fn simulate_json(config: &ConfigV3) -> eyre::Result<serde_json::Value> {
+    Ok(serde_json::to_value(simulate_traces(config)?.results)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use sprs::{num_kinds::Pattern, CsMatI, TriMatI};
+
+    use super::*;
+    use crate::{algorithms::spmm::Spmm, pim::configv2::LevelConfig};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_replay_recorded_trace() -> eyre::Result<()> {
+        let matrix_tri: TriMatI<Pattern, u32> =
+            sprs::io::read_matrix_market("test_mtx/test_large.mtx")?;
+        let matrix: CsMatI<Pattern, u32> = matrix_tri.to_csr();
+        let dir = std::env::temp_dir().join("spmspm_replay_test");
+        let config = ConfigV3 {
+            subarrays: 4,
+            columns: 256,
+            remap_cycle: 3,
+            channels: LevelConfig {
+                num: 1,
+                ..Default::default()
+            },
+            banks: LevelConfig {
+                num: 2,
+                ..Default::default()
+            },
+            mapping: MappingType::SameBank,
+            access_trace_dir: Some(dir.clone()),
+            ..Default::default()
+        };
+        // the same threshold as the simulations of the graphs
+        let row_evil_threshold = ((matrix.rows() as f32 * EVIL_RATE) as usize).max(1);
+        let (mapping, translated_csr) = SameBankMapping::new(
+            config.banks.num,
+            config.channels.num,
+            config.subarrays,
+            row_evil_threshold,
+            config.columns,
+            &matrix_tri,
+            &matrix,
+        );
+        let recorded = run_with_mapping_recorded(
+            &mapping,
+            &config,
+            translated_csr.view(),
+            Spmm::new(translated_csr.view()),
+            Some(20),
+            SimulationRecorders::for_graph(&config, "test_large.mtx", "spmm")?,
+            &CancellationToken::new(),
+        )?;
+
+        let trace = dir.join("test_large_SameBank_spmm.access");
+        let config = ConfigV3 {
+            access_trace_dir: None,
+            ..config
+        };
+        let replayed = replay_trace(&config, trace.to_str().unwrap(), &CancellationToken::new())?;
+        assert_eq!(
+            serde_json::to_value(&recorded)?,
+            serde_json::to_value(&replayed)?
+        );
+        // the ids are translated for 4 subarrays
+        let config = ConfigV3 {
+            subarrays: 8,
+            ..config
+        };
+        assert!(replay_trace(&config, trace.to_str().unwrap(), &CancellationToken::new()).is_err());
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-05
+//! Description: record the statistics of each round of the real jump simulation into a json-lines file,
+//! so the phase behaviour of the algorithms can be seen without the debug logs
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{pim::configv2::MappingType, tools::graph_reader};
+
+/// the statistics of a single round(one frontier)
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct RoundStats {
+    pub round: usize,
+    /// the number of rows in the frontier
+    pub frontier_size: usize,
+    /// the max local cycle of all subarrays, for each jump type
+    pub max_local_cycle: Vec<usize>,
+    /// the max sending cycle of all dispatchers
+    pub dispatcher_sending_cycle: usize,
+    /// the max receiving cycle of all dispatchers
+    pub dispatcher_reading_cycle: usize,
+    /// the cycles of the bank network, 0 when it's not set
+    #[serde(default)]
+    pub dispatcher_network_cycle: usize,
+    /// the max evil row cycle of all subarrays, for each jump type
+    pub evil_row_cycles: Vec<usize>,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-05
+/// Description: write one [`RoundStats`] per line, the line is flushed after each round
+/// so the file can be watched while the simulation is still running
+pub struct TimeSeriesRecorder {
+    writer: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl TimeSeriesRecorder {
+    pub fn create(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .wrap_err(format!("fail to create dir: {:?}", parent))?;
+        }
+        let file =
+            File::create(&path).wrap_err(format!("fail to create time series: {:?}", path))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            path,
+        })
+    }
+
+    /// open an existing time series and append to it, used when resuming from a checkpoint
+    pub fn open_append(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .wrap_err(format!("fail to open time series: {:?}", path))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            path,
+        })
+    }
+
+    /// create the recorder at `{dir}/{graph}_{mapping}_{name}.jsonl`,
+    /// when `resume` is set, the existing file is kept
+    pub fn for_graph(
+        dir: impl AsRef<Path>,
+        graph: &str,
+        mapping: &MappingType,
+        name: &str,
+        resume: bool,
+    ) -> eyre::Result<Self> {
+        let graph_name = graph_reader::entry_file_stem(graph);
+        let file_name = format!("{graph_name}_{mapping:?}_{name}.jsonl");
+        let path = dir.as_ref().join(file_name);
+        if resume && path.exists() {
+            Self::open_append(path)
+        } else {
+            Self::create(path)
+        }
+    }
+
+    /// drop all rounds after `next_round`, those rounds were written after the last checkpoint
+    /// and will be simulated again
+    pub fn truncate_to(&mut self, next_round: usize) -> eyre::Result<()> {
+        self.writer.flush()?;
+        let kept = read_time_series(&self.path)?
+            .into_iter()
+            .filter(|stats| stats.round < next_round)
+            .collect::<Vec<_>>();
+        *self = Self::create(&self.path)?;
+        for stats in &kept {
+            self.record(stats)?;
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, stats: &RoundStats) -> eyre::Result<()> {
+        serde_json::to_writer(&mut self.writer, stats)?;
+        self.writer.write_all(b"\n")?;
+        self.writer
+            .flush()
+            .wrap_err(format!("fail to write time series: {:?}", self.path))?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// read all rounds from a time series file
+pub fn read_time_series(path: impl AsRef<Path>) -> eyre::Result<Vec<RoundStats>> {
+    let content = std::fs::read_to_string(path)?;
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_read() -> eyre::Result<()> {
+        let path = std::env::temp_dir().join("spmspm_time_series_test.jsonl");
+        let rounds = (0..3)
+            .map(|round| RoundStats {
+                round,
+                frontier_size: round * 2,
+                max_local_cycle: vec![round; 4],
+                dispatcher_sending_cycle: round + 1,
+                dispatcher_reading_cycle: round + 2,
+                dispatcher_network_cycle: round + 3,
+                evil_row_cycles: vec![round * 3; 4],
+            })
+            .collect::<Vec<_>>();
+        {
+            let mut recorder = TimeSeriesRecorder::create(&path)?;
+            for round in &rounds {
+                recorder.record(round)?;
+            }
+        }
+        assert_eq!(read_time_series(&path)?, rounds);
+
+        let mut recorder = TimeSeriesRecorder::open_append(&path)?;
+        recorder.truncate_to(2)?;
+        recorder.record(&rounds[2])?;
+        assert_eq!(read_time_series(&path)?, rounds);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
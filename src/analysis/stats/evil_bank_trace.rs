@@ -1,5 +1,12 @@
-#![allow(unused)]
-
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-16
+//! Description: count the accesses of each bank by the gearbox simulation, the accesses are split by how the
+//! row of matrix b is distributed(evil row, evil col, local or remote), so the banks that hold the evil rows can be
+//! found. a bank serves one access per cycle, the cycle of a round is the accesses of its busiest bank.
+//! the upstream simulator only had `todo!()` stubs and swapped `get_evil_row_ids`/`get_evil_col_ids`, this counting
+//! model was written with the checkpoint support so the resume could be tested, it's not calibrated against the
+//! other gearbox analyses.
 use std::collections::BTreeSet;
 
 use crate::analysis::remap_analyze::row_cycle::*;
@@ -12,28 +19,81 @@ use crate::{
         traits::{AnalyzeTool, DistributeType, GearboxSimTrait},
     },
     pim::configv2::ConfigV2,
+    tools::dram_trace::DramTrace,
 };
-#[derive(Serialize, Deserialize, Default, Clone, Debug)]
-pub struct SingleResult;
+
+/// the accesses of a bank
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+pub struct BankAccesses {
+    pub evil_row: usize,
+    pub evil_col: usize,
+    pub local: usize,
+    pub remote: usize,
+}
+
+impl BankAccesses {
+    fn add(&mut self, distribute_type: DistributeType) {
+        match distribute_type {
+            DistributeType::EvilRow => self.evil_row += 1,
+            DistributeType::EvilCol => self.evil_col += 1,
+            DistributeType::Local => self.local += 1,
+            DistributeType::Remote => self.remote += 1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct SingleResult {
+    pub name: String,
+    pub batch: usize,
+    pub topk: f32,
+    /// the accesses of each bank
+    pub banks: Vec<BankAccesses>,
+    /// the sum of the cycles of all finished rounds
+    pub cycle: u64,
+    pub rounds: usize,
+}
+
+/// the state of the simulator, it's saved in the checkpoint
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+struct BankTraceState {
+    result: SingleResult,
+    /// the accesses of each bank in the current round
+    round_accesses: Vec<usize>,
+}
 
 pub struct AnalyzeBankTrace;
 pub struct Simulator<'m, 'c, T> {
     mapping: T,
-    result: SingleResult,
+    state: BankTraceState,
     matrix_b: &'m CsMatI<Pattern, u32>,
     config: &'c ConfigV2,
     evil_col_ids: BTreeSet<usize>,
     evil_row_ids: BTreeSet<usize>,
+    dram_trace: Option<DramTrace>,
 }
 
 impl AnalyzeTool for AnalyzeBankTrace {
     type ResultType = SingleResult;
 
-    type GearboxSimType<'m,'c,T> = Simulator<'m,'c,T> where T: Mapping;
+    type GearboxSimType<'m, 'c, T>
+        = Simulator<'m, 'c, T>
+    where
+        T: Mapping;
 
     const SUBARRAY_SIZE: usize = 0;
 }
 
+impl<'m, 'c, T> Simulator<'m, 'c, T>
+where
+    T: Mapping,
+{
+    /// the bank that holds the row of matrix b
+    fn bank_of(&self, mat_b_row_id: LogicRowId) -> usize {
+        self.mapping.get_matrix_b_location(mat_b_row_id).0 .0 / self.config.subarrays
+    }
+}
+
 impl<'m, 'c, T> GearboxSimTrait<'m, 'c> for Simulator<'m, 'c, T>
 where
     T: Mapping,
@@ -49,41 +109,87 @@ where
         config: &'c crate::pim::configv2::ConfigV2,
         mapping: Self::Mapping,
     ) -> Self {
+        let banks = config.channels.num * config.banks.num;
         Self {
             mapping,
-            result: Default::default(),
+            state: BankTraceState {
+                result: SingleResult {
+                    banks: vec![Default::default(); banks],
+                    ..Default::default()
+                },
+                round_accesses: vec![0; banks],
+            },
             matrix_b,
             config,
             evil_col_ids: evil_col_ids.into_iter().collect(),
             evil_row_ids: evil_row_ids.into_iter().collect(),
+            dram_trace: None,
         }
     }
     fn handle_distribute_default(
         &mut self,
-        target_id: LogicRowId,
+        _target_id: LogicRowId,
         mat_b_row_id: LogicRowId,
-        mat_b_col_id: LogicColId,
+        _mat_b_col_id: LogicColId,
         distribute_type: DistributeType,
     ) {
-        match distribute_type {
-            DistributeType::EvilRow => {
-                todo!()
-            }
-            DistributeType::EvilCol => todo!(),
-            DistributeType::Local => todo!(),
-            DistributeType::Remote => todo!(),
-        };
+        let bank = self.bank_of(mat_b_row_id);
+        self.state.result.banks[bank].add(distribute_type);
+        self.state.round_accesses[bank] += 1;
+    }
+
+    fn handle_update_stats(&mut self) -> u64 {
+        let busiest = self.state.round_accesses.iter().max().copied().unwrap_or(0) as u64;
+        self.state.result.cycle += busiest;
+        self.state.result.rounds += 1;
+        self.state.round_accesses.fill(0);
+        busiest
     }
-    fn report(&self, _name: String, _batch: usize, _topk: f32) -> Self::SingleResult {
-        self.result.clone()
+
+    fn dram_trace(&mut self) -> Option<&mut DramTrace> {
+        self.dram_trace.as_mut()
+    }
+
+    fn set_dram_trace(&mut self, trace: DramTrace) {
+        self.dram_trace = Some(trace);
+    }
+
+    fn supports_checkpoint(&self) -> bool {
+        true
+    }
+
+    fn checkpoint_state(&self) -> Option<serde_json::Value> {
+        Some(serde_json::to_value(&self.state).expect("the state is always serializable"))
+    }
+
+    fn restore_checkpoint_state(&mut self, state: serde_json::Value) -> eyre::Result<()> {
+        let state: BankTraceState = serde_json::from_value(state)?;
+        if state.round_accesses.len() != self.state.round_accesses.len() {
+            eyre::bail!(
+                "the checkpoint has {} banks, but the config has {}",
+                state.round_accesses.len(),
+                self.state.round_accesses.len()
+            );
+        }
+        self.state = state;
+        Ok(())
+    }
+
+    fn report(&self, name: String, batch: usize, topk: f32) -> Self::SingleResult {
+        SingleResult {
+            name,
+            batch,
+            topk,
+            ..self.state.result.clone()
+        }
     }
 
     fn get_evil_row_ids(&self) -> usize {
-        self.evil_col_ids.len()
+        self.evil_row_ids.len()
     }
 
     fn get_evil_col_ids(&self) -> usize {
-        self.evil_row_ids.len()
+        self.evil_col_ids.len()
     }
 
     fn get_mapping(&self) -> &Self::Mapping {
@@ -102,3 +208,148 @@ where
         self.matrix_b
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::mapping::same_bank::SameBankMapping,
+        pim::config_migration::parse_config,
+        tools::{
+            cancel::CancellationToken,
+            checkpoint::{Checkpoint, Checkpointer},
+            dram_trace::DramTraceConfig,
+            graph_generator::GraphGenerator,
+        },
+    };
+
+    use super::*;
+
+    const EVIL_ROWS: [usize; 2] = [0, 2];
+
+    fn test_config() -> eyre::Result<ConfigV2> {
+        Ok(parse_config(include_str!("../../../configs/real_jump_test.toml"))?.into())
+    }
+
+    /// a 1000x64 matrix a and a 64x64 matrix b
+    fn test_matrices() -> eyre::Result<(CsMatI<Pattern, u32>, CsMatI<Pattern, u32>)> {
+        let matrix_a = GraphGenerator::parse("er:n=1000,m=64,d=4")?
+            .unwrap()
+            .generate()
+            .to_csr();
+        let matrix_b = GraphGenerator::parse("er:n=64,d=8")?
+            .unwrap()
+            .generate()
+            .to_csr();
+        Ok((matrix_a, matrix_b))
+    }
+
+    fn new_simulator<'m, 'c>(
+        config: &'c ConfigV2,
+        matrix_b: &'m CsMatI<Pattern, u32>,
+    ) -> Simulator<'m, 'c, SameBankMapping> {
+        let mapping = SameBankMapping::new(
+            config.banks.num,
+            config.channels.num,
+            config.subarrays,
+            config.columns,
+            matrix_b,
+        );
+        Simulator::new([1, 3], EVIL_ROWS, matrix_b, config, mapping)
+    }
+
+    fn run(
+        config: &ConfigV2,
+        matrix_a: &CsMatI<Pattern, u32>,
+        matrix_b: &CsMatI<Pattern, u32>,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<SingleResult> {
+        let mut sim = new_simulator(config, matrix_b);
+        sim.run_checkpointed(matrix_a, 4, 0.1, config, "test_resume", cancel)?;
+        Ok(sim.report("test_resume".to_string(), 4, 0.1))
+    }
+
+    #[test]
+    fn test_resume() -> eyre::Result<()> {
+        let mut config = test_config()?;
+        let dir = std::env::temp_dir().join("spmspm_evil_bank_trace_test");
+        std::fs::remove_dir_all(&dir).ok();
+        config.checkpoint_dir = Some(dir.clone());
+        // only the stop writes a checkpoint
+        config.checkpoint_interval = 3600;
+        let (matrix_a, matrix_b) = test_matrices()?;
+
+        let expected = run(&config, &matrix_a, &matrix_b, &CancellationToken::new())?;
+        assert!(expected.cycle > 0);
+        assert_eq!(expected.rounds, 1000 / 4);
+
+        // the cancel is found at row 10, the third batch is finished before saving
+        std::fs::remove_dir_all(&dir).ok();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let stopped = run(&config, &matrix_a, &matrix_b, &cancel)?;
+        assert!(stopped.rounds < expected.rounds);
+        let checkpointer = Checkpointer::for_graph(
+            &dir,
+            "test_resume",
+            &format!("{:?}_4_0.1", config.mapping),
+            Default::default(),
+            true,
+        )
+        .with_fingerprint(config.checkpoint_fingerprint());
+        let checkpoint: Checkpoint<serde_json::Value> = checkpointer.load()?.unwrap();
+        assert_eq!(checkpoint.target_id, 12);
+        assert!(!checkpoint.finished);
+
+        config.resume = true;
+        let resumed = run(&config, &matrix_a, &matrix_b, &CancellationToken::new())?;
+        assert_eq!(resumed, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dram_trace() -> eyre::Result<()> {
+        let config = test_config()?;
+        let (matrix_a, matrix_b) = test_matrices()?;
+        let dir = std::env::temp_dir().join("spmspm_evil_bank_trace_dram_trace_test");
+        std::fs::remove_dir_all(&dir).ok();
+        let total_size = crate::pim::level::ddr4::Storage::new(
+            config.channels.num,
+            config.ranks.num,
+            config.chips.num,
+            config.bank_groups.num,
+            config.banks.num,
+            config.subarrays,
+            config.rows,
+            config.columns,
+        );
+        let trace_config = DramTraceConfig {
+            dir: dir.clone(),
+            format: Default::default(),
+        };
+        let trace = DramTrace::for_graph(&trace_config, total_size, "test", "gearbox")?;
+        let trace_path = dir.join("test_gearbox.ramulator.trace");
+        let mut sim = new_simulator(&config, &matrix_b);
+        sim.set_dram_trace(trace);
+        sim.run(&matrix_a, 4, 0.1, &CancellationToken::new())?;
+        let result = sim.report("test".to_string(), 4, 0.1);
+        drop(sim);
+
+        // an evil row is read once, the other rows read and write each of their cols
+        let accesses = matrix_a
+            .indices()
+            .iter()
+            .map(|&row| {
+                let row = row as usize;
+                if EVIL_ROWS.contains(&row) {
+                    1
+                } else {
+                    2 * matrix_b.outer_view(row).unwrap().nnz()
+                }
+            })
+            .sum::<usize>();
+        let lines = std::fs::read_to_string(&trace_path)?;
+        assert_eq!(lines.lines().count(), accesses);
+        assert!(result.cycle > 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-12
+//! Description: run a config over the cartesian product of some of its fields.
+//! the fields are listed in the `[sweep]` table of the config, each with a list of values, the nested fields use
+//! the dotted keys:
+//! ```toml
+//! [sweep]
+//! subarrays = [16, 32, 64]
+//! mapping = ["SameBank", "SameBankWeightedMapping"]
+//! banks.num = [32, 64]
+//! ```
+//! each point of the product is a [`ConfigV3`], all points run in parallel under the memory limit of
+//! [`crate::try_acquire_memory_sections`], the results are written to the `output_path` of the config, keyed by the
+//! swept values like `mapping=SameBank,subarrays=16`.
+use std::{collections::BTreeMap, fs::File, io::BufWriter, path::Path};
+
+use eyre::{bail, eyre, Context};
+use rayon::prelude::*;
+use serde::Serialize;
+use toml::{map::Map, Value};
+use tracing::{error, info};
+
+use super::remap_analyze;
+use crate::pim::{
+    config_migration::migrate,
+    config_validation::{validate, ValidationTarget},
+    configv2::ConfigV3,
+};
+
+/// a config of the sweep
+#[derive(Debug, Clone)]
+pub struct SweepPoint {
+    /// the swept values, like `mapping=SameBank,subarrays=16`
+    pub key: String,
+    /// the swept fields and their values in this point
+    pub params: BTreeMap<String, Value>,
+    pub config: ConfigV3,
+}
+
+/// the result of a point, a failed point has the error instead of the results
+#[derive(Debug, Serialize)]
+pub struct SweepEntry {
+    pub params: BTreeMap<String, Value>,
+    /// the results of all graphs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// collect the swept fields, the lists are the values and the tables are the nested fields
+fn collect_axes(
+    prefix: &str,
+    table: Map<String, Value>,
+    axes: &mut Vec<(String, Vec<Value>)>,
+) -> eyre::Result<()> {
+    for (name, value) in table {
+        let path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        match value {
+            Value::Array(values) => {
+                if values.is_empty() {
+                    bail!("sweep.{}: should not be empty", path);
+                }
+                axes.push((path, values));
+            }
+            Value::Table(table) => collect_axes(&path, table, axes)?,
+            other => bail!("sweep.{}: should be a list of values, got {}", path, other),
+        }
+    }
+    Ok(())
+}
+
+/// set the field at the dotted path, the parent tables should already exist
+fn set_field(config: &mut Value, path: &str, value: Value) -> eyre::Result<()> {
+    let mut names = path.split('.').collect::<Vec<_>>();
+    let last = names.pop().ok_or_else(|| eyre!("empty sweep path"))?;
+    let mut table = config
+        .as_table_mut()
+        .ok_or_else(|| eyre!("the config should be a table"))?;
+    for name in names {
+        table = table
+            .get_mut(name)
+            .and_then(Value::as_table_mut)
+            .ok_or_else(|| eyre!("sweep.{}: {} is not a table of the config", path, name))?;
+    }
+    table.insert(last.to_string(), value);
+    Ok(())
+}
+
+fn value_to_key(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-12
+/// Description: expand the config of any version into the points of its sweep, a config without `[sweep]` is a
+/// single point with an empty key
+pub fn parse_sweep(content: &str) -> eyre::Result<Vec<SweepPoint>> {
+    let mut base: Value = toml::from_str(content).wrap_err("the config is not a valid toml")?;
+    migrate(&mut base)?;
+    let sweep = base
+        .as_table_mut()
+        .ok_or_else(|| eyre!("the config should be a table"))?
+        .remove("sweep");
+    let mut axes = vec![];
+    match sweep {
+        Some(Value::Table(table)) => collect_axes("", table, &mut axes)?,
+        Some(other) => bail!("sweep should be a table, got {}", other),
+        None => {}
+    }
+
+    let total = axes
+        .iter()
+        .map(|(_, values)| values.len())
+        .product::<usize>();
+    let mut points = Vec::with_capacity(total);
+    for index in 0..total {
+        // the last axis changes fastest
+        let mut rest = index;
+        let mut params = BTreeMap::new();
+        for (path, values) in axes.iter().rev() {
+            params.insert(path.clone(), values[rest % values.len()].clone());
+            rest /= values.len();
+        }
+        let key = params
+            .iter()
+            .map(|(path, value)| format!("{}={}", path, value_to_key(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut value = base.clone();
+        for (path, param) in &params {
+            set_field(&mut value, path, param.clone())?;
+        }
+        let mut config: ConfigV3 = value
+            .try_into()
+            .wrap_err(format!("the sweep point {} does not match the schema", key))?;
+        // the points should not share the checkpoints, the time series and the traces of the same graph
+        if !key.is_empty() {
+            for dir in [
+                config.checkpoint_dir.as_mut(),
+                config.time_series_dir.as_mut(),
+                config.access_trace_dir.as_mut(),
+                config.dram_trace.as_mut().map(|trace| &mut trace.dir),
+                config.chrome_trace.as_mut().map(|trace| &mut trace.dir),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                *dir = dir.join(&key);
+            }
+        }
+        points.push(SweepPoint {
+            key,
+            params,
+            config,
+        });
+    }
+    Ok(points)
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-12
+/// Description: read the config and expand its sweep
+pub fn load_sweep(path: impl AsRef<Path>) -> eyre::Result<Vec<SweepPoint>> {
+    let path = path.as_ref();
+    let content =
+        std::fs::read_to_string(path).wrap_err(format!("fail to read config {:?}", path))?;
+    parse_sweep(&content).wrap_err(format!("fail to load the sweep of config {:?}", path))
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-12
+/// Description: validate all points first, then run them and write all results to one file
+pub fn run_sweep(path: impl AsRef<Path>, resume: bool) -> eyre::Result<()> {
+    let current_time = std::time::Instant::now();
+    let mut points = load_sweep(path)?;
+    let errors = points
+        .iter()
+        .filter_map(|point| {
+            validate(&point.config, ValidationTarget::RealJump)
+                .err()
+                .map(|e| format!("[{}] {}", point.key, e))
+        })
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        bail!("invalid sweep points:\n{}", errors.join("\n"));
+    }
+    let output_path = points
+        .first()
+        .map(|point| point.config.output_path.clone())
+        .ok_or_else(|| eyre!("the sweep has no point"))?;
+    for point in &mut points {
+        point.config.resume = resume;
+    }
+    info!("sweep {} points", points.len());
+
+    let results = points
+        .into_par_iter()
+        .map(|point| {
+            info!("run sweep point: {}", point.key);
+            let (results, error) = match remap_analyze::simulate(&point.config) {
+                Ok(results) => {
+                    info!("finish sweep point: {}", point.key);
+                    (Some(results), None)
+                }
+                Err(e) => {
+                    error!("fail to run sweep point {}: {:?}", point.key, e);
+                    (None, Some(format!("{:?}", e)))
+                }
+            };
+            (
+                point.key,
+                SweepEntry {
+                    params: point.params,
+                    results,
+                    error,
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+    // the finished points are written even when some points failed
+    serde_json::to_writer_pretty(BufWriter::new(File::create(&output_path)?), &results)?;
+    let failed = results
+        .iter()
+        .filter(|(_, entry)| entry.error.is_some())
+        .map(|(key, _)| key.as_str())
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        bail!(
+            "{} of {} sweep points failed, the errors are written to {:?}: {:?}",
+            failed.len(),
+            results.len(),
+            output_path,
+            failed
+        );
+    }
+    info!(
+        "sweep finished in {:?}, the results are written to {:?}",
+        current_time.elapsed(),
+        output_path
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pim::configv2::MappingType;
+
+    #[test]
+    fn test_parse_sweep() -> eyre::Result<()> {
+        let base = include_str!("../../configs/real_jump_test.toml");
+        let points = parse_sweep(base)?;
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].key, "");
+
+        let content = format!(
+            "checkpoint_dir = \"output/checkpoints\"\n{}\n[dram_trace]\ndir = \"output/dram_trace\"\n[sweep]\nsubarrays = [16, 32, 64]\nmapping = [\"SameBank\", \"SameBankWeightedMapping\"]\nbanks.num = [32, 64]\n",
+            base
+        );
+        let points = parse_sweep(&content)?;
+        assert_eq!(points.len(), 12);
+        assert_eq!(points[0].key, "banks.num=32,mapping=SameBank,subarrays=16");
+        assert_eq!(points[0].config.banks.num, 32);
+        assert_eq!(points[0].config.mapping, MappingType::SameBank);
+        assert_eq!(points[0].config.subarrays, 16);
+        let last = points.last().unwrap();
+        assert_eq!(last.config.banks.num, 64);
+        assert_eq!(last.config.mapping, MappingType::SameBankWeightedMapping);
+        assert_eq!(last.config.subarrays, 64);
+        assert_eq!(
+            points[0].config.checkpoint_dir.as_deref(),
+            Some(Path::new(
+                "output/checkpoints/banks.num=32,mapping=SameBank,subarrays=16"
+            ))
+        );
+        assert_eq!(
+            points[0].config.dram_trace.as_ref().unwrap().dir,
+            Path::new("output/dram_trace/banks.num=32,mapping=SameBank,subarrays=16")
+        );
+        let keys = points
+            .iter()
+            .map(|point| &point.key)
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(keys.len(), 12);
+
+        let empty = format!("{}\n[sweep]\nsubarrays = []\n", base);
+        assert!(parse_sweep(&empty).is_err());
+        let missing = format!("{}\n[sweep]\nnot_a_table.num = [1]\n", base);
+        assert!(parse_sweep(&missing).is_err());
+        let wrong_type = format!("{}\n[sweep]\nsubarrays = [\"many\"]\n", base);
+        assert!(parse_sweep(&wrong_type).is_err());
+        Ok(())
+    }
+}
@@ -20,20 +20,17 @@
 //!                 ||     ||
 //! ```
 use crate::analysis::mapping::*;
-use crate::tools::stop_signal;
-use crate::{
-    analysis::mapping::Mapping,
-    tools::{
-        crossbare_simulator::CrossBareSimulator,
-        crossbare_simulator_no_conflic::CrossBareSimulatorNoConflict,
-        ring_simulator::RingSimulator, CrossBarPacket, Direction, FlatInterleaveTrait, IcntPacket,
-    },
+use crate::tools::{
+    cancel::{self, CancellationToken},
+    control::TaskProgress,
+    graph_reader, matrix_cache,
 };
+use crate::{analysis::mapping::Mapping, tools::FlatInterleaveTrait};
 use hashbrown::HashSet;
 use itertools::Itertools;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use sprs::{io::MatrixHead, num_kinds::Pattern, CsMatI, TriMatI};
+use sprs::{num_kinds::Pattern, CsMatI};
 use statrs::statistics::*;
 use std::{
     cmp::Reverse,
@@ -44,6 +41,7 @@ use std::{
         atomic::{AtomicUsize, Ordering},
         RwLock,
     },
+    time::Duration,
 };
 use tracing::{debug, info};
 
@@ -116,7 +114,9 @@ impl Display for GearboxResult {
 }
 
 /// analyze the split spmm
-pub(crate) fn analyze_gearbox(config: &ConfigV2) -> Vec<((usize, f32), Vec<SingleResult>)> {
+pub(crate) fn analyze_gearbox(
+    config: &ConfigV2,
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>> {
     match config.dram_type {
         DramType::DDR3 => unimplemented!(),
         DramType::DDR4 => {
@@ -523,15 +523,6 @@ struct TsvReport {
     pub max_use_valid: usize,
     pub real_use: usize,
 }
-#[allow(dead_code)]
-#[derive(Debug, Default)]
-struct TsvReportV2 {
-    pub cycle_normal: usize,
-    pub cycle_no_conflict: usize,
-    pub max_use: usize,
-    pub max_use_valid: usize,
-    pub real_use: usize,
-}
 
 #[allow(dead_code)]
 fn compute_result(mut tsv_traffic: Vec<VecDeque<&RingTask>>) -> TsvReport {
@@ -597,93 +588,6 @@ fn get_ring_interleave(rings_tasks: Vec<&RingTasksInAllBanks>) -> Vec<Vec<VecDeq
         })
         .collect()
 }
-#[allow(dead_code)]
-struct RingTraffic<T> {
-    from: usize,
-    to: usize,
-    direction: Direction,
-    traffic: T,
-}
-#[allow(dead_code)]
-
-impl<T> RingTraffic<T> {
-    fn new(from: usize, to: usize, direction: Direction, traffic: T) -> Self {
-        Self {
-            from,
-            to,
-            direction,
-            traffic,
-        }
-    }
-}
-impl<T> IcntPacket for RingTraffic<T> {
-    fn get_source(&self) -> usize {
-        self.from
-    }
-
-    fn get_next_hop(&self) -> usize {
-        self.to
-    }
-
-    fn get_direction(&self) -> Direction {
-        self.direction
-    }
-}
-
-#[allow(dead_code)]
-struct CrossBarTraffic<T> {
-    from: usize,
-    to: usize,
-    traffic: T,
-}
-#[allow(dead_code)]
-impl<T> CrossBarTraffic<T> {
-    fn new(from: usize, to: usize, traffic: T) -> Self {
-        Self { from, to, traffic }
-    }
-}
-impl<T> CrossBarPacket for CrossBarTraffic<T> {
-    fn get_source(&self) -> usize {
-        self.from
-    }
-
-    fn get_dest(&self) -> usize {
-        self.to
-    }
-}
-
-trait CrossBarCommon<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T>;
-    fn pop(&mut self, node: usize) -> Option<T>;
-    fn cycle(&mut self);
-}
-
-impl<T: CrossBarPacket> CrossBarCommon<T> for CrossBareSimulator<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T> {
-        self.add(node, traffic)
-    }
-
-    fn pop(&mut self, node: usize) -> Option<T> {
-        self.pop(node)
-    }
-
-    fn cycle(&mut self) {
-        self.cycle()
-    }
-}
-impl<T: CrossBarPacket> CrossBarCommon<T> for CrossBareSimulatorNoConflict<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T> {
-        self.add(node, traffic)
-    }
-
-    fn pop(&mut self, node: usize) -> Option<T> {
-        self.pop(node)
-    }
-
-    fn cycle(&mut self) {
-        self.cycle()
-    }
-}
 
 impl<'a, MP: Mapping> Hardware<'a, MP> {
     #[allow(dead_code)]
@@ -700,171 +604,6 @@ impl<'a, MP: Mapping> Hardware<'a, MP> {
         tsv_traffic
     }
 
-    /// # situation
-    /// - when a round is finished, all traffic from each input port are ready to route to the other port
-    /// - the traffic is routed by a crossbar network in the base layer
-    /// # inbanlance reason
-    /// - the input traffic is not balanced
-    /// - the output traffic is not balanced
-    /// # solution
-    /// - need a score to represent the reason why usage is low
-    /// - the input reason
-    /// - the output reason
-    /// # TODO
-    /// - redesign the buffer to store the traffic from each subarray. then use the [flat_interleave] to
-    ///  route the traffic
-    #[allow(dead_code)]
-    fn calculate_tsv_traffic(&self) -> TsvReportV2 {
-        // the input traffic from each bank in the layer
-
-        // the old method is deprecated
-        // `compute_result(self.get_tsv_interleave())`
-
-        // first get the traffic for each bank and each ring
-        let rings_tasks = self.ring.iter().map(|r| &r.tasks).collect_vec();
-        let ring_bank_traffic: Vec<Vec<VecDeque<&RingTask>>> = get_ring_interleave(rings_tasks);
-        // second, build the hardware:
-        // - the ring simulator for each ring
-        let ports = self.config.channels.num;
-        let cycle_normal = {
-            self.calculate_icnt(
-                ring_bank_traffic.clone(),
-                CrossBareSimulator::new(ports, 16),
-            )
-        };
-        let cycle_no_conflict = {
-            self.calculate_icnt(
-                ring_bank_traffic,
-                CrossBareSimulatorNoConflict::new(ports, 16),
-            )
-        };
-        // println!("cycle: {}", cycle);
-        tracing::debug!("cycle: {cycle_normal} {cycle_no_conflict}");
-        TsvReportV2 {
-            cycle_normal,
-            cycle_no_conflict,
-            max_use: 0,
-            max_use_valid: 0,
-            real_use: 0,
-        }
-    }
-
-    fn calculate_icnt<Cross>(
-        &self,
-        mut ring_bank_traffic: Vec<Vec<VecDeque<&'a RingTask>>>,
-        mut crossbar_simulator: Cross,
-    ) -> usize
-    where
-        Cross: CrossBarCommon<CrossBarTraffic<&'a (RingPort, RingPort, (RingId, RingPort))>>,
-    {
-        // - the corssbar simulator for the base layer
-        let mut ring_simulators = (0..self.config.channels.num)
-            .map(|_ring_id| RingSimulator::new(self.config.banks.num, 128))
-            .collect_vec();
-
-        let mut tsv_buffer = (0..self.config.channels.num)
-            .map(|_| VecDeque::with_capacity(16))
-            .collect_vec();
-
-        let mut total_remain_traffic = 0;
-        let mut cycle = 0;
-        loop {
-            for (ring_id, (ring_sim, ring_traffic)) in ring_simulators
-                .iter_mut()
-                .zip(ring_bank_traffic.iter_mut())
-                .enumerate()
-            {
-                for (bank_id, bank_traffic) in ring_traffic.iter_mut().enumerate() {
-                    // first add the traffic to the input of the ring
-
-                    if let Some(traffic) = bank_traffic.pop_front() {
-                        // add the traffic to ring port  `bank_id`
-                        let from = traffic.0 .0 as usize;
-                        let to = traffic.1 .0 as usize;
-                        let nodes = self.config.banks.num;
-                        let right_distance = (to + nodes - from) % nodes;
-                        let left_distance = (from + nodes - to) % nodes;
-                        let direction = if right_distance < left_distance {
-                            Direction::Right
-                        } else {
-                            Direction::Left
-                        };
-                        match ring_sim.add(bank_id, RingTraffic::new(from, to, direction, traffic))
-                        {
-                            Ok(_) => {
-                                total_remain_traffic += 1;
-                            }
-                            Err(RingTraffic { traffic, .. }) => {
-                                bank_traffic.push_front(traffic);
-                            }
-                        }
-                    }
-                    // then pop the traffic from the output of the ring
-
-                    if let Some(RingTraffic { traffic, .. }) = ring_sim.front(bank_id) {
-                        if bank_id == 0 {
-                            // the tsv port
-                            // test if the traffic should go to other ring
-                            let target_ring_id = traffic.2 .0 .0;
-                            if ring_id == target_ring_id {
-                                // no!
-                                // do nothing and pop the traffic
-                                ring_sim.pop(bank_id);
-                                total_remain_traffic -= 1;
-                            } else {
-                                // yes, go to the target ring
-                                // push it to the tsv buffer
-                                if tsv_buffer[ring_id].len() < 16 {
-                                    let traffic = ring_sim.pop(bank_id).unwrap();
-                                    tsv_buffer[ring_id].push_back(traffic);
-                                    // do not need to decrease the total_remain_traffic because we will do it on pop of the crossbar
-                                } else {
-                                    // the buffer is full, do nothing
-                                }
-                            }
-                        } else {
-                            // just pop the traffic
-                            ring_sim.pop(bank_id).unwrap();
-                            total_remain_traffic -= 1;
-                        }
-                    }
-                }
-            }
-
-            // handle the tsv buffer traffic
-            for (tsv_id, tsv) in tsv_buffer.iter_mut().enumerate() {
-                if let Some(traffic) = tsv.pop_front() {
-                    let crossbare_packet =
-                        CrossBarTraffic::new(tsv_id, traffic.traffic.2 .0 .0, traffic.traffic);
-                    match crossbar_simulator.add(tsv_id, crossbare_packet) {
-                        Ok(_) => {
-                            // do nothing
-                        }
-                        Err(_) => {
-                            tsv.push_front(traffic);
-                        }
-                    }
-                }
-                // handle the crossbar traffic out
-                if let Some(_p) = crossbar_simulator.pop(tsv_id) {
-                    // the packet is finished
-                    total_remain_traffic -= 1;
-                }
-            }
-            // cycle the simulators
-            for ring_sim in ring_simulators.iter_mut() {
-                ring_sim.cycle();
-            }
-            crossbar_simulator.cycle();
-
-            cycle += 1;
-            if total_remain_traffic == 0 {
-                break;
-            }
-        }
-        cycle
-    }
-
     #[allow(unused)]
     fn subarrays(&self) -> usize {
         self.sub_array.len()
@@ -1076,6 +815,8 @@ impl<'a, 'b, MP: Mapping> GearboxSim<'a, 'b, MP> {
         input_vec: &CsMatI<Pattern, u32>,
         current_batch: usize,
         _current_topk: f32,
+        progress: &mut TaskProgress,
+        cancel: &CancellationToken,
     ) -> GlobalStatV2 {
         let mut global_stats = GlobalStatV2::default();
         let now = std::time::Instant::now();
@@ -1104,7 +845,9 @@ impl<'a, 'b, MP: Mapping> GearboxSim<'a, 'b, MP> {
                 // if next_print_time > 3000 {
                 //     break;
                 // }
-                if stop_signal::read() {
+                progress.update(target_id);
+                if cancel.is_cancelled() {
+                    cancel::record_partial_run();
                     info!("received stop signal, start writing results");
                     break;
                 }
@@ -1437,7 +1180,11 @@ fn update_stats<T>(hardware: &mut Hardware<T>, global_stats: &mut GlobalStatV2)
     }
 }
 
-fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
+fn compute_gearbox(
+    config: &ConfigV2,
+    path: &str,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<SingleResult>> {
     // for hbm config, they should be 1!
     assert!(config.ranks.num == 1);
     assert!(config.chips.num == 1);
@@ -1452,7 +1199,7 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
     info!(?partitions, "compute gearbox");
     info!("reading mtx file: {}", path);
     let read_time = std::time::Instant::now();
-    let matrix_head: MatrixHead<Pattern, u32> = sprs::io::read_matrix_market_head(path).unwrap();
+    let matrix_head = graph_reader::read_graph_head(path).unwrap();
     let matrix_size = matrix_head.ind_ptr_size() + matrix_head.ind_size() + matrix_head.data_size();
     // two csr csc matrix during runtime
     let matrix_size = matrix_size * 2;
@@ -1465,19 +1212,18 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
         "info there will be {} bytes,start acquire the space",
         sim_size + temp_size
     );
-    let mut _guard = crate::acquire_memory_sections(&[sim_size, temp_size]);
+    let mut _guard = crate::try_acquire_memory_sections([sim_size, temp_size])?;
     let _guard_temp = _guard.pop().unwrap();
     let _guard_sim = _guard.pop().unwrap();
 
-    let tri_mat: TriMatI<Pattern, u32> = sprs::io::read_matrix_market(path).unwrap();
+    let matrix = matrix_cache::load_or_build(path).unwrap();
     info!(
         "finished read the matrix: time:{:.2} secs",
         read_time.elapsed().as_secs_f32()
     );
     let (matrix_a, matrix_b): (CsMatI<Pattern, u32>, CsMatI<Pattern, u32>) =
-        (tri_mat.to_csr(), tri_mat.transpose_view().to_csr());
+        (matrix.csr, matrix.csc.transpose_into());
 
-    drop(tri_mat);
     drop(_guard_temp);
 
     info!(
@@ -1546,7 +1292,13 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
                         mapping,
                     );
                     info!("start running the sim");
-                    let result = gearbox.run(&matrix_a, batch, top_k);
+                    let mut progress = TaskProgress::register(
+                        format!("{path}_{batch}_{top_k}"),
+                        matrix_a.rows(),
+                        cancel,
+                    )
+                    .without_partial();
+                    let result = gearbox.run(&matrix_a, batch, top_k, &mut progress, cancel);
                     TOTAL_FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
                     info!(
                         "finished task: {}/{}",
@@ -1570,7 +1322,13 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
                         mapping,
                     );
                     info!("start running the sim");
-                    let result = gearbox.run(&matrix_a, batch, top_k);
+                    let mut progress = TaskProgress::register(
+                        format!("{path}_{batch}_{top_k}"),
+                        matrix_a.rows(),
+                        cancel,
+                    )
+                    .without_partial();
+                    let result = gearbox.run(&matrix_a, batch, top_k, &mut progress, cancel);
                     TOTAL_FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
                     info!(
                         "finished task: {}/{}",
@@ -1585,7 +1343,7 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
     drop(matrix_a);
     drop(matrix_b);
     drop(_guard_sim);
-    results
+    Ok(results)
 }
 pub fn transpose2<T>(v: Vec<Vec<T>>) -> Vec<Vec<T>> {
     assert!(!v.is_empty());
@@ -1607,7 +1365,7 @@ static TOTAL_TASKS: RwLock<usize> = RwLock::new(0);
 fn analyze_gearbox_inner<LevelType: LevelTrait>(
     config: &ConfigV2,
     _total_size: &LevelType::Storage,
-) -> Vec<((usize, f32), Vec<SingleResult>)>
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>>
 where
     LevelType::Storage: Debug + Sync,
     LevelType::Mapping: Debug,
@@ -1617,6 +1375,8 @@ where
     let total_tasks = total_graphs * total_configs;
     info!(?total_graphs, ?total_configs, ?total_tasks, "total tasks");
     *TOTAL_TASKS.write().unwrap() = total_tasks;
+    let config_cancel =
+        cancel::root().child_with_timeout(config.deadline_secs.map(Duration::from_secs));
 
     let results: Vec<_> = config
         .graph_path
@@ -1624,9 +1384,11 @@ where
         .enumerate()
         .map(|(index, path)| {
             info!("analyzing graph {}/{}", index + 1, total_graphs);
-            compute_gearbox(config, path)
+            let graph_cancel = config_cancel
+                .child_with_timeout(config.graph_deadline_secs.map(Duration::from_secs));
+            compute_gearbox(config, path, &graph_cancel)
         })
-        .collect();
+        .collect::<eyre::Result<_>>()?;
     let results = transpose2(results);
     let configs = config
         .gearbox_config
@@ -1637,7 +1399,7 @@ where
         .collect_vec();
     info!(?configs, "configs");
     assert_eq!(configs.len(), results.len());
-    configs.into_iter().zip(results).collect()
+    Ok(configs.into_iter().zip(results).collect())
     // GearboxResult { results }
 }
 
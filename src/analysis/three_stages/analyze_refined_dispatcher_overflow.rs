@@ -9,16 +9,28 @@
 //! - the dispatching stage send the received packages to local subarrays.
 //!  w
 use crate::{
-    analysis::{remap_analyze::row_cycle::*, RingTask, RingTasksInAllBanks},
+    analysis::{
+        remap_analyze::row_cycle::*,
+        traits::{
+            gearbox_dram_trace, load_gearbox_matrices, sorted_nnz, trace_read_write,
+            GearboxMatrices,
+        },
+        RingTask, RingTasksInAllBanks,
+    },
     tools::{
-        crossbare_simulator::CrossBareSimulator,
+        cancel::{self, CancellationToken},
+        control::TaskProgress,
         crossbare_simulator_no_conflic::CrossBareSimulatorNoConflict,
-        ring_simulator::RingSimulator, stop_signal, CrossBarPacket, Direction, FlatInterleaveTrait,
-        IcntPacket,
+        dram_trace::DramTrace,
+        interconnect::{
+            build_interconnect, build_interconnect_with_ring, drive_networks, packet_words,
+            Interconnect, InterconnectType,
+        },
+        mesh_simulator::{merge_link_stats, LinkStats},
+        CrossBarPacket, FlatInterleaveTrait,
     },
 };
 use std::{
-    cmp::Reverse,
     collections::{BTreeMap, BTreeSet, VecDeque},
     error::Error,
     fmt::{Debug, Display},
@@ -27,6 +39,7 @@ use std::{
         atomic::{AtomicUsize, Ordering},
         RwLock,
     },
+    time::Duration,
 };
 
 use hashbrown::HashSet;
@@ -34,7 +47,7 @@ use itertools::Itertools;
 use plotters::{coord::Shift, prelude::*};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use sprs::{io::MatrixHead, num_kinds::Pattern, CsMatI, TriMatI};
+use sprs::{num_kinds::Pattern, CsMatI};
 use tracing::{debug, info};
 
 use crate::{
@@ -107,7 +120,9 @@ impl Display for GearboxResult {
 }
 
 /// analyze the split spmm
-pub(crate) fn analyze_gearbox(config: &ConfigV2) -> Vec<((usize, f32), Vec<SingleResult>)> {
+pub(crate) fn analyze_gearbox(
+    config: &ConfigV2,
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>> {
     match config.dram_type {
         DramType::DDR3 => unimplemented!(),
         DramType::DDR4 => {
@@ -531,6 +546,8 @@ struct TsvReportV2 {
     pub max_use: usize,
     pub max_use_valid: usize,
     pub real_use: usize,
+    /// the usage of the links of the bank network in each channel
+    pub bank_links: Vec<Vec<LinkStats>>,
 }
 
 #[allow(dead_code)]
@@ -585,8 +602,6 @@ fn compute_result(mut tsv_traffic: Vec<VecDeque<&RingTask>>) -> TsvReport {
         max_use_valid,
     }
 }
-#[allow(dead_code)]
-
 fn get_ring_interleave(rings_tasks: Vec<&RingTasksInAllBanks>) -> Vec<Vec<VecDeque<&RingTask>>> {
     rings_tasks
         .into_iter()
@@ -601,32 +616,33 @@ fn get_ring_interleave(rings_tasks: Vec<&RingTasksInAllBanks>) -> Vec<Vec<VecDeq
 struct RingTraffic<T> {
     from: usize,
     to: usize,
-    direction: Direction,
+    /// the words of the packet, see [`packet_words`]
+    size: usize,
     traffic: T,
 }
 #[allow(dead_code)]
 
 impl<T> RingTraffic<T> {
-    fn new(from: usize, to: usize, direction: Direction, traffic: T) -> Self {
+    fn new(from: usize, to: usize, size: usize, traffic: T) -> Self {
         Self {
             from,
             to,
-            direction,
+            size,
             traffic,
         }
     }
 }
-impl<T> IcntPacket for RingTraffic<T> {
+impl<T> CrossBarPacket for RingTraffic<T> {
     fn get_source(&self) -> usize {
         self.from
     }
 
-    fn get_next_hop(&self) -> usize {
+    fn get_dest(&self) -> usize {
         self.to
     }
 
-    fn get_direction(&self) -> Direction {
-        self.direction
+    fn get_size(&self) -> usize {
+        self.size
     }
 }
 
@@ -652,39 +668,6 @@ impl<T> CrossBarPacket for CrossBarTraffic<T> {
     }
 }
 
-trait CrossBarCommon<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T>;
-    fn pop(&mut self, node: usize) -> Option<T>;
-    fn cycle(&mut self);
-}
-
-impl<T: CrossBarPacket> CrossBarCommon<T> for CrossBareSimulator<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T> {
-        self.add(node, traffic)
-    }
-
-    fn pop(&mut self, node: usize) -> Option<T> {
-        self.pop(node)
-    }
-
-    fn cycle(&mut self) {
-        self.cycle()
-    }
-}
-impl<T: CrossBarPacket> CrossBarCommon<T> for CrossBareSimulatorNoConflict<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T> {
-        self.add(node, traffic)
-    }
-
-    fn pop(&mut self, node: usize) -> Option<T> {
-        self.pop(node)
-    }
-
-    fn cycle(&mut self) {
-        self.cycle()
-    }
-}
-
 impl Hardware {
     fn new(
         dense_dim: usize,
@@ -734,8 +717,7 @@ impl Hardware {
     /// # TODO
     /// - redesign the buffer to store the traffic from each subarray. then use the [flat_interleave] to
     ///  route the traffic
-    #[allow(dead_code)]
-    fn calculate_tsv_traffic(&self) -> TsvReportV2 {
+    fn calculate_tsv_traffic(&self) -> eyre::Result<TsvReportV2> {
         // the input traffic from each bank in the layer
 
         // the old method is deprecated
@@ -747,49 +729,68 @@ impl Hardware {
         // second, build the hardware:
         // - the ring simulator for each ring
         let ports = self.config.channels.num;
-        let cycle_normal = {
+        let (cycle_normal, bank_links) = {
             self.calculate_icnt(
                 ring_bank_traffic.clone(),
-                CrossBareSimulator::new(ports, 16),
-            )
+                build_interconnect(
+                    self.config.interconnect,
+                    ports,
+                    self.config.network_buffer_size,
+                ),
+            )?
         };
-        let cycle_no_conflict = {
+        let (cycle_no_conflict, _) = {
             self.calculate_icnt(
                 ring_bank_traffic,
-                CrossBareSimulatorNoConflict::new(ports, 16),
-            )
+                CrossBareSimulatorNoConflict::new(ports, self.config.network_buffer_size),
+            )?
         };
         // println!("cycle: {}", cycle);
         tracing::debug!("cycle: {cycle_normal} {cycle_no_conflict}");
-        TsvReportV2 {
+        Ok(TsvReportV2 {
             cycle_normal,
             cycle_no_conflict,
             max_use: 0,
             max_use_valid: 0,
             real_use: 0,
-        }
+            bank_links,
+        })
     }
 
+    /// return the cycles and the usage of the links of the bank network in each channel
     fn calculate_icnt<'a, Cross>(
         &'a self,
         mut ring_bank_traffic: Vec<Vec<VecDeque<&'a RingTask>>>,
         mut crossbar_simulator: Cross,
-    ) -> usize
+    ) -> eyre::Result<(usize, Vec<Vec<LinkStats>>)>
     where
-        Cross: CrossBarCommon<CrossBarTraffic<&'a (RingPort, RingPort, (RingId, RingPort))>>,
+        Cross: Interconnect<CrossBarTraffic<&'a (RingPort, RingPort, (RingId, RingPort))>>,
     {
         // - the corssbar simulator for the base layer
+        // - the bank network of each channel, the ring by default
+        let bank_interconnect = self
+            .config
+            .bank_interconnect
+            .unwrap_or(InterconnectType::Ring);
         let mut ring_simulators = (0..self.config.channels.num)
-            .map(|_ring_id| RingSimulator::new(self.config.banks.num, 128))
+            .map(|_ring_id| {
+                build_interconnect_with_ring(
+                    bank_interconnect,
+                    self.config.banks.num,
+                    128,
+                    self.config.ring,
+                )
+            })
             .collect_vec();
 
         let mut tsv_buffer = (0..self.config.channels.num)
             .map(|_| VecDeque::with_capacity(16))
             .collect_vec();
-
+        // an u32 index and the value
+        let packet_size = packet_words(size_of::<u32>(), self.config.packet_value_bytes);
         let mut total_remain_traffic = 0;
-        let mut cycle = 0;
-        loop {
+        // stop with the state of the networks instead of hanging when they stall
+        let cycles = drive_networks(self.config.network_watchdog_cycles, |clock| {
             for (ring_id, (ring_sim, ring_traffic)) in ring_simulators
                 .iter_mut()
                 .zip(ring_bank_traffic.iter_mut())
@@ -802,15 +803,8 @@ impl Hardware {
                         // add the traffic to ring port  `bank_id`
                         let from = traffic.0 .0 as usize;
                         let to = traffic.1 .0 as usize;
-                        let nodes = self.config.banks.num;
-                        let right_distance = (to + nodes - from) % nodes;
-                        let left_distance = (from + nodes - to) % nodes;
-                        let direction = if right_distance < left_distance {
-                            Direction::Right
-                        } else {
-                            Direction::Left
-                        };
-                        match ring_sim.add(bank_id, RingTraffic::new(from, to, direction, traffic))
+                        match ring_sim
+                            .inject(bank_id, RingTraffic::new(from, to, packet_size, traffic))
                         {
                             Ok(_) => {
                                 total_remain_traffic += 1;
@@ -822,7 +816,7 @@ impl Hardware {
                     }
                     // then pop the traffic from the output of the ring
 
-                    if let Some(RingTraffic { traffic, .. }) = ring_sim.front(bank_id) {
+                    if let Some(RingTraffic { traffic, .. }) = ring_sim.peek(bank_id) {
                         if bank_id == 0 {
                             // the tsv port
                             // test if the traffic should go to other ring
@@ -830,13 +824,13 @@ impl Hardware {
                             if ring_id == target_ring_id {
                                 // no!
                                 // do nothing and pop the traffic
-                                ring_sim.pop(bank_id);
+                                ring_sim.eject(bank_id);
                                 total_remain_traffic -= 1;
                             } else {
                                 // yes, go to the target ring
                                 // push it to the tsv buffer
                                 if tsv_buffer[ring_id].len() < 16 {
-                                    let traffic = ring_sim.pop(bank_id).unwrap();
+                                    let traffic = ring_sim.eject(bank_id).unwrap();
                                     tsv_buffer[ring_id].push_back(traffic);
                                     // do not need to decrease the total_remain_traffic because we will do it on pop of the crossbar
                                 } else {
@@ -845,7 +839,7 @@ impl Hardware {
                             }
                         } else {
                             // just pop the traffic
-                            ring_sim.pop(bank_id).unwrap();
+                            ring_sim.eject(bank_id).unwrap();
                             total_remain_traffic -= 1;
                         }
                     }
@@ -857,7 +851,7 @@ impl Hardware {
                 if let Some(traffic) = tsv.pop_front() {
                     let crossbare_packet =
                         CrossBarTraffic::new(tsv_id, traffic.traffic.2 .0 .0, traffic.traffic);
-                    match crossbar_simulator.add(tsv_id, crossbare_packet) {
+                    match crossbar_simulator.inject(tsv_id, crossbare_packet) {
                         Ok(_) => {
                             // do nothing
                         }
@@ -867,23 +861,27 @@ impl Hardware {
                     }
                 }
                 // handle the crossbar traffic out
-                if let Some(_p) = crossbar_simulator.pop(tsv_id) {
+                if let Some(_p) = crossbar_simulator.eject(tsv_id) {
                     // the packet is finished
                     total_remain_traffic -= 1;
                 }
             }
             // cycle the simulators
-            for ring_sim in ring_simulators.iter_mut() {
-                ring_sim.cycle();
-            }
-            crossbar_simulator.cycle();
-
-            cycle += 1;
-            if total_remain_traffic == 0 {
-                break;
+            for (ring_id, ring_sim) in ring_simulators.iter_mut().enumerate() {
+                clock.cycle(ring_sim, || {
+                    format!("the ring of channel {} is stalled", ring_id)
+                })?;
             }
-        }
-        cycle
+            clock.cycle(&mut crossbar_simulator, || {
+                "the base layer network is stalled".to_string()
+            })?;
+            Ok(total_remain_traffic)
+        })?;
+        let links = ring_simulators
+            .iter()
+            .map(|ring_sim| ring_sim.link_stats())
+            .collect();
+        Ok((cycles, links))
     }
 
     #[allow(unused)]
@@ -1017,7 +1015,6 @@ impl Hardware {
         TsvId(ring_id.0)
     }
 
-    #[allow(dead_code)]
     fn ring_port_from_subarray(&self, subarray_id: SubarrayId) -> RingPort {
         RingPort(((subarray_id.0 / self.config.subarrays) % self.config.banks.num) as u8)
     }
@@ -1046,8 +1043,9 @@ impl Hardware {
         _subarray_id: SubarrayId,
         col_id: LogicColId,
     ) {
-        // ignore the ring and tsv here cause it's overlaped with local accumulation.
+        // the ring and tsv are overlaped with local accumulation, they are reported separately
         let target_partition_id = self.get_partition_id_col(col_id);
+        self.add_ring_task(_subarray_id, target_partition_id);
         let remote_dense_row_write = self.get_row_id_dense(target_row_id, col_id);
         let col_id = self.get_col_id_dense(target_row_id, col_id);
         // the remote write task
@@ -1059,8 +1057,30 @@ impl Hardware {
         // the local tasks
         self.ring_buffer[self_bank_id.0].add_send_tasks();
     }
+    /// the remote traffic goes through the bank network of the source channel, the traffic to other channels leaves
+    /// it from port 0 to the base layer
+    fn add_ring_task(&mut self, subarray_id: SubarrayId, target_partition_id: SubarrayId) {
+        let source_layer = self.ring_id_from_subarray(subarray_id);
+        let target_layer = self.ring_id_from_subarray(target_partition_id);
+        let source = self.ring_port_from_subarray(subarray_id);
+        let target = self.ring_port_from_subarray(target_partition_id);
+        let next_port = if source_layer == target_layer {
+            if source == target {
+                // the same bank, no need to use the ring
+                return;
+            }
+            target
+        } else {
+            RingPort(0)
+        };
+        self.ring[source_layer.0].add_task(
+            subarray_id.0 % self.config.subarrays,
+            source,
+            next_port,
+            (target_layer, target),
+        );
+    }
     /// from bank id to ring id
-    #[allow(unused)]
     fn ring_id_from_subarray(&self, partition_id: SubarrayId) -> RingId {
         let bank_id = partition_id.0 / self.config.subarrays;
         RingId(bank_id / self.config.banks.num)
@@ -1129,6 +1149,8 @@ pub struct GearboxSim<'a> {
     pub evil_row_ids: HashSet<usize>,
     pub matrix_b: &'a CsMatI<Pattern, u32>,
     pub hardware: Hardware,
+    /// the trace of the dram accesses, `None` when the trace is not enabled in the config
+    pub dram_trace: Option<DramTrace>,
 }
 impl<'a> GearboxSim<'a> {
     fn new(
@@ -1167,9 +1189,37 @@ impl<'a> GearboxSim<'a> {
                 row_per_partition,
                 col_per_partition,
             ),
+            dram_trace: None,
         }
     }
 
+    /// issue the read of the row of matrix b and the write of the result to the dram trace, there is no write when
+    /// `mat_b_col_id` is `None`. the row of matrix b is stored in its partition from the first row
+    fn trace_distribute(
+        &mut self,
+        target_id: LogicRowId,
+        mat_b_row_id: LogicRowId,
+        mat_b_col_id: Option<LogicColId>,
+    ) {
+        let Some(trace) = &mut self.dram_trace else {
+            return;
+        };
+        let hardware = &self.hardware;
+        let read = (
+            hardware.get_partition_id_row(mat_b_row_id),
+            PhysicRowId(mat_b_row_id.0 % self.row_per_partition),
+            PhysicColId(0),
+        );
+        let write = mat_b_col_id.map(|col_id| {
+            (
+                hardware.get_partition_id_col(col_id),
+                hardware.get_row_id_dense(target_id, col_id),
+                PhysicColId(hardware.get_col_id_dense(target_id, col_id)),
+            )
+        });
+        trace_read_write(trace, read, write);
+    }
+
     /// distribute the task to components
     ///
     /// we should analyze the gearbox overflow overhead and the icnt unbanlance traffic in this
@@ -1179,7 +1229,9 @@ impl<'a> GearboxSim<'a> {
         input_vec: &CsMatI<Pattern, u32>,
         current_batch: usize,
         _current_topk: f32,
-    ) -> GlobalStatV2 {
+        progress: &mut TaskProgress,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<GlobalStatV2> {
         let mut global_stats = GlobalStatV2::default();
         let now = std::time::Instant::now();
         debug!("run gearbox sim");
@@ -1209,7 +1261,9 @@ impl<'a> GearboxSim<'a> {
                 // if next_print_time > 3000 {
                 //     break;
                 // }
-                if stop_signal::read() {
+                progress.update(target_id);
+                if cancel.is_cancelled() {
+                    cancel::record_partial_run();
                     info!("received stop signal, start write results");
                     break;
                 }
@@ -1222,6 +1276,7 @@ impl<'a> GearboxSim<'a> {
                 let mat_b_row_id = mat_b_row_id as usize;
                 if self.evil_row_ids.contains(&mat_b_row_id) {
                     // the row is evil, no need to access remote
+                    self.trace_distribute(LogicRowId(target_id), LogicRowId(mat_b_row_id), None);
                     self.hardware.distribute_evil_row(
                         LogicRowId(target_id),
                         LogicRowId(mat_b_row_id),
@@ -1242,6 +1297,11 @@ impl<'a> GearboxSim<'a> {
                         .iter()
                         .map(|i| *i as usize)
                     {
+                        self.trace_distribute(
+                            LogicRowId(target_id),
+                            LogicRowId(mat_b_row_id),
+                            Some(LogicColId(col)),
+                        );
                         if self.evil_col_ids.contains(&col) {
                             // the col is evil, no need to access remote
                             // self.hardware
@@ -1279,12 +1339,16 @@ impl<'a> GearboxSim<'a> {
             // reduce the tasks and clear the tasks
             // the cycle of this round
             if (target_id + 1) % current_batch == 0 {
-                update_stats(&mut self.hardware, &mut global_stats);
+                update_stats(&mut self.hardware, &mut global_stats)?;
+                // the stats have no single cycle of the round, the next round starts after the busiest bank
+                if let Some(trace) = &mut self.dram_trace {
+                    trace.end_round(0)?;
+                }
                 // the data for overflow:
             }
             // add the result to the total result and continue to the next line
         }
-        global_stats
+        Ok(global_stats)
     }
 
     /// reduce the result and return the result
@@ -1351,6 +1415,8 @@ pub struct GlobalStatV2 {
     pub global_max_real_local: usize,
     pub global_max_ring_buffer: usize,
     pub global_max_dispatching: usize,
+    /// the usage of the links of the bank network in each channel, empty for the networks without links
+    pub global_bank_links: Vec<Vec<LinkStats>>,
 }
 
 enum PackType {
@@ -1382,20 +1448,32 @@ fn packets_to_cycle(packets: usize, p_type: PackType) -> usize {
     }
 }
 
-fn update_stats(hardware: &mut Hardware, global_stats: &mut GlobalStatV2) {
+fn update_stats(hardware: &mut Hardware, global_stats: &mut GlobalStatV2) -> eyre::Result<()> {
     // test if the size is overflow!
-    // let tsv_report_base = hardware.calculate_tsv_traffic();
-    // global_stats.global_tsv_base_total += tsv_report_base.max_use;
-    // global_stats.global_tsv_base_real += tsv_report_base.real_use;
-    // global_stats.global_tsv_base_cycle_normal += tsv_report_base.cycle_normal;
-    // global_stats.global_tsv_base_cycle_no_conflict += tsv_report_base.cycle_no_conflict;
-    // global_stats.global_tsv_base_max_use_validt += tsv_report_base.max_use_valid;
-    // let ring_max_cycle = hardware
-    //     .ring
-    //     .iter_mut()
-    //     .map(|ring| ring.report_current_round())
-    //     .max()
-    //     .unwrap();
+    let tsv_report_base = hardware.calculate_tsv_traffic()?;
+    global_stats.global_tsv_base_total += tsv_report_base.max_use;
+    global_stats.global_tsv_base_real += tsv_report_base.real_use;
+    global_stats.global_tsv_base_cycle_normal += tsv_report_base.cycle_normal;
+    global_stats.global_tsv_base_cycle_no_conflict += tsv_report_base.cycle_no_conflict;
+    global_stats.global_tsv_base_max_use_validt += tsv_report_base.max_use_valid;
+    global_stats
+        .global_bank_links
+        .resize(tsv_report_base.bank_links.len(), vec![]);
+    for (total, round) in global_stats
+        .global_bank_links
+        .iter_mut()
+        .zip(&tsv_report_base.bank_links)
+    {
+        merge_link_stats(total, round, global_stats.global_tsv_base_cycle_normal);
+    }
+    // clear the ring tasks of this round
+    let ring_max_cycle = hardware
+        .ring
+        .iter_mut()
+        .map(|ring| ring.report_current_round())
+        .max()
+        .unwrap();
+    global_stats.global_max_acc_ring += ring_max_cycle;
     // let tsv_max_cycle = hardware
     //     .tsv
     //     .iter_mut()
@@ -1446,6 +1524,7 @@ fn update_stats(hardware: &mut Hardware, global_stats: &mut GlobalStatV2) {
     global_stats.global_max_real_local += max_real_local_cycle;
     global_stats.global_max_ring_buffer += max_local_dispatcher;
     global_stats.global_max_dispatching += max_dispatching;
+    Ok(())
 }
 
 struct DistributionDrawer;
@@ -1481,7 +1560,11 @@ impl DrawFn for DistributionDrawer {
     }
 }
 
-fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
+fn compute_gearbox(
+    config: &ConfigV2,
+    path: &str,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<SingleResult>> {
     // for hbm config, they should be 1!
     assert!(config.ranks.num == 1);
     assert!(config.chips.num == 1);
@@ -1494,55 +1577,13 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
         * config.subarrays;
 
     info!(?partitions, "compute gearbox");
-    info!("reading mtx file: {}", path);
-    let read_time = std::time::Instant::now();
-    let matrix_head: MatrixHead<Pattern, u32> = sprs::io::read_matrix_market_head(path).unwrap();
-    let matrix_size = matrix_head.ind_ptr_size() + matrix_head.ind_size() + matrix_head.data_size();
-    // two csr csc matrix during runtime
-    let matrix_size = matrix_size * 2;
-    let sim_size = partitions * (size_of::<SubArray>()) * 2;
-    let sim_size = matrix_size + sim_size;
-
-    let temp_size = matrix_head.tri_size();
-
-    info!(
-        "info there will be {} bytes,start acquire the space",
-        sim_size + temp_size
-    );
-    let mut _guard = crate::acquire_memory_sections([sim_size, temp_size]);
-    let _guard_temp = _guard.pop().unwrap();
-    let _guard_sim = _guard.pop().unwrap();
-
-    let tri_mat: TriMatI<Pattern, u32> = sprs::io::read_matrix_market(path).unwrap();
-    info!(
-        "finished read the matrix: time:{:.2} secs",
-        read_time.elapsed().as_secs_f32()
-    );
-    let (matrix_a, matrix_b): (CsMatI<Pattern, u32>, CsMatI<Pattern, u32>) =
-        (tri_mat.to_csr(), tri_mat.transpose_view().to_csr());
-
-    drop(tri_mat);
-    drop(_guard_temp);
-
-    info!(
-        "finished transpose the matrix: time:{:.2} secs",
-        read_time.elapsed().as_secs_f32()
-    );
-    let mat_b_rows = matrix_b.rows();
-    let mat_b_cols = matrix_b.cols();
-
-    info!(?mat_b_rows, ?mat_b_cols, "matrix b shape");
-
-    // the nnz of matrix b rows
-    let mut mat_b_row_ids = (0..mat_b_rows)
-        .zip(matrix_b.outer_iterator().map(|row| row.nnz()))
-        .collect_vec();
-    // the nnz of matrix b cols
-    let mut mat_b_col_ids = (0..mat_b_cols)
-        .zip(matrix_a.outer_iterator().map(|row| row.nnz()))
-        .collect_vec();
-    mat_b_row_ids.sort_by_key(|(_index, nnz)| Reverse(*nnz));
-    mat_b_col_ids.sort_by_key(|(_index, nnz)| Reverse(*nnz));
+    let GearboxMatrices {
+        matrix_a,
+        matrix_b,
+        guard: _guard_sim,
+        ..
+    } = load_gearbox_matrices(path, partitions * size_of::<SubArray>() * 2)?;
+    let (mat_b_row_ids, mat_b_col_ids) = sorted_nnz(matrix_b.view());
     // draw_distribution(
     //     &mat_b_row_ids,
     //     Path::new(&format!("output/{}-{}", file_name, "mat_b_row_ids.png")),
@@ -1568,6 +1609,7 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
             let top_cols = if top_cols == 0 { 1 } else { top_cols };
             info!(?top_cols, "top cols");
             assert!(top_cols > 0);
+            let dram_trace = gearbox_dram_trace(config, path, &format!("{batch}_{top_k}"))?;
 
             let mut gearbox = GearboxSim::new(
                 mat_b_col_ids.iter().take(top_cols).map(|(idx, _)| *idx),
@@ -1575,21 +1617,25 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
                 &matrix_b,
                 config,
             );
+            gearbox.dram_trace = dram_trace;
             info!("start running the sim");
-            let result = gearbox.run(&matrix_a, batch, top_k);
+            let mut progress =
+                TaskProgress::register(format!("{path}_{batch}_{top_k}"), matrix_a.rows(), cancel)
+                    .without_partial();
+            let result = gearbox.run(&matrix_a, batch, top_k, &mut progress, cancel)?;
             TOTAL_FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
             info!(
                 "finished task: {}/{}",
                 TOTAL_FINISHED_TASKS.load(Ordering::Relaxed),
                 *TOTAL_TASKS.read().unwrap()
             );
-            gearbox.report(path.to_string(), result, batch, top_k)
+            Ok(gearbox.report(path.to_string(), result, batch, top_k))
         })
-        .collect();
+        .collect::<eyre::Result<_>>()?;
     drop(matrix_a);
     drop(matrix_b);
     drop(_guard_sim);
-    results
+    Ok(results)
 }
 pub fn transpose2<T>(v: Vec<Vec<T>>) -> Vec<Vec<T>> {
     assert!(!v.is_empty());
@@ -1611,7 +1657,7 @@ static TOTAL_TASKS: RwLock<usize> = RwLock::new(0);
 fn analyze_gearbox_inner<LevelType: LevelTrait>(
     config: &ConfigV2,
     _total_size: &LevelType::Storage,
-) -> Vec<((usize, f32), Vec<SingleResult>)>
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>>
 where
     LevelType::Storage: Debug + Sync,
     LevelType::Mapping: Debug,
@@ -1621,6 +1667,8 @@ where
     let total_tasks = total_graphs * total_configs;
     info!(?total_graphs, ?total_configs, ?total_tasks, "total tasks");
     *TOTAL_TASKS.write().unwrap() = total_tasks;
+    let config_cancel =
+        cancel::root().child_with_timeout(config.deadline_secs.map(Duration::from_secs));
 
     let results: Vec<_> = config
         .graph_path
@@ -1628,9 +1676,11 @@ where
         .enumerate()
         .map(|(index, path)| {
             info!("analyzing graph {}/{}", index + 1, total_graphs);
-            compute_gearbox(config, path)
+            let graph_cancel = config_cancel
+                .child_with_timeout(config.graph_deadline_secs.map(Duration::from_secs));
+            compute_gearbox(config, path, &graph_cancel)
         })
-        .collect();
+        .collect::<eyre::Result<_>>()?;
     let results = transpose2(results);
     let configs = config
         .gearbox_config
@@ -1642,7 +1692,7 @@ where
 
     info!(?configs, "configs");
     assert_eq!(configs.len(), results.len());
-    configs.into_iter().zip(results).collect()
+    Ok(configs.into_iter().zip(results).collect())
     // GearboxResult { results }
 }
 
@@ -2067,3 +2117,48 @@ impl SubarrayStatus {
 //     //     }
 //     // }
 // }
+
+#[cfg(test)]
+mod tests {
+    use crate::pim::config_migration::parse_config;
+
+    use super::*;
+
+    /// the total result of a random graph when the banks are connected by `kind`
+    fn run_bank_network(kind: InterconnectType) -> eyre::Result<GlobalStatV2> {
+        let mut config: ConfigV2 =
+            parse_config(include_str!("../../../configs/real_jump_test.toml"))?.into();
+        // a single channel, so all remote traffic goes through the bank network
+        config.banks.num = 8;
+        config.graph_path = vec!["er:n=512,d=8".to_string()];
+        config.gearbox_config.batch = vec![16];
+        config.gearbox_config.topk = vec![0.0];
+        config.bank_interconnect = Some(kind);
+        let mut results = analyze_gearbox(&config)?;
+        Ok(results.pop().unwrap().1.pop().unwrap().total_result)
+    }
+
+    #[test]
+    fn test_bank_interconnect() -> eyre::Result<()> {
+        let ring = run_bank_network(InterconnectType::Ring)?;
+        assert!(ring.global_tsv_base_cycle_normal > 0);
+        assert!(ring.global_bank_links.iter().all(Vec::is_empty));
+        for kind in [InterconnectType::Mesh, InterconnectType::Torus] {
+            let result = run_bank_network(kind)?;
+            assert_ne!(
+                result.global_tsv_base_cycle_normal, ring.global_tsv_base_cycle_normal,
+                "{:?}",
+                kind
+            );
+            assert_eq!(result.global_bank_links.len(), 1);
+            let links = &result.global_bank_links[0];
+            assert!(links.iter().any(|link| link.busy_cycles > 0), "{:?}", kind);
+            assert!(
+                links.iter().all(|link| link.utilization <= 1.0),
+                "{:?}",
+                kind
+            );
+        }
+        Ok(())
+    }
+}
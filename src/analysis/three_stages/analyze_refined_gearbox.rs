@@ -10,17 +10,22 @@
 //!  w
 use crate::{
     analysis::{
-        remap_analyze::row_cycle::*, results::SubArrayResult, RingTask, RingTasksInAllBanks,
+        remap_analyze::row_cycle::*,
+        results::SubArrayResult,
+        traits::{
+            end_trace_round, gearbox_dram_trace, load_gearbox_matrices, sorted_nnz,
+            trace_read_write, GearboxMatrices,
+        },
+        RingTask, RingTasksInAllBanks,
     },
     tools::{
-        crossbare_simulator::CrossBareSimulator,
-        crossbare_simulator_no_conflic::CrossBareSimulatorNoConflict,
-        ring_simulator::RingSimulator, stop_signal, CrossBarPacket, Direction, FlatInterleaveTrait,
-        IcntPacket,
+        cancel::{self, CancellationToken},
+        control::TaskProgress,
+        dram_trace::DramTrace,
+        FlatInterleaveTrait,
     },
 };
 use std::{
-    cmp::Reverse,
     collections::{BTreeMap, BTreeSet, VecDeque},
     error::Error,
     fmt::{Debug, Display},
@@ -29,6 +34,7 @@ use std::{
         atomic::{AtomicUsize, Ordering},
         RwLock,
     },
+    time::Duration,
 };
 
 use hashbrown::HashSet;
@@ -36,7 +42,7 @@ use itertools::Itertools;
 use plotters::{coord::Shift, prelude::*};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use sprs::{io::MatrixHead, num_kinds::Pattern, CsMatI, TriMatI};
+use sprs::{num_kinds::Pattern, CsMatI};
 use tracing::{debug, info};
 
 use crate::{
@@ -109,7 +115,9 @@ impl Display for GearboxResult {
 }
 
 /// analyze the split spmm
-pub(crate) fn analyze_gearbox(config: &ConfigV2) -> Vec<((usize, f32), Vec<SingleResult>)> {
+pub(crate) fn analyze_gearbox(
+    config: &ConfigV2,
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>> {
     match config.dram_type {
         DramType::DDR3 => unimplemented!(),
         DramType::DDR4 => {
@@ -466,15 +474,6 @@ struct TsvReport {
     pub max_use_valid: usize,
     pub real_use: usize,
 }
-#[allow(dead_code)]
-#[derive(Debug, Default)]
-struct TsvReportV2 {
-    pub cycle_normal: usize,
-    pub cycle_no_conflict: usize,
-    pub max_use: usize,
-    pub max_use_valid: usize,
-    pub real_use: usize,
-}
 
 #[allow(dead_code)]
 fn compute_result(mut tsv_traffic: Vec<VecDeque<&RingTask>>) -> TsvReport {
@@ -540,93 +539,6 @@ fn get_ring_interleave(rings_tasks: Vec<&RingTasksInAllBanks>) -> Vec<Vec<VecDeq
         })
         .collect()
 }
-#[allow(dead_code)]
-struct RingTraffic<T> {
-    from: usize,
-    to: usize,
-    direction: Direction,
-    traffic: T,
-}
-#[allow(dead_code)]
-
-impl<T> RingTraffic<T> {
-    fn new(from: usize, to: usize, direction: Direction, traffic: T) -> Self {
-        Self {
-            from,
-            to,
-            direction,
-            traffic,
-        }
-    }
-}
-impl<T> IcntPacket for RingTraffic<T> {
-    fn get_source(&self) -> usize {
-        self.from
-    }
-
-    fn get_next_hop(&self) -> usize {
-        self.to
-    }
-
-    fn get_direction(&self) -> Direction {
-        self.direction
-    }
-}
-
-#[allow(dead_code)]
-struct CrossBarTraffic<T> {
-    from: usize,
-    to: usize,
-    traffic: T,
-}
-#[allow(dead_code)]
-impl<T> CrossBarTraffic<T> {
-    fn new(from: usize, to: usize, traffic: T) -> Self {
-        Self { from, to, traffic }
-    }
-}
-impl<T> CrossBarPacket for CrossBarTraffic<T> {
-    fn get_source(&self) -> usize {
-        self.from
-    }
-
-    fn get_dest(&self) -> usize {
-        self.to
-    }
-}
-
-trait CrossBarCommon<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T>;
-    fn pop(&mut self, node: usize) -> Option<T>;
-    fn cycle(&mut self);
-}
-
-impl<T: CrossBarPacket> CrossBarCommon<T> for CrossBareSimulator<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T> {
-        self.add(node, traffic)
-    }
-
-    fn pop(&mut self, node: usize) -> Option<T> {
-        self.pop(node)
-    }
-
-    fn cycle(&mut self) {
-        self.cycle()
-    }
-}
-impl<T: CrossBarPacket> CrossBarCommon<T> for CrossBareSimulatorNoConflict<T> {
-    fn add(&mut self, node: usize, traffic: T) -> Result<(), T> {
-        self.add(node, traffic)
-    }
-
-    fn pop(&mut self, node: usize) -> Option<T> {
-        self.pop(node)
-    }
-
-    fn cycle(&mut self) {
-        self.cycle()
-    }
-}
 
 impl Hardware {
     fn new(
@@ -665,171 +577,6 @@ impl Hardware {
         tsv_traffic
     }
 
-    /// # situation
-    /// - when a round is finished, all traffic from each input port are ready to route to the other port
-    /// - the traffic is routed by a crossbar network in the base layer
-    /// # inbanlance reason
-    /// - the input traffic is not balanced
-    /// - the output traffic is not balanced
-    /// # solution
-    /// - need a score to represent the reason why usage is low
-    /// - the input reason
-    /// - the output reason
-    /// # TODO
-    /// - redesign the buffer to store the traffic from each subarray. then use the [flat_interleave] to
-    ///  route the traffic
-    #[allow(dead_code)]
-    fn calculate_tsv_traffic(&self) -> TsvReportV2 {
-        // the input traffic from each bank in the layer
-
-        // the old method is deprecated
-        // `compute_result(self.get_tsv_interleave())`
-
-        // first get the traffic for each bank and each ring
-        let rings_tasks = self.ring.iter().map(|r| &r.tasks).collect_vec();
-        let ring_bank_traffic: Vec<Vec<VecDeque<&RingTask>>> = get_ring_interleave(rings_tasks);
-        // second, build the hardware:
-        // - the ring simulator for each ring
-        let ports = self.config.channels.num;
-        let cycle_normal = {
-            self.calculate_icnt(
-                ring_bank_traffic.clone(),
-                CrossBareSimulator::new(ports, 16),
-            )
-        };
-        let cycle_no_conflict = {
-            self.calculate_icnt(
-                ring_bank_traffic,
-                CrossBareSimulatorNoConflict::new(ports, 16),
-            )
-        };
-        // println!("cycle: {}", cycle);
-        tracing::debug!("cycle: {cycle_normal} {cycle_no_conflict}");
-        TsvReportV2 {
-            cycle_normal,
-            cycle_no_conflict,
-            max_use: 0,
-            max_use_valid: 0,
-            real_use: 0,
-        }
-    }
-
-    fn calculate_icnt<'a, Cross>(
-        &'a self,
-        mut ring_bank_traffic: Vec<Vec<VecDeque<&'a RingTask>>>,
-        mut crossbar_simulator: Cross,
-    ) -> usize
-    where
-        Cross: CrossBarCommon<CrossBarTraffic<&'a (RingPort, RingPort, (RingId, RingPort))>>,
-    {
-        // - the corssbar simulator for the base layer
-        let mut ring_simulators = (0..self.config.channels.num)
-            .map(|_ring_id| RingSimulator::new(self.config.banks.num, 128))
-            .collect_vec();
-
-        let mut tsv_buffer = (0..self.config.channels.num)
-            .map(|_| VecDeque::with_capacity(16))
-            .collect_vec();
-
-        let mut total_remain_traffic = 0;
-        let mut cycle = 0;
-        loop {
-            for (ring_id, (ring_sim, ring_traffic)) in ring_simulators
-                .iter_mut()
-                .zip(ring_bank_traffic.iter_mut())
-                .enumerate()
-            {
-                for (bank_id, bank_traffic) in ring_traffic.iter_mut().enumerate() {
-                    // first add the traffic to the input of the ring
-
-                    if let Some(traffic) = bank_traffic.pop_front() {
-                        // add the traffic to ring port  `bank_id`
-                        let from = traffic.0 .0 as usize;
-                        let to = traffic.1 .0 as usize;
-                        let nodes = self.config.banks.num;
-                        let right_distance = (to + nodes - from) % nodes;
-                        let left_distance = (from + nodes - to) % nodes;
-                        let direction = if right_distance < left_distance {
-                            Direction::Right
-                        } else {
-                            Direction::Left
-                        };
-                        match ring_sim.add(bank_id, RingTraffic::new(from, to, direction, traffic))
-                        {
-                            Ok(_) => {
-                                total_remain_traffic += 1;
-                            }
-                            Err(RingTraffic { traffic, .. }) => {
-                                bank_traffic.push_front(traffic);
-                            }
-                        }
-                    }
-                    // then pop the traffic from the output of the ring
-
-                    if let Some(RingTraffic { traffic, .. }) = ring_sim.front(bank_id) {
-                        if bank_id == 0 {
-                            // the tsv port
-                            // test if the traffic should go to other ring
-                            let target_ring_id = traffic.2 .0 .0;
-                            if ring_id == target_ring_id {
-                                // no!
-                                // do nothing and pop the traffic
-                                ring_sim.pop(bank_id);
-                                total_remain_traffic -= 1;
-                            } else {
-                                // yes, go to the target ring
-                                // push it to the tsv buffer
-                                if tsv_buffer[ring_id].len() < 16 {
-                                    let traffic = ring_sim.pop(bank_id).unwrap();
-                                    tsv_buffer[ring_id].push_back(traffic);
-                                    // do not need to decrease the total_remain_traffic because we will do it on pop of the crossbar
-                                } else {
-                                    // the buffer is full, do nothing
-                                }
-                            }
-                        } else {
-                            // just pop the traffic
-                            ring_sim.pop(bank_id).unwrap();
-                            total_remain_traffic -= 1;
-                        }
-                    }
-                }
-            }
-
-            // handle the tsv buffer traffic
-            for (tsv_id, tsv) in tsv_buffer.iter_mut().enumerate() {
-                if let Some(traffic) = tsv.pop_front() {
-                    let crossbare_packet =
-                        CrossBarTraffic::new(tsv_id, traffic.traffic.2 .0 .0, traffic.traffic);
-                    match crossbar_simulator.add(tsv_id, crossbare_packet) {
-                        Ok(_) => {
-                            // do nothing
-                        }
-                        Err(_) => {
-                            tsv.push_front(traffic);
-                        }
-                    }
-                }
-                // handle the crossbar traffic out
-                if let Some(_p) = crossbar_simulator.pop(tsv_id) {
-                    // the packet is finished
-                    total_remain_traffic -= 1;
-                }
-            }
-            // cycle the simulators
-            for ring_sim in ring_simulators.iter_mut() {
-                ring_sim.cycle();
-            }
-            crossbar_simulator.cycle();
-
-            cycle += 1;
-            if total_remain_traffic == 0 {
-                break;
-            }
-        }
-        cycle
-    }
-
     #[allow(unused)]
     fn subarrays(&self) -> usize {
         self.sub_array.len()
@@ -1070,6 +817,8 @@ pub struct GearboxSim<'a> {
     pub evil_row_ids: HashSet<usize>,
     pub matrix_b: &'a CsMatI<Pattern, u32>,
     pub hardware: Hardware,
+    /// the trace of the dram accesses, `None` when the trace is not enabled in the config
+    pub dram_trace: Option<DramTrace>,
 }
 impl<'a> GearboxSim<'a> {
     fn new(
@@ -1108,9 +857,37 @@ impl<'a> GearboxSim<'a> {
                 row_per_partition,
                 col_per_partition,
             ),
+            dram_trace: None,
         }
     }
 
+    /// issue the read of the row of matrix b and the write of the result to the dram trace, there is no write when
+    /// `mat_b_col_id` is `None`. the row of matrix b is stored in its partition from the first row
+    fn trace_distribute(
+        &mut self,
+        target_id: LogicRowId,
+        mat_b_row_id: LogicRowId,
+        mat_b_col_id: Option<LogicColId>,
+    ) {
+        let Some(trace) = &mut self.dram_trace else {
+            return;
+        };
+        let hardware = &self.hardware;
+        let read = (
+            hardware.get_partition_id_row(mat_b_row_id),
+            PhysicRowId(mat_b_row_id.0 % self.row_per_partition),
+            PhysicColId(0),
+        );
+        let write = mat_b_col_id.map(|col_id| {
+            (
+                hardware.get_partition_id_col(col_id),
+                hardware.get_row_id_dense(target_id, col_id),
+                PhysicColId(hardware.get_col_id_dense(target_id, col_id)),
+            )
+        });
+        trace_read_write(trace, read, write);
+    }
+
     /// distribute the task to components
     ///
     /// we should analyze the gearbox overflow overhead and the icnt unbanlance traffic in this
@@ -1120,6 +897,8 @@ impl<'a> GearboxSim<'a> {
         input_vec: &CsMatI<Pattern, u32>,
         current_batch: usize,
         _current_topk: f32,
+        progress: &mut TaskProgress,
+        cancel: &CancellationToken,
     ) -> GlobalStatV2 {
         let mut global_stats = GlobalStatV2::default();
         let now = std::time::Instant::now();
@@ -1148,7 +927,9 @@ impl<'a> GearboxSim<'a> {
                 // if next_print_time > 3000 {
                 //     break;
                 // }
-                if stop_signal::read() {
+                progress.update(target_id);
+                if cancel.is_cancelled() {
+                    cancel::record_partial_run();
                     info!("received stop signal, start write results");
                     break;
                 }
@@ -1161,6 +942,7 @@ impl<'a> GearboxSim<'a> {
                 let mat_b_row_id = mat_b_row_id as usize;
                 if self.evil_row_ids.contains(&mat_b_row_id) {
                     // the row is evil, no need to access remote
+                    self.trace_distribute(LogicRowId(target_id), LogicRowId(mat_b_row_id), None);
                     self.hardware.distribute_evil_row(
                         LogicRowId(target_id),
                         LogicRowId(mat_b_row_id),
@@ -1181,6 +963,11 @@ impl<'a> GearboxSim<'a> {
                         .iter()
                         .map(|i| *i as usize)
                     {
+                        self.trace_distribute(
+                            LogicRowId(target_id),
+                            LogicRowId(mat_b_row_id),
+                            Some(LogicColId(col)),
+                        );
                         if self.evil_col_ids.contains(&col) {
                             // the col is evil, no need to access remote
                             // self.hardware
@@ -1219,6 +1006,8 @@ impl<'a> GearboxSim<'a> {
             // the cycle of this round
             if (target_id + 1) % current_batch == 0 {
                 update_stats(&mut self.hardware, &mut global_stats);
+                // the stats have no single cycle of the round, the next round starts after the busiest bank
+                end_trace_round(&mut self.dram_trace, 0);
                 // the data for overflow:
             }
             // add the result to the total result and continue to the next line
@@ -1389,7 +1178,11 @@ impl DrawFn for DistributionDrawer {
     }
 }
 
-fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
+fn compute_gearbox(
+    config: &ConfigV2,
+    path: &str,
+    cancel: &CancellationToken,
+) -> eyre::Result<Vec<SingleResult>> {
     // for hbm config, they should be 1!
     assert!(config.ranks.num == 1);
     assert!(config.chips.num == 1);
@@ -1402,55 +1195,13 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
         * config.subarrays;
 
     info!(?partitions, "compute gearbox");
-    info!("reading mtx file: {}", path);
-    let read_time = std::time::Instant::now();
-    let matrix_head: MatrixHead<Pattern, u32> = sprs::io::read_matrix_market_head(path).unwrap();
-    let matrix_size = matrix_head.ind_ptr_size() + matrix_head.ind_size() + matrix_head.data_size();
-    // two csr csc matrix during runtime
-    let matrix_size = matrix_size * 2;
-    let sim_size = partitions * (size_of::<SubArray>()) * 2;
-    let sim_size = matrix_size + sim_size;
-
-    let temp_size = matrix_head.tri_size();
-
-    info!(
-        "info there will be {} bytes,start acquire the space",
-        sim_size + temp_size
-    );
-    let mut _guard = crate::acquire_memory_sections([sim_size, temp_size]);
-    let _guard_temp = _guard.pop().unwrap();
-    let _guard_sim = _guard.pop().unwrap();
-
-    let tri_mat: TriMatI<Pattern, u32> = sprs::io::read_matrix_market(path).unwrap();
-    info!(
-        "finished read the matrix: time:{:.2} secs",
-        read_time.elapsed().as_secs_f32()
-    );
-    let (matrix_a, matrix_b): (CsMatI<Pattern, u32>, CsMatI<Pattern, u32>) =
-        (tri_mat.to_csr(), tri_mat.transpose_view().to_csr());
-
-    drop(tri_mat);
-    drop(_guard_temp);
-
-    info!(
-        "finished transpose the matrix: time:{:.2} secs",
-        read_time.elapsed().as_secs_f32()
-    );
-    let mat_b_rows = matrix_b.rows();
-    let mat_b_cols = matrix_b.cols();
-
-    info!(?mat_b_rows, ?mat_b_cols, "matrix b shape");
-
-    // the nnz of matrix b rows
-    let mut mat_b_row_ids = (0..mat_b_rows)
-        .zip(matrix_b.outer_iterator().map(|row| row.nnz()))
-        .collect_vec();
-    // the nnz of matrix b cols
-    let mut mat_b_col_ids = (0..mat_b_cols)
-        .zip(matrix_a.outer_iterator().map(|row| row.nnz()))
-        .collect_vec();
-    mat_b_row_ids.sort_by_key(|(_index, nnz)| Reverse(*nnz));
-    mat_b_col_ids.sort_by_key(|(_index, nnz)| Reverse(*nnz));
+    let GearboxMatrices {
+        matrix_a,
+        matrix_b,
+        guard: _guard_sim,
+        ..
+    } = load_gearbox_matrices(path, partitions * size_of::<SubArray>() * 2)?;
+    let (mat_b_row_ids, mat_b_col_ids) = sorted_nnz(matrix_b.view());
     // draw_distribution(
     //     &mat_b_row_ids,
     //     Path::new(&format!("output/{}-{}", file_name, "mat_b_row_ids.png")),
@@ -1476,6 +1227,7 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
             let top_cols = if top_cols == 0 { 1 } else { top_cols };
             info!(?top_cols, "top cols");
             assert!(top_cols > 0);
+            let dram_trace = gearbox_dram_trace(config, path, &format!("{batch}_{top_k}"))?;
 
             let mut gearbox = GearboxSim::new(
                 mat_b_col_ids.iter().take(top_cols).map(|(idx, _)| *idx),
@@ -1483,21 +1235,25 @@ fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<SingleResult> {
                 &matrix_b,
                 config,
             );
+            gearbox.dram_trace = dram_trace;
             info!("start running the sim");
-            let result = gearbox.run(&matrix_a, batch, top_k);
+            let mut progress =
+                TaskProgress::register(format!("{path}_{batch}_{top_k}"), matrix_a.rows(), cancel)
+                    .without_partial();
+            let result = gearbox.run(&matrix_a, batch, top_k, &mut progress, cancel);
             TOTAL_FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
             info!(
                 "finished task: {}/{}",
                 TOTAL_FINISHED_TASKS.load(Ordering::Relaxed),
                 *TOTAL_TASKS.read().unwrap()
             );
-            gearbox.report(path.to_string(), result, batch, top_k)
+            Ok(gearbox.report(path.to_string(), result, batch, top_k))
         })
-        .collect();
+        .collect::<eyre::Result<_>>()?;
     drop(matrix_a);
     drop(matrix_b);
     drop(_guard_sim);
-    results
+    Ok(results)
 }
 pub fn transpose2<T>(v: Vec<Vec<T>>) -> Vec<Vec<T>> {
     assert!(!v.is_empty());
@@ -1519,7 +1275,7 @@ static TOTAL_TASKS: RwLock<usize> = RwLock::new(0);
 fn analyze_gearbox_inner<LevelType: LevelTrait>(
     config: &ConfigV2,
     _total_size: &LevelType::Storage,
-) -> Vec<((usize, f32), Vec<SingleResult>)>
+) -> eyre::Result<Vec<((usize, f32), Vec<SingleResult>)>>
 where
     LevelType::Storage: Debug + Sync,
     LevelType::Mapping: Debug,
@@ -1529,6 +1285,8 @@ where
     let total_tasks = total_graphs * total_configs;
     info!(?total_graphs, ?total_configs, ?total_tasks, "total tasks");
     *TOTAL_TASKS.write().unwrap() = total_tasks;
+    let config_cancel =
+        cancel::root().child_with_timeout(config.deadline_secs.map(Duration::from_secs));
 
     let results: Vec<_> = config
         .graph_path
@@ -1536,9 +1294,11 @@ where
         .enumerate()
         .map(|(index, path)| {
             info!("analyzing graph {}/{}", index + 1, total_graphs);
-            compute_gearbox(config, path)
+            let graph_cancel = config_cancel
+                .child_with_timeout(config.graph_deadline_secs.map(Duration::from_secs));
+            compute_gearbox(config, path, &graph_cancel)
         })
-        .collect();
+        .collect::<eyre::Result<_>>()?;
     let results = transpose2(results);
     let configs = config
         .gearbox_config
@@ -1549,7 +1309,7 @@ where
         .collect_vec();
     info!(?configs, "configs");
     assert_eq!(configs.len(), results.len());
-    configs.into_iter().zip(results).collect()
+    Ok(configs.into_iter().zip(results).collect())
     // GearboxResult { results }
 }
 
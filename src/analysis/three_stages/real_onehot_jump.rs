@@ -22,7 +22,7 @@
 // use crate::analysis::mapping::same_bank::SameBankMapping;
 // use crate::analysis::mapping::same_bank_weighted::SameBankWeightedMapping;
 // use crate::analysis::mapping::*;
-// use crate::tools::{self, stop_signal};
+// use crate::tools::{self, cancel};
 // use crate::{
 //     analysis::mapping::Mapping,
 //     tools::{
@@ -1188,7 +1188,7 @@
 //                 next_print_percent = target_id + total_rows / 100;
 //                 next_print_time = now.elapsed().as_secs() + TIME_TO_LOG;
 
-//                 if stop_signal::read() {
+//                 if cancel::root().is_cancelled() {
 //                     info!("received stop signal, start writing results");
 //                     break;
 //                 }
@@ -1508,7 +1508,7 @@
 //         "info there will be {} bytes,start acquire the space",
 //         sim_size + temp_size
 //     );
-//     let mut _guard = crate::acquire_memory_sections(&[sim_size, temp_size]);
+//     let mut _guard = crate::try_acquire_memory_sections([sim_size, temp_size])?;
 //     let _guard_temp = _guard.pop().unwrap();
 //     let _guard_sim = _guard.pop().unwrap();
 
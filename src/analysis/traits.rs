@@ -1,7 +1,10 @@
-use std::{cmp::Reverse, collections::BTreeMap, sync::atomic::Ordering};
+use std::{
+    cmp::Reverse, collections::BTreeMap, mem::size_of, sync::atomic::Ordering, time::Duration,
+};
 
 use crate::analysis::remap_analyze::row_cycle::*;
 use crate::{
+    algorithms::replay::{self, AccessTrace},
     analysis::{
         mapping::{
             same_bank::{self, SameBankMapping},
@@ -14,14 +17,21 @@ use crate::{
         configv2::{ConfigV2, DramType},
         level::{ddr4, LevelTrait},
     },
-    tools::stop_signal,
-    TIME_TO_LOG,
+    tools::{
+        cancel::{self, CancellationToken},
+        checkpoint::Checkpointer,
+        control::{self, TaskProgress},
+        dram_trace::{DramTrace, TraceOp},
+        graph_reader, matrix_cache,
+        memory_profile::{self, MemoryReport},
+    },
+    MemoryGuard, TIME_TO_LOG,
 };
 use itertools::Itertools;
 use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
-use sprs::{io::MatrixHead, num_kinds::Pattern, CsMatI, TriMatI};
+use sprs::{num_kinds::Pattern, CsMatI, CsMatViewI};
 use statrs::statistics::Statistics;
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 /// give an array of data, return each filed's mean, std, max
 /// # Example
 /// ```ingore
@@ -58,6 +68,155 @@ pub enum DistributeType {
     Local,
     Remote,
 }
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-09
+/// Description: create the dram trace of a gearbox simulation of `graph`, `None` when the trace is not enabled in the
+/// config
+pub(crate) fn gearbox_dram_trace(
+    config: &ConfigV2,
+    graph: &str,
+    name: &str,
+) -> eyre::Result<Option<DramTrace>> {
+    let Some(trace_config) = &config.dram_trace else {
+        return Ok(None);
+    };
+    let total_size = ddr4::Storage::new(
+        config.channels.num,
+        config.ranks.num,
+        config.chips.num,
+        config.bank_groups.num,
+        config.banks.num,
+        config.subarrays,
+        config.rows,
+        config.columns,
+    );
+    DramTrace::for_graph(trace_config, total_size, graph, name).map(Some)
+}
+
+/// issue the read of the row of matrix b at `read` and the write of the result at `write` to the dram trace
+pub(crate) fn trace_read_write(
+    trace: &mut DramTrace,
+    read: (SubarrayId, PhysicRowId, PhysicColId),
+    write: Option<(SubarrayId, PhysicRowId, PhysicColId)>,
+) {
+    for ((subarray_id, row_id, col_id), op) in
+        std::iter::once((read, TraceOp::Read)).chain(write.map(|write| (write, TraceOp::Write)))
+    {
+        let path = trace
+            .mapping()
+            .subarray_path(subarray_id.0, row_id.0, col_id.0);
+        trace.issue(&path, op);
+    }
+}
+
+/// end the round of the trace of the simulators that can not return an error, a failed trace is dropped and the
+/// simulation continues without it
+pub(crate) fn end_trace_round(trace: &mut Option<DramTrace>, cycles: u64) {
+    if let Some(Err(e)) = trace.as_mut().map(|trace| trace.end_round(cycles)) {
+        error!("fail to write the dram trace, stop tracing: {:?}", e);
+        *trace = None;
+    }
+}
+
+/// matrix a and matrix b of a gearbox simulation
+pub(crate) struct GearboxMatrices {
+    pub matrix_a: CsMatI<Pattern, u32>,
+    pub matrix_b: CsMatI<Pattern, u32>,
+    /// the memory of the matrices and the simulator, keep it until the simulation finishes
+    pub guard: MemoryGuard,
+    pub estimated_bytes: usize,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-19
+/// Description: read the matrices of the gearbox simulation of `path` and acquire the memory of the matrices and the
+/// `sim_size` bytes of the simulator. each round of an access trace is a row of matrix a and the ids of the trace are
+/// used as they are, a graph is matrix a and its transpose is matrix b
+pub(crate) fn load_gearbox_matrices(path: &str, sim_size: usize) -> eyre::Result<GearboxMatrices> {
+    info!("reading mtx file: {}", path);
+    let read_time = std::time::Instant::now();
+    let (matrix_a, matrix_b, guard, estimated_bytes) = if replay::is_access_trace(path) {
+        // the trace is parsed before the memory is acquired, the size of a compressed trace says nothing about it
+        let (matrix_a, matrix_b) = AccessTrace::read(path)?.into_matrices();
+        let matrix_size = (matrix_a.rows() + matrix_b.rows() + 2) * size_of::<u32>()
+            + (matrix_a.nnz() + matrix_b.nnz()) * size_of::<u32>();
+        let sim_size = matrix_size + sim_size;
+        info!(
+            "info there will be {} bytes,start acquire the space",
+            sim_size
+        );
+        let guard = crate::try_acquire_memory_sections([sim_size])?
+            .pop()
+            .unwrap();
+        (matrix_a, matrix_b, guard, sim_size)
+    } else {
+        let matrix_head = graph_reader::read_graph_head(path)?;
+        let matrix_size =
+            matrix_head.ind_ptr_size() + matrix_head.ind_size() + matrix_head.data_size();
+        // two csr csc matrix during runtime
+        let matrix_size = matrix_size * 2;
+        let sim_size = matrix_size + sim_size;
+
+        let temp_size = matrix_head.tri_size();
+
+        info!(
+            "info there will be {} bytes,start acquire the space",
+            sim_size + temp_size
+        );
+        let mut guard = crate::try_acquire_memory_sections([sim_size, temp_size])?;
+        let guard_temp = guard.pop().unwrap();
+        let guard_sim = guard.pop().unwrap();
+
+        let matrix = matrix_cache::load_or_build(path)?;
+        info!(
+            "finished read the matrix: time:{:.2} secs",
+            read_time.elapsed().as_secs_f32()
+        );
+        let (matrix_a, matrix_b): (CsMatI<Pattern, u32>, CsMatI<Pattern, u32>) =
+            (matrix.csr, matrix.csc.transpose_into());
+
+        drop(guard_temp);
+        (matrix_a, matrix_b, guard_sim, sim_size + temp_size)
+    };
+
+    info!(
+        "finished transpose the matrix: time:{:.2} secs",
+        read_time.elapsed().as_secs_f32()
+    );
+    info!(
+        mat_b_rows = matrix_b.rows(),
+        mat_b_cols = matrix_b.cols(),
+        "matrix b shape"
+    );
+    Ok(GearboxMatrices {
+        matrix_a,
+        matrix_b,
+        guard,
+        estimated_bytes,
+    })
+}
+
+/// the (id, nnz) of the rows and the cols of matrix b, the ids with more non-zeros go first. matrix a is not the
+/// transpose of matrix b when replaying a trace, so the cols are counted in matrix b
+pub(crate) fn sorted_nnz(
+    matrix_b: CsMatViewI<Pattern, u32>,
+) -> (Vec<(usize, usize)>, Vec<(usize, usize)>) {
+    let mut mat_b_row_ids = (0..matrix_b.rows())
+        .zip(matrix_b.outer_iterator().map(|row| row.nnz()))
+        .collect_vec();
+    let mut mat_b_col_nnz = vec![0usize; matrix_b.cols()];
+    for &col in matrix_b.indices() {
+        mat_b_col_nnz[col as usize] += 1;
+    }
+    let mut mat_b_col_ids = (0..matrix_b.cols()).zip(mat_b_col_nnz).collect_vec();
+    mat_b_row_ids.sort_by_key(|(_index, nnz)| Reverse(*nnz));
+    mat_b_col_ids.sort_by_key(|(_index, nnz)| Reverse(*nnz));
+    (mat_b_row_ids, mat_b_col_ids)
+}
+
 use super::mapping::Mapping;
 #[allow(unused_variables)]
 pub trait GearboxSimTrait<'matrix, 'config> {
@@ -138,36 +297,189 @@ pub trait GearboxSimTrait<'matrix, 'config> {
     ) {
     }
 
-    fn handle_update_stats(&mut self) {}
+    /// update the stats at the end of a round, return the cycles of the round
+    fn handle_update_stats(&mut self) -> u64 {
+        0
+    }
 
     fn get_matrix_b(&self) -> &CsMatI<Pattern, u32>;
-    fn run(&mut self, input_vec: &CsMatI<Pattern, u32>, current_batch: usize, _current_topk: f32) {
+
+    /// the trace of the dram accesses, `None` means the trace is not enabled in the config
+    fn dram_trace(&mut self) -> Option<&mut DramTrace>;
+    /// record the dram accesses of the simulation to `trace`
+    fn set_dram_trace(&mut self, trace: DramTrace);
+    /// issue the read of the row of matrix b and the write of the result to the dram trace, there is no write when
+    /// `mat_b_col_id` is `None`
+    fn trace_distribute(
+        &mut self,
+        target_id: LogicRowId,
+        mat_b_row_id: LogicRowId,
+        mat_b_col_id: Option<LogicColId>,
+    ) {
+        if self.dram_trace().is_none() {
+            return;
+        }
+        let mapping = self.get_mapping();
+        let read = mapping.get_matrix_b_location(mat_b_row_id);
+        let write = mat_b_col_id.map(|col_id| mapping.get_result_dense_location(target_id, col_id));
+        trace_read_write(self.dram_trace().unwrap(), read, write);
+    }
+
+    /// whether [`GearboxSimTrait::checkpoint_state`] returns a state, the state is only serialized when a
+    /// checkpoint is saved
+    fn supports_checkpoint(&self) -> bool {
+        false
+    }
+    /// the state saved in the checkpoint, `None` means the simulator can not be checkpointed
+    fn checkpoint_state(&self) -> Option<serde_json::Value> {
+        None
+    }
+    /// restore the state returned by [`GearboxSimTrait::checkpoint_state`]
+    fn restore_checkpoint_state(&mut self, _state: serde_json::Value) -> eyre::Result<()> {
+        eyre::bail!("this simulator does not support checkpoint")
+    }
+
+    /// run the simulation until all rows are processed or `cancel` is cancelled
+    fn run(
+        &mut self,
+        input_vec: &CsMatI<Pattern, u32>,
+        current_batch: usize,
+        current_topk: f32,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<()> {
+        self.run_from(
+            input_vec,
+            current_batch,
+            current_topk,
+            0,
+            None,
+            "gearbox",
+            cancel,
+        )
+    }
+
+    /// run the simulation and save the checkpoints to `config.checkpoint_dir`,
+    /// when `config.resume` is set, continue from the last checkpoint
+    fn run_checkpointed(
+        &mut self,
+        input_vec: &CsMatI<Pattern, u32>,
+        current_batch: usize,
+        current_topk: f32,
+        config: &ConfigV2,
+        graph: &str,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<()> {
+        let name = format!("{:?}_{current_batch}_{current_topk}", config.mapping);
+        let task_name = format!("{graph}_{name}");
+        let Some(dir) = &config.checkpoint_dir else {
+            if config.resume {
+                eyre::bail!("`--resume` needs `checkpoint_dir` in the config");
+            }
+            return self.run_from(
+                input_vec,
+                current_batch,
+                current_topk,
+                0,
+                None,
+                &task_name,
+                cancel,
+            );
+        };
+        if !self.supports_checkpoint() {
+            eyre::bail!(
+                "the simulator of {} can not be checkpointed, remove `checkpoint_dir` and `--resume`",
+                task_name
+            );
+        }
+        let mut checkpointer = Checkpointer::for_graph(
+            dir,
+            graph,
+            &name,
+            Duration::from_secs(config.checkpoint_interval),
+            config.resume,
+        )
+        .with_fingerprint(config.checkpoint_fingerprint());
+        let mut start_row = 0;
+        if let Some(checkpoint) = checkpointer.load::<serde_json::Value>()? {
+            self.restore_checkpoint_state(checkpoint.state)?;
+            if checkpoint.finished {
+                return Ok(());
+            }
+            start_row = checkpoint.target_id;
+        }
+        self.run_from(
+            input_vec,
+            current_batch,
+            current_topk,
+            start_row,
+            Some(&mut checkpointer),
+            &task_name,
+            cancel,
+        )
+    }
+
+    /// run from `start_row`, the checkpoint is only saved at the end of a batch.
+    /// the progress is reported to the control server as `task_name`,
+    /// when `cancel` is cancelled, it returns early and the stats so far can still be reported,
+    /// with a checkpointer the current batch is finished first, its state is saved so the resumed run starts from
+    /// the next batch
+    fn run_from(
+        &mut self,
+        input_vec: &CsMatI<Pattern, u32>,
+        current_batch: usize,
+        _current_topk: f32,
+        start_row: usize,
+        mut checkpointer: Option<&mut Checkpointer>,
+        task_name: &str,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<()> {
         let now = std::time::Instant::now();
         debug!("run gearbox sim");
 
         // distribute the task to components
         let total_rows = input_vec.rows();
+        let mut progress = TaskProgress::register(task_name, total_rows - start_row, cancel);
+        if !self.supports_checkpoint() {
+            // `dump-partial` reports the task instead of waiting for a file that never comes
+            progress = progress.without_partial();
+        }
         // print every 1% or every 60s
         let mut next_print_percent = total_rows / 100;
         let mut next_print_time = TIME_TO_LOG;
+        // only the end of a batch is a consistent state, a cancelled run with a checkpointer stops there
+        let mut stopping = false;
         //each data size if 8 bytes and there are 512 rows in a subarray
 
-        for (target_id, row) in input_vec.outer_iterator().enumerate() {
+        for (target_id, row) in input_vec.outer_iterator().enumerate().skip(start_row) {
             if target_id >= next_print_percent || now.elapsed().as_secs() >= next_print_time {
                 let time = now.elapsed().as_secs_f32();
                 let min = time / 60.;
-                let remaining = time / target_id as f32 * (total_rows - target_id) as f32;
+                let processed = target_id - start_row;
+                let remaining = time / processed as f32 * (total_rows - target_id) as f32;
                 let min_r = remaining / 60.;
-                let speed = target_id as f32 / min;
+                let speed = processed as f32 / min;
                 tracing::trace!("{target_id} of {total_rows} rows processed, time eclips: {min:.2}, estimate remaining time:{min_r:.2},speed: {speed} rows per min");
                 next_print_percent = target_id + total_rows / 100;
                 next_print_time = now.elapsed().as_secs() + TIME_TO_LOG;
                 // if next_print_time > 3000 {
                 //     break;
                 // }
-                if stop_signal::read() {
-                    info!("received stop signal, start writing results");
-                    break;
+                // only the simulators with a state are asked for the partial result
+                if let Some((path, state)) = progress
+                    .update(processed)
+                    .and_then(|path| Some((path, self.checkpoint_state()?)))
+                {
+                    if let Err(e) = control::write_partial(&path, &state) {
+                        error!("failed to write partial result: {}", e);
+                    }
+                }
+                if let Some(reason) = cancel.cancel_reason().filter(|_| !stopping) {
+                    info!("{:?}: {}, start writing results", reason, task_name);
+                    cancel::record_partial_run();
+                    if checkpointer.is_none() {
+                        return Ok(());
+                    }
+                    stopping = true;
                 }
             }
             // fix bug here, we should collect the evil col for each target id
@@ -177,6 +489,7 @@ pub trait GearboxSimTrait<'matrix, 'config> {
             for &mat_b_row_id in row.indices() {
                 let mat_b_row_id = mat_b_row_id as usize;
                 if self.evil_row_contains(mat_b_row_id) {
+                    self.trace_distribute(LogicRowId(target_id), LogicRowId(mat_b_row_id), None);
                     self.handle_evil_row(LogicRowId(target_id), LogicRowId(mat_b_row_id));
                 } else {
                     // the row is not evil, need to access remote
@@ -186,6 +499,11 @@ pub trait GearboxSimTrait<'matrix, 'config> {
                         .unwrap()
                         .to_owned();
                     for col in row.indices().iter().map(|i| *i as usize) {
+                        self.trace_distribute(
+                            LogicRowId(target_id),
+                            LogicRowId(mat_b_row_id),
+                            Some(LogicColId(col)),
+                        );
                         if self.evil_col_contains(col) {
                             // the col is evil, no need to access remote
                             // self.hardware
@@ -253,16 +571,43 @@ pub trait GearboxSimTrait<'matrix, 'config> {
             // the cycle of this round
             if (target_id + 1) % current_batch == 0 {
                 // update_stats(&mut self.hardware, &mut global_stats);
-                self.handle_update_stats();
+                let cycles = self.handle_update_stats();
+                if let Some(trace) = self.dram_trace() {
+                    trace.end_round(cycles)?;
+                }
                 // the data for overflow:
+                if let Some(checkpointer) = checkpointer.as_deref_mut() {
+                    if stopping || checkpointer.is_due() {
+                        if let Some(state) = self.checkpoint_state() {
+                            checkpointer.save(target_id + 1, false, &state)?;
+                        }
+                    }
+                }
+                if stopping {
+                    return Ok(());
+                }
             }
             // add the result to the total result and continue to the next line
         }
+        if let Some(checkpointer) = checkpointer {
+            if let Some(state) = self.checkpoint_state() {
+                checkpointer.save(total_rows, true, &state)?;
+            }
+        }
+        Ok(())
     }
     fn report(&self, name: String, batch: usize, topk: f32) -> Self::SingleResult;
 }
 type BatchTopk = (usize, f32);
 
+/// the output of [`AnalyzeTool::analyze_gearbox`]
+pub struct GearboxOutput<R> {
+    /// the results of each (batch, topk), a result for each graph
+    pub results: Vec<(BatchTopk, Vec<R>)>,
+    /// the memory of each graph
+    pub memory: Vec<MemoryReport>,
+}
+
 /// the tool to perform analysis of gearbox of multiple config and graphs
 pub trait AnalyzeTool {
     /// the result that reported by the tool
@@ -280,7 +625,7 @@ pub trait AnalyzeTool {
     fn analyze_gearbox_inner<LevelType: LevelTrait>(
         config: &ConfigV2,
         _total_size: &LevelType::Storage,
-    ) -> Vec<(BatchTopk, Vec<Self::ResultType>)>
+    ) -> eyre::Result<GearboxOutput<Self::ResultType>>
     where
         LevelType::Storage: Debug + Sync,
         LevelType::Mapping: Debug,
@@ -290,6 +635,8 @@ pub trait AnalyzeTool {
         let total_tasks = total_graphs * total_configs;
         info!(?total_graphs, ?total_configs, ?total_tasks, "total tasks");
         *TOTAL_TASKS.write().unwrap() = total_tasks;
+        let config_cancel =
+            cancel::root().child_with_timeout(config.deadline_secs.map(Duration::from_secs));
 
         let results: Vec<_> = config
             .graph_path
@@ -297,9 +644,12 @@ pub trait AnalyzeTool {
             .enumerate()
             .map(|(index, path)| {
                 info!("analyzing graph {}/{}", index + 1, total_graphs);
-                Self::compute_gearbox(config, path)
+                let graph_cancel = config_cancel
+                    .child_with_timeout(config.graph_deadline_secs.map(Duration::from_secs));
+                Self::compute_gearbox(config, path, &graph_cancel)
             })
-            .collect();
+            .collect::<eyre::Result<Vec<_>>>()?;
+        let (results, memory): (Vec<_>, Vec<_>) = results.into_iter().unzip();
         let results = super::transpose2(results);
         let configs = config
             .gearbox_config
@@ -310,12 +660,15 @@ pub trait AnalyzeTool {
             .collect_vec();
         info!(?configs, "configs");
         assert_eq!(configs.len(), results.len());
-        configs.into_iter().zip(results).collect()
+        Ok(GearboxOutput {
+            results: configs.into_iter().zip(results).collect(),
+            memory,
+        })
     }
     /// the entry point of the analysis
     /// ## Return
-    /// a vector of (config, results)
-    fn analyze_gearbox(config: &ConfigV2) -> Vec<(BatchTopk, Vec<Self::ResultType>)> {
+    /// a vector of (config, results) and the memory of each graph
+    fn analyze_gearbox(config: &ConfigV2) -> eyre::Result<GearboxOutput<Self::ResultType>> {
         match config.dram_type {
             DramType::DDR3 => unimplemented!(),
             DramType::DDR4 => {
@@ -337,8 +690,13 @@ pub trait AnalyzeTool {
             DramType::HBM2 => unimplemented!(),
         }
     }
-    /// compute a simgle graph
-    fn compute_gearbox(config: &ConfigV2, path: &str) -> Vec<Self::ResultType> {
+    /// compute a simgle graph, the configs of this graph share the same `cancel`,
+    /// return the result of each config and the memory used by the graph
+    fn compute_gearbox(
+        config: &ConfigV2,
+        path: &str,
+        cancel: &CancellationToken,
+    ) -> eyre::Result<(Vec<Self::ResultType>, MemoryReport)> {
         // for hbm config, they should be 1!
         assert!(config.ranks.num == 1);
         assert!(config.chips.num == 1);
@@ -351,57 +709,15 @@ pub trait AnalyzeTool {
             * config.subarrays;
 
         info!(?partitions, "compute gearbox");
-        info!("reading mtx file: {}", path);
-        let read_time = std::time::Instant::now();
-        let matrix_head: MatrixHead<Pattern, u32> =
-            sprs::io::read_matrix_market_head(path).unwrap();
-        let matrix_size =
-            matrix_head.ind_ptr_size() + matrix_head.ind_size() + matrix_head.data_size();
-        // two csr csc matrix during runtime
-        let matrix_size = matrix_size * 2;
-        let sim_size = partitions * Self::SUBARRAY_SIZE * 2;
-        let sim_size = matrix_size + sim_size;
-
-        let temp_size = matrix_head.tri_size();
-
-        info!(
-            "info there will be {} bytes,start acquire the space",
-            sim_size + temp_size
-        );
-        let mut _guard = crate::acquire_memory_sections([sim_size, temp_size]);
-        let _guard_temp = _guard.pop().unwrap();
-        let _guard_sim = _guard.pop().unwrap();
-
-        let tri_mat: TriMatI<Pattern, u32> = sprs::io::read_matrix_market(path).unwrap();
-        info!(
-            "finished read the matrix: time:{:.2} secs",
-            read_time.elapsed().as_secs_f32()
-        );
-        let (matrix_a, matrix_b): (CsMatI<Pattern, u32>, CsMatI<Pattern, u32>) =
-            (tri_mat.to_csr(), tri_mat.transpose_view().to_csr());
-
-        drop(tri_mat);
-        drop(_guard_temp);
-
-        info!(
-            "finished transpose the matrix: time:{:.2} secs",
-            read_time.elapsed().as_secs_f32()
-        );
-        let mat_b_rows = matrix_b.rows();
-        let mat_b_cols = matrix_b.cols();
-
-        info!(?mat_b_rows, ?mat_b_cols, "matrix b shape");
-
-        // the nnz of matrix b rows
-        let mut mat_b_row_ids = (0..mat_b_rows)
-            .zip(matrix_b.outer_iterator().map(|row| row.nnz()))
-            .collect_vec();
-        // the nnz of matrix b cols
-        let mut mat_b_col_ids = (0..mat_b_cols)
-            .zip(matrix_a.outer_iterator().map(|row| row.nnz()))
-            .collect_vec();
-        mat_b_row_ids.sort_by_key(|(_index, nnz)| Reverse(*nnz));
-        mat_b_col_ids.sort_by_key(|(_index, nnz)| Reverse(*nnz));
+        let GearboxMatrices {
+            matrix_a,
+            matrix_b,
+            guard: _guard_sim,
+            estimated_bytes,
+        } = load_gearbox_matrices(path, partitions * Self::SUBARRAY_SIZE * 2)?;
+        let memory_profile =
+            memory_profile::start_job(path, estimated_bytes as u64, _guard_sim.waited());
+        let (mat_b_row_ids, mat_b_col_ids) = sorted_nnz(matrix_b.view());
         // draw_distribution(
         //     &mat_b_row_ids,
         //     Path::new(&format!("output/{}-{}", file_name, "mat_b_row_ids.png")),
@@ -433,6 +749,12 @@ pub trait AnalyzeTool {
                 let num_cols = matrix_b.cols();
                 let row_per_partition = (num_rows + num_partitions - 1) / num_partitions;
                 let col_per_partition = (num_cols + num_partitions - 1) / num_partitions;
+                // a resumed run only traces the rows after the checkpoint
+                let dram_trace = gearbox_dram_trace(
+                    config,
+                    path,
+                    &format!("{:?}_{batch}_{top_k}", config.mapping),
+                )?;
 
                 match config.mapping {
                     crate::pim::configv2::MappingType::SameSubarray => {
@@ -448,15 +770,18 @@ pub trait AnalyzeTool {
                             config,
                             mapping,
                         );
+                        if let Some(trace) = dram_trace {
+                            gearbox.set_dram_trace(trace);
+                        }
                         info!("start running the sim");
-                        gearbox.run(&matrix_a, batch, top_k);
+                        gearbox.run_checkpointed(&matrix_a, batch, top_k, config, path, cancel)?;
                         TOTAL_FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
                         info!(
                             "finished task: {}/{}",
                             TOTAL_FINISHED_TASKS.load(Ordering::Relaxed),
                             *TOTAL_TASKS.read().unwrap()
                         );
-                        gearbox.report(path.to_string(), batch, top_k)
+                        Ok(gearbox.report(path.to_string(), batch, top_k))
                     }
                     crate::pim::configv2::MappingType::SameBank => {
                         let mapping = same_bank::SameBankMapping::new(
@@ -473,15 +798,18 @@ pub trait AnalyzeTool {
                             config,
                             mapping,
                         );
+                        if let Some(trace) = dram_trace {
+                            gearbox.set_dram_trace(trace);
+                        }
                         info!("start running the sim");
-                        gearbox.run(&matrix_a, batch, top_k);
+                        gearbox.run_checkpointed(&matrix_a, batch, top_k, config, path, cancel)?;
                         TOTAL_FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
                         info!(
                             "finished task: {}/{}",
                             TOTAL_FINISHED_TASKS.load(Ordering::Relaxed),
                             *TOTAL_TASKS.read().unwrap()
                         );
-                        gearbox.report(path.to_string(), batch, top_k)
+                        Ok(gearbox.report(path.to_string(), batch, top_k))
                     }
                     crate::pim::configv2::MappingType::SameBankWeightedMapping => {
                         let mapping = SameBankWeightedMapping::new(
@@ -499,23 +827,27 @@ pub trait AnalyzeTool {
                                 config,
                                 mapping,
                             );
+                        if let Some(trace) = dram_trace {
+                            gearbox.set_dram_trace(trace);
+                        }
                         info!("start running the sim");
-                        gearbox.run(&matrix_a, batch, top_k);
+                        gearbox.run_checkpointed(&matrix_a, batch, top_k, config, path, cancel)?;
                         TOTAL_FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
                         info!(
                             "finished task: {}/{}",
                             TOTAL_FINISHED_TASKS.load(Ordering::Relaxed),
                             *TOTAL_TASKS.read().unwrap()
                         );
-                        gearbox.report(path.to_string(), batch, top_k)
+                        Ok(gearbox.report(path.to_string(), batch, top_k))
                     }
                 }
             })
-            .collect();
+            .collect::<eyre::Result<_>>()?;
         drop(matrix_a);
         drop(matrix_b);
+        let memory = memory_profile.finish();
         drop(_guard_sim);
-        results
+        Ok((results, memory))
     }
 }
 
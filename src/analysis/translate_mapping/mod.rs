@@ -1,6 +1,9 @@
 pub mod evil_mapping;
 pub mod weighted;
-use crate::analysis::remap_analyze::row_cycle::*;
+use crate::{
+    analysis::remap_analyze::row_cycle::*,
+    tools::mapping_cache::{MappingCache, MappingKey},
+};
 use itertools::Itertools;
 use sprs::{num_kinds::Pattern, CsMatI, CsMatViewI, CsVecViewI, TriMatI};
 use tracing::debug;
@@ -32,6 +35,46 @@ pub trait TranslateMapping {
     fn is_evil(&self, row_id: LogicRowId) -> bool;
 }
 
+/// the mappings that can be saved in the [`MappingCache`], the translated graph and the evil sub graphs are cached
+pub trait CachedTranslateMapping: TranslateMapping<RowSubMapping = AverageMapping> + Sized {
+    /// the name of the mapping in the cache key
+    const KIND: &'static str;
+    /// rebuild the mapping from the cached row sub mapping
+    fn from_row_sub_mapping(row_sub_mapping: AverageMapping, evil_threshold: usize) -> Self;
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-09
+/// Description: load the mapping and the translated graph from the cache, when the cache is missed,
+/// `build` is called and the result is stored.
+pub fn build_cached<M: CachedTranslateMapping>(
+    cache: &MappingCache,
+    key: &MappingKey,
+    build: impl FnOnce() -> (M, CsMatI<Pattern, u32>),
+) -> (M, CsMatI<Pattern, u32>) {
+    if let Some(cached) = cache.load(key) {
+        let row_sub_mapping = AverageMapping::from_evil_sub_graph(
+            cached.translated.view(),
+            key.evil_threshold,
+            key.total_subarrays(),
+            key.cols,
+            cached.evil_row_sub_graph,
+        );
+        return (
+            M::from_row_sub_mapping(row_sub_mapping, key.evil_threshold),
+            cached.translated,
+        );
+    }
+    let (mapping, translated) = build();
+    cache.store(
+        key,
+        translated.view(),
+        mapping.get_row_sub_mapping().evil_row_sub_graph(),
+    );
+    (mapping, translated)
+}
+
 /// this trait is a mapping which get the logic location to physic location
 pub trait RowSubarrayMapping {
     /// get the physic location of a matrix b row
@@ -81,23 +124,9 @@ impl AverageMapping {
         subarrays: usize,
         cols: usize,
     ) -> Self {
-        // first calculate the accumulated nnz of the graph
-        let non_evil_accumulated_nnz =
-            graph
-                .outer_iterator()
-                .skip(evil_rows)
-                .fold(vec![0], |mut acc, row| {
-                    let last = acc.last().unwrap();
-                    acc.push(last + row.nnz());
-                    acc
-                });
-        let non_evil_rows = graph.rows() - evil_rows;
-        debug!(non_evil_rows);
-
-        let row_bounds = get_upper_lower_bound(non_evil_rows, subarrays);
         let col_bounds = get_upper_lower_bound(graph.rows(), subarrays);
 
-        // then handle the evil row
+        // handle the evil row
         // for each evil row, we biuld a subgraph for it
         // fix the bug here, the row if the partial graph is evil_rows, otherwise the size will be too large!(2000GB+)
         let mut evil_row_sub_graph: Vec<TriMatI<Pattern, u32>> = (0..subarrays)
@@ -113,6 +142,34 @@ impl AverageMapping {
             .into_iter()
             .map(|x| x.to_csr())
             .collect_vec();
+        Self::from_evil_sub_graph(graph, evil_rows, subarrays, cols, evil_row_sub_graph)
+    }
+
+    /// same as [`AverageMapping::new`], but the evil sub graphs are already built, used by the mapping cache
+    pub fn from_evil_sub_graph(
+        graph: CsMatViewI<Pattern, u32>,
+        evil_rows: usize,
+        subarrays: usize,
+        cols: usize,
+        evil_row_sub_graph: Vec<CsMatI<Pattern, u32>>,
+    ) -> Self {
+        assert_eq!(evil_row_sub_graph.len(), subarrays);
+        // first calculate the accumulated nnz of the graph
+        let non_evil_accumulated_nnz =
+            graph
+                .outer_iterator()
+                .skip(evil_rows)
+                .fold(vec![0], |mut acc, row| {
+                    let last = acc.last().unwrap();
+                    acc.push(last + row.nnz());
+                    acc
+                });
+        let non_evil_rows = graph.rows() - evil_rows;
+        debug!(non_evil_rows);
+
+        let row_bounds = get_upper_lower_bound(non_evil_rows, subarrays);
+        let col_bounds = get_upper_lower_bound(graph.rows(), subarrays);
+
         let evil_row_accumulated_nnz = evil_row_sub_graph
             .iter()
             .map(|x| {
@@ -138,6 +195,11 @@ impl AverageMapping {
             col_mapping: col_bounds,
         }
     }
+
+    /// the evil rows of each subarray
+    pub fn evil_row_sub_graph(&self) -> &[CsMatI<Pattern, u32>] {
+        &self.row_mapping.evil_row_sub_graph
+    }
 }
 #[derive(Debug)]
 pub struct UpperLowerBound {
@@ -337,9 +399,15 @@ mod tests {
     use itertools::Itertools;
     use sprs::{num_kinds::Pattern, TriMatI};
 
-    use crate::tools;
+    use crate::tools::{
+        self,
+        mapping_cache::{MappingCache, MappingKey},
+    };
 
-    use super::{AverageMapping, RowSubarrayMapping};
+    use super::{
+        build_cached, weighted::SameBankWeightedMapping, AverageMapping, CachedTranslateMapping,
+        RowSubarrayMapping, TranslateMapping,
+    };
 
     #[test]
     #[cfg_attr(miri, ignore)]
@@ -379,4 +447,62 @@ mod tests {
             println!("{:?}", dense_location);
         }
     }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_mapping_cache() {
+        let graph: TriMatI<Pattern, u32> =
+            sprs::io::read_matrix_market("test_mtx/bcspwr03.mtx").unwrap();
+        let graph_csr = graph.to_csr();
+        let dir = std::env::temp_dir().join("spmspm_mapping_cache_test");
+        std::fs::remove_dir_all(&dir).ok();
+        let cache = MappingCache::new(&dir);
+        let key = MappingKey::new(
+            SameBankWeightedMapping::KIND,
+            graph_csr.view(),
+            2,
+            2,
+            2,
+            4,
+            16,
+        )
+        .unwrap();
+        let build = || SameBankWeightedMapping::new(2, 2, 2, 4, 16, &graph, &graph_csr);
+        let (built, built_csr) = build_cached(&cache, &key, build);
+        assert!(cache.path(&key).exists());
+        let (cached, cached_csr): (SameBankWeightedMapping, _) = build_cached(&cache, &key, || {
+            panic!("the mapping should be loaded from the cache")
+        });
+        assert_eq!(built_csr, cached_csr);
+        assert_eq!(
+            built.get_row_sub_mapping().evil_row_sub_graph(),
+            cached.get_row_sub_mapping().evil_row_sub_graph()
+        );
+        for row in 0..4 {
+            assert_eq!(
+                format!(
+                    "{:?}",
+                    built.get_location_evil(row.into(), built_csr.view())
+                ),
+                format!(
+                    "{:?}",
+                    cached.get_location_evil(row.into(), cached_csr.view())
+                )
+            );
+        }
+        for row in 4..graph_csr.rows() {
+            assert_eq!(
+                format!("{:?}", built.get_location(row.into(), built_csr.view())),
+                format!("{:?}", cached.get_location(row.into(), cached_csr.view()))
+            );
+        }
+
+        // another hardware config is another entry
+        let other_key = MappingKey {
+            banks: 4,
+            ..key.clone()
+        };
+        assert!(cache.load(&other_key).is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
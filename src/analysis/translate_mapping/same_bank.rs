@@ -22,6 +22,17 @@ impl super::TranslateMapping for SameBankMapping {
     }
 }
 
+impl super::CachedTranslateMapping for SameBankMapping {
+    const KIND: &'static str = "same_bank";
+
+    fn from_row_sub_mapping(row_sub_mapping: super::AverageMapping, evil_threshold: usize) -> Self {
+        Self {
+            row_sub_mapping,
+            evil_threshold,
+        }
+    }
+}
+
 impl SameBankMapping {
     pub fn new(
         total_banks: usize,
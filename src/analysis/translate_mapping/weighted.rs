@@ -36,6 +36,17 @@ impl TranslateMapping for SameBankWeightedMapping {
     }
 }
 
+impl super::CachedTranslateMapping for SameBankWeightedMapping {
+    const KIND: &'static str = "same_bank_weighted";
+
+    fn from_row_sub_mapping(row_sub_mapping: super::AverageMapping, evil_threshold: usize) -> Self {
+        Self {
+            row_sub_mapping,
+            evil_threshold,
+        }
+    }
+}
+
 impl SameBankWeightedMapping {
     /// create the new mapping,
     /// in this mapping, it achieve the goles defined here [`SameBankWeightedMapping`]
@@ -1,7 +1,7 @@
 use clap::Parser;
 use image::{ImageBuffer, Luma};
 use spmspm_pim::cli::GenerateGraphCli;
-use sprs::{num_kinds::Pattern, CsMat};
+use sprs::{num_kinds::Pattern, CsMat, CsMatI, SpIndex};
 use statrs::statistics::{Data, OrderStatistics};
 
 fn main() {
@@ -16,9 +16,9 @@ fn main() {
     let output_path = cli.output.unwrap_or("image.png".into());
     image.save(output_path).unwrap();
 }
-// This function takes a CsMat<T> matrix and returns an ImageBuffer<Luma<u8>> image
-pub fn matrix_to_image<T>(
-    matrix: CsMat<T>,
+// This function takes a CsMatI<T, I> matrix and returns an ImageBuffer<Luma<u8>> image
+pub fn matrix_to_image<T, I: SpIndex>(
+    matrix: CsMatI<T, I>,
     image_size: (usize, usize),
 ) -> ImageBuffer<Luma<u8>, Vec<u8>> {
     // Get the shape of the matrix
@@ -32,7 +32,7 @@ pub fn matrix_to_image<T>(
     for (row, vec) in matrix.outer_iterator().enumerate() {
         for (col, _) in vec.iter() {
             // Map the matrix coordinates to the image coordinates
-            let x = col * image_size.0 / cols;
+            let x = col.index() * image_size.0 / cols;
             let y = row * image_size.1 / rows;
             counts[x][y] += 1;
         }
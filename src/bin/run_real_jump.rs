@@ -14,8 +14,11 @@ use spmspm_pim::{
         translate_mapping::TranslateMapping,
     },
     init_logger_info,
-    pim::configv2::{ConfigV3, MappingType},
-    tools::file_server,
+    pim::{
+        config_migration,
+        configv2::{ConfigV3, MappingType},
+    },
+    tools::graph_reader,
 };
 use sprs::{num_kinds::Pattern, CsMatI, TriMatI};
 use tracing::{info, span::EnteredSpan};
@@ -24,8 +27,9 @@ static FINISHED_TASKS: AtomicUsize = AtomicUsize::new(0);
 static TOTAL_TASKS: AtomicUsize = AtomicUsize::new(0);
 fn main() -> eyre::Result<()> {
     init_logger_info();
-    let config: ConfigV3 =
-        toml::from_str(include_str!("../../configs/real_jump_same_bank-1-16.toml")).unwrap();
+    let config: ConfigV3 = config_migration::parse_config(include_str!(
+        "../../configs/real_jump_same_bank-1-16.toml"
+    ))?;
     let total_graphs = config.graph_path.len() * 2;
     TOTAL_TASKS.store(total_graphs, Ordering::SeqCst);
 
@@ -49,10 +53,7 @@ fn run_with_graph_path(
 ) -> (String, BTreeMap<MappingType, real_jump::RealJumpResult>) {
     let graph_path_file_name = graph_path.split('/').last().unwrap();
     let _span = tracing::span!(tracing::Level::INFO, "", g = graph_path_file_name).entered();
-    let matrix_tri: TriMatI<Pattern, u32> = sprs::io::read_matrix_market_from_bufread(
-        &mut file_server::file_reader(&graph_path).unwrap(),
-    )
-    .unwrap();
+    let matrix_tri: TriMatI<Pattern, u32> = graph_reader::read_graph(&graph_path).unwrap();
     let matrix_csr: CsMatI<Pattern, u32> = matrix_tri.to_csr();
     let result: BTreeMap<_, _> = [MappingType::SameBank, MappingType::SameBankWeightedMapping]
         .into_par_iter()
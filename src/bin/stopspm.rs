@@ -1,18 +1,37 @@
-use std::{fs, io::Write, net::TcpStream};
+use std::fs;
 
 use clap::Parser;
-use spmspm_pim::cli::StopCli;
+use spmspm_pim::{
+    cli::{ControlCommand, StopCli},
+    tools::control::{self, Command, Response},
+};
 
-fn main() {
+fn main() -> eyre::Result<()> {
     let cli = StopCli::parse();
-    let port = cli.port.unwrap_or_else(|| {
-        let path = cli.file_path.unwrap_or_else(|| "port".into());
-
-        fs::read_to_string(path).unwrap().parse().unwrap()
-    });
-    let addr = format!("127.0.0.1:{}", port);
-    let mut stream = TcpStream::connect(addr).unwrap();
-    stream.write_all(&33u32.to_le_bytes()).unwrap();
+    let port = match cli.port {
+        Some(port) => port,
+        None => {
+            let path = cli.file_path.unwrap_or_else(|| "port".into());
+            fs::read_to_string(path)?.trim().parse()?
+        }
+    };
+    let command = match cli.command {
+        ControlCommand::Status => Command::Status,
+        ControlCommand::Pause => Command::Pause,
+        ControlCommand::Resume => Command::Resume,
+        ControlCommand::Stop => Command::Stop,
+        ControlCommand::Cancel => Command::Cancel(
+            cli.task
+                .ok_or_else(|| eyre::eyre!("the task id is required by cancel"))?,
+        ),
+        ControlCommand::DumpPartial => Command::DumpPartial(cli.dump_dir),
+    };
+    let response = control::send_command(("127.0.0.1", port), &command)?;
+    println!("{}", serde_json::to_string_pretty(&response)?);
+    if let Response::Error { message } = response {
+        eyre::bail!("the server rejected the command: {}", message);
+    }
+    Ok(())
 }
 #[cfg(test)]
 mod tests {
@@ -30,6 +30,14 @@ pub enum Operation {
     Analyze(AnalyzeArgs),
     /// draw the graphs
     Draw(DrawCli),
+    /// build the binary matrix caches of the graphs in a config
+    Convert(ConvertArgs),
+    /// write the synthetic graphs to `.mtx` files
+    Generate(GenerateArgs),
+    /// run the real jump simulation over the `[sweep]` of a config
+    Sweep(SweepArgs),
+    /// run the analysis jobs listed in a manifest, the finished jobs are skipped on restart
+    Batch(BatchArgs),
 }
 
 /// the arguments of the run subcommand
@@ -39,6 +47,44 @@ pub struct RunArgs {
     pub config: PathBuf,
 }
 
+/// the arguments of the convert subcommand
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// the config file path, all graphs in its `graph_path` are converted
+    pub config: PathBuf,
+    /// rebuild the caches that are still fresh
+    #[clap(long)]
+    pub force: bool,
+}
+
+/// the arguments of the generate subcommand
+#[derive(Debug, Args)]
+pub struct GenerateArgs {
+    /// the generator uris, such as `rmat:scale=20,ef=16,a=0.57,seed=1` or `er:n=1e6,d=8`
+    #[clap(required = true)]
+    pub graphs: Vec<String>,
+    /// the dir of the generated `.mtx` files
+    #[clap(short, long, default_value = "mtx")]
+    pub output: PathBuf,
+}
+
+/// the arguments of the sweep subcommand
+#[derive(Debug, Args)]
+pub struct SweepArgs {
+    /// the config file path, its `[sweep]` table lists the values of the swept fields
+    pub config: PathBuf,
+    /// continue each point from its last checkpoint
+    #[clap(long)]
+    pub resume: bool,
+}
+
+/// the arguments of the batch subcommand
+#[derive(Debug, Args)]
+pub struct BatchArgs {
+    /// the manifest file path, see `configs/batch/real_jump.toml`
+    pub manifest: PathBuf,
+}
+
 /// the arguments of the analyze subcommand
 #[derive(Debug, Args)]
 pub struct AnalyzeArgs {
@@ -46,6 +92,9 @@ pub struct AnalyzeArgs {
     pub analyze: AnalyzeType,
     /// the config file path
     pub config: PathBuf,
+    /// continue from the last checkpoint in the `checkpoint_dir` of the config
+    #[clap(long)]
+    pub resume: bool,
 }
 
 /// the type of analysis
@@ -106,6 +155,16 @@ pub enum AnalyzeType {
     NewAnalysis,
 }
 
+impl AnalyzeType {
+    /// whether the analysis saves checkpoints and can continue from them by `--resume`
+    pub fn supports_resume(&self) -> bool {
+        matches!(
+            self,
+            AnalyzeType::AnalyzeBankTrace | AnalyzeType::NewAnalysis
+        )
+    }
+}
+
 #[derive(Debug, Subcommand)]
 pub enum DrawType {
     /// draw the speed up of spmm and gearbox
@@ -143,14 +202,40 @@ pub struct DrawCli {
 }
 
 #[derive(Parser, Debug)]
-#[command(name = "stopspm", about = "stop spmm experiment")]
+#[command(name = "stopspm", about = "control a running spmm experiment")]
 pub struct StopCli {
+    /// the command sent to the experiment
+    #[clap(value_enum, default_value = "stop")]
+    pub command: ControlCommand,
     /// the port of the server
     #[clap(short, long)]
     pub port: Option<u16>,
     /// the path of the file storing the port
     #[clap(short, long)]
     pub file_path: Option<PathBuf>,
+    /// the dir of the partial results, only used by `dump-partial`
+    #[clap(short, long)]
+    pub dump_dir: Option<PathBuf>,
+    /// the id of the task shown in `status`, only used by `cancel`
+    #[clap(short, long)]
+    pub task: Option<usize>,
+}
+
+/// the commands of the control protocol, see [`crate::tools::control`]
+#[derive(Debug, Clone, ValueEnum)]
+pub enum ControlCommand {
+    /// show the progress of the running tasks
+    Status,
+    /// pause all running tasks
+    Pause,
+    /// resume the paused tasks
+    Resume,
+    /// stop the experiment and write the results
+    Stop,
+    /// stop a single task and write its results
+    Cancel,
+    /// write the partial results of the running tasks
+    DumpPartial,
 }
 
 #[derive(Args, Debug)]
@@ -6,22 +6,13 @@ pub mod pim;
 use clap::Parser;
 use cli::{AnalyzeArgs, Cli, RunArgs};
 use eyre::Result;
-use once_cell::sync::Lazy;
 pub use pim::Simulator;
 pub mod tools;
-use crate::tools::stop_signal;
+use crate::tools::{control, memory_scheduler};
 pub mod algorithms;
 use lazy_static::lazy_static;
-use std::{
-    env,
-    ffi::OsString,
-    fs,
-    io::{self, Read},
-    net::TcpListener,
-    sync::{Condvar, Mutex},
-};
-use sysinfo::SystemExt;
-use tracing::{error, info, metadata::LevelFilter};
+use std::{ffi::OsString, fs, io, net::TcpListener};
+use tracing::{info, metadata::LevelFilter};
 use tracing_subscriber::fmt::MakeWriter;
 pub mod cli;
 pub mod draw;
@@ -63,47 +54,12 @@ pub fn init_logger(
 ) {
     init_logger_with_ansi(filter, writter, false);
 }
-static CURRENT_MEMORY_USAGE: Mutex<u64> = Mutex::new(0);
-static MEMORY_CONDVAR: Condvar = Condvar::new();
-fn get_memory_free() -> u64 {
-    let mut sysinfo = sysinfo::System::new_all();
-    sysinfo.refresh_memory();
-    sysinfo.available_memory()
-}
-/// parse the memory limit from the environment variable MEMORY_LIMIT, if error, return the current memory available
-fn parse_memory_limit() -> u64 {
-    let limit = match env::var("MEMORY_LIMIT") {
-        Ok(limmit) => parse_size::parse_size(limmit).unwrap_or_else(|e| {
-            eprintln!("failed to parse memory limit: {}", e);
-            get_memory_free()
-        }),
-        Err(_) => get_memory_free(),
-    };
-    info!("memory limit: {} bytes", limit);
-    limit
-}
 lazy_static! {}
-static TOTAL_MEMORY: Lazy<u64> = Lazy::new(parse_memory_limit);
-pub struct MemoryGuard(u64);
+pub use tools::memory_scheduler::MemoryGuard;
 
-///acquire memory, if the memory limit is exceeded, wait until the memory is released
-///
-///
-///  the returned value is a guard, when the guard is dropped, the memory is released
-#[must_use]
-pub fn acquire_memory<T: IntoU64>(size: T) -> MemoryGuard {
-    let size = size.into_u64();
-    info!("trying to acquire memory: {} bytes", size);
-    if size > *TOTAL_MEMORY {
-        panic!("memory limit exceeded");
-    }
-    let mut memory = CURRENT_MEMORY_USAGE.lock().unwrap();
-    while *memory + size > *TOTAL_MEMORY {
-        memory = MEMORY_CONDVAR.wait(memory).unwrap();
-    }
-    info!("memory acquired");
-    *memory += size;
-    MemoryGuard(size)
+/// the memory that can be acquired by [`try_acquire_memory_sections`]
+pub fn memory_limit() -> u64 {
+    memory_scheduler::scheduler().limit()
 }
 
 pub trait IntoU64 {
@@ -132,38 +88,20 @@ impl IntoU64 for i32 {
     }
 }
 
-#[must_use]
-pub fn acquire_memory_sections<T: IntoU64>(size: impl IntoIterator<Item = T>) -> Vec<MemoryGuard> {
-    let size = size.into_iter().map(IntoU64::into_u64).collect::<Vec<_>>();
-    assert!(!size.is_empty(), "size is empty");
-    let total_size = size.iter().sum::<u64>();
-    info!(
-        "trying to acquire memory: {} GB",
-        total_size as f32 / 1024.0 / 1024.0 / 1024.0
-    );
-    if total_size > *TOTAL_MEMORY {
-        panic!("memory limit exceeded");
-    }
-    let mut memory = CURRENT_MEMORY_USAGE.lock().unwrap();
-    while *memory + total_size > *TOTAL_MEMORY {
-        memory = MEMORY_CONDVAR.wait(memory).unwrap();
-    }
-    info!(
-        "memory {} GB acquired, {} GB reamaining",
-        total_size as f32 / 1024.0 / 1024.0 / 1024.0,
-        (*TOTAL_MEMORY - *memory - total_size) as f32 / 1024.0 / 1024.0 / 1024.0
-    );
-    *memory += total_size;
-    size.into_iter().map(MemoryGuard).collect()
+/// acquire all the sections together, wait in the queue of the memory scheduler until they fit
+pub fn try_acquire_memory_sections<T: IntoU64>(
+    size: impl IntoIterator<Item = T>,
+) -> eyre::Result<Vec<MemoryGuard>> {
+    try_acquire_memory_sections_with_priority(size, 0)
 }
 
-impl Drop for MemoryGuard {
-    fn drop(&mut self) {
-        let mut memory = CURRENT_MEMORY_USAGE.lock().unwrap();
-        *memory -= self.0;
-        drop(memory);
-        MEMORY_CONDVAR.notify_all();
-    }
+/// like [`try_acquire_memory_sections`], the jobs with larger `priority` go first when `MEMORY_POLICY=priority`
+pub fn try_acquire_memory_sections_with_priority<T: IntoU64>(
+    size: impl IntoIterator<Item = T>,
+    priority: i64,
+) -> eyre::Result<Vec<MemoryGuard>> {
+    let size = size.into_iter().map(IntoU64::into_u64).collect::<Vec<_>>();
+    memory_scheduler::scheduler().acquire(size, priority)
 }
 
 #[allow(dead_code)]
@@ -182,38 +120,16 @@ pub fn init_logger_stderr(filter: LevelFilter) {
 }
 pub const TIME_TO_LOG: u64 = 15;
 
-/// listen to the port and wait for the command to stop
+/// listen to the port and serve the control protocol, see [`tools::control`]
 /// - the port is written to the file "port"
 /// - it will pannic when no port is available or unable to write to the file "port"
 fn setup_exit_receiver() {
-    std::thread::spawn(|| {
-        let listener = TcpListener::bind(":::0").unwrap();
-        let port = listener.local_addr().unwrap().port();
-        info!("listening on port {}", port);
-        println!("listening on port {}", port);
-        fs::write("port", port.to_string()).unwrap();
-        for stream in listener.incoming() {
-            match stream {
-                Ok(mut stream) => {
-                    let mut buffer = 0u32.to_le_bytes();
-                    match stream.read_exact(&mut buffer) {
-                        Err(e) => {
-                            error!("failed to read command: {}", e);
-                        }
-                        Ok(_) => {
-                            let cmd = u32::from_le_bytes(buffer);
-                            if cmd == 33 {
-                                stop_signal::stop();
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("failed to accept connection: {}", e);
-                }
-            }
-        }
-    });
+    let listener = TcpListener::bind(":::0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    info!("listening on port {}", port);
+    println!("listening on port {}", port);
+    fs::write("port", port.to_string()).unwrap();
+    std::thread::spawn(move || control::serve(listener));
 }
 
 /// the main function of the simulator
@@ -238,7 +154,7 @@ mod tests {
     use sprs::{num_kinds::Pattern, CsMat};
     use tracing::{info, metadata::LevelFilter};
 
-    use crate::{acquire_memory, init_logger_stderr};
+    use crate::{init_logger_stderr, try_acquire_memory_sections};
 
     #[test]
     fn it_works() {
@@ -335,14 +251,17 @@ mod tests {
                 std::thread::spawn(move || {
                     let _span = tracing::info_span!("thread", i).entered();
                     info!("thread {} start running", i);
-                    let _memory = acquire_memory(size);
+                    let _memory = try_acquire_memory_sections([size])?;
                     info!("thread {} is runing!", i);
                     std::thread::sleep(std::time::Duration::from_secs(5));
                     info!("thread {} finished", i);
+                    eyre::Ok(())
                 })
             })
             .collect::<Vec<_>>();
-        threads.into_iter().for_each(|t| t.join().unwrap());
+        for t in threads {
+            t.join().unwrap()?;
+        }
         Ok(())
     }
 
@@ -2,27 +2,13 @@ use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
-use crate::analysis::analyze_gearbox::GearboxConfig;
+use super::config_migration::load_config;
+pub use super::configv2::{DramType, LevelConfig};
+use crate::{
+    analysis::analyze_gearbox::GearboxConfig,
+    tools::{chrome_trace::ChromeTraceConfig, dram_trace::DramTraceConfig},
+};
 
-#[derive(Deserialize, Serialize, Debug, Default, Clone)]
-pub enum DramType {
-    DDR3,
-    #[default]
-    DDR4,
-    LPDDR3,
-    LPDDR4,
-    HBM,
-    HBM2,
-}
-
-#[derive(Deserialize, Serialize, Debug, Default, Clone)]
-pub struct LevelConfig {
-    pub num: usize,
-    pub merger_num: usize,
-    pub max_msg_in: usize,
-    pub max_msg_out: usize,
-    pub max_msg_generated: usize,
-}
 #[allow(missing_docs)]
 #[derive(Deserialize, Serialize, Debug, Default, Clone)]
 pub struct Config {
@@ -45,11 +31,21 @@ pub struct Config {
     pub bank_groups: LevelConfig,
     pub banks: LevelConfig,
     pub gearbox_config: GearboxConfig,
+    /// write the dram accesses to ramulator or dramsim3 traces
+    #[serde(default)]
+    pub dram_trace: Option<DramTraceConfig>,
+    /// write the timeline of the stream mergers to chrome traces
+    #[serde(default)]
+    pub chrome_trace: Option<ChromeTraceConfig>,
+    /// stop the simulation of a graph after this many cycles and write its stats with `finished: false`, `None` runs
+    /// until all tasks are finished
+    #[serde(default)]
+    pub max_cycles: Option<u64>,
 }
 impl Config {
-    /// create a config from path
-    pub fn new(path: impl AsRef<Path>) -> Self {
-        toml::from_str(std::fs::read_to_string(path).unwrap().as_str()).unwrap()
+    /// create a config from path, the config file of any version is accepted
+    pub fn new(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        Ok(load_config(path)?.into())
     }
 }
 
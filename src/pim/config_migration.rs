@@ -0,0 +1,273 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-10
+//! Description: load any config file into the single versioned schema [`ConfigV3`].
+//! the file is parsed as a toml value first, the version is read from the `version` field or guessed from the
+//! fields of the old files, then the value is upgraded one version at a time:
+//! - v1(`Config`): `gearbox_config.topk` and `gearbox_config.batch` are numbers, there is no `mapping`
+//! - v2(`ConfigV2`): `topk` and `batch` are lists, `mapping` is added(`SameSubarray` for the old files)
+//! - v3(`ConfigV3`): `remap_cycle` and `analysis` are added
+//!
+//! the old structs are still used by the old analyses, they are converted from the loaded [`ConfigV3`], so every
+//! subcommand accepts the same file.
+use std::path::Path;
+
+use eyre::{bail, eyre, Context};
+use toml::{map::Map, Value};
+
+use super::{
+    config::Config,
+    configv2::{ConfigV2, ConfigV3},
+};
+use crate::analysis::{analyze_gearbox::GearboxConfig, EVIL_RATE};
+
+/// the version of the current schema
+pub const CURRENT_CONFIG_VERSION: u32 = 3;
+
+/// the version of a config without the `version` field, guessed from its fields
+fn guess_version(table: &Map<String, Value>) -> u32 {
+    if table.contains_key("remap_cycle") || table.contains_key("analysis") {
+        return 3;
+    }
+    let list_gearbox = table
+        .get("gearbox_config")
+        .and_then(Value::as_table)
+        .map(|gearbox| {
+            ["batch", "topk"]
+                .iter()
+                .any(|key| gearbox.get(*key).map_or(false, Value::is_array))
+        })
+        .unwrap_or(false);
+    if table.contains_key("mapping") || list_gearbox {
+        2
+    } else {
+        1
+    }
+}
+
+/// wrap a single number into a list, keep the lists unchanged
+fn to_list(value: Value) -> Value {
+    match value {
+        Value::Array(_) => value,
+        other => Value::Array(vec![other]),
+    }
+}
+
+fn upgrade_v1_to_v2(table: &mut Map<String, Value>) -> eyre::Result<()> {
+    let gearbox = table
+        .entry("gearbox_config")
+        .or_insert(Value::Table(Map::new()))
+        .as_table_mut()
+        .ok_or_else(|| eyre!("gearbox_config should be a table"))?;
+    let batch = gearbox.remove("batch").unwrap_or(Value::Integer(1));
+    gearbox.insert("batch".into(), to_list(batch));
+    let topk = gearbox
+        .remove("topk")
+        .unwrap_or(Value::Float(EVIL_RATE as f64));
+    gearbox.insert("topk".into(), to_list(topk));
+    Ok(())
+}
+
+fn upgrade_v2_to_v3(table: &mut Map<String, Value>) -> eyre::Result<()> {
+    // the v2 analyses have no remap cost
+    table.entry("remap_cycle").or_insert(Value::Integer(0));
+    // some v2 files miss the mapping or the topk
+    table
+        .entry("mapping")
+        .or_insert(Value::String("SameSubarray".into()));
+    table
+        .entry("analysis")
+        .or_insert(Value::String("RealJump".into()));
+    let gearbox = table
+        .get_mut("gearbox_config")
+        .and_then(Value::as_table_mut)
+        .ok_or_else(|| eyre!("gearbox_config should be a table"))?;
+    gearbox
+        .entry("topk")
+        .or_insert(Value::Array(vec![Value::Float(EVIL_RATE as f64)]));
+    Ok(())
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-10
+/// Description: upgrade a parsed config to the current version, return the version of the input
+pub fn migrate(value: &mut Value) -> eyre::Result<u32> {
+    let table = value
+        .as_table_mut()
+        .ok_or_else(|| eyre!("the config should be a table"))?;
+    let version = match table.get("version") {
+        Some(version) => {
+            let version = version
+                .as_integer()
+                .ok_or_else(|| eyre!("version should be an integer, got {}", version))?;
+            if version < 1 || version > CURRENT_CONFIG_VERSION as i64 {
+                bail!(
+                    "unsupported config version {}, the supported versions are 1 to {}",
+                    version,
+                    CURRENT_CONFIG_VERSION
+                );
+            }
+            version as u32
+        }
+        None => guess_version(table),
+    };
+    if version < 2 {
+        upgrade_v1_to_v2(table).wrap_err("fail to upgrade the config from v1 to v2")?;
+    }
+    if version < 3 {
+        upgrade_v2_to_v3(table).wrap_err("fail to upgrade the config from v2 to v3")?;
+    }
+    table.insert(
+        "version".into(),
+        Value::Integer(CURRENT_CONFIG_VERSION as i64),
+    );
+    Ok(version)
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-10
+/// Description: parse a config of any version
+pub fn parse_config(content: &str) -> eyre::Result<ConfigV3> {
+    let mut value: Value = toml::from_str(content).wrap_err("the config is not a valid toml")?;
+    migrate(&mut value)?;
+    let config = value
+        .try_into()
+        .wrap_err("the config does not match the schema")?;
+    Ok(config)
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-10
+/// Description: read and parse a config file of any version
+pub fn load_config(path: impl AsRef<Path>) -> eyre::Result<ConfigV3> {
+    let path = path.as_ref();
+    let content =
+        std::fs::read_to_string(path).wrap_err(format!("fail to read config {:?}", path))?;
+    parse_config(&content).wrap_err(format!("fail to load config {:?}", path))
+}
+
+impl From<ConfigV3> for ConfigV2 {
+    fn from(config: ConfigV3) -> Self {
+        Self {
+            dram_type: config.dram_type,
+            subarray_provider_size: config.subarray_provider_size,
+            subarray_task_queue_size: config.subarray_task_queue_size,
+            subarrays: config.subarrays,
+            precharge_cycle: config.precharge_cycle,
+            activate_cycle: config.activate_cycle,
+            cas: config.cas,
+            rows: config.rows,
+            window_size: config.window_size,
+            columns: config.columns,
+            graph_path: config.graph_path,
+            output_path: config.output_path,
+            channels: config.channels,
+            ranks: config.ranks,
+            chips: config.chips,
+            bank_groups: config.bank_groups,
+            banks: config.banks,
+            gearbox_config: config.gearbox_config,
+            mapping: config.mapping,
+            interconnect: config.interconnect,
+            bank_interconnect: config.bank_interconnect,
+            ring: config.ring,
+            network_buffer_size: config.network_buffer_size,
+            packet_value_bytes: config.packet_value_bytes,
+            network_watchdog_cycles: config.network_watchdog_cycles,
+            dram_trace: config.dram_trace,
+            checkpoint_dir: config.checkpoint_dir,
+            checkpoint_interval: config.checkpoint_interval,
+            resume: config.resume,
+            deadline_secs: config.deadline_secs,
+            graph_deadline_secs: config.graph_deadline_secs,
+        }
+    }
+}
+
+/// the v1 analyses only run a single batch and topk, the first ones of the lists are used
+impl From<ConfigV3> for Config {
+    fn from(config: ConfigV3) -> Self {
+        let gearbox_config = GearboxConfig {
+            batch: config.gearbox_config.batch.first().copied().unwrap_or(1),
+            topk: config
+                .gearbox_config
+                .topk
+                .first()
+                .copied()
+                .unwrap_or(EVIL_RATE),
+            stacks: config.gearbox_config.stacks,
+            layers: config.gearbox_config.layers,
+        };
+        Self {
+            dram_type: config.dram_type,
+            subarray_provider_size: config.subarray_provider_size,
+            subarray_task_queue_size: config.subarray_task_queue_size,
+            subarrays: config.subarrays,
+            precharge_cycle: config.precharge_cycle,
+            activate_cycle: config.activate_cycle,
+            cas: config.cas,
+            rows: config.rows,
+            window_size: config.window_size,
+            columns: config.columns,
+            graph_path: config.graph_path,
+            output_path: config.output_path,
+            channels: config.channels,
+            ranks: config.ranks,
+            chips: config.chips,
+            bank_groups: config.bank_groups,
+            banks: config.banks,
+            gearbox_config,
+            dram_trace: config.dram_trace,
+            chrome_trace: config.chrome_trace,
+            max_cycles: config.max_cycles,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pim::configv2::MappingType;
+
+    #[test]
+    fn test_load_all_configs() -> eyre::Result<()> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("configs");
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "toml") {
+                let config = load_config(&path)?;
+                assert_eq!(config.version, CURRENT_CONFIG_VERSION);
+                assert!(!config.gearbox_config.batch.is_empty(), "{:?}", path);
+                assert!(!config.gearbox_config.topk.is_empty(), "{:?}", path);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate() -> eyre::Result<()> {
+        let v1 = std::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("configs/gearbox_001.toml"),
+        )?;
+        let mut value: Value = toml::from_str(&v1)?;
+        assert_eq!(migrate(&mut value)?, 1);
+        let config: ConfigV3 = value.try_into()?;
+        assert_eq!(config.gearbox_config.batch, vec![1]);
+        assert_eq!(config.gearbox_config.topk, vec![0.001]);
+        assert_eq!(config.gearbox_config.stacks, 4);
+        assert_eq!(config.mapping, MappingType::SameSubarray);
+        assert_eq!(config.remap_cycle, 0);
+
+        let config = Config::from(config);
+        assert_eq!(config.gearbox_config.topk, 0.001);
+        assert_eq!(config.gearbox_config.layers, 8);
+
+        let error = parse_config(&format!("version = 9\n{}", v1)).unwrap_err();
+        assert!(format!("{:?}", error).contains("unsupported config version 9"));
+        assert!(load_config("configs/not_exist.toml").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,450 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-11
+//! Description: check the constraints of a loaded config before any graph is read.
+//! the analyses used to assert these constraints in the middle of a run, now all the violations are collected
+//! and reported together, each with the path of the field, like `banks.num` or `graph_path[2]`.
+//! the `A*B` entries of `graph_path` are only accepted by the stream merger simulator.
+use std::{fmt::Display, path::Path};
+
+use eyre::bail;
+
+use super::{
+    config_migration::load_config,
+    configv2::{ConfigV3, DramType, MappingType},
+};
+use crate::{
+    algorithms::replay,
+    analysis::remap_analyze::{real_jump, SimulationType},
+    tools::graph_reader::{self, GraphSource, ProductEntry},
+};
+
+/// what the config is going to be used for, each target has its own constraints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationTarget {
+    /// the cycle level simulator of the `run` subcommand
+    Simulator,
+    /// the v1 analyses
+    Analysis,
+    /// the gearbox analyses, only one rank, chip and bank group is modeled
+    Gearbox,
+    /// the real jump simulations of `NewAnalysis`
+    RealJump,
+}
+
+/// a single violated constraint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigViolation {
+    /// the path of the field, like `banks.num`
+    pub field: String,
+    pub message: String,
+}
+
+impl Display for ConfigViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+#[derive(Default)]
+struct Violations(Vec<ConfigViolation>);
+
+impl Violations {
+    fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.push(ConfigViolation {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    fn check_power_of_two(&mut self, field: &str, value: usize) {
+        if !value.is_power_of_two() {
+            self.add(field, format!("should be a power of two, got {}", value));
+        }
+    }
+
+    fn check_positive(&mut self, field: &str, value: usize) {
+        if value == 0 {
+            self.add(field, "should be greater than 0");
+        }
+    }
+}
+
+fn check_common(config: &ConfigV3, violations: &mut Violations) {
+    // the address bits are computed by `count_to_log`, which rounds up the other sizes
+    violations.check_power_of_two("subarrays", config.subarrays);
+    violations.check_power_of_two("banks.num", config.banks.num);
+    violations.check_power_of_two("columns", config.columns);
+    violations.check_positive("rows", config.rows);
+    violations.check_positive("network_buffer_size", config.network_buffer_size);
+    violations.check_positive("packet_value_bytes", config.packet_value_bytes);
+    violations.check_positive("network_watchdog_cycles", config.network_watchdog_cycles);
+    if let Some(network) = &config.hierarchical_network {
+        for (level, link) in [
+            ("subarray_bus", network.subarray_bus),
+            ("bank_network", network.bank_network),
+            ("tsv", network.tsv),
+            ("base_layer", network.base_layer),
+        ] {
+            violations.check_positive(
+                &format!("hierarchical_network.{}.bandwidth", level),
+                link.bandwidth,
+            );
+        }
+    }
+    for (field, level) in [
+        ("channels.num", &config.channels),
+        ("ranks.num", &config.ranks),
+        ("chips.num", &config.chips),
+        ("bank_groups.num", &config.bank_groups),
+    ] {
+        violations.check_positive(field, level.num);
+    }
+    if config.gearbox_config.batch.is_empty() {
+        violations.add("gearbox_config.batch", "should not be empty");
+    }
+    for (i, &batch) in config.gearbox_config.batch.iter().enumerate() {
+        violations.check_positive(&format!("gearbox_config.batch[{}]", i), batch);
+    }
+    if config.gearbox_config.topk.is_empty() {
+        violations.add("gearbox_config.topk", "should not be empty");
+    }
+    for (i, &topk) in config.gearbox_config.topk.iter().enumerate() {
+        if topk.is_nan() || topk <= 0.0 || topk > 1.0 {
+            violations.add(
+                format!("gearbox_config.topk[{}]", i),
+                format!("should be in (0, 1], got {}", topk),
+            );
+        }
+    }
+    if config.graph_path.is_empty() {
+        violations.add("graph_path", "should not be empty");
+    }
+    for (i, graph) in config.graph_path.iter().enumerate() {
+        let entry = match ProductEntry::parse(graph) {
+            Ok(entry) => entry,
+            Err(e) => {
+                violations.add(format!("graph_path[{}]", i), e.to_string());
+                continue;
+            }
+        };
+        for source in entry.sources() {
+            match GraphSource::parse(source) {
+                Ok(source) => {
+                    if let Some(path) = source.file_path() {
+                        if !path.exists() {
+                            violations.add(
+                                format!("graph_path[{}]", i),
+                                format!("the file {:?} does not exist", path),
+                            );
+                        }
+                    }
+                }
+                Err(e) => violations.add(format!("graph_path[{}]", i), e.to_string()),
+            }
+        }
+    }
+}
+
+/// the simulator needs the columns of `A` to be the rows of `B`, the other targets only accept single graphs.
+/// skip the entries that already failed
+fn check_products(config: &ConfigV3, target: ValidationTarget, violations: &mut Violations) {
+    for (i, graph) in config.graph_path.iter().enumerate() {
+        let field = format!("graph_path[{}]", i);
+        if violations
+            .0
+            .iter()
+            .any(|violation| violation.field == field)
+        {
+            continue;
+        }
+        let Ok(ProductEntry { a, b: Some(b) }) = ProductEntry::parse(graph) else {
+            continue;
+        };
+        if target != ValidationTarget::Simulator {
+            violations.add(
+                field,
+                "the `A*B` entries are only supported by the stream merger simulator",
+            );
+            continue;
+        }
+        match (
+            graph_reader::read_graph_head(a),
+            graph_reader::read_graph_head(b),
+        ) {
+            (Ok(head_a), Ok(head_b)) => {
+                if head_a.cols != head_b.rows {
+                    violations.add(
+                        field,
+                        format!(
+                            "can not multiply the {}x{} A by the {}x{} B",
+                            head_a.rows, head_a.cols, head_b.rows, head_b.cols
+                        ),
+                    );
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                violations.add(field, format!("fail to read the graph head: {}", e));
+            }
+        }
+    }
+}
+
+fn check_single_bank_group(config: &ConfigV3, violations: &mut Violations) {
+    for (field, num) in [
+        ("ranks.num", config.ranks.num),
+        ("chips.num", config.chips.num),
+        ("bank_groups.num", config.bank_groups.num),
+    ] {
+        if num != 1 {
+            violations.add(
+                field,
+                format!("only 1 is supported by this analysis, got {}", num),
+            );
+        }
+    }
+}
+
+/// the graphs should be square and fit in the memory limit, skip the graphs that already failed.
+/// the access traces have no head, they are checked when they are replayed
+fn check_real_jump_graphs(config: &ConfigV3, limit: u64, violations: &mut Violations) {
+    let replaying = matches!(config.analysis, SimulationType::Replay);
+    for (i, graph) in config.graph_path.iter().enumerate() {
+        let field = format!("graph_path[{}]", i);
+        if violations
+            .0
+            .iter()
+            .any(|violation| violation.field == field)
+        {
+            continue;
+        }
+        match (replaying, replay::is_access_trace(graph)) {
+            (true, true) => continue,
+            (true, false) => {
+                violations.add(
+                    field,
+                    "the Replay analysis only accepts the `.access` traces",
+                );
+                continue;
+            }
+            (false, true) => {
+                violations.add(
+                    field,
+                    "the access traces are only accepted by the Replay analysis",
+                );
+                continue;
+            }
+            (false, false) => {}
+        }
+        let head = match graph_reader::read_graph_head(graph) {
+            Ok(head) => head,
+            Err(e) => {
+                violations.add(field, format!("fail to read the graph head: {}", e));
+                continue;
+            }
+        };
+        if head.rows != head.cols {
+            // the rows and the columns share one remapping
+            violations.add(
+                field,
+                format!(
+                    "the real jump simulations need a square graph, got {}x{}, use an `A*B` entry with the stream merger simulator for the rectangular matrices",
+                    head.rows, head.cols
+                ),
+            );
+            continue;
+        }
+        let needed: u64 = real_jump::memory_sections(config, head.rows, head.nnz)
+            .iter()
+            .sum();
+        if needed > limit {
+            violations.add(
+                field,
+                format!(
+                    "the simulation needs {} bytes, more than the memory limit {} bytes",
+                    needed, limit
+                ),
+            );
+        }
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-11
+/// Description: collect all the violations of the config for the target, an empty list means the config is valid
+pub fn check_config(config: &ConfigV3, target: ValidationTarget) -> Vec<ConfigViolation> {
+    check_config_with_limit(config, target, crate::memory_limit())
+}
+
+/// check the config as if `limit` bytes of memory can be acquired
+fn check_config_with_limit(
+    config: &ConfigV3,
+    target: ValidationTarget,
+    limit: u64,
+) -> Vec<ConfigViolation> {
+    let mut violations = Violations::default();
+    check_common(config, &mut violations);
+    check_products(config, target, &mut violations);
+    match target {
+        ValidationTarget::Simulator => {
+            if !matches!(config.dram_type, DramType::DDR4) {
+                violations.add(
+                    "dram_type",
+                    format!("only DDR4 is supported, got {:?}", config.dram_type),
+                );
+            }
+            if config.max_cycles == Some(0) {
+                violations.add("max_cycles", "should be greater than 0");
+            }
+        }
+        ValidationTarget::Analysis => {}
+        ValidationTarget::Gearbox => check_single_bank_group(config, &mut violations),
+        ValidationTarget::RealJump => {
+            check_single_bank_group(config, &mut violations);
+            if config.remap_cycle == 0 {
+                violations.add("remap_cycle", "should be greater than 0");
+            }
+            if config.mapping == MappingType::SameSubarray {
+                violations.add(
+                    "mapping",
+                    "SameSubarray is not supported by the real jump simulation, use SameBank or SameBankWeightedMapping",
+                );
+            }
+            check_real_jump_graphs(config, limit, &mut violations);
+        }
+    }
+    violations.0
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-11
+/// Description: return an error listing all the violations, if any
+pub fn validate(config: &ConfigV3, target: ValidationTarget) -> eyre::Result<()> {
+    let violations = check_config(config, target);
+    if !violations.is_empty() {
+        let lines = violations
+            .iter()
+            .map(|violation| format!("  - {}", violation))
+            .collect::<Vec<_>>()
+            .join("\n");
+        bail!(
+            "the config has {} invalid field(s):\n{}",
+            violations.len(),
+            lines
+        );
+    }
+    Ok(())
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-11
+/// Description: load a config of any version and validate it for the target
+pub fn load_validated(path: impl AsRef<Path>, target: ValidationTarget) -> eyre::Result<ConfigV3> {
+    let path = path.as_ref();
+    let config = load_config(path)?;
+    validate(&config, target).map_err(|e| e.wrap_err(format!("invalid config {:?}", path)))?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pim::config_migration::parse_config,
+        tools::hierarchical_network::{HierarchicalNetworkConfig, LinkConfig},
+    };
+
+    #[test]
+    fn test_check_config() -> eyre::Result<()> {
+        let mut config = parse_config(include_str!("../../configs/real_jump_test.toml"))?;
+        config.graph_path = vec!["test_mtx/test.mtx".to_string()];
+        assert_eq!(check_config(&config, ValidationTarget::Gearbox), vec![]);
+
+        config.subarrays = 12;
+        config.chips.num = 2;
+        config.remap_cycle = 0;
+        config.mapping = MappingType::SameSubarray;
+        config.graph_path.push("test_mtx/not_exist.mtx".to_string());
+        // the memory limit of the process is changed by the other tests
+        let violations = check_config_with_limit(&config, ValidationTarget::RealJump, u64::MAX);
+        let fields = violations
+            .iter()
+            .map(|violation| violation.field.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            vec![
+                "subarrays",
+                "graph_path[1]",
+                "chips.num",
+                "remap_cycle",
+                "mapping"
+            ]
+        );
+        let error = validate(&config, ValidationTarget::RealJump).unwrap_err();
+        assert!(error.to_string().contains("chips.num: only 1 is supported"));
+
+        // the rectangular graphs are rejected before they are loaded
+        config.graph_path = vec![
+            "test_mtx/test.mtx".to_string(),
+            "er:n=100,m=50,d=4".to_string(),
+        ];
+        let violations = check_config_with_limit(&config, ValidationTarget::RealJump, u64::MAX);
+        let graph_violations = violations
+            .iter()
+            .filter(|violation| violation.field.starts_with("graph_path"))
+            .collect::<Vec<_>>();
+        assert_eq!(graph_violations.len(), 1);
+        assert_eq!(graph_violations[0].field, "graph_path[1]");
+        assert!(graph_violations[0]
+            .message
+            .contains("square graph, got 100x50"));
+
+        config.graph_path.truncate(1);
+        config.analysis = SimulationType::Replay;
+        let violations = check_config_with_limit(&config, ValidationTarget::RealJump, u64::MAX);
+        assert!(violations
+            .iter()
+            .any(|violation| violation.field == "graph_path[0]"));
+
+        // a link that serves nothing would stall the hierarchical network
+        config.hierarchical_network = Some(HierarchicalNetworkConfig {
+            tsv: LinkConfig {
+                bandwidth: 0,
+                latency: 1,
+            },
+            ..Default::default()
+        });
+        let violations = check_config_with_limit(&config, ValidationTarget::RealJump, u64::MAX);
+        assert!(violations
+            .iter()
+            .any(|violation| violation.field == "hierarchical_network.tsv.bandwidth"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_products() -> eyre::Result<()> {
+        let mut config = parse_config(include_str!("../../configs/real_jump_test.toml"))?;
+        config.graph_path = vec![
+            "er:n=100,m=50,d=4*er:n=50,m=20,d=2".to_string(),
+            "test_mtx/test.mtx*er:n=100,d=4".to_string(),
+            "test_mtx/test.mtx*test_mtx/not_exist.mtx".to_string(),
+        ];
+        let violations = check_config(&config, ValidationTarget::Simulator);
+        let fields = violations
+            .iter()
+            .map(|violation| violation.field.as_str())
+            .collect::<Vec<_>>();
+        // test.mtx is 6x6, the missing file is reported first by the common checks
+        assert_eq!(fields, vec!["graph_path[2]", "graph_path[1]"]);
+        assert!(violations[1].message.contains("6x6 A by the 100x100 B"));
+
+        config.graph_path.truncate(1);
+        let violations = check_config(&config, ValidationTarget::Gearbox);
+        assert!(violations[0].message.contains("only supported by"));
+        Ok(())
+    }
+}
@@ -2,7 +2,25 @@ use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 
-use crate::analysis::{analyze_gearbox::GearboxConfigV2, remap_analyze::SimulationType};
+use super::config_migration::{load_config, CURRENT_CONFIG_VERSION};
+use crate::{
+    analysis::{analyze_gearbox::GearboxConfigV2, remap_analyze::SimulationType},
+    tools::{
+        checkpoint::default_checkpoint_interval,
+        chrome_trace::ChromeTraceConfig,
+        dram_trace::DramTraceConfig,
+        hierarchical_network::HierarchicalNetworkConfig,
+        interconnect::{
+            default_buffer_size, default_packet_value_bytes, default_watchdog_cycles,
+            InterconnectType,
+        },
+        ring_simulator::RingConfig,
+    },
+};
+
+fn current_config_version() -> u32 {
+    CURRENT_CONFIG_VERSION
+}
 
 #[derive(Deserialize, Serialize, Debug, Default, Clone)]
 pub enum DramType {
@@ -46,11 +64,51 @@ pub struct ConfigV2 {
     pub banks: LevelConfig,
     pub gearbox_config: GearboxConfigV2,
     pub mapping: MappingType,
+    /// the network connecting the channels in the base layer
+    #[serde(default)]
+    pub interconnect: InterconnectType,
+    /// the network connecting the banks of a channel for the remote dispatch, `None` keeps the old model: the ring
+    /// in the dispatcher overflow analysis and no network in the real jump simulator
+    #[serde(default)]
+    pub bank_interconnect: Option<InterconnectType>,
+    /// the link width and hop latency of the rings connecting the banks
+    #[serde(default)]
+    pub ring: RingConfig,
+    /// the packets each buffer of the base layer network and the real jump bank network holds
+    #[serde(default = "default_buffer_size")]
+    pub network_buffer_size: usize,
+    /// the bytes of the value in a packet of the bank networks, the packet also carries an u32 index
+    #[serde(default = "default_packet_value_bytes")]
+    pub packet_value_bytes: usize,
+    /// stop the simulation of a network with an error when it delivers no packet for this many cycles
+    #[serde(default = "default_watchdog_cycles")]
+    pub network_watchdog_cycles: usize,
+    /// write the dram accesses to ramulator or dramsim3 traces
+    #[serde(default)]
+    pub dram_trace: Option<DramTraceConfig>,
+    /// save the checkpoints of each graph to this dir
+    #[serde(default)]
+    pub checkpoint_dir: Option<PathBuf>,
+    /// the gap between two checkpoints, in seconds
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: u64,
+    /// continue from the last checkpoint, set by `--resume`
+    #[serde(skip)]
+    pub resume: bool,
+    /// cancel all graphs of this config after this many seconds, the partial results are still written
+    #[serde(default)]
+    pub deadline_secs: Option<u64>,
+    /// cancel a single graph after this many seconds
+    #[serde(default)]
+    pub graph_deadline_secs: Option<u64>,
 }
 
 #[allow(missing_docs)]
 #[derive(Deserialize, Serialize, Debug, Default, Clone)]
 pub struct ConfigV3 {
+    /// the version of the schema, see [`CURRENT_CONFIG_VERSION`]
+    #[serde(default = "current_config_version")]
+    pub version: u32,
     // memory config
     pub dram_type: DramType,
     pub subarray_provider_size: usize,
@@ -65,8 +123,12 @@ pub struct ConfigV3 {
     pub columns: usize,
     /// 32,64,the size of the walker
     // pub walker_size: usize,
+    /// the cycles to remap a row, should be positive for the real jump simulations
+    #[serde(default)]
     pub remap_cycle: usize,
     // pub remap_gap: usize,
+    /// the graphs to simulate, see [`crate::tools::graph_reader::GraphSource`]. the `A*B` entries multiply two
+    /// matrices and are only supported by the stream merger simulator, the real jump simulations need square graphs
     pub graph_path: Vec<String>,
     pub output_path: PathBuf,
     pub channels: LevelConfig,
@@ -77,6 +139,72 @@ pub struct ConfigV3 {
     pub gearbox_config: GearboxConfigV2,
     pub mapping: MappingType,
     pub analysis: SimulationType,
+    /// the network connecting the channels in the base layer
+    #[serde(default)]
+    pub interconnect: InterconnectType,
+    /// the network connecting the banks of a channel for the remote dispatch, `None` keeps the old model: the ring
+    /// in the dispatcher overflow analysis and no network in the real jump simulator
+    #[serde(default)]
+    pub bank_interconnect: Option<InterconnectType>,
+    /// the link width and hop latency of the rings connecting the banks
+    #[serde(default)]
+    pub ring: RingConfig,
+    /// the packets each buffer of the base layer network and the real jump bank network holds
+    #[serde(default = "default_buffer_size")]
+    pub network_buffer_size: usize,
+    /// the bytes of the value in a packet of the bank networks, the packet also carries an u32 index
+    #[serde(default = "default_packet_value_bytes")]
+    pub packet_value_bytes: usize,
+    /// stop the simulation of a network with an error when it delivers no packet for this many cycles
+    #[serde(default = "default_watchdog_cycles")]
+    pub network_watchdog_cycles: usize,
+    /// write the dram accesses to ramulator or dramsim3 traces
+    #[serde(default)]
+    pub dram_trace: Option<DramTraceConfig>,
+    /// write the timeline of the stream mergers of the pim simulator to chrome traces
+    #[serde(default)]
+    pub chrome_trace: Option<ChromeTraceConfig>,
+    /// stop the pim simulation of a graph after this many cycles and write its stats with `finished: false`, `None`
+    /// runs until all tasks are finished
+    #[serde(default)]
+    pub max_cycles: Option<u64>,
+    /// route the remote writes of the real jump simulator through the subarray bus, bank network, tsv and base
+    /// layer, and report the congestion of each level
+    #[serde(default)]
+    pub hierarchical_network: Option<HierarchicalNetworkConfig>,
+    /// the iterations of page rank in the iterative real jump simulation, `None` runs
+    /// [`crate::analysis::remap_analyze::real_jump_iterative::PAGE_RANK_ITERATIONS`]
+    #[serde(default)]
+    pub page_rank_iterations: Option<usize>,
+    /// write the stats of each round to `{time_series_dir}/{graph}_{mapping}_{algorithm}.jsonl`
+    #[serde(default)]
+    pub time_series_dir: Option<PathBuf>,
+    /// write matrix b and the frontiers of each round to `{access_trace_dir}/{graph}_{mapping}_{algorithm}.access`,
+    /// the traces can be replayed by the `Replay` analysis
+    #[serde(default)]
+    pub access_trace_dir: Option<PathBuf>,
+    /// cache the translated graphs and the mappings in this dir, the later runs with the same graph and
+    /// hardware skip the translation
+    #[serde(default)]
+    pub mapping_cache_dir: Option<PathBuf>,
+    /// the graphs with larger priority acquire their memory first when `MEMORY_POLICY=priority`
+    #[serde(default)]
+    pub memory_priority: i64,
+    /// save the checkpoints of each graph to this dir
+    #[serde(default)]
+    pub checkpoint_dir: Option<PathBuf>,
+    /// the gap between two checkpoints, in seconds
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: u64,
+    /// continue from the last checkpoint, set by `--resume`
+    #[serde(skip)]
+    pub resume: bool,
+    /// cancel all graphs of this config after this many seconds, the partial results are still written
+    #[serde(default)]
+    pub deadline_secs: Option<u64>,
+    /// cancel a single graph after this many seconds
+    #[serde(default)]
+    pub graph_deadline_secs: Option<u64>,
 }
 #[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub enum MappingType {
@@ -86,14 +214,66 @@ pub enum MappingType {
     SameBankWeightedMapping,
 }
 impl ConfigV2 {
-    /// create a config from path
-    pub fn new(path: impl AsRef<Path>) -> Self {
-        toml::from_str(std::fs::read_to_string(path).unwrap().as_str()).unwrap()
+    /// create a config from path, the config file of any version is accepted
+    pub fn new(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        Ok(load_config(path)?.into())
+    }
+
+    /// the hardware and the mapping of the gearbox simulations, the checkpoints saved with another fingerprint are
+    /// not resumed
+    pub fn checkpoint_fingerprint(&self) -> String {
+        format!(
+            "dram_type={:?},channels={},ranks={},chips={},bank_groups={},banks={},subarrays={},rows={},columns={},\
+             mapping={:?},interconnect={:?},bank_interconnect={:?},ring={:?},network_buffer_size={},\
+             packet_value_bytes={}",
+            self.dram_type,
+            self.channels.num,
+            self.ranks.num,
+            self.chips.num,
+            self.bank_groups.num,
+            self.banks.num,
+            self.subarrays,
+            self.rows,
+            self.columns,
+            self.mapping,
+            self.interconnect,
+            self.bank_interconnect,
+            self.ring,
+            self.network_buffer_size,
+            self.packet_value_bytes
+        )
     }
 }
 impl ConfigV3 {
-    pub fn new(path: impl AsRef<Path>) -> Self {
-        toml::from_str(std::fs::read_to_string(path).unwrap().as_str()).unwrap()
+    /// create a config from path, the older config files are upgraded to this version
+    pub fn new(path: impl AsRef<Path>) -> eyre::Result<Self> {
+        load_config(path)
+    }
+
+    /// the hardware and the mapping of the real jump simulations, the checkpoints saved with another fingerprint
+    /// are not resumed
+    pub fn checkpoint_fingerprint(&self) -> String {
+        format!(
+            "dram_type={:?},channels={},ranks={},chips={},bank_groups={},banks={},subarrays={},rows={},columns={},\
+             remap_cycle={},mapping={:?},bank_interconnect={:?},ring={:?},network_buffer_size={},\
+             packet_value_bytes={},hierarchical_network={:?}",
+            self.dram_type,
+            self.channels.num,
+            self.ranks.num,
+            self.chips.num,
+            self.bank_groups.num,
+            self.banks.num,
+            self.subarrays,
+            self.rows,
+            self.columns,
+            self.remap_cycle,
+            self.mapping,
+            self.bank_interconnect,
+            self.ring,
+            self.network_buffer_size,
+            self.packet_value_bytes,
+            self.hierarchical_network
+        )
     }
 }
 
@@ -188,7 +368,7 @@ mod tests {
     #[test]
     #[ignore]
     fn read_config() {
-        let config = ConfigV2::new("ddr4.toml");
+        let config = ConfigV2::new("ddr4.toml").unwrap();
         println!("{:?}", config);
     }
 }
@@ -222,6 +222,13 @@ impl LevelTrait for Level {
         }
         storage
     }
+
+    fn trace_path(path: &Storage, row: usize, column: usize) -> Storage {
+        let mut path = path.clone();
+        path.data[6] = row;
+        path.data[7] = column;
+        path
+    }
 }
 
 #[cfg(test)]
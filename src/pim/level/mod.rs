@@ -50,6 +50,8 @@ pub trait LevelTrait: Sized + Clone + Copy + Debug {
     fn get_row_detail(mapping: &Self::Mapping, row: usize) -> &GraphBRow<Self::Storage>;
     /// set the size of storage to 1 until the level(keep the level and later the same)
     fn set_one_to_level(storage: &Self::Storage, level: &Self) -> Self::Storage;
+    /// the ddr4 path of an access to `row` and `column` of the subarray of `path`, used by the dram traces
+    fn trace_path(path: &Self::Storage, row: usize, column: usize) -> ddr4::Storage;
 }
 
 /// the infomation of a row in matrix
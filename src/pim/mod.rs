@@ -1,10 +1,11 @@
 //! the pim module
 
-use std::{fmt::Debug, fs, marker::PhantomData, process::exit};
+use std::{collections::BTreeMap, fmt::Debug, fs, marker::PhantomData, path::Path};
 
 use serde::Serialize;
-use sprs::{num_kinds::Pattern, CsMat};
-use tracing::info;
+use serde_json::Value;
+use sprs::{num_kinds::Pattern, CsMat, TriMat};
+use tracing::{error, info};
 
 use self::{
     config::Config,
@@ -13,8 +14,15 @@ use self::{
     task::{StreamMessage, TaskEndData, TaskTo},
     task_manager::TaskManager,
 };
+use crate::tools::{
+    chrome_trace::ChromeTrace,
+    dram_trace::{DramTrace, TraceOp},
+    graph_reader::{self, ProductEntry},
+};
 
 pub mod config;
+pub mod config_migration;
+pub mod config_validation;
 pub mod configv2;
 pub mod level;
 pub mod row_buffer;
@@ -38,10 +46,131 @@ pub struct SimulationContext<LevelType> {
     stats: Statistics,
     current_sending_task: usize,
     total_tasks: usize,
+    /// the trace of the accesses of the providers
+    dram_trace: Option<DramTrace>,
+    /// the timeline of the components
+    chrome_trace: Option<ChromeTrace>,
+    /// the components registered by [`SimulationContext::register_component`]
+    components: Vec<ComponentRecord>,
 }
 #[derive(Serialize, Debug, Default)]
 struct Statistics {
     cycle: u64,
+    /// false when the simulation is stopped by `max_cycles` before all tasks are finished
+    finished: bool,
+    /// the stall statistics of the components, by the level
+    levels: BTreeMap<String, LevelStatistics>,
+}
+
+/// a component of the context
+#[derive(Debug)]
+struct ComponentRecord {
+    level: String,
+    id: usize,
+    stats: StallStatistics,
+    /// the track in the chrome trace
+    track: Option<usize>,
+}
+
+/// the state of a component in a cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentState {
+    /// nothing to do
+    Idle,
+    /// made progress in this cycle
+    Busy,
+    /// cannot make progress
+    Stall(StallReason),
+}
+
+/// the cycles of a component in each state
+/// - `busy`, `idle`, `output_full`, `input_empty` and `row_activation` are the states of the cycles, a cycle has a
+///   single state
+/// - `waiting_on_child` and `task_queue_full` count the tasks rejected by a child or by the task queue, they overlap
+///   with the states
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct StallStatistics {
+    pub busy: u64,
+    pub idle: u64,
+    pub waiting_on_child: u64,
+    pub output_full: u64,
+    pub input_empty: u64,
+    pub row_activation: u64,
+    pub task_queue_full: u64,
+}
+
+impl StallStatistics {
+    fn add_stall(&mut self, stall: StallReason) {
+        match stall {
+            StallReason::WaitingOnChild => self.waiting_on_child += 1,
+            StallReason::OutputFull => self.output_full += 1,
+            StallReason::InputEmpty => self.input_empty += 1,
+            StallReason::RowActivation => self.row_activation += 1,
+            StallReason::TaskQueueFull => self.task_queue_full += 1,
+        }
+    }
+
+    fn add_state(&mut self, state: ComponentState) {
+        match state {
+            ComponentState::Idle => self.idle += 1,
+            ComponentState::Busy => self.busy += 1,
+            ComponentState::Stall(stall) => self.add_stall(stall),
+        }
+    }
+
+    fn merge(&mut self, other: &StallStatistics) {
+        self.busy += other.busy;
+        self.idle += other.idle;
+        self.waiting_on_child += other.waiting_on_child;
+        self.output_full += other.output_full;
+        self.input_empty += other.input_empty;
+        self.row_activation += other.row_activation;
+        self.task_queue_full += other.task_queue_full;
+    }
+}
+
+/// the stall statistics of a component
+#[derive(Serialize, Debug, Clone)]
+struct ComponentStatistics {
+    id: usize,
+    #[serde(flatten)]
+    stats: StallStatistics,
+}
+
+/// the stall statistics of a level, the components are in the order of registration. in the simulator the first
+/// cycle registers all components, so they are in the order of the tree, the first child first
+#[derive(Serialize, Debug, Default)]
+struct LevelStatistics {
+    total: StallStatistics,
+    components: Vec<ComponentStatistics>,
+}
+
+/// why a component cannot make progress in a cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum StallReason {
+    /// the task is rejected by a child
+    WaitingOnChild,
+    /// the output buffer is full, the parent does not take the data
+    OutputFull,
+    /// a child has not provided the data to merge
+    InputEmpty,
+    /// the provider is opening a row
+    RowActivation,
+    /// the task queue of the provider is full
+    TaskQueueFull,
+}
+
+impl StallReason {
+    /// the name in the chrome trace
+    pub fn name(&self) -> &'static str {
+        match self {
+            StallReason::WaitingOnChild => "waiting on child",
+            StallReason::OutputFull => "output full",
+            StallReason::InputEmpty => "input empty",
+            StallReason::RowActivation => "row activation",
+            StallReason::TaskQueueFull => "task queue full",
+        }
+    }
 }
 
 impl<LevelType: LevelTrait> SimulationContext<LevelType> {
@@ -55,6 +184,139 @@ impl<LevelType: LevelTrait> SimulationContext<LevelType> {
             current_sending_task: 0,
             stats: Default::default(),
             total_tasks: 0,
+            dram_trace: None,
+            chrome_trace: None,
+            components: vec![],
+        }
+    }
+    /// write the accesses of the providers to the trace
+    pub fn set_dram_trace(&mut self, trace: DramTrace) {
+        self.dram_trace = Some(trace);
+    }
+    /// record an access to `row` and `column` of the subarray of `path`
+    pub fn record_access(
+        &mut self,
+        path: &LevelType::Storage,
+        row: usize,
+        column: usize,
+        cycle: u64,
+        op: TraceOp,
+    ) {
+        if let Some(trace) = &mut self.dram_trace {
+            trace.record(cycle, &LevelType::trace_path(path, row, column), op);
+        }
+    }
+    /// write the recorded accesses to the trace file
+    pub fn flush_dram_trace(&mut self) -> eyre::Result<()> {
+        match &mut self.dram_trace {
+            Some(trace) => trace.flush(),
+            None => Ok(()),
+        }
+    }
+    /// record the events of the components to the chrome trace
+    pub fn set_chrome_trace(&mut self, trace: ChromeTrace) {
+        self.chrome_trace = Some(trace);
+    }
+    /// the index of a component, it's registered at the first call and added to the chrome trace
+    /// - `component`: the index stored in the component, the clones of a component should not share the index, so
+    ///   it's registered lazily at the first cycle
+    /// - `level`: the name of the level
+    pub fn register_component(
+        &mut self,
+        component: &mut Option<usize>,
+        level: impl FnOnce() -> String,
+        id: usize,
+    ) -> usize {
+        *component.get_or_insert_with(|| {
+            let level = level();
+            let track = self
+                .chrome_trace
+                .as_mut()
+                .map(|trace| trace.track(&level, id));
+            self.components.push(ComponentRecord {
+                level,
+                id,
+                stats: Default::default(),
+                track,
+            });
+            self.components.len() - 1
+        })
+    }
+    /// record the state of a component at `cycle`, the stalls are added to the chrome trace
+    pub fn record_state(&mut self, component: usize, state: ComponentState, cycle: u64) {
+        let record = &mut self.components[component];
+        record.stats.add_state(state);
+        if let (Some(trace), Some(track)) = (&mut self.chrome_trace, record.track) {
+            let stall = match state {
+                ComponentState::Stall(stall) => Some(stall.name()),
+                _ => None,
+            };
+            trace.state(track, stall, cycle);
+        }
+    }
+    /// record a task rejected by a child or by the task queue of the component
+    pub fn record_rejected(&mut self, component: usize, stall: StallReason) {
+        self.components[component].stats.add_stall(stall);
+    }
+    /// record an event without duration, the `args` are only built when the trace is enabled
+    pub fn trace_instant(
+        &mut self,
+        component: usize,
+        name: &str,
+        cycle: u64,
+        args: impl FnOnce() -> Value,
+    ) {
+        if let (Some(trace), Some(track)) =
+            (&mut self.chrome_trace, self.components[component].track)
+        {
+            trace.instant(track, name, cycle, args());
+        }
+    }
+    /// record an event lasts `duration` cycles from `cycle`
+    pub fn trace_complete(
+        &mut self,
+        component: usize,
+        name: &str,
+        cycle: u64,
+        duration: u64,
+        args: impl FnOnce() -> Value,
+    ) {
+        if let (Some(trace), Some(track)) =
+            (&mut self.chrome_trace, self.components[component].track)
+        {
+            trace.complete(track, name, cycle, duration, args());
+        }
+    }
+    /// the stall statistics of the components, by the level
+    fn level_statistics(&self) -> BTreeMap<String, LevelStatistics> {
+        let mut levels: BTreeMap<String, LevelStatistics> = BTreeMap::new();
+        for record in &self.components {
+            let level = levels.entry(record.level.clone()).or_default();
+            level.total.merge(&record.stats);
+            level.components.push(ComponentStatistics {
+                id: record.id,
+                stats: record.stats.clone(),
+            });
+        }
+        levels
+    }
+    /// write the cycles and the stall statistics to `output_path`
+    fn write_stats(&mut self, output_path: &Path, cycle: u64, finished: bool) -> eyre::Result<()> {
+        if let Some(ouput_dir) = output_path.parent() {
+            // create the output dir if not exists
+            fs::create_dir_all(ouput_dir)?;
+        }
+        self.stats.cycle = cycle;
+        self.stats.finished = finished;
+        self.stats.levels = self.level_statistics();
+        serde_json::to_writer_pretty(fs::File::create(output_path)?, &self.stats)?;
+        Ok(())
+    }
+    /// close the chrome trace
+    pub fn finish_chrome_trace(&mut self) -> eyre::Result<()> {
+        match &mut self.chrome_trace {
+            Some(trace) => trace.finish(),
+            None => Ok(()),
         }
     }
     /// return if the simulation is finished
@@ -97,14 +359,62 @@ impl Simulator {
 
     /// run the simulator and print the statistics
     pub fn run(&mut self, config: &Config) {
-        for graph in &config.graph_path {
+        for entry in &config.graph_path {
             info!("run config: {:?}", config);
+            let product = ProductEntry::parse(entry).unwrap();
+            info!("generating graph A: {}", product.a);
+            let graph_a = Self::read_matrix(product.a).unwrap();
+            let graph_b = match product.b {
+                Some(b) => {
+                    info!("generating graph B: {}", b);
+                    Self::read_matrix(b).unwrap()
+                }
+                None => {
+                    info!("generating graph B: the transpose of A");
+                    graph_a.transpose_view().to_csr()
+                }
+            };
+            if graph_a.cols() != graph_b.rows() {
+                error!(
+                    "can not multiply the {}x{} A by the {}x{} B in {}",
+                    graph_a.rows(),
+                    graph_a.cols(),
+                    graph_b.rows(),
+                    graph_b.cols(),
+                    entry
+                );
+                continue;
+            }
+            // the traces of a product are named `{a}_x_{b}`, see `ProductEntry::file_stem`
             let mut context = SimulationContext::<ddr4::Level>::new(config);
-            info!("generating graph A: {:?}", config.graph_path);
-            let graph_a = sprs::io::read_matrix_market(graph).unwrap().to_csr();
-            info!("generating graph B: {:?}", config.graph_path);
-
-            let graph_b = graph_a.transpose_view().to_csr();
+            if let Some(trace_config) = &config.dram_trace {
+                let total_size = ddr4::Storage::new(
+                    config.channels.num,
+                    config.ranks.num,
+                    config.chips.num,
+                    config.bank_groups.num,
+                    config.banks.num,
+                    config.subarrays,
+                    config.rows,
+                    config.columns,
+                );
+                match DramTrace::for_graph(trace_config, total_size, entry, "pim") {
+                    Ok(trace) => context.set_dram_trace(trace),
+                    Err(e) => {
+                        error!("fail to create the dram trace of {}: {:?}", entry, e);
+                        continue;
+                    }
+                }
+            }
+            if let Some(trace_config) = &config.chrome_trace {
+                match ChromeTrace::for_graph(trace_config, entry, "pim") {
+                    Ok(trace) => context.set_chrome_trace(trace),
+                    Err(e) => {
+                        error!("fail to create the chrome trace of {}: {:?}", entry, e);
+                        continue;
+                    }
+                }
+            }
             match config.dram_type {
                 config::DramType::DDR3 => todo!(),
                 config::DramType::DDR4 => {
@@ -119,6 +429,16 @@ impl Simulator {
             }
         }
     }
+
+    /// read a matrix of a product entry, the indices are converted to `usize`
+    fn read_matrix(source: &str) -> eyre::Result<CsMat<Pattern>> {
+        let triplets = graph_reader::read_graph(source)?;
+        let mut matrix = TriMat::with_capacity(triplets.shape(), triplets.nnz());
+        for (_, (row, col)) in triplets.triplet_iter() {
+            matrix.add_triplet(row as usize, col as usize, Pattern);
+        }
+        Ok(matrix.to_csr())
+    }
     fn run_inner<LevelType: LevelTrait + Debug>(
         &mut self,
         config: &Config,
@@ -129,12 +449,33 @@ impl Simulator {
         let start_time = std::time::Instant::now();
         let mut last_record_time = std::time::Instant::now();
         let mut last_target = 0;
+        let start_cycle = self.cycle;
 
         while !context.finished() {
+            if config
+                .max_cycles
+                .map_or(false, |max_cycles| self.cycle - start_cycle >= max_cycles)
+            {
+                error!(
+                    "the simulation is stopped after {} cycles, {}/{} tasks are sent",
+                    self.cycle - start_cycle,
+                    context.current_sending_task,
+                    context.total_tasks
+                );
+                context.flush_dram_trace().unwrap();
+                context.finish_chrome_trace().unwrap();
+                // keep the stalls of the stopped run, they show where it got stuck
+                context
+                    .write_stats(&config.output_path, self.cycle, false)
+                    .unwrap();
+                info!("partial stats has been saved to {:?}", config.output_path);
+                return;
+            }
             merger.cycle(context, self.cycle);
             self.cycle += 1;
             if self.cycle % 100000 == 0 {
                 info!("-------------------");
+                context.flush_dram_trace().unwrap();
 
                 let total_time = start_time.elapsed();
                 let last_time = last_record_time.elapsed();
@@ -167,10 +508,6 @@ impl Simulator {
                 );
 
                 println!("current runing status: {:?}", context);
-
-                if self.cycle == 2000000 {
-                    exit(1);
-                }
             }
         }
         info!("simulation finished");
@@ -179,6 +516,8 @@ impl Simulator {
             merger.cycle(context, self.cycle);
             self.cycle += 1;
         }
+        context.flush_dram_trace().unwrap();
+        context.finish_chrome_trace().unwrap();
 
         // finished,
         // make sure all tasks are finished
@@ -187,16 +526,9 @@ impl Simulator {
             info!("simulation finished");
             info!("stats: {:?}", context.stats);
             info!("context has been saved to {:?}", config.output_path);
-            if let Some(ouput_dir) = config.output_path.parent() {
-                // create the output dir if not exists
-                fs::create_dir_all(ouput_dir).unwrap();
-            }
-            serde_json::to_writer_pretty(
-                fs::File::create(&config.output_path).unwrap(),
-                &context.stats,
-            )
-            .unwrap();
-            context.stats.cycle = self.cycle;
+            context
+                .write_stats(&config.output_path, self.cycle, true)
+                .unwrap();
             info!("total cycle: {}", self.cycle);
         } else {
             tracing::error!("simulation failed: {:?}", result);
@@ -1,13 +1,17 @@
 use std::{collections::VecDeque, fmt::Debug};
 
+use serde_json::json;
 use sprs::{num_kinds::Pattern, CsMat};
 use tracing::debug;
 
-use crate::pim::{
-    level::LevelTrait,
-    row_buffer::BankState,
-    task::{StreamMessage, Task, TaskData},
-    Component, SimulationContext,
+use crate::{
+    pim::{
+        level::LevelTrait,
+        row_buffer::BankState,
+        task::{StreamMessage, Task, TaskData},
+        Component, ComponentState, SimulationContext, StallReason,
+    },
+    tools::dram_trace::TraceOp,
 };
 
 use super::{EmptyComponent, StreamProvider, TaskReceiver};
@@ -34,6 +38,8 @@ pub struct Provider<'a, LevelType: LevelTrait> {
     /// ready cycle, row_id
     current_opening_row: Option<(u64, usize)>,
     graph_b: &'a CsMat<Pattern>,
+    /// the index in the context, see [`SimulationContext::register_component`]
+    component: Option<usize>,
 }
 impl<'a, LevelType: LevelTrait> Provider<'a, LevelType> {
     /// create a new provider
@@ -68,6 +74,7 @@ impl<'a, LevelType: LevelTrait> Provider<'a, LevelType> {
             pre_charge: pre,
             act,
             graph_b: graph,
+            component: None,
         }
     }
 }
@@ -113,18 +120,31 @@ where
     fn receive_task(
         &mut self,
         task: Self::InputTask,
-        _context: &mut Self::SimContext,
-        _current_cycle: u64,
+        context: &mut Self::SimContext,
+        current_cycle: u64,
     ) -> Result<(), (Self::LevelType, Self::InputTask)> {
         if let Task::TaskData(data) = task {
+            let level = self.level;
+            let component =
+                context.register_component(&mut self.component, || format!("{:?}", level), self.id);
             return if self.task_queue.len() < self.max_task_queue_size {
                 debug!(
                     ?self.level,
                     self.id, "provider receive task at level:{:?} : {:?}", self.level, data
                 );
+                context.trace_instant(component, "receive task", current_cycle, || {
+                    json!({"to": data.to.to, "round": data.to.round, "from": data.from, "size": data.size})
+                });
                 self.task_queue.push_back(data);
                 Ok(())
             } else {
+                context.record_rejected(component, StallReason::TaskQueueFull);
+                context.trace_instant(
+                    component,
+                    "task queue full",
+                    current_cycle,
+                    || json!({"to": data.to.to, "round": data.to.round, "from": data.from}),
+                );
                 Err((self.level, Task::TaskData(data)))
             };
         }
@@ -163,6 +183,10 @@ impl<'a, LevelType: LevelTrait + Copy> Component for Provider<'a, LevelType> {
     type SimContext = SimulationContext<LevelType>;
 
     fn cycle(&mut self, context: &mut Self::SimContext, current_cycle: u64) {
+        let level = self.level;
+        let component =
+            context.register_component(&mut self.component, || format!("{:?}", level), self.id);
+        let ready_messages = self.ready_queue.len();
         // a row is being opened
         if let Some((ready_cycle, row_id)) = self.current_opening_row {
             if ready_cycle <= current_cycle {
@@ -184,6 +208,13 @@ impl<'a, LevelType: LevelTrait + Copy> Component for Provider<'a, LevelType> {
                     // if ready, push the task to ready queue:
                     let idx = self.graph_b.outer_view(from).unwrap().indices()[row_b_idx];
                     let message = context.generate_msg(working_taks.task.to, idx, current_cycle);
+                    context.record_access(
+                        &working_taks.task.target_id.level_path,
+                        row_id,
+                        working_taks.current_size % self.row_size,
+                        current_cycle,
+                        TraceOp::Read,
+                    );
                     self.ready_queue.push_back(message);
                     // update the current working task:
                     working_taks.current_size += 4;
@@ -203,6 +234,13 @@ impl<'a, LevelType: LevelTrait + Copy> Component for Provider<'a, LevelType> {
                         self.act
                     };
                     self.current_opening_row = Some((current_cycle + latency, row_id));
+                    context.trace_complete(
+                        component,
+                        "open row",
+                        current_cycle,
+                        latency,
+                        || json!({"row": row_id}),
+                    );
                 }
             } else if let Some(data) = self.task_queue.pop_front() {
                 self.current_working_task = Some(WorkingTask {
@@ -212,6 +250,17 @@ impl<'a, LevelType: LevelTrait + Copy> Component for Provider<'a, LevelType> {
                 });
             }
         }
+        let has_task = self.current_working_task.is_some() || !self.task_queue.is_empty();
+        let state = if self.current_opening_row.is_some() {
+            ComponentState::Stall(StallReason::RowActivation)
+        } else if has_task && self.ready_queue.len() >= self.max_provider_size {
+            ComponentState::Stall(StallReason::OutputFull)
+        } else if has_task || self.ready_queue.len() > ready_messages {
+            ComponentState::Busy
+        } else {
+            ComponentState::Idle
+        };
+        context.record_state(component, state, current_cycle);
     }
 }
 
@@ -4,13 +4,14 @@ use std::{
 };
 
 use enum_as_inner::EnumAsInner;
+use serde_json::json;
 use tracing::debug;
 
 use crate::pim::{
     config::Config,
     level::LevelTrait,
     task::{self, StreamMessage, StreamMessageData, Task, TaskTo},
-    Component, SimulationContext,
+    Component, ComponentState, SimulationContext, StallReason,
 };
 
 use super::{EmptyComponent, StreamProvider, TaskReceiver};
@@ -45,27 +46,38 @@ pub struct MergerStatus {
     pub waiting_data_childs: BTreeSet<usize>,
     /// the message stored from lower level
     pub buffered_message: Vec<VecDeque<StreamMessageData>>,
+    /// why no message is generated in the last cycle
+    pub stall: Option<StallReason>,
 }
 
 impl MergerStatus {
     /// return if finished
     /// - will try to merger the input,
     /// - will return true is all done
+    /// - `component`: the index of the merger in the context
     fn cycle<LevelType: LevelTrait>(
         &mut self,
         context: &mut SimulationContext<LevelType>,
         current_cycle: u64,
+        component: usize,
     ) -> bool {
+        self.stall = None;
         match self.status {
             Waiting::WaitingForData => {
-                for _ in 0..self.max_generated_message {
+                for generated in 0..self.max_generated_message {
                     // the result buffer is full
                     if self.generated_message.len() >= self.max_stored_generated_message {
+                        if generated == 0 {
+                            self.stall = Some(StallReason::OutputFull);
+                        }
                         return false;
                     }
                     // the input is not enough
                     for waiting_child in self.waiting_data_childs.iter() {
                         if self.buffered_message[*waiting_child].is_empty() {
+                            if generated == 0 {
+                                self.stall = Some(StallReason::InputEmpty);
+                            }
                             return false;
                         }
                     }
@@ -79,6 +91,14 @@ impl MergerStatus {
                                 current_cycle,
                             );
                             debug!(self.id, "merger {} generated msg: {:?}", self.id, msg);
+                            context.trace_instant(component, "merge", current_cycle, || {
+                                json!({
+                                    "pe": self.id,
+                                    "to": self.current_target.to,
+                                    "round": self.current_target.round,
+                                    "idx": idx,
+                                })
+                            });
                             self.generated_message.push_back(msg);
                         }
                         // no message generated, means that all input is empty
@@ -101,6 +121,7 @@ impl MergerStatus {
                     self.status = Waiting::Idle;
                     true
                 } else {
+                    self.stall = Some(StallReason::OutputFull);
                     false
                 }
             }
@@ -149,6 +170,7 @@ impl MergerStatus {
             max_generated_message,
             max_stored_generated_message,
             generated_message: VecDeque::with_capacity(max_stored_generated_message),
+            stall: None,
         }
     }
 }
@@ -201,6 +223,8 @@ pub struct SimpleStreamMerger<LevelType, Child> {
     current_working_targets: BTreeMap<TaskTo, usize>,
     /// free pes
     free_pes: BTreeSet<usize>,
+    /// the index in the context, see [`SimulationContext::register_component`]
+    component: Option<usize>,
 }
 impl<LevelType: Debug, Child> EmptyComponent for SimpleStreamMerger<LevelType, Child>
 where
@@ -287,6 +311,7 @@ impl<LevelType: LevelTrait + Debug, Child> SimpleStreamMerger<LevelType, Child>
             current_receiving_targets: BTreeMap::new(),
             current_working_targets: BTreeMap::new(),
             free_pes: (0..num_merger).collect(),
+            component: None,
         }
     }
 
@@ -399,6 +424,9 @@ where
         // recursively receive task
         //
 
+        let level = self.current_level;
+        let component =
+            context.register_component(&mut self.component, || format!("{:?}", level), self.id);
         // check if self can accept the task
         match task {
             Task::TaskData(ref task_data) => {
@@ -407,6 +435,12 @@ where
                         self.id,
                         "merger can not receive task,level: {:?}", self.current_level
                     );
+                    context.trace_instant(
+                        component,
+                        "no free pe",
+                        current_cycle,
+                        || json!({"to": task_data.to.to, "round": task_data.to.round}),
+                    );
                     return Err((self.current_level, task));
                 }
             }
@@ -430,7 +464,12 @@ where
                 // first test if self can receive this task
                 debug!(?self.current_level,self.id, "test child:{:?}-{}", child_level, child_id,);
                 let to = task_data.to;
-                self.children[child_id].receive_task(task, context, current_cycle)?;
+                if let Err(rejected) =
+                    self.children[child_id].receive_task(task, context, current_cycle)
+                {
+                    context.record_rejected(component, StallReason::WaitingOnChild);
+                    return Err(rejected);
+                }
                 // record this task
                 debug!(?self.current_level,
                     self.id,
@@ -439,6 +478,12 @@ where
                     to
                 );
                 self.self_receive_task(to, child_id);
+                context.trace_instant(
+                    component,
+                    "receive task",
+                    current_cycle,
+                    || json!({"to": to.to, "round": to.round, "child": child_id}),
+                );
                 Ok(())
             }
             Task::End(ref end_data) => {
@@ -530,6 +575,9 @@ where
     /// - will fetch data from children
     /// - will try to perform merge for each pe
     fn cycle(&mut self, context: &mut Self::SimContext, current_cycle: u64) {
+        let level = self.current_level;
+        let component =
+            context.register_component(&mut self.component, || format!("{:?}", level), self.id);
         // 1. first fetch data for children
         for (child_id, child) in self.children.iter_mut().enumerate() {
             child.cycle(context, current_cycle);
@@ -556,13 +604,26 @@ where
         }
 
         // 2. perform merge for each pe
+        let mut busy = false;
         for (pe_id, pe) in self.mergers.iter_mut().enumerate() {
-            if pe.cycle(context, current_cycle) {
+            let generated = pe.generated_message.len();
+            if pe.cycle(context, current_cycle, component) {
                 // the pe is finished and turned to be idle,change the status
                 self.current_working_targets.remove(&pe.current_target);
                 self.free_pes.insert(pe_id);
             }
+            busy |= pe.generated_message.len() > generated;
         }
+        // the merger stalls only if no pe generated a message
+        let state = if busy {
+            ComponentState::Busy
+        } else {
+            match self.mergers.iter().filter_map(|pe| pe.stall).min() {
+                Some(stall) => ComponentState::Stall(stall),
+                None => ComponentState::Idle,
+            }
+        };
+        context.record_state(component, state, current_cycle);
     }
 }
 
@@ -574,6 +635,7 @@ mod tests {
     use crate::{
         init_logger_debug,
         pim::{config::LevelConfig, level::ddr4, stream_merger::provider::Provider, task::PathId},
+        tools::chrome_trace::ChromeTrace,
     };
 
     use super::*;
@@ -704,6 +766,96 @@ mod tests {
         }
     }
 
+    /// record the timeline and the stall statistics of 2 mergers and 4 providers
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_trace_and_stall_statistics() -> eyre::Result<()> {
+        let config = Config::from_ddr4_3200(LevelConfig::default(), LevelConfig::default());
+        let graph_b = sprs::io::read_matrix_market("test_mtx/test.mtx")?.to_csr();
+        let providers: Vec<_> = (0..2)
+            .map(|id| Provider::<ddr4::Level>::new(id, 2, 2, 2, 2, 2, &graph_b))
+            .collect();
+        let bank_mergers = (0..2)
+            .map(|id| {
+                SimpleStreamMerger::new(
+                    id,
+                    &config,
+                    providers.clone(),
+                    ddr4::Level::Bank,
+                    2,
+                    4,
+                    4,
+                    4,
+                )
+            })
+            .collect();
+        let mut bg_merger =
+            SimpleStreamMerger::new(0, &config, bank_mergers, ddr4::Level::BankGroup, 2, 4, 4, 4);
+        let mut context = SimulationContext::new(&config);
+        let path = std::env::temp_dir().join("spmspm_merger_chrome_trace_test.trace.json");
+        context.set_chrome_trace(ChromeTrace::create(&path, None)?);
+
+        let path1 = PathId::new(ddr4::Storage::new(0, 0, 0, 0, 0, 0, 0, 0));
+        let path2 = PathId::new(ddr4::Storage::new(0, 0, 0, 0, 1, 1, 0, 0));
+        let task1 = context.gen_task(path1, 0, TaskTo { to: 0, round: 0 }, 4);
+        let task2 = context.gen_task(path2, 0, TaskTo { to: 0, round: 0 }, 4);
+        bg_merger
+            .receive_task(Task::TaskData(task1), &mut context, 0)
+            .unwrap();
+        bg_merger
+            .receive_task(Task::TaskData(task2), &mut context, 0)
+            .unwrap();
+        let end_task = context.gen_end_task(TaskTo { to: 0, round: 0 });
+        bg_merger
+            .receive_task(Task::End(end_task), &mut context, 0)
+            .unwrap();
+        for i in 0..100 {
+            bg_merger.cycle(&mut context, i);
+            bg_merger.get_data(&mut context, i);
+        }
+        assert!(bg_merger.is_empty().is_empty());
+        context.finish_chrome_trace()?;
+
+        let levels = context.level_statistics();
+        assert_eq!(levels["BankGroup"].components.len(), 1);
+        assert_eq!(levels["Bank"].components.len(), 2);
+        assert_eq!(levels["SubArray"].components.len(), 4);
+        assert!(levels["SubArray"].total.row_activation > 0);
+        assert!(levels["BankGroup"].total.busy > 0);
+        // no task is rejected, every cycle has a single state
+        for level in levels.values() {
+            for component in &level.components {
+                let stats = &component.stats;
+                assert_eq!(stats.waiting_on_child + stats.task_queue_full, 0);
+                assert_eq!(
+                    stats.busy
+                        + stats.idle
+                        + stats.output_full
+                        + stats.input_empty
+                        + stats.row_activation,
+                    100
+                );
+            }
+        }
+
+        let value: serde_json::Value = serde_json::from_reader(std::fs::File::open(&path)?)?;
+        let events = value["traceEvents"].as_array().unwrap();
+        let names: BTreeSet<_> = events.iter().filter_map(|e| e["name"].as_str()).collect();
+        for name in ["receive task", "open row", "merge", "row activation"] {
+            assert!(
+                names.contains(name),
+                "{} is not recorded: {:?}",
+                name,
+                names
+            );
+        }
+        // the bank group merger, 2 bank mergers and their providers, the clones have their own tracks
+        let threads = events.iter().filter(|e| e["name"] == "thread_name").count();
+        assert_eq!(threads, 7);
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
     /// test multiple task working in parallel
     #[test]
     #[cfg_attr(miri, ignore)]
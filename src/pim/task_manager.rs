@@ -4,6 +4,7 @@ use std::{
     ops::RangeFull,
 };
 
+use serde_json::json;
 use sprs::{num_kinds::Pattern, CsMat};
 use tracing::debug;
 
@@ -11,7 +12,7 @@ use super::{
     level::{GraphBRow, LevelTrait},
     stream_merger::{EmptyComponent, StreamProvider, TaskReceiver},
     task::{PathId, StreamMessage, Task, TaskData, TaskTo},
-    Component, SimulationContext,
+    Component, ComponentState, SimulationContext, StallReason,
 };
 
 /// track the finish of tasks
@@ -24,6 +25,8 @@ pub struct TaskManager<Child, LevelType: LevelTrait> {
     graph_a_tasks: GraphATasks<LevelType>,
     unfinished_tasks: BTreeSet<TaskTo>,
     recent_to: Option<TaskTo>,
+    /// the index in the context, see [`SimulationContext::register_component`]
+    component: Option<usize>,
 }
 
 impl<Child: EmptyComponent, LevelType: LevelTrait> EmptyComponent
@@ -176,7 +179,7 @@ impl<LevelType: LevelTrait> GraphATasks<LevelType> {
         IntoIterRound { tasks: self }
     }
 
-    /// from graph a genrate a list of froms
+    /// from graph a genrate a list of froms, the columns of a are the rows of b, so a and b can be rectangular
     pub fn generate_mappings_for_a(
         graph_a: &CsMat<Pattern>,
         graph_b_mappings: &LevelType::Mapping,
@@ -261,6 +264,11 @@ impl<Child, LevelType: LevelTrait> TaskManager<Child, LevelType> {
     where
         LevelType::Storage: Ord,
     {
+        assert_eq!(
+            graph_a.cols(),
+            graph_b.rows(),
+            "the columns of A should be the rows of B"
+        );
         let graph_b_mappings = LevelType::get_mapping(total_size, graph_b);
         let graph_a_tasks =
             GraphATasks::generate_mappings_for_a(graph_a, &graph_b_mappings, context);
@@ -270,6 +278,7 @@ impl<Child, LevelType: LevelTrait> TaskManager<Child, LevelType> {
             graph_a_tasks,
             unfinished_tasks: BTreeSet::new(),
             recent_to: None,
+            component: None,
         }
     }
 }
@@ -287,12 +296,16 @@ where
     type SimContext = SimulationContext<LevelType>;
 
     fn cycle(&mut self, context: &mut Self::SimContext, current_cycle: u64) {
+        let component =
+            context.register_component(&mut self.component, || "TaskManager".to_string(), 0);
+        let mut state = ComponentState::Idle;
         self.child.cycle(context, current_cycle);
         // send task to child
         if self.graph_a_tasks.current_working_target < self.graph_a_tasks.tasks.len() {
             let row_task = &mut self.graph_a_tasks.tasks[self.graph_a_tasks.current_working_target];
             if let Some(round_task) = row_task.tasks.front_mut() {
                 if let Some(task) = round_task.tasks.pop_front() {
+                    let from = task.from;
                     match self
                         .child
                         .receive_task(Task::TaskData(task), context, current_cycle)
@@ -311,11 +324,23 @@ where
                                 self.graph_a_tasks.current_working_target
                             );
                             self.unfinished_tasks.insert(task_to);
+                            state = ComponentState::Busy;
+                            context.trace_instant(
+                                component,
+                                "issue task",
+                                current_cycle,
+                                || json!({"to": task_to.to, "round": task_to.round, "from": from}),
+                            );
                         }
-                        Err((_err_level, task)) => {
+                        Err((err_level, task)) => {
                             // some level cannot handle the task,
                             // put it back to the queue
                             round_task.tasks.push_front(task.into_task_data().unwrap());
+                            state = ComponentState::Stall(if err_level.is_last() {
+                                StallReason::TaskQueueFull
+                            } else {
+                                StallReason::WaitingOnChild
+                            });
                         }
                     }
                 } else {
@@ -328,6 +353,7 @@ where
                     self.child
                         .receive_task(Task::End(end_task), context, current_cycle)
                         .unwrap();
+                    state = ComponentState::Busy;
                     // next round
                     row_task.tasks.pop_front();
                 }
@@ -357,6 +383,12 @@ where
                     debug!("removing from unfinished task: {:?}", target);
                     let removed = self.unfinished_tasks.remove(&target);
                     assert!(removed);
+                    context.trace_instant(
+                        component,
+                        "finish round",
+                        current_cycle,
+                        || json!({"to": target.to, "round": target.round}),
+                    );
                 }
             }
         }
@@ -364,6 +396,7 @@ where
         let is_finished = self.unfinished_tasks.is_empty()
             && self.graph_a_tasks.current_working_target == self.graph_a_tasks.tasks.len();
         context.finished = is_finished;
+        context.record_state(component, state, current_cycle);
     }
 }
 
@@ -399,6 +432,42 @@ mod tests {
         }
     }
     #[test]
+    fn test_rectangular_task_generation() {
+        let total_size = ddr4::Storage {
+            data: [1, 1, 1, 1, 1, 2, 100, 4],
+        };
+        // A is 3x5 and B is 5x2
+        let mut graph_a = sprs::TriMat::new((3, 5));
+        for (row, col) in [(0, 4), (0, 1), (1, 0), (2, 2), (2, 3), (2, 4)] {
+            graph_a.add_triplet(row, col, Pattern);
+        }
+        let graph_a = graph_a.to_csr();
+        let mut graph_b = sprs::TriMat::new((5, 2));
+        for (row, col) in [(0, 0), (1, 1), (2, 0), (3, 1), (4, 0), (4, 1)] {
+            graph_b.add_triplet(row, col, Pattern);
+        }
+        let graph_b: CsMat<Pattern> = graph_b.to_csr();
+        let graph_b_mappings = ddr4::Level::get_mapping(&total_size, &graph_b);
+        let mut context = SimulationContext::new(&Config::from_ddr4_3200(
+            LevelConfig::default(),
+            LevelConfig::default(),
+        ));
+        let graph_a_tasks: GraphATasks<ddr4::Level> =
+            GraphATasks::generate_mappings_for_a(&graph_a, &graph_b_mappings, &mut context);
+        assert_eq!(graph_a_tasks.tasks.len(), 3);
+        for (to, row_tasks) in graph_a_tasks.tasks.iter().enumerate() {
+            let mut froms: Vec<_> = row_tasks
+                .tasks
+                .iter()
+                .flat_map(|round| round.tasks.iter())
+                .inspect(|task| assert_eq!(task.to.to, to))
+                .map(|task| task.from)
+                .collect();
+            froms.sort_unstable();
+            assert_eq!(froms, graph_a.outer_view(to).unwrap().indices());
+        }
+    }
+    #[test]
     fn test_iter() {
         // let a = vec![1, 2, 3];
         // let mut iter = a.iter();
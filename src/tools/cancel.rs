@@ -0,0 +1,164 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-08
+//! Description: the cancellation token of the simulations.
+//! the tokens form a tree: the [`root`] token is cancelled by the `stop` command of the control server,
+//! each config creates a child of the root and each graph creates a child of its config,
+//! so a single graph can be cancelled without touching the others.
+//! a token can also have a deadline, it's cancelled automatically when the deadline has passed.
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// why a token is cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CancelReason {
+    /// [`CancellationToken::cancel`] is called on this token or one of its parents
+    Cancelled,
+    /// the deadline of this token or one of its parents has passed
+    DeadlineExceeded,
+}
+
+#[derive(Debug)]
+struct TokenInner {
+    cancelled: AtomicBool,
+    deadline: Option<Instant>,
+    parent: Option<CancellationToken>,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-08
+/// Description: a cheap cloneable token, all clones share the same state
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    inner: Arc<TokenInner>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static ROOT: Lazy<CancellationToken> = Lazy::new(CancellationToken::new);
+
+/// the token of the whole process, all other tokens used by the analysis should be its children
+pub fn root() -> &'static CancellationToken {
+    &ROOT
+}
+
+static PARTIAL_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// record that a simulation returned early because its token is cancelled, see [`partial_runs`]
+pub fn record_partial_run() {
+    PARTIAL_RUNS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// the simulations that returned a partial result in this process, the batch runner compares it before and after a
+/// job to find the jobs that did not finish
+pub fn partial_runs() -> usize {
+    PARTIAL_RUNS.load(Ordering::Relaxed)
+}
+
+impl CancellationToken {
+    /// a new token without parent and deadline
+    pub fn new() -> Self {
+        Self::build(None, None)
+    }
+
+    fn build(parent: Option<CancellationToken>, deadline: Option<Instant>) -> Self {
+        Self {
+            inner: Arc::new(TokenInner {
+                cancelled: AtomicBool::new(false),
+                deadline,
+                parent,
+            }),
+        }
+    }
+
+    /// a token that is cancelled when `self` is cancelled, cancelling the child does not affect `self`
+    pub fn child(&self) -> Self {
+        Self::build(Some(self.clone()), None)
+    }
+
+    /// same as [`CancellationToken::child`], but also cancelled after `timeout`
+    pub fn child_with_timeout(&self, timeout: Option<Duration>) -> Self {
+        Self::build(
+            Some(self.clone()),
+            timeout.map(|timeout| Instant::now() + timeout),
+        )
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// the deadline of this token, the deadlines of the parents are not included
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.deadline
+    }
+
+    /// return the reason if this token or one of its parents is cancelled
+    pub fn cancel_reason(&self) -> Option<CancelReason> {
+        let mut token = Some(self);
+        while let Some(current) = token {
+            if current.inner.cancelled.load(Ordering::Relaxed) {
+                return Some(CancelReason::Cancelled);
+            }
+            if current
+                .inner
+                .deadline
+                .map_or(false, |deadline| Instant::now() >= deadline)
+            {
+                return Some(CancelReason::DeadlineExceeded);
+            }
+            token = current.inner.parent.as_ref();
+        }
+        None
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel_reason().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_tree() {
+        let config = CancellationToken::new();
+        let graph_a = config.child();
+        let graph_b = config.child();
+        graph_a.cancel();
+        assert_eq!(graph_a.cancel_reason(), Some(CancelReason::Cancelled));
+        assert!(!graph_b.is_cancelled());
+        assert!(!config.is_cancelled());
+
+        // the clones share the state
+        let graph_b_clone = graph_b.clone();
+        config.cancel();
+        assert!(graph_b_clone.is_cancelled());
+    }
+
+    #[test]
+    fn test_deadline() {
+        let config = CancellationToken::new();
+        let expired = config.child_with_timeout(Some(Duration::ZERO));
+        let graph = expired.child_with_timeout(Some(Duration::from_secs(3600)));
+        assert_eq!(graph.cancel_reason(), Some(CancelReason::DeadlineExceeded));
+        assert!(!config.is_cancelled());
+        assert!(!config
+            .child_with_timeout(Some(Duration::from_secs(3600)))
+            .is_cancelled());
+    }
+}
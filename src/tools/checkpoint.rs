@@ -0,0 +1,193 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-06
+//! Description: save and load the checkpoint of long running simulations.
+//! the checkpoint is written to a temp file first and then renamed, so a crash during saving never
+//! leaves a broken checkpoint.
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use eyre::{bail, Context};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::info;
+
+use super::graph_reader;
+
+/// the default gap between two checkpoints
+pub const DEFAULT_CHECKPOINT_INTERVAL_SECS: u64 = 600;
+
+pub fn default_checkpoint_interval() -> u64 {
+    DEFAULT_CHECKPOINT_INTERVAL_SECS
+}
+
+/// the saved state of a simulator
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Checkpoint<S> {
+    /// the next target row to be processed
+    pub target_id: usize,
+    /// the simulation is already finished, the state contains the final result
+    pub finished: bool,
+    /// the config of the simulation, see [`Checkpointer::with_fingerprint`]
+    #[serde(default)]
+    pub fingerprint: String,
+    pub state: S,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-06
+/// Description: write the checkpoint of a single simulation periodically
+#[derive(Debug)]
+pub struct Checkpointer {
+    path: PathBuf,
+    interval: Duration,
+    last_save: Instant,
+    /// load the existing checkpoint before running
+    resume: bool,
+    fingerprint: String,
+}
+
+impl Checkpointer {
+    pub fn new(path: impl Into<PathBuf>, interval: Duration, resume: bool) -> Self {
+        Self {
+            path: path.into(),
+            interval,
+            last_save: Instant::now(),
+            resume,
+            fingerprint: String::new(),
+        }
+    }
+
+    /// the fields of the config that change the state, a checkpoint saved with another fingerprint is not resumed,
+    /// so the jobs with different hardware never continue from each other's checkpoints
+    pub fn with_fingerprint(mut self, fingerprint: impl Into<String>) -> Self {
+        self.fingerprint = fingerprint.into();
+        self
+    }
+
+    /// the checkpoint of `graph` will be saved at `{dir}/{graph}_{name}.checkpoint.json`
+    pub fn for_graph(
+        dir: impl AsRef<Path>,
+        graph: &str,
+        name: &str,
+        interval: Duration,
+        resume: bool,
+    ) -> Self {
+        let graph_name = graph_reader::entry_file_stem(graph);
+        let file_name = format!("{graph_name}_{name}.checkpoint.json");
+        Self::new(dir.as_ref().join(file_name), interval, resume)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// load the checkpoint, return `None` when not resuming or there is no checkpoint yet
+    pub fn load<S: DeserializeOwned>(&self) -> eyre::Result<Option<Checkpoint<S>>> {
+        if !self.resume || !self.path.exists() {
+            return Ok(None);
+        }
+        let file =
+            File::open(&self.path).wrap_err(format!("fail to open checkpoint: {:?}", self.path))?;
+        let checkpoint: Checkpoint<S> = serde_json::from_reader(BufReader::new(file))
+            .wrap_err(format!("fail to parse checkpoint: {:?}", self.path))?;
+        if checkpoint.fingerprint != self.fingerprint {
+            bail!(
+                "the checkpoint {:?} is saved with another config, remove it or run without `--resume`\n  saved:   {}\n  current: {}",
+                self.path,
+                checkpoint.fingerprint,
+                self.fingerprint
+            );
+        }
+        info!(
+            "resume from checkpoint {:?}, target row: {}, finished: {}",
+            self.path, checkpoint.target_id, checkpoint.finished
+        );
+        Ok(Some(checkpoint))
+    }
+
+    /// whether the interval has passed since the last saving
+    pub fn is_due(&self) -> bool {
+        self.last_save.elapsed() >= self.interval
+    }
+
+    /// save the checkpoint when the interval has passed, return true if it's saved
+    pub fn save_if_due<S: Serialize>(&mut self, target_id: usize, state: &S) -> eyre::Result<bool> {
+        if self.is_due() {
+            self.save(target_id, false, state)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn save<S: Serialize>(
+        &mut self,
+        target_id: usize,
+        finished: bool,
+        state: &S,
+    ) -> eyre::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)
+                .wrap_err(format!("fail to create dir: {:?}", parent))?;
+        }
+        let temp_path = self.path.with_extension("tmp");
+        let mut writer = BufWriter::new(
+            File::create(&temp_path)
+                .wrap_err(format!("fail to create checkpoint: {:?}", temp_path))?,
+        );
+        serde_json::to_writer(
+            &mut writer,
+            &Checkpoint {
+                target_id,
+                finished,
+                fingerprint: self.fingerprint.clone(),
+                state,
+            },
+        )?;
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(&temp_path, &self.path)
+            .wrap_err(format!("fail to save checkpoint: {:?}", self.path))?;
+        self.last_save = Instant::now();
+        info!(
+            "checkpoint saved: {:?}, target row: {}",
+            self.path, target_id
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() -> eyre::Result<()> {
+        let dir = std::env::temp_dir().join("spmspm_checkpoint_test");
+        let mut checkpointer =
+            Checkpointer::for_graph(&dir, "mtx/test.mtx", "spmm", Duration::from_secs(0), true);
+        assert!(checkpointer.load::<Vec<usize>>()?.is_none());
+        assert!(checkpointer.save_if_due(3, &vec![1usize, 2, 3])?);
+        let checkpoint: Checkpoint<Vec<usize>> = checkpointer.load()?.unwrap();
+        assert_eq!(checkpoint.target_id, 3);
+        assert!(!checkpoint.finished);
+        assert_eq!(checkpoint.state, vec![1, 2, 3]);
+
+        // without resume, the checkpoint is ignored
+        let fresh = Checkpointer::new(checkpointer.path(), Duration::from_secs(0), false);
+        assert!(fresh.load::<Vec<usize>>()?.is_none());
+
+        // the checkpoint of another config is refused
+        let other = Checkpointer::new(checkpointer.path(), Duration::from_secs(0), true)
+            .with_fingerprint("subarrays=32");
+        let error = other.load::<Vec<usize>>().unwrap_err().to_string();
+        assert!(error.contains("another config"), "{}", error);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-20
+//! Description: record the events of the cycle-level simulator as a chrome trace(the json array format), the file
+//! can be opened by `chrome://tracing` or <https://ui.perfetto.dev>.
+//!
+//! a component is a thread(track) of the trace, the components of the same level are grouped into a process. the
+//! timestamps are cycles, so a microsecond in the viewer is a cycle. there are three kinds of events:
+//! - instant events, like a task is issued or a message is merged
+//! - complete events with a known duration, like opening a row
+//! - states, a state is reported every cycle and the consecutive cycles of the same state are merged into a single
+//!   complete event, it's used for the stall reasons
+//!
+//! the events are written when they are recorded, the array is closed by [`ChromeTrace::finish`] or when the trace
+//! is dropped.
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{error, warn};
+
+use super::graph_reader;
+
+/// the `chrome_trace` field of the config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChromeTraceConfig {
+    /// write the trace of each graph to `{dir}/{graph}_{name}.trace.json`
+    pub dir: PathBuf,
+    /// stop recording after this many events, the traces of large graphs are too large for the viewers
+    #[serde(default)]
+    pub max_events: Option<usize>,
+}
+
+/// the state of a track, ended at `end`(exclusive)
+#[derive(Debug, Clone)]
+struct OpenState {
+    name: &'static str,
+    start: u64,
+    end: u64,
+}
+
+/// a thread of the trace
+#[derive(Debug, Clone)]
+struct Track {
+    pid: usize,
+    state: Option<OpenState>,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-20
+/// Description: the trace sink, see the module doc
+#[derive(Debug)]
+pub struct ChromeTrace {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    /// the names of the processes, the index is the pid
+    processes: Vec<String>,
+    /// the index is the tid
+    tracks: Vec<Track>,
+    max_events: Option<usize>,
+    /// the events written, the metadata events are not counted
+    written: usize,
+    dropped: usize,
+    /// no event is written yet, the first event has no separator
+    empty: bool,
+    finished: bool,
+}
+
+impl ChromeTrace {
+    pub fn create(path: impl AsRef<Path>, max_events: Option<usize>) -> eyre::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .wrap_err(format!("fail to create dir: {:?}", parent))?;
+        }
+        let file = File::create(&path).wrap_err(format!("fail to create trace: {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+        write!(writer, "{{\"displayTimeUnit\":\"ns\",\"traceEvents\":[")
+            .wrap_err(format!("fail to write trace: {:?}", path))?;
+        Ok(Self {
+            writer,
+            path,
+            processes: vec![],
+            tracks: vec![],
+            max_events,
+            written: 0,
+            dropped: 0,
+            empty: true,
+            finished: false,
+        })
+    }
+
+    /// create the trace at `{dir}/{graph}_{name}.trace.json`
+    pub fn for_graph(config: &ChromeTraceConfig, graph: &str, name: &str) -> eyre::Result<Self> {
+        let graph_name = graph_reader::entry_file_stem(graph);
+        let file_name = format!("{graph_name}_{name}.trace.json");
+        Self::create(config.dir.join(file_name), config.max_events)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// the number of events written to the file
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// the number of events dropped after `max_events`
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    fn write_value(&mut self, value: &Value) {
+        let separator = if self.empty { "" } else { "," };
+        self.empty = false;
+        if let Err(e) = write!(self.writer, "{separator}\n{value}") {
+            error!("fail to write trace {:?}: {:?}", self.path, e);
+        }
+    }
+
+    fn write_event(&mut self, value: Value) {
+        if self.finished {
+            return;
+        }
+        if self.max_events.map_or(false, |max| self.written >= max) {
+            self.dropped += 1;
+            return;
+        }
+        self.write_value(&value);
+        self.written += 1;
+    }
+
+    /// add a track named `{process} {id}` to the process, return the track id
+    pub fn track(&mut self, process: &str, id: usize) -> usize {
+        let pid = match self.processes.iter().position(|p| p == process) {
+            Some(pid) => pid,
+            None => {
+                self.processes.push(process.to_string());
+                let pid = self.processes.len() - 1;
+                self.write_value(&json!({
+                    "name": "process_name",
+                    "ph": "M",
+                    "pid": pid,
+                    "args": {"name": process},
+                }));
+                pid
+            }
+        };
+        self.tracks.push(Track { pid, state: None });
+        let tid = self.tracks.len() - 1;
+        self.write_value(&json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": pid,
+            "tid": tid,
+            "args": {"name": format!("{process} {id}")},
+        }));
+        tid
+    }
+
+    /// an event without duration
+    pub fn instant(&mut self, track: usize, name: &str, cycle: u64, args: Value) {
+        let pid = self.tracks[track].pid;
+        self.write_event(json!({
+            "name": name,
+            "ph": "i",
+            "s": "t",
+            "ts": cycle,
+            "pid": pid,
+            "tid": track,
+            "args": args,
+        }));
+    }
+
+    /// an event lasts `duration` cycles from `cycle`
+    pub fn complete(&mut self, track: usize, name: &str, cycle: u64, duration: u64, args: Value) {
+        let pid = self.tracks[track].pid;
+        self.write_event(json!({
+            "name": name,
+            "ph": "X",
+            "ts": cycle,
+            "dur": duration,
+            "pid": pid,
+            "tid": track,
+            "args": args,
+        }));
+    }
+
+    /// the state of the track at `cycle`, `None` ends the current state
+    pub fn state(&mut self, track: usize, state: Option<&'static str>, cycle: u64) {
+        if let (Some(open), Some(name)) = (&mut self.tracks[track].state, state) {
+            if open.name == name && open.end == cycle {
+                open.end = cycle + 1;
+                return;
+            }
+        }
+        self.close_state(track);
+        self.tracks[track].state = state.map(|name| OpenState {
+            name,
+            start: cycle,
+            end: cycle + 1,
+        });
+    }
+
+    fn close_state(&mut self, track: usize) {
+        if let Some(open) = self.tracks[track].state.take() {
+            self.complete(
+                track,
+                open.name,
+                open.start,
+                open.end - open.start,
+                json!({"cycles": open.end - open.start}),
+            );
+        }
+    }
+
+    /// close the open states and the json, the later events are ignored
+    pub fn finish(&mut self) -> eyre::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        for track in 0..self.tracks.len() {
+            self.close_state(track);
+        }
+        self.finished = true;
+        if self.dropped > 0 {
+            warn!(
+                "{} events are not written to {:?}, the limit is {:?}",
+                self.dropped, self.path, self.max_events
+            );
+        }
+        write!(self.writer, "\n]}}").wrap_err(format!("fail to write trace: {:?}", self.path))?;
+        self.writer
+            .flush()
+            .wrap_err(format!("fail to write trace: {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+impl Drop for ChromeTrace {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            error!("fail to finish the chrome trace: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chrome_trace() -> eyre::Result<()> {
+        let dir = std::env::temp_dir().join("spmspm_chrome_trace_test");
+        let config = ChromeTraceConfig {
+            dir: dir.clone(),
+            max_events: Some(4),
+        };
+        let mut trace = ChromeTrace::for_graph(&config, "graphs/test.mtx", "pim")?;
+        let bank = trace.track("Bank", 0);
+        let provider = trace.track("SubArray", 0);
+        trace.instant(bank, "receive task", 0, json!({"to": 1}));
+        trace.complete(provider, "open row", 1, 22, json!({"row": 3}));
+        // cycles 2 to 4 are merged
+        for cycle in 2..5 {
+            trace.state(provider, Some("row activation"), cycle);
+        }
+        trace.state(provider, Some("output full"), 5);
+        trace.state(provider, None, 6);
+        trace.state(bank, Some("input empty"), 6);
+        trace.finish()?;
+        assert_eq!(trace.written(), 4);
+        assert_eq!(trace.dropped(), 1);
+
+        let path = dir.join("test_pim.trace.json");
+        let value: Value = serde_json::from_reader(File::open(&path)?)?;
+        let events = value["traceEvents"].as_array().unwrap();
+        // 2 processes, 2 threads and 4 events
+        assert_eq!(events.len(), 8);
+        let stall = events
+            .iter()
+            .find(|e| e["name"] == "row activation")
+            .unwrap();
+        assert_eq!(stall["ts"], 2);
+        assert_eq!(stall["dur"], 3);
+        assert_eq!(stall["tid"], provider);
+        assert!(events.iter().all(|e| e["name"] != "input empty"));
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,620 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-07
+//! Description: the control protocol of a running simulation.
+//! the client sends one command per line and the server answers each command with one json line:
+//! - `status`: the progress of all running tasks
+//! - `pause`/`resume`: block/unblock the running tasks at their next progress report
+//! - `stop`: stop all tasks, the tasks will write the results they already have
+//! - `cancel <id>`: stop a single task, the id is shown in `status`
+//! - `dump-partial [dir]`: the running tasks write their partial results into `dir`(default: `partial`), it's an
+//!   error when none of the running tasks can write partial results
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use eyre::{bail, Context};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use super::cancel::{self, CancellationToken};
+
+/// the default dir of `dump-partial`
+pub const DEFAULT_DUMP_DIR: &str = "partial";
+
+/// the commands understood by the control server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Status,
+    Pause,
+    Resume,
+    Stop,
+    /// cancel the task with the id
+    Cancel(usize),
+    /// write the partial results into the dir, `None` means [`DEFAULT_DUMP_DIR`]
+    DumpPartial(Option<PathBuf>),
+}
+
+impl FromStr for Command {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let command = match words.next() {
+            Some("status") => Command::Status,
+            Some("pause") => Command::Pause,
+            Some("resume") => Command::Resume,
+            Some("stop") => Command::Stop,
+            Some("cancel") => match words.next() {
+                Some(id) => {
+                    Command::Cancel(id.parse().wrap_err(format!("invalid task id: {}", id))?)
+                }
+                None => bail!("cancel needs a task id"),
+            },
+            Some("dump-partial") => Command::DumpPartial(words.next().map(PathBuf::from)),
+            Some(other) => bail!("unknown command: {}", other),
+            None => bail!("empty command"),
+        };
+        if let Some(extra) = words.next() {
+            bail!("unexpected argument: {}", extra);
+        }
+        Ok(command)
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::Status => write!(f, "status"),
+            Command::Pause => write!(f, "pause"),
+            Command::Resume => write!(f, "resume"),
+            Command::Stop => write!(f, "stop"),
+            Command::Cancel(id) => write!(f, "cancel {}", id),
+            Command::DumpPartial(None) => write!(f, "dump-partial"),
+            Command::DumpPartial(Some(dir)) => write!(f, "dump-partial {}", dir.display()),
+        }
+    }
+}
+
+/// the progress of a running task
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskStatus {
+    pub id: usize,
+    /// usually the graph and the algorithm
+    pub name: String,
+    pub processed: usize,
+    pub total: usize,
+    pub elapsed_secs: f64,
+    /// estimated remaining time, `None` before the first row is processed
+    pub eta_secs: Option<f64>,
+    pub cancelled: bool,
+    /// the task writes its partial result on `dump-partial`
+    pub partial: bool,
+}
+
+/// the answer of the control server
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Status {
+        paused: bool,
+        stopping: bool,
+        tasks: Vec<TaskStatus>,
+    },
+    Ok {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+struct RunningTask {
+    name: String,
+    processed: usize,
+    total: usize,
+    start: Instant,
+    cancel: CancellationToken,
+    partial: bool,
+}
+
+impl RunningTask {
+    fn status(&self, id: usize) -> TaskStatus {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let eta_secs = (self.processed > 0).then(|| {
+            elapsed / self.processed as f64 * self.total.saturating_sub(self.processed) as f64
+        });
+        TaskStatus {
+            id,
+            name: self.name.clone(),
+            processed: self.processed,
+            total: self.total,
+            elapsed_secs: elapsed,
+            eta_secs,
+            cancelled: self.cancel.is_cancelled(),
+            partial: self.partial,
+        }
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-07
+/// Description: the running tasks, the pause flag and the dump requests served by a control server.
+/// the simulations use the [`global`] state, a separated state only sees the tasks registered in it
+pub struct ControlState {
+    /// cancelled by `stop`
+    root: CancellationToken,
+    next_task_id: AtomicUsize,
+    tasks: Mutex<BTreeMap<usize, RunningTask>>,
+    paused: Mutex<bool>,
+    pause_condvar: Condvar,
+    /// the number of dump requests and the dir of the last one
+    dump_request: Mutex<(usize, Option<PathBuf>)>,
+}
+
+static GLOBAL: Lazy<Arc<ControlState>> =
+    Lazy::new(|| Arc::new(ControlState::new(cancel::root().clone())));
+
+/// the state of this process, `stop` cancels [`cancel::root`]
+pub fn global() -> &'static Arc<ControlState> {
+    &GLOBAL
+}
+
+pub fn is_paused() -> bool {
+    global().is_paused()
+}
+
+/// block the current thread until the simulation is resumed or `cancel` is cancelled
+pub fn wait_if_paused(cancel: &CancellationToken) {
+    global().wait_if_paused(cancel)
+}
+
+pub fn handle_command(command: &Command) -> Response {
+    global().handle_command(command)
+}
+
+impl ControlState {
+    /// `stop` cancels `root`
+    pub fn new(root: CancellationToken) -> Self {
+        Self {
+            root,
+            next_task_id: AtomicUsize::new(0),
+            tasks: Mutex::new(BTreeMap::new()),
+            paused: Mutex::new(false),
+            pause_condvar: Condvar::new(),
+            dump_request: Mutex::new((0, None)),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
+    }
+
+    fn set_paused(&self, paused: bool) {
+        *self.paused.lock().unwrap() = paused;
+        self.pause_condvar.notify_all();
+    }
+
+    /// block the current thread until this state is resumed or `cancel` is cancelled
+    pub fn wait_if_paused(&self, cancel: &CancellationToken) {
+        let mut paused = self.paused.lock().unwrap();
+        if *paused {
+            info!("paused by the control server");
+        }
+        while *paused && !cancel.is_cancelled() {
+            // the tokens do not notify the condvar, so check it periodically
+            paused = self
+                .pause_condvar
+                .wait_timeout(paused, Duration::from_secs(1))
+                .unwrap()
+                .0;
+        }
+    }
+
+    pub fn handle_command(&self, command: &Command) -> Response {
+        info!("received control command: {}", command);
+        match command {
+            Command::Status => Response::Status {
+                paused: self.is_paused(),
+                stopping: self.root.is_cancelled(),
+                tasks: self
+                    .tasks
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(id, task)| task.status(*id))
+                    .collect(),
+            },
+            Command::Pause => {
+                self.set_paused(true);
+                Response::Ok {
+                    message: "paused".to_string(),
+                }
+            }
+            Command::Resume => {
+                self.set_paused(false);
+                Response::Ok {
+                    message: "resumed".to_string(),
+                }
+            }
+            Command::Stop => {
+                self.root.cancel();
+                Response::Ok {
+                    message: "stopping".to_string(),
+                }
+            }
+            Command::Cancel(id) => match self.tasks.lock().unwrap().get(id) {
+                Some(task) => {
+                    task.cancel.cancel();
+                    Response::Ok {
+                        message: format!("cancelling task {}: {}", id, task.name),
+                    }
+                }
+                None => Response::Error {
+                    message: format!("no such task: {}", id),
+                },
+            },
+            Command::DumpPartial(dir) => {
+                let tasks = self.tasks.lock().unwrap();
+                let skipped = tasks
+                    .values()
+                    .filter(|task| !task.partial)
+                    .map(|task| task.name.clone())
+                    .collect::<Vec<_>>();
+                if skipped.len() == tasks.len() {
+                    return Response::Error {
+                        message: format!(
+                            "no running task can write partial results, running tasks: {:?}",
+                            skipped
+                        ),
+                    };
+                }
+                drop(tasks);
+                let mut request = self.dump_request.lock().unwrap();
+                request.0 += 1;
+                request.1 = dir.clone();
+                let mut message = format!(
+                    "partial results will be written to {:?}",
+                    dir.as_deref().unwrap_or(Path::new(DEFAULT_DUMP_DIR))
+                );
+                if !skipped.is_empty() {
+                    message += &format!(", these tasks have no partial result: {:?}", skipped);
+                }
+                Response::Ok { message }
+            }
+        }
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-07
+/// Description: the handle of a running task, the task is shown in `status` until the handle is dropped
+pub struct TaskProgress {
+    id: usize,
+    name: String,
+    /// the dump requests before this number are already handled
+    dump_generation: usize,
+    state: Arc<ControlState>,
+}
+
+impl TaskProgress {
+    /// the task can be cancelled by the `cancel` command through `cancel`
+    pub fn register(name: impl Into<String>, total: usize, cancel: &CancellationToken) -> Self {
+        Self::register_in(global().clone(), name, total, cancel)
+    }
+
+    /// register the task in `state` instead of the [`global`] state
+    pub fn register_in(
+        state: Arc<ControlState>,
+        name: impl Into<String>,
+        total: usize,
+        cancel: &CancellationToken,
+    ) -> Self {
+        let name = name.into();
+        let id = state.next_task_id.fetch_add(1, Ordering::Relaxed);
+        state.tasks.lock().unwrap().insert(
+            id,
+            RunningTask {
+                name: name.clone(),
+                processed: 0,
+                total,
+                start: Instant::now(),
+                cancel: cancel.clone(),
+                partial: true,
+            },
+        );
+        let dump_generation = state.dump_request.lock().unwrap().0;
+        Self {
+            id,
+            name,
+            dump_generation,
+            state,
+        }
+    }
+
+    /// the task has no partial result, `dump-partial` skips it
+    pub fn without_partial(self) -> Self {
+        if let Some(task) = self.state.tasks.lock().unwrap().get_mut(&self.id) {
+            task.partial = false;
+        }
+        self
+    }
+
+    /// report the progress, block while paused.
+    /// return the file the partial result should be written to when a dump is requested
+    pub fn update(&mut self, processed: usize) -> Option<PathBuf> {
+        let (cancel, partial) = {
+            let mut tasks = self.state.tasks.lock().unwrap();
+            let task = tasks.get_mut(&self.id).expect("the task is registered");
+            task.processed = processed;
+            (task.cancel.clone(), task.partial)
+        };
+        self.state.wait_if_paused(&cancel);
+        let request = self.state.dump_request.lock().unwrap();
+        if request.0 == self.dump_generation {
+            return None;
+        }
+        self.dump_generation = request.0;
+        if !partial {
+            return None;
+        }
+        let dir = request
+            .1
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_DUMP_DIR));
+        let file_name = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        Some(dir.join(format!("{}_{}.partial.json", file_name, self.id)))
+    }
+}
+
+impl Drop for TaskProgress {
+    fn drop(&mut self) {
+        self.state.tasks.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// write the partial result returned by [`TaskProgress::update`]
+pub fn write_partial<T: Serialize>(path: impl AsRef<Path>, value: &T) -> eyre::Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).wrap_err(format!("fail to create dir: {:?}", parent))?;
+    }
+    let file = File::create(path).wrap_err(format!("fail to create {:?}", path))?;
+    serde_json::to_writer(BufWriter::new(file), value)?;
+    info!("partial result written to {:?}", path);
+    Ok(())
+}
+
+fn handle_client(stream: TcpStream, state: &ControlState) -> eyre::Result<()> {
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match line.parse::<Command>() {
+            Ok(command) => state.handle_command(&command),
+            Err(e) => Response::Error {
+                message: e.to_string(),
+            },
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// accept the clients forever, each client is handled in its own thread
+pub fn serve(listener: TcpListener) {
+    serve_with(listener, global().clone())
+}
+
+/// like [`serve`], but the commands are applied to `state`
+pub fn serve_with(listener: TcpListener, state: Arc<ControlState>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = handle_client(stream, &state) {
+                        error!("failed to handle control client: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("failed to accept connection: {}", e);
+            }
+        }
+    }
+}
+
+/// start the server in a new thread, return the address it's listening on
+pub fn spawn_server(addr: impl ToSocketAddrs) -> eyre::Result<SocketAddr> {
+    spawn_server_with(addr, global().clone())
+}
+
+/// like [`spawn_server`], but the commands are applied to `state`
+pub fn spawn_server_with(
+    addr: impl ToSocketAddrs,
+    state: Arc<ControlState>,
+) -> eyre::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    std::thread::spawn(move || serve_with(listener, state));
+    Ok(local_addr)
+}
+
+/// send a single command and wait for the response
+pub fn send_command(addr: impl ToSocketAddrs, command: &Command) -> eyre::Result<Response> {
+    let stream = TcpStream::connect(addr).wrap_err("fail to connect to the control server")?;
+    let mut writer = BufWriter::new(stream.try_clone()?);
+    writeln!(writer, "{}", command)?;
+    writer.flush()?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    if line.is_empty() {
+        bail!("the control server closed the connection");
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() -> eyre::Result<()> {
+        for command in [
+            Command::Status,
+            Command::Pause,
+            Command::Resume,
+            Command::Stop,
+            Command::Cancel(3),
+            Command::DumpPartial(None),
+            Command::DumpPartial(Some("output/partial".into())),
+        ] {
+            assert_eq!(command.to_string().parse::<Command>()?, command);
+        }
+        assert!("jump".parse::<Command>().is_err());
+        assert!("status now".parse::<Command>().is_err());
+        assert!("cancel".parse::<Command>().is_err());
+        assert!("cancel all".parse::<Command>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_loopback_server() -> eyre::Result<()> {
+        // a separated state, the other tests running in parallel are never paused or dumped
+        let root = CancellationToken::new();
+        let state = Arc::new(ControlState::new(root.clone()));
+        let addr = spawn_server_with("127.0.0.1:0", state.clone())?;
+        let cancel = CancellationToken::new();
+        let mut progress =
+            TaskProgress::register_in(state.clone(), "test_loopback_server", 100, &cancel);
+        assert_eq!(progress.update(50), None);
+
+        let Response::Status { tasks, .. } = send_command(addr, &Command::Status)? else {
+            panic!("status should return the tasks");
+        };
+        let task = tasks
+            .iter()
+            .find(|task| task.name == "test_loopback_server")
+            .unwrap();
+        assert_eq!(task.processed, 50);
+        assert_eq!(task.total, 100);
+        assert!(task.eta_secs.is_some());
+
+        send_command(addr, &Command::Pause)?;
+        assert!(state.is_paused());
+        assert!(!is_paused());
+        send_command(addr, &Command::Resume)?;
+        assert!(!state.is_paused());
+
+        let dir = std::env::temp_dir().join("spmspm_control_test");
+        send_command(addr, &Command::DumpPartial(Some(dir.clone())))?;
+        let path = progress.update(60).unwrap();
+        assert!(path.starts_with(&dir));
+        // the request is only handled once
+        assert_eq!(progress.update(70), None);
+
+        // a running task writes its partial result, the task without partial result is skipped
+        let mut skipped =
+            TaskProgress::register_in(state.clone(), "test_loopback_skipped", 100, &cancel)
+                .without_partial();
+        let running_state = state.clone();
+        let running = std::thread::spawn(move || {
+            let cancel = CancellationToken::new();
+            let mut progress =
+                TaskProgress::register_in(running_state, "test_loopback_running", 10000, &cancel);
+            for processed in 0..10000 {
+                if let Some(path) = progress.update(processed) {
+                    write_partial(&path, &processed).unwrap();
+                    return Some(path);
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            None
+        });
+        // the dump requests before the task is registered are not for it
+        loop {
+            let Response::Status { tasks, .. } = send_command(addr, &Command::Status)? else {
+                panic!("status should return the tasks");
+            };
+            if let Some(task) = tasks
+                .iter()
+                .find(|task| task.name == "test_loopback_running")
+            {
+                assert!(task.partial);
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let Response::Ok { message } =
+            send_command(addr, &Command::DumpPartial(Some(dir.clone())))?
+        else {
+            panic!("the running task can write partial results");
+        };
+        assert!(message.contains("test_loopback_skipped"));
+        let path = running.join().unwrap().expect("the dump is handled");
+        assert!(path.starts_with(&dir));
+        let processed: usize = serde_json::from_reader(File::open(&path)?)?;
+        assert!(processed < 10000);
+        assert_eq!(skipped.update(50), None);
+        drop(skipped);
+
+        // only the task itself is cancelled
+        send_command(addr, &Command::Cancel(task.id))?;
+        assert!(cancel.is_cancelled());
+        assert!(!root.is_cancelled());
+        assert!(matches!(
+            send_command(addr, &Command::Cancel(usize::MAX))?,
+            Response::Error { .. }
+        ));
+
+        // unknown commands are answered with an error instead of closing the connection
+        let mut stream = TcpStream::connect(addr)?;
+        stream.write_all(b"jump\nstatus\n")?;
+        let mut lines = BufReader::new(stream).lines();
+        let error: Response = serde_json::from_str(&lines.next().unwrap()?)?;
+        assert!(matches!(error, Response::Error { .. }));
+        let status: Response = serde_json::from_str(&lines.next().unwrap()?)?;
+        assert!(matches!(status, Response::Status { .. }));
+
+        drop(progress);
+        let Response::Status { tasks, .. } = send_command(addr, &Command::Status)? else {
+            panic!("status should return the tasks");
+        };
+        assert!(tasks.is_empty());
+
+        // `stop` cancels the root of the state
+        send_command(addr, &Command::Stop)?;
+        assert!(root.is_cancelled());
+        assert!(!cancel::root().is_cancelled());
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::{BTreeSet, VecDeque};
 
-use super::CrossBarPacket;
+use super::{interconnect::IcntStats, CrossBarPacket};
 
 pub struct CrossBareSimulator<T> {
     ports: usize,
@@ -9,6 +9,7 @@ pub struct CrossBareSimulator<T> {
     output_buffer: Vec<VecDeque<T>>,
     shift: usize,
     pub target_conflict: usize,
+    pub(super) stats: IcntStats,
 }
 
 impl<T> CrossBareSimulator<T> {
@@ -24,18 +25,47 @@ impl<T> CrossBareSimulator<T> {
                 .collect(),
             shift: 0,
             target_conflict: 0,
+            stats: IcntStats::default(),
         }
     }
     pub fn add(&mut self, port: usize, packet: T) -> Result<(), T> {
         if self.input_buffer[port].len() < self.buffer_size {
             self.input_buffer[port].push_back(packet);
+            self.stats.injected += 1;
             Ok(())
         } else {
+            self.stats.rejected += 1;
             Err(packet)
         }
     }
     pub fn pop(&mut self, port: usize) -> Option<T> {
-        self.output_buffer[port].pop_front()
+        let packet = self.output_buffer[port].pop_front();
+        if packet.is_some() {
+            self.stats.ejected += 1;
+        }
+        packet
+    }
+    pub fn front(&self, port: usize) -> Option<&T> {
+        self.output_buffer[port].front()
+    }
+    pub fn ports(&self) -> usize {
+        self.ports
+    }
+    /// the occupancy of each buffer, used to diagnose a stalled network
+    pub fn dump_state(&self) -> String {
+        (0..self.ports)
+            .map(|port| {
+                format!(
+                    "port {}: input {}/{}, output {}/{}",
+                    port,
+                    self.input_buffer[port].len(),
+                    self.buffer_size,
+                    self.output_buffer[port].len(),
+                    self.buffer_size
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 }
 
@@ -50,6 +80,7 @@ impl<T: CrossBarPacket> CrossBareSimulator<T> {
                 if current_round_target.contains(&traffic.get_dest()) {
                     self.input_buffer[index].push_front(traffic);
                     self.target_conflict += 1;
+                    self.stats.conflicts += 1;
                 } else if self.output_buffer[target].len() >= self.buffer_size {
                     self.input_buffer[index].push_front(traffic);
                 } else {
@@ -59,6 +90,7 @@ impl<T: CrossBarPacket> CrossBareSimulator<T> {
             }
         }
         self.shift = (self.shift + 1) % self.ports;
+        self.stats.cycles += 1;
     }
 }
 
@@ -0,0 +1,349 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-19
+//! Description: write the dram accesses of the simulators as the memory traces of ramulator and dramsim3, so the
+//! cycles can be cross-checked with an established dram simulator.
+//!
+//! a access is a [`ddr4::Storage`] path, it's encoded into a physical address with the default address mapping of
+//! the target simulator, so the simulator decodes the same channel, rank, bank group, bank, row and column:
+//! - ramulator(`RoBaRaCoCh`), from the lowest bit: channel, column, rank, bank group, bank, row. a line is
+//!   `0x{address} R`, ramulator replays the lines in order so the cycles are only used to sort the lines
+//! - dramsim3(`rochrababgco`), from the lowest bit: column, bank group, bank, rank, channel, row. a line is
+//!   `0x{address} READ {cycle}`
+//!
+//! the lowest 6 bits are the offset of a 64-byte access. the subarray and the row are merged into the row of the
+//! bank, the chips of a rank work in lockstep so the chip is not encoded.
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use super::{graph_reader, math::count_to_log};
+use crate::pim::level::ddr4;
+
+/// the bits of a 64-byte access
+const TRANSACTION_BITS: usize = 6;
+
+/// the format of the trace file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TraceFormat {
+    #[default]
+    Ramulator,
+    DramSim3,
+}
+
+impl TraceFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            TraceFormat::Ramulator => "ramulator.trace",
+            TraceFormat::DramSim3 => "dramsim3.trace",
+        }
+    }
+}
+
+/// the `dram_trace` field of the config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DramTraceConfig {
+    /// write the trace of each graph to `{dir}/{graph}_{name}.{format}.trace`
+    pub dir: PathBuf,
+    #[serde(default)]
+    pub format: TraceFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TraceOp {
+    Read,
+    Write,
+}
+
+/// a single access of the trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub path: ddr4::Storage,
+    pub op: TraceOp,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-19
+/// Description: the address mapping of a trace format, the widths of the fields are calculated from the size of the
+/// whole dram
+#[derive(Debug, Clone)]
+pub struct AddressMapping {
+    format: TraceFormat,
+    total_size: ddr4::Storage,
+    channel: usize,
+    rank: usize,
+    bank_group: usize,
+    bank: usize,
+    row: usize,
+    column: usize,
+}
+
+impl AddressMapping {
+    pub fn new(format: TraceFormat, total_size: ddr4::Storage) -> Self {
+        let size = &total_size.data;
+        Self {
+            format,
+            channel: count_to_log(size[0]),
+            rank: count_to_log(size[1]),
+            bank_group: count_to_log(size[3]),
+            bank: count_to_log(size[4]),
+            row: count_to_log(size[5] * size[6]),
+            column: count_to_log(size[7]),
+            total_size,
+        }
+    }
+
+    /// the physical address of the path, the fields are truncated to their widths
+    pub fn address(&self, path: &ddr4::Storage) -> u64 {
+        let data = &path.data;
+        let row = data[5] * self.total_size.data[6] + data[6];
+        let fields = match self.format {
+            TraceFormat::Ramulator => [
+                (data[0], self.channel),
+                (data[7], self.column),
+                (data[1], self.rank),
+                (data[3], self.bank_group),
+                (data[4], self.bank),
+                (row, self.row),
+            ],
+            TraceFormat::DramSim3 => [
+                (data[7], self.column),
+                (data[3], self.bank_group),
+                (data[4], self.bank),
+                (data[1], self.rank),
+                (data[0], self.channel),
+                (row, self.row),
+            ],
+        };
+        let mut address = 0;
+        let mut shift = TRANSACTION_BITS;
+        for (value, bits) in fields {
+            address |= (value as u64 & ((1 << bits) - 1)) << shift;
+            shift += bits;
+        }
+        address
+    }
+
+    /// the path of a flat subarray id of the analyses, the banks of a channel are interleaved across the bank
+    /// groups, a column beyond the row goes to the next rows
+    pub fn subarray_path(&self, subarray_id: usize, row: usize, column: usize) -> ddr4::Storage {
+        let size = &self.total_size.data;
+        let (row, column) = (row + column / size[7], column % size[7]);
+        let subarray = subarray_id % size[5];
+        let flat_bank = subarray_id / size[5];
+        let (channel, bank) = (flat_bank / size[4], flat_bank % size[4]);
+        let bank_groups = size[3].max(1);
+        ddr4::Storage::new(
+            channel,
+            0,
+            0,
+            bank % bank_groups,
+            bank / bank_groups,
+            subarray,
+            row,
+            column,
+        )
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-19
+/// Description: the trace sink. the records are buffered and sorted by their cycles when flushed, the records of a
+/// flush should not be earlier than the records already written.
+///
+/// the analytical models have no global clock, they [`DramTrace::issue`] the accesses of a round and call
+/// [`DramTrace::end_round`] at its end: an access is issued one cycle after the previous access of the same bank
+#[derive(Debug)]
+pub struct DramTrace {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    mapping: AddressMapping,
+    pending: Vec<TraceRecord>,
+    /// the first cycle of the current round
+    round_start: u64,
+    /// the accesses issued to each bank in the current round
+    bank_issued: BTreeMap<[usize; 4], u64>,
+    written: usize,
+}
+
+impl DramTrace {
+    pub fn create(
+        path: impl AsRef<Path>,
+        format: TraceFormat,
+        total_size: ddr4::Storage,
+    ) -> eyre::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .wrap_err(format!("fail to create dir: {:?}", parent))?;
+        }
+        let file = File::create(&path).wrap_err(format!("fail to create trace: {:?}", path))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            path,
+            mapping: AddressMapping::new(format, total_size),
+            pending: vec![],
+            round_start: 0,
+            bank_issued: BTreeMap::new(),
+            written: 0,
+        })
+    }
+
+    /// create the trace at `{dir}/{graph}_{name}.{format}.trace`
+    pub fn for_graph(
+        config: &DramTraceConfig,
+        total_size: ddr4::Storage,
+        graph: &str,
+        name: &str,
+    ) -> eyre::Result<Self> {
+        let graph_name = graph_reader::entry_file_stem(graph);
+        let file_name = format!("{graph_name}_{name}.{}", config.format.extension());
+        Self::create(config.dir.join(file_name), config.format, total_size)
+    }
+
+    pub fn mapping(&self) -> &AddressMapping {
+        &self.mapping
+    }
+
+    /// the number of records written to the file
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// record an access at `cycle`
+    pub fn record(&mut self, cycle: u64, path: &ddr4::Storage, op: TraceOp) {
+        self.pending.push(TraceRecord {
+            cycle,
+            path: path.clone(),
+            op,
+        });
+    }
+
+    /// record an access of the current round, see the struct doc
+    pub fn issue(&mut self, path: &ddr4::Storage, op: TraceOp) {
+        let data = &path.data;
+        let issued = self
+            .bank_issued
+            .entry([data[0], data[1], data[3], data[4]])
+            .or_default();
+        let cycle = self.round_start + *issued;
+        *issued += 1;
+        self.record(cycle, path, op);
+    }
+
+    /// the next round starts after `cycles`, or after the busiest bank finished its accesses, then flush the round
+    pub fn end_round(&mut self, cycles: u64) -> eyre::Result<()> {
+        let busiest = self.bank_issued.values().copied().max().unwrap_or(0);
+        self.round_start += cycles.max(busiest);
+        self.bank_issued.clear();
+        self.flush()
+    }
+
+    /// write the buffered records in the order of their cycles
+    pub fn flush(&mut self) -> eyre::Result<()> {
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.sort_by_key(|record| record.cycle);
+        for record in &pending {
+            let address = self.mapping.address(&record.path);
+            match (self.mapping.format, record.op) {
+                (TraceFormat::Ramulator, TraceOp::Read) => writeln!(self.writer, "0x{address:x} R"),
+                (TraceFormat::Ramulator, TraceOp::Write) => {
+                    writeln!(self.writer, "0x{address:x} W")
+                }
+                (TraceFormat::DramSim3, TraceOp::Read) => {
+                    writeln!(self.writer, "0x{address:x} READ {}", record.cycle)
+                }
+                (TraceFormat::DramSim3, TraceOp::Write) => {
+                    writeln!(self.writer, "0x{address:x} WRITE {}", record.cycle)
+                }
+            }
+            .wrap_err(format!("fail to write trace: {:?}", self.path))?;
+        }
+        self.written += pending.len();
+        self.writer
+            .flush()
+            .wrap_err(format!("fail to write trace: {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+impl Drop for DramTrace {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("fail to flush the dram trace: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_mapping() {
+        // 2 channels, 2 ranks, 4 bank groups of 4 banks, 2 subarrays of 8 rows, 16 columns
+        let total_size = ddr4::Storage::new(2, 2, 8, 4, 4, 2, 8, 16);
+        let path = ddr4::Storage::new(1, 1, 3, 2, 3, 1, 5, 9);
+
+        let ramulator = AddressMapping::new(TraceFormat::Ramulator, total_size.clone());
+        // row 13, bank 3, bank group 2, rank 1, column 9, channel 1
+        let expected = ((((((((((13 << 2) | 3) << 2) | 2) << 1) | 1) << 4) | 9) << 1) | 1) << 6;
+        assert_eq!(ramulator.address(&path), expected);
+
+        let dramsim3 = AddressMapping::new(TraceFormat::DramSim3, total_size);
+        // row 13, channel 1, rank 1, bank 3, bank group 2, column 9
+        let expected = ((((((((((13 << 1) | 1) << 1) | 1) << 2) | 3) << 2) | 2) << 4) | 9) << 6;
+        assert_eq!(dramsim3.address(&path), expected);
+
+        // the 4th bank of channel 1: bank group 3, bank 0
+        let path = dramsim3.subarray_path((4 + 3) * 2 + 1, 5, 9);
+        assert_eq!(path, ddr4::Storage::new(1, 0, 0, 3, 0, 1, 5, 9));
+        let path = dramsim3.subarray_path(0, 5, 16 * 2 + 1);
+        assert_eq!(path, ddr4::Storage::new(0, 0, 0, 0, 0, 0, 7, 1));
+    }
+
+    #[test]
+    fn test_dram_trace() -> eyre::Result<()> {
+        let dir = std::env::temp_dir().join("spmspm_dram_trace_test");
+        let config = DramTraceConfig {
+            dir: dir.clone(),
+            format: TraceFormat::DramSim3,
+        };
+        let total_size = ddr4::Storage::new(1, 1, 1, 1, 2, 1, 4, 4);
+        let mut trace = DramTrace::for_graph(&config, total_size, "graphs/test.mtx", "spmm")?;
+        let bank_0 = ddr4::Storage::new(0, 0, 0, 0, 0, 0, 1, 2);
+        let bank_1 = ddr4::Storage::new(0, 0, 0, 0, 1, 0, 1, 2);
+        trace.issue(&bank_0, TraceOp::Read);
+        trace.issue(&bank_0, TraceOp::Read);
+        trace.issue(&bank_1, TraceOp::Write);
+        trace.end_round(1)?;
+        trace.issue(&bank_1, TraceOp::Read);
+        trace.record(10, &bank_0, TraceOp::Write);
+        drop(trace);
+
+        let content = std::fs::read_to_string(dir.join("test_spmm.dramsim3.trace"))?;
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "0x280 READ 0",
+                "0x380 WRITE 0",
+                "0x280 READ 1",
+                "0x380 READ 2",
+                "0x280 WRITE 10",
+            ]
+        );
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
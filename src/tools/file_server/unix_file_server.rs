@@ -3,12 +3,31 @@ use std::{
     io::{BufRead, BufReader, Read},
 };
 
+use flate2::bufread::MultiGzDecoder;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// the reader of a file, the gzip and zstd files are decompressed on the fly
 pub struct FileServer {
-    reader: BufReader<File>,
+    reader: Box<dyn BufRead + Send>,
 }
 pub fn file_reader(file_name: &str) -> eyre::Result<FileServer> {
     let file = File::open(file_name)?;
-    let reader = BufReader::new(file);
+    let mut reader = BufReader::new(file);
+    // the compression is detected by the magic number instead of the extension
+    let magic = reader.fill_buf()?;
+    let is_gzip = magic.starts_with(&GZIP_MAGIC);
+    let is_zstd = magic.starts_with(&ZSTD_MAGIC);
+    let reader: Box<dyn BufRead + Send> = if is_gzip {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else if is_zstd {
+        Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(
+            reader,
+        )?))
+    } else {
+        Box::new(reader)
+    };
     Ok(FileServer { reader })
 }
 
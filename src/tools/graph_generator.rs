@@ -0,0 +1,456 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-10
+//! Description: the synthetic graphs, they can be used in `graph_path` in place of the files:
+//! - `rmat:scale=20,ef=16,a=0.57,b=0.19,c=0.19,seed=1`: the R-MAT graph with `2^scale` nodes and `ef * 2^scale` edges
+//! - `er:n=1e6,d=8,seed=1`: the Erdős–Rényi graph with `n` nodes and `n * d` uniformly random edges, `m` makes it a
+//!   rectangular `n x m` matrix, like `er:n=1e4,m=1e6,d=8`, the default `m` is `n`
+//! - `banded:n=1e5,bw=4`: each row `i` has the columns from `i - bw` to `i + bw`
+//! - `powerlaw:n=1e6,d=8,alpha=2.1,seed=1`: the Chung-Lu graph whose degrees follow a power law with exponent `alpha`
+//!
+//! the numbers can be written as `1e6`, the parameters with default values can be omitted.
+//! the same uri always generates the same graph.
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use eyre::{bail, Context};
+use sprs::{num_kinds::Pattern, CsMatI, TriMatI};
+use tracing::info;
+
+/// the schemes of the generator uris
+const GENERATORS: [&str; 4] = ["rmat", "er", "banded", "powerlaw"];
+
+/// the generators of the synthetic graphs
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphGenerator {
+    Rmat {
+        scale: u32,
+        edge_factor: usize,
+        a: f64,
+        b: f64,
+        c: f64,
+        seed: u64,
+    },
+    ErdosRenyi {
+        nodes: usize,
+        /// the number of columns, the other generators are square
+        cols: usize,
+        degree: usize,
+        seed: u64,
+    },
+    Banded {
+        nodes: usize,
+        bandwidth: usize,
+    },
+    PowerLaw {
+        nodes: usize,
+        degree: usize,
+        alpha: f64,
+        seed: u64,
+    },
+}
+
+/// the `key=value` parameters of a generator uri
+struct Params<'a> {
+    uri: &'a str,
+    values: BTreeMap<&'a str, &'a str>,
+}
+
+impl<'a> Params<'a> {
+    fn parse(uri: &'a str, params: &'a str) -> eyre::Result<Self> {
+        let mut values = BTreeMap::new();
+        for param in params.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let Some((key, value)) = param.split_once('=') else {
+                bail!("expect key=value, got `{}` in {}", param, uri);
+            };
+            values.insert(key.trim(), value.trim());
+        }
+        Ok(Self { uri, values })
+    }
+
+    fn float(&mut self, key: &str, default: Option<f64>) -> eyre::Result<f64> {
+        match self.values.remove(key) {
+            Some(value) => value
+                .parse()
+                .wrap_err(format!("invalid `{}` in {}", key, self.uri)),
+            None => default.ok_or_else(|| eyre::eyre!("missing `{}` in {}", key, self.uri)),
+        }
+    }
+
+    /// the integers also accept the scientific notation such as `1e6`
+    fn int(&mut self, key: &str, default: Option<u64>) -> eyre::Result<u64> {
+        let value = self.float(key, default.map(|d| d as f64))?;
+        if value < 0. || value.fract() != 0. {
+            bail!("`{}` should be a non-negative integer in {}", key, self.uri);
+        }
+        Ok(value as u64)
+    }
+
+    fn finish(self) -> eyre::Result<()> {
+        if let Some(key) = self.values.keys().next() {
+            bail!("unknown parameter `{}` in {}", key, self.uri);
+        }
+        Ok(())
+    }
+}
+
+/// the splitmix64 generator, it's small and the results never change between versions
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// uniform in `[0, 1)`
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// uniform in `[0, n)`
+    fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+impl GraphGenerator {
+    /// parse the generator uri, return `None` when `uri` is not a generator uri
+    pub fn parse(uri: &str) -> eyre::Result<Option<Self>> {
+        let Some((scheme, params)) = uri.split_once(':') else {
+            return Ok(None);
+        };
+        // not a generator, might be a windows path
+        if !GENERATORS.contains(&scheme) {
+            return Ok(None);
+        }
+        let mut params = Params::parse(uri, params)?;
+        let generator = match scheme {
+            "rmat" => {
+                let scale = params.int("scale", None)?;
+                if scale >= 32 {
+                    bail!("the scale should be less than 32 in {}", uri);
+                }
+                let generator = Self::Rmat {
+                    scale: scale as u32,
+                    edge_factor: params.int("ef", Some(16))? as usize,
+                    a: params.float("a", Some(0.57))?,
+                    b: params.float("b", Some(0.19))?,
+                    c: params.float("c", Some(0.19))?,
+                    seed: params.int("seed", Some(1))?,
+                };
+                if let Self::Rmat { a, b, c, .. } = generator {
+                    if a < 0. || b < 0. || c < 0. || a + b + c > 1. {
+                        bail!(
+                            "a, b, c should be non-negative and a + b + c <= 1 in {}",
+                            uri
+                        );
+                    }
+                }
+                generator
+            }
+            "er" => {
+                let nodes = params.int("n", None)?;
+                Self::ErdosRenyi {
+                    nodes: nodes as usize,
+                    cols: params.int("m", Some(nodes))? as usize,
+                    degree: params.int("d", None)? as usize,
+                    seed: params.int("seed", Some(1))?,
+                }
+            }
+            "banded" => Self::Banded {
+                nodes: params.int("n", None)? as usize,
+                bandwidth: params.int("bw", None)? as usize,
+            },
+            "powerlaw" => {
+                let alpha = params.float("alpha", Some(2.1))?;
+                if alpha <= 1. {
+                    bail!("alpha should be larger than 1 in {}", uri);
+                }
+                Self::PowerLaw {
+                    nodes: params.int("n", None)? as usize,
+                    degree: params.int("d", None)? as usize,
+                    alpha,
+                    seed: params.int("seed", Some(1))?,
+                }
+            }
+            _ => unreachable!(),
+        };
+        params.finish()?;
+        if generator.nodes() == 0 || generator.nodes() >= u32::MAX as usize {
+            bail!("the number of nodes should be in [1, 2^32 - 1) in {}", uri);
+        }
+        if generator.cols() == 0 || generator.cols() >= u32::MAX as usize {
+            bail!(
+                "the number of columns should be in [1, 2^32 - 1) in {}",
+                uri
+            );
+        }
+        Ok(Some(generator))
+    }
+
+    pub fn nodes(&self) -> usize {
+        match self {
+            Self::Rmat { scale, .. } => 1 << scale,
+            Self::ErdosRenyi { nodes, .. }
+            | Self::Banded { nodes, .. }
+            | Self::PowerLaw { nodes, .. } => *nodes,
+        }
+    }
+
+    /// the number of columns, it's the number of nodes except for the rectangular `er`
+    pub fn cols(&self) -> usize {
+        match self {
+            Self::ErdosRenyi { cols, .. } => *cols,
+            _ => self.nodes(),
+        }
+    }
+
+    /// the number of the generated triplets, the duplicated edges are counted
+    pub fn edges(&self) -> usize {
+        match self {
+            Self::Rmat { edge_factor, .. } => edge_factor * self.nodes(),
+            Self::ErdosRenyi { nodes, degree, .. } | Self::PowerLaw { nodes, degree, .. } => {
+                nodes * degree
+            }
+            Self::Banded { nodes, bandwidth } => {
+                // the full rows minus the missing corners
+                let bandwidth = (*bandwidth).min(nodes - 1);
+                nodes * (2 * bandwidth + 1) - bandwidth * (bandwidth + 1)
+            }
+        }
+    }
+
+    /// ## rust function
+    /// ## Author: Jiangqiu Shen
+    /// ## Date: 2023-06-10
+    /// Description: generate the graph, the duplicated edges are kept in the triplets and merged when converted
+    /// to csr
+    pub fn generate(&self) -> TriMatI<Pattern, u32> {
+        let nodes = self.nodes();
+        let mut matrix = TriMatI::with_capacity((nodes, self.cols()), self.edges());
+        match *self {
+            Self::Rmat {
+                scale,
+                a,
+                b,
+                c,
+                seed,
+                ..
+            } => {
+                let mut rng = SplitMix64::new(seed);
+                for _ in 0..self.edges() {
+                    let (mut row, mut col) = (0, 0);
+                    for _ in 0..scale {
+                        let p = rng.next_f64();
+                        let (row_bit, col_bit) = if p < a {
+                            (0, 0)
+                        } else if p < a + b {
+                            (0, 1)
+                        } else if p < a + b + c {
+                            (1, 0)
+                        } else {
+                            (1, 1)
+                        };
+                        row = (row << 1) | row_bit;
+                        col = (col << 1) | col_bit;
+                    }
+                    matrix.add_triplet(row, col, Pattern);
+                }
+            }
+            Self::ErdosRenyi { cols, seed, .. } => {
+                let mut rng = SplitMix64::new(seed);
+                for _ in 0..self.edges() {
+                    matrix.add_triplet(rng.below(nodes), rng.below(cols), Pattern);
+                }
+            }
+            Self::Banded { bandwidth, .. } => {
+                for row in 0..nodes {
+                    let start = row.saturating_sub(bandwidth);
+                    let end = (row + bandwidth).min(nodes - 1);
+                    for col in start..=end {
+                        matrix.add_triplet(row, col, Pattern);
+                    }
+                }
+            }
+            Self::PowerLaw { alpha, seed, .. } => {
+                let mut rng = SplitMix64::new(seed);
+                // the expected degree of node i is proportional to (i + 1)^(-1 / (alpha - 1))
+                let exponent = -1. / (alpha - 1.);
+                let mut accumulated = Vec::with_capacity(nodes);
+                let mut total = 0.;
+                for node in 0..nodes {
+                    total += ((node + 1) as f64).powf(exponent);
+                    accumulated.push(total);
+                }
+                let mut sample = || {
+                    let target = rng.next_f64() * total;
+                    accumulated
+                        .partition_point(|&weight| weight <= target)
+                        .min(nodes - 1)
+                };
+                for _ in 0..self.edges() {
+                    let row = sample();
+                    let col = sample();
+                    matrix.add_triplet(row, col, Pattern);
+                }
+            }
+        }
+        matrix
+    }
+
+    /// the file name of the generated graph, without the extension
+    pub fn file_stem(uri: &str) -> String {
+        uri.chars()
+            .map(|c| match c {
+                ':' | ',' | '?' | '/' | '\\' => '_',
+                c => c,
+            })
+            .collect()
+    }
+}
+
+/// write a pattern matrix in the Matrix Market format, the duplicated entries are merged
+pub fn write_matrix_market(
+    path: impl AsRef<Path>,
+    matrix: &CsMatI<Pattern, u32>,
+) -> eyre::Result<()> {
+    let path = path.as_ref();
+    let mut writer =
+        BufWriter::new(File::create(path).wrap_err(format!("fail to create {:?}", path))?);
+    writeln!(writer, "%%MatrixMarket matrix coordinate pattern general")?;
+    writeln!(
+        writer,
+        "{} {} {}",
+        matrix.rows(),
+        matrix.cols(),
+        matrix.nnz()
+    )?;
+    for (row, vec) in matrix.outer_iterator().enumerate() {
+        for col in vec.indices() {
+            writeln!(writer, "{} {}", row + 1, col + 1)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-10
+/// Description: generate the graphs of `uris` and write them to `{output_dir}/{uri}.mtx`, the options after `?` are
+/// applied
+pub fn write_generated(
+    uris: &[String],
+    output_dir: impl AsRef<Path>,
+) -> eyre::Result<Vec<PathBuf>> {
+    let output_dir = output_dir.as_ref();
+    std::fs::create_dir_all(output_dir)?;
+    uris.iter()
+        .map(|uri| {
+            let source = super::graph_reader::GraphSource::parse(uri)?;
+            if !source.is_generated() {
+                bail!("{} is not a generator uri", uri);
+            }
+            let matrix: CsMatI<Pattern, u32> = super::graph_reader::read_graph(uri)?.to_csr();
+            let path = output_dir.join(format!("{}.mtx", GraphGenerator::file_stem(uri)));
+            write_matrix_market(&path, &matrix)?;
+            info!(
+                "generated {} with {} nodes and {} edges to {:?}",
+                uri,
+                matrix.rows(),
+                matrix.nnz(),
+                path
+            );
+            Ok(path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_generator() -> eyre::Result<()> {
+        assert_eq!(
+            GraphGenerator::parse("er:n=1e3,d=8")?,
+            Some(GraphGenerator::ErdosRenyi {
+                nodes: 1000,
+                cols: 1000,
+                degree: 8,
+                seed: 1
+            })
+        );
+        assert_eq!(GraphGenerator::parse("mtx/a.mtx")?, None);
+        assert_eq!(GraphGenerator::parse("C:/graphs/a.mtx")?, None);
+        assert!(GraphGenerator::parse("rmat:ef=16").is_err());
+        assert!(GraphGenerator::parse("er:n=10,d=2,q=1").is_err());
+        assert!(GraphGenerator::parse("er:n=1.5,d=2").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate() -> eyre::Result<()> {
+        for uri in [
+            "rmat:scale=8,ef=4,seed=3",
+            "er:n=300,d=4",
+            "er:n=300,m=50,d=4",
+            "banded:n=50,bw=3",
+            "powerlaw:n=300,d=4,alpha=2.5",
+        ] {
+            let generator = GraphGenerator::parse(uri)?.unwrap();
+            let matrix = generator.generate();
+            assert_eq!(matrix.shape(), (generator.nodes(), generator.cols()));
+            assert_eq!(matrix.nnz(), generator.edges());
+            // the same uri always gives the same graph
+            let first: CsMatI<Pattern, u32> = matrix.to_csr();
+            let second: CsMatI<Pattern, u32> = generator.generate().to_csr();
+            assert_eq!(first, second);
+        }
+        let banded: CsMatI<Pattern, u32> = GraphGenerator::parse("banded:n=5,bw=1")?
+            .unwrap()
+            .generate()
+            .to_csr();
+        assert_eq!(banded.outer_view(0).unwrap().indices(), &[0, 1]);
+        assert_eq!(banded.outer_view(2).unwrap().indices(), &[1, 2, 3]);
+        assert_eq!(banded.outer_view(4).unwrap().indices(), &[3, 4]);
+
+        // the power law graph is skewed to the first nodes
+        let powerlaw: CsMatI<Pattern, u32> = GraphGenerator::parse("powerlaw:n=1000,d=8")?
+            .unwrap()
+            .generate()
+            .to_csr();
+        let first_degree = powerlaw.outer_view(0).unwrap().nnz();
+        let last_degree = powerlaw.outer_view(999).unwrap().nnz();
+        assert!(first_degree > last_degree);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_generated() -> eyre::Result<()> {
+        let dir = std::env::temp_dir().join("spmspm_graph_generator_test");
+        let uri = "er:n=100,d=3,seed=7?symmetrize".to_string();
+        let paths = write_generated(&[uri.clone()], &dir)?;
+        let written: TriMatI<Pattern, u32> = sprs::io::read_matrix_market(&paths[0])?;
+        let written: CsMatI<Pattern, u32> = written.to_csr();
+        let expected: CsMatI<Pattern, u32> = super::super::graph_reader::read_graph(&uri)?.to_csr();
+        assert_eq!(written, expected);
+        assert_eq!(written, written.transpose_view().to_csr());
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
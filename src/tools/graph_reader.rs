@@ -0,0 +1,587 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-10
+//! Description: read the graphs in `graph_path`, the supported inputs are:
+//! - Matrix Market files, detected by the `%%MatrixMarket` banner
+//! - whitespace(or comma) separated edge lists such as the SNAP datasets, `#` and `%` lines are comments and the
+//!   columns after the first two are ignored
+//!
+//! both can be compressed by gzip or zstd, see [`file_server::file_reader`].
+//!
+//! the options are appended to the path after a `?`, separated by `,`:
+//! - `symmetrize`: add the reversed edge of each edge
+//! - `remove_self_loops`: drop the edges from a node to itself
+//! - `relabel`: map the node ids of an edge list to `0..n` by the order they first appear. the SNAP ids can be sparse
+//!   or larger than `u32`, without it the graph has `max id + 1` nodes
+//!
+//! for example: `graphs/com-orkut.ungraph.txt.gz?symmetrize,remove_self_loops,relabel`
+//!
+//! the entry can also be a generator uri such as `rmat:scale=20,ef=16`, see [`graph_generator`], the options except
+//! `relabel` also apply to the generated graphs.
+//!
+//! the stream merger simulator computes `C = A * B`, by default `B` is the transpose of the graph. two sources
+//! joined by `*` give `A` and `B` separately, each with its own options, for example
+//! `graphs/restriction.mtx*graphs/laplacian.mtx` or `er:n=1e4,m=1e5,d=8*er:n=1e5,d=8`. see [`ProductEntry`].
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+use eyre::{bail, Context};
+use sprs::{io::MatrixHead, num_kinds::Pattern, TriMatI};
+
+use super::{file_server, graph_generator::GraphGenerator};
+
+const MATRIX_MARKET_BANNER: &[u8] = b"%%MatrixMarket";
+
+/// the separator of `A` and `B` in a product entry
+pub const PRODUCT_SEPARATOR: char = '*';
+
+/// the format of the graph file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    MatrixMarket,
+    EdgeList,
+    /// generated by a [`GraphGenerator`], no file is read
+    Generated,
+}
+
+/// the options applied after the graph is read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GraphOptions {
+    pub symmetrize: bool,
+    pub remove_self_loops: bool,
+    /// only for the edge lists
+    pub relabel: bool,
+}
+
+impl GraphOptions {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn apply(&self, row: usize, col: usize, mut add: impl FnMut(usize, usize)) {
+        if self.remove_self_loops && row == col {
+            return;
+        }
+        add(row, col);
+        if self.symmetrize && row != col {
+            add(col, row);
+        }
+    }
+}
+
+/// where the graph comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphLocation {
+    File(PathBuf),
+    Generated(GraphGenerator),
+}
+
+/// an entry of `graph_path`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphSource {
+    pub location: GraphLocation,
+    pub options: GraphOptions,
+}
+
+impl GraphSource {
+    pub fn parse(source: &str) -> eyre::Result<Self> {
+        let (path, options) = match source.split_once('?') {
+            Some((path, options)) => (path, options),
+            None => (source, ""),
+        };
+        let mut graph_options = GraphOptions::default();
+        for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+            match option {
+                "symmetrize" => graph_options.symmetrize = true,
+                "remove_self_loops" => graph_options.remove_self_loops = true,
+                "relabel" => graph_options.relabel = true,
+                _ => bail!("unknown graph option `{}` in {}", option, source),
+            }
+        }
+        let location = match GraphGenerator::parse(path)? {
+            Some(generator) => GraphLocation::Generated(generator),
+            None => GraphLocation::File(PathBuf::from(path)),
+        };
+        Ok(Self {
+            location,
+            options: graph_options,
+        })
+    }
+
+    /// the file of the graph, `None` for the generated graphs
+    pub fn file_path(&self) -> Option<&Path> {
+        match &self.location {
+            GraphLocation::File(path) => Some(path),
+            GraphLocation::Generated(_) => None,
+        }
+    }
+
+    pub fn is_generated(&self) -> bool {
+        matches!(self.location, GraphLocation::Generated(_))
+    }
+
+    /// a short tag of the options, it's empty when no option is set. used to name the caches of the graph
+    pub fn options_tag(&self) -> String {
+        if self.options.is_default() {
+            return String::new();
+        }
+        let mut tags = vec![];
+        if self.options.symmetrize {
+            tags.push("sym");
+        }
+        if self.options.remove_self_loops {
+            tags.push("noloop");
+        }
+        if self.options.relabel {
+            tags.push("relabel");
+        }
+        tags.join("_")
+    }
+
+    pub fn format(&self) -> eyre::Result<GraphFormat> {
+        match &self.location {
+            GraphLocation::File(path) => Ok(open_file(path)?.0),
+            GraphLocation::Generated(_) => Ok(GraphFormat::Generated),
+        }
+    }
+}
+
+/// an entry of `graph_path` for `C = A * B`, `b` is `None` when the entry is a single graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProductEntry<'a> {
+    pub a: &'a str,
+    pub b: Option<&'a str>,
+}
+
+impl<'a> ProductEntry<'a> {
+    pub fn parse(entry: &'a str) -> eyre::Result<Self> {
+        let mut sources = entry.split(PRODUCT_SEPARATOR).map(str::trim);
+        let a = sources.next().unwrap_or_default();
+        let b = sources.next();
+        if sources.next().is_some() {
+            bail!("expect at most two matrices in {}", entry);
+        }
+        if a.is_empty() || b.map_or(false, str::is_empty) {
+            bail!("empty matrix in {}", entry);
+        }
+        Ok(Self { a, b })
+    }
+
+    pub fn is_product(&self) -> bool {
+        self.b.is_some()
+    }
+
+    /// the sources of `A` and `B`
+    pub fn sources(&self) -> impl Iterator<Item = &'a str> {
+        std::iter::once(self.a).chain(self.b)
+    }
+
+    /// the name of the output files, `{a}_x_{b}` for a product, the options of a source are appended as
+    /// `{graph}_{options_tag}`
+    pub fn file_stem(&self) -> String {
+        let stem = |source: &str| match GraphSource::parse(source) {
+            Ok(graph_source) => {
+                let stem = match &graph_source.location {
+                    GraphLocation::File(path) => path
+                        .file_stem()
+                        .and_then(|s| s.to_str())
+                        .unwrap_or(source)
+                        .to_string(),
+                    // the parameters of the generator are kept in the name
+                    GraphLocation::Generated(_) => {
+                        GraphGenerator::file_stem(source.split('?').next().unwrap_or(source))
+                    }
+                };
+                match graph_source.options_tag() {
+                    tag if tag.is_empty() => stem,
+                    tag => format!("{stem}_{tag}"),
+                }
+            }
+            Err(_) => GraphGenerator::file_stem(source),
+        };
+        match self.b {
+            Some(b) => format!("{}_x_{}", stem(self.a), stem(b)),
+            None => stem(self.a),
+        }
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-19
+/// Description: the name of the files written for an entry of `graph_path`, such as the checkpoints and the traces.
+/// it's the [`ProductEntry::file_stem`], so the entries that only differ in the generator parameters or the options
+/// never share a file
+pub fn entry_file_stem(entry: &str) -> String {
+    ProductEntry::parse(entry)
+        .map(|product| product.file_stem())
+        .unwrap_or_else(|_| GraphGenerator::file_stem(entry))
+}
+
+/// open the file and detect the format
+fn open_file(path: &Path) -> eyre::Result<(GraphFormat, file_server::FileServer)> {
+    let path = path_str(path)?;
+    let mut reader =
+        file_server::file_reader(path).wrap_err(format!("fail to read path:{}", path))?;
+    let format = if reader.fill_buf()?.starts_with(MATRIX_MARKET_BANNER) {
+        GraphFormat::MatrixMarket
+    } else {
+        GraphFormat::EdgeList
+    };
+    Ok((format, reader))
+}
+
+fn path_str(path: &Path) -> eyre::Result<&str> {
+    path.to_str()
+        .ok_or_else(|| eyre::eyre!("the path {:?} is not utf8", path))
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-10
+/// Description: the size of a graph, used to acquire the memory before the graph is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GraphHead {
+    pub rows: usize,
+    pub cols: usize,
+    /// the number of the triplets, the duplicated edges are counted
+    pub nnz: usize,
+}
+
+impl GraphHead {
+    pub fn ind_ptr_size(&self) -> u64 {
+        ((self.rows + 1) * std::mem::size_of::<u32>()) as u64
+    }
+    pub fn ind_size(&self) -> u64 {
+        (self.nnz * std::mem::size_of::<u32>()) as u64
+    }
+    pub fn data_size(&self) -> u64 {
+        (self.nnz * std::mem::size_of::<Pattern>()) as u64
+    }
+    pub fn tri_size(&self) -> u64 {
+        self.ind_size() * 2 + self.data_size()
+    }
+}
+
+/// parse a line of the edge list, return `None` for the comments and empty lines
+fn parse_edge(line: &str) -> eyre::Result<Option<(usize, usize)>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with('%') {
+        return Ok(None);
+    }
+    let mut ids = line
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|id| !id.is_empty());
+    match (ids.next(), ids.next()) {
+        (Some(source), Some(target)) => Ok(Some((
+            source
+                .parse()
+                .wrap_err(format!("invalid node id in line: {}", line))?,
+            target
+                .parse()
+                .wrap_err(format!("invalid node id in line: {}", line))?,
+        ))),
+        _ => bail!("expect two node ids in line: {}", line),
+    }
+}
+
+fn for_each_edge(
+    reader: impl BufRead,
+    mut f: impl FnMut(usize, usize) -> eyre::Result<()>,
+) -> eyre::Result<()> {
+    for line in reader.lines() {
+        if let Some((source, target)) = parse_edge(&line?)? {
+            f(source, target)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_id(id: usize) -> eyre::Result<()> {
+    if id >= u32::MAX as usize {
+        bail!("the node id {} is too large, try the `relabel` option", id);
+    }
+    Ok(())
+}
+
+/// the dense ids of the nodes of an edge list, a node gets the next id when it first appears
+#[derive(Debug, Default)]
+struct DenseIds(HashMap<usize, usize>);
+
+impl DenseIds {
+    fn get(&mut self, id: usize) -> usize {
+        let next = self.0.len();
+        *self.0.entry(id).or_insert(next)
+    }
+}
+
+/// call `f` with the edges of the edge list, the ids are relabeled when `relabel` is set
+fn for_each_dense_edge(
+    reader: impl BufRead,
+    relabel: bool,
+    mut f: impl FnMut(usize, usize) -> eyre::Result<()>,
+) -> eyre::Result<()> {
+    let mut dense_ids = relabel.then(DenseIds::default);
+    for_each_edge(reader, |source, target| match &mut dense_ids {
+        Some(ids) => {
+            let source = ids.get(source);
+            f(source, ids.get(target))
+        }
+        None => f(source, target),
+    })
+}
+
+fn check_relabel(options: GraphOptions, source: &str) -> eyre::Result<()> {
+    if options.relabel {
+        bail!(
+            "the `relabel` option only applies to the edge lists, got {}",
+            source
+        );
+    }
+    Ok(())
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-10
+/// Description: read the graph of an entry of `graph_path`, the edge lists are square, the matrix market files and
+/// the `er` graphs can be rectangular. `source` is a single graph, the product entries are split by
+/// [`ProductEntry::parse`] first
+pub fn read_graph(source: &str) -> eyre::Result<TriMatI<Pattern, u32>> {
+    let graph_source = GraphSource::parse(source)?;
+    let options = graph_source.options;
+    let matrix = match &graph_source.location {
+        GraphLocation::Generated(generator) => {
+            check_relabel(options, source)?;
+            generator.generate()
+        }
+        GraphLocation::File(path) => match open_file(path)? {
+            (GraphFormat::EdgeList, reader) => {
+                // the options are applied while parsing so the edges are not copied again
+                return read_edge_list(reader, options)
+                    .wrap_err(format!("fail to parse edge list in file {}", source));
+            }
+            (_, mut reader) => {
+                check_relabel(options, source)?;
+                sprs::io::read_matrix_market_from_bufread(&mut reader)
+                    .wrap_err(format!("fail to parse mtx format in file {}", source))?
+            }
+        },
+    };
+    if options.is_default() {
+        return Ok(matrix);
+    }
+    if options.symmetrize && matrix.rows() != matrix.cols() {
+        bail!(
+            "can not symmetrize the {}x{} matrix {}",
+            matrix.rows(),
+            matrix.cols(),
+            source
+        );
+    }
+    let mut result = TriMatI::with_capacity(matrix.shape(), matrix.nnz());
+    for (_, (row, col)) in matrix.triplet_iter() {
+        options.apply(row as usize, col as usize, |row, col| {
+            result.add_triplet(row, col, Pattern)
+        });
+    }
+    Ok(result)
+}
+
+fn read_edge_list(
+    reader: impl BufRead,
+    options: GraphOptions,
+) -> eyre::Result<TriMatI<Pattern, u32>> {
+    let mut edges = vec![];
+    let mut nodes = 0;
+    for_each_dense_edge(reader, options.relabel, |source, target| {
+        check_id(source.max(target))?;
+        nodes = nodes.max(source.max(target) + 1);
+        options.apply(source, target, |source, target| {
+            edges.push((source as u32, target as u32))
+        });
+        Ok(())
+    })?;
+    let (rows, cols): (Vec<u32>, Vec<u32>) = edges.into_iter().unzip();
+    let nnz = rows.len();
+    Ok(TriMatI::from_triplets(
+        (nodes, nodes),
+        rows,
+        cols,
+        vec![Pattern; nnz],
+    ))
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-10
+/// Description: read the size of the graph without building it, the edge lists are scanned once
+pub fn read_graph_head(source: &str) -> eyre::Result<GraphHead> {
+    let graph_source = GraphSource::parse(source)?;
+    let options = graph_source.options;
+    // the upper bound of the edges after the options are applied
+    let scale = if options.symmetrize { 2 } else { 1 };
+    let path = match &graph_source.location {
+        GraphLocation::Generated(generator) => {
+            check_relabel(options, source)?;
+            return Ok(GraphHead {
+                rows: generator.nodes(),
+                cols: generator.cols(),
+                nnz: generator.edges() * scale,
+            });
+        }
+        GraphLocation::File(path) => path,
+    };
+    match open_file(path)? {
+        (GraphFormat::MatrixMarket | GraphFormat::Generated, mut reader) => {
+            check_relabel(options, source)?;
+            let head: MatrixHead<Pattern, u32> =
+                sprs::io::read_matrix_market_from_bufread_head(&mut reader)
+                    .wrap_err(format!("fail to parse mtx head in file {}", source))?;
+            Ok(GraphHead {
+                rows: head.rows,
+                cols: head.cols,
+                nnz: head.nnz * scale,
+            })
+        }
+        (GraphFormat::EdgeList, reader) => {
+            let mut nodes = 0;
+            let mut nnz = 0;
+            for_each_dense_edge(reader, options.relabel, |source, target| {
+                check_id(source.max(target))?;
+                nodes = nodes.max(source.max(target) + 1);
+                nnz += 1;
+                Ok(())
+            })
+            .wrap_err(format!("fail to parse edge list in file {}", source))?;
+            Ok(GraphHead {
+                rows: nodes,
+                cols: nodes,
+                nnz: nnz * scale,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use sprs::CsMatI;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_source() -> eyre::Result<()> {
+        let source = GraphSource::parse("graphs/web.txt.gz?symmetrize, remove_self_loops")?;
+        assert_eq!(source.file_path(), Some(Path::new("graphs/web.txt.gz")));
+        assert!(source.options.symmetrize && source.options.remove_self_loops);
+        assert_eq!(source.options_tag(), "sym_noloop");
+        assert_eq!(GraphSource::parse("a.mtx")?.options_tag(), "");
+        assert!(GraphSource::parse("a.mtx?transpose").is_err());
+        let generated = GraphSource::parse("er:n=100,d=4?remove_self_loops")?;
+        assert!(generated.is_generated() && generated.options.remove_self_loops);
+        assert_eq!(generated.format()?, GraphFormat::Generated);
+        let head = read_graph_head("er:n=100,d=4?symmetrize")?;
+        assert_eq!((head.rows, head.nnz), (100, 800));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_product() -> eyre::Result<()> {
+        let entry = ProductEntry::parse("graphs/r.mtx?remove_self_loops * er:n=100,m=50,d=4")?;
+        assert_eq!(entry.a, "graphs/r.mtx?remove_self_loops");
+        assert_eq!(entry.b, Some("er:n=100,m=50,d=4"));
+        assert_eq!(entry.file_stem(), "r_noloop_x_er_n=100_m=50_d=4");
+        let single = ProductEntry::parse("graphs/web.mtx")?;
+        assert!(!single.is_product());
+        assert_eq!(single.sources().collect::<Vec<_>>(), ["graphs/web.mtx"]);
+        assert_eq!(single.file_stem(), "web");
+        // the entries that only differ in the parameters or the options get different names
+        assert_ne!(
+            entry_file_stem("rmat:scale=20,a=0.57"),
+            entry_file_stem("rmat:scale=20,a=0.55")
+        );
+        assert_eq!(entry_file_stem("web.txt.gz"), "web.txt");
+        assert_eq!(entry_file_stem("web.txt.gz?symmetrize"), "web.txt_sym");
+        assert!(ProductEntry::parse("a.mtx*").is_err());
+        assert!(ProductEntry::parse("a.mtx*b.mtx*c.mtx").is_err());
+
+        let head = read_graph_head("er:n=100,m=50,d=4")?;
+        assert_eq!((head.rows, head.cols), (100, 50));
+        assert!(read_graph("er:n=100,m=50,d=4?symmetrize").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_graph_formats() -> eyre::Result<()> {
+        let dir = std::env::temp_dir().join("spmspm_graph_reader_test");
+        std::fs::create_dir_all(&dir)?;
+        let edge_list = "# a SNAP graph\n# FromNodeId\tToNodeId\n0\t1\n1\t2\n2\t2\n3,0\n";
+        let plain = dir.join("graph.txt");
+        std::fs::write(&plain, edge_list)?;
+        let gz = dir.join("graph.txt.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(std::fs::File::create(&gz)?, Default::default());
+        encoder.write_all(edge_list.as_bytes())?;
+        encoder.finish()?;
+        let zst = dir.join("graph.txt.zst");
+        std::fs::write(&zst, zstd::encode_all(edge_list.as_bytes(), 0)?)?;
+
+        for path in [&plain, &gz, &zst] {
+            let path = path.to_str().unwrap();
+            let graph: CsMatI<Pattern, u32> = read_graph(path)?.to_csr();
+            assert_eq!(graph.shape(), (4, 4));
+            assert_eq!(graph.nnz(), 4);
+            let head = read_graph_head(path)?;
+            assert_eq!((head.rows, head.nnz), (4, 4));
+
+            let options = format!("{}?symmetrize,remove_self_loops", path);
+            let graph: CsMatI<Pattern, u32> = read_graph(&options)?.to_csr();
+            assert_eq!(graph.nnz(), 6);
+            assert_eq!(graph.outer_view(0).unwrap().indices(), &[1, 3]);
+            assert_eq!(graph.outer_view(2).unwrap().indices(), &[1]);
+        }
+
+        // the compressed mtx is the same as the plain one
+        let mtx_gz = dir.join("test.mtx.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(std::fs::File::create(&mtx_gz)?, Default::default());
+        encoder.write_all(&std::fs::read("test_mtx/test.mtx")?)?;
+        encoder.finish()?;
+        let expected: TriMatI<Pattern, u32> = sprs::io::read_matrix_market("test_mtx/test.mtx")?;
+        let graph: CsMatI<Pattern, u32> = read_graph(mtx_gz.to_str().unwrap())?.to_csr();
+        let expected: CsMatI<Pattern, u32> = expected.to_csr();
+        assert_eq!(graph, expected);
+        assert_eq!(
+            GraphSource::parse(mtx_gz.to_str().unwrap())?.format()?,
+            GraphFormat::MatrixMarket
+        );
+
+        // the sparse SNAP ids are relabeled by the order they first appear
+        let sparse = dir.join("sparse.txt");
+        std::fs::write(
+            &sparse,
+            "# sparse ids\n100\t7\n7\t5000000000\n5000000000\t100\n",
+        )?;
+        let sparse = sparse.to_str().unwrap();
+        assert!(read_graph(sparse).is_err());
+        let relabeled = format!("{}?relabel", sparse);
+        assert_eq!(GraphSource::parse(&relabeled)?.options_tag(), "relabel");
+        let graph: CsMatI<Pattern, u32> = read_graph(&relabeled)?.to_csr();
+        assert_eq!(graph.shape(), (3, 3));
+        assert_eq!(graph.outer_view(0).unwrap().indices(), &[1]);
+        assert_eq!(graph.outer_view(1).unwrap().indices(), &[2]);
+        assert_eq!(graph.outer_view(2).unwrap().indices(), &[0]);
+        let head = read_graph_head(&relabeled)?;
+        assert_eq!((head.rows, head.cols, head.nnz), (3, 3, 3));
+        assert!(read_graph("test_mtx/test.mtx?relabel").is_err());
+        assert!(read_graph_head("er:n=100,d=4?relabel").is_err());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,419 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-18
+//! Description: the end to end model of the remote traffic. a packet from a subarray goes through the levels:
+//! - the subarray bus of its bank
+//! - the bank network of its channel
+//! - the tsv of its channel, down to the base layer
+//! - the base layer connecting the channels
+//!
+//! and back up through the tsv, the bank network and the subarray bus of the target. a packet only climbs as high
+//! as needed: a packet inside a bank only takes the subarray bus, a packet inside a channel never takes the tsv.
+//! each link of a level serves `bandwidth` packets per cycle in fifo order, a served packet arrives at the next link
+//! `latency` cycles later. the packets of a round are injected at the beginning of the round.
+use std::collections::VecDeque;
+
+use eyre::bail;
+use serde::{Deserialize, Serialize};
+
+/// the bandwidth and latency of the links of a level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkConfig {
+    /// packets per cycle
+    pub bandwidth: usize,
+    /// cycles from leaving a link to arriving at the next one, at least 1
+    pub latency: usize,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self {
+            bandwidth: 1,
+            latency: 1,
+        }
+    }
+}
+
+/// the `hierarchical_network` field of the config
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HierarchicalNetworkConfig {
+    pub subarray_bus: LinkConfig,
+    pub bank_network: LinkConfig,
+    pub tsv: LinkConfig,
+    pub base_layer: LinkConfig,
+}
+
+/// the levels of the network, from the lowest to the highest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetworkLevel {
+    SubarrayBus,
+    BankNetwork,
+    Tsv,
+    BaseLayer,
+}
+
+pub const NETWORK_LEVELS: [NetworkLevel; 4] = [
+    NetworkLevel::SubarrayBus,
+    NetworkLevel::BankNetwork,
+    NetworkLevel::Tsv,
+    NetworkLevel::BaseLayer,
+];
+
+/// the congestion of a level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelReport {
+    pub level: NetworkLevel,
+    /// the packets served by the links of this level, a packet crossing two tsvs is counted twice
+    pub packets: usize,
+    /// the cycles the busiest link of this level served at least one packet
+    pub max_busy_cycles: usize,
+    /// the longest queue of all links of this level
+    pub max_queue: usize,
+    /// the cycles all packets waited in the queues of this level
+    pub queueing_delay: usize,
+}
+
+impl LevelReport {
+    fn new(level: NetworkLevel) -> Self {
+        Self {
+            level,
+            packets: 0,
+            max_busy_cycles: 0,
+            max_queue: 0,
+            queueing_delay: 0,
+        }
+    }
+
+    /// the cycles a packet waited in this level on average
+    pub fn mean_queueing_delay(&self) -> f64 {
+        if self.packets == 0 {
+            0.
+        } else {
+            self.queueing_delay as f64 / self.packets as f64
+        }
+    }
+}
+
+/// the result of the network, accumulated over the rounds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HierarchyReport {
+    /// the cycles to deliver all packets of each round, summed over the rounds
+    pub cycles: usize,
+    pub packets: usize,
+    pub levels: Vec<LevelReport>,
+}
+
+impl Default for HierarchyReport {
+    fn default() -> Self {
+        Self {
+            cycles: 0,
+            packets: 0,
+            levels: NETWORK_LEVELS.into_iter().map(LevelReport::new).collect(),
+        }
+    }
+}
+
+impl HierarchyReport {
+    /// accumulate the result of another round or run into this one
+    pub fn add(&mut self, other: &HierarchyReport) {
+        self.cycles += other.cycles;
+        self.packets += other.packets;
+        for (level, other) in self.levels.iter_mut().zip(&other.levels) {
+            level.packets += other.packets;
+            level.max_busy_cycles = level.max_busy_cycles.max(other.max_busy_cycles);
+            level.max_queue = level.max_queue.max(other.max_queue);
+            level.queueing_delay += other.queueing_delay;
+        }
+    }
+}
+
+/// a link of a level
+struct Link {
+    level: NetworkLevel,
+    config: LinkConfig,
+    /// (packet, the cycle it arrived)
+    queue: VecDeque<(usize, usize)>,
+    /// (packet, the cycle it arrives at the next link)
+    in_flight: VecDeque<(usize, usize)>,
+    busy_cycles: usize,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-18
+/// Description: the network of `channels` channels with `banks` banks each, see the module doc. the links are
+/// indexed as: the subarray buses of all global banks, the bank networks of all channels, the tsvs of all channels,
+/// then the base layer
+pub struct HierarchicalNetwork {
+    channels: usize,
+    banks: usize,
+    config: HierarchicalNetworkConfig,
+    /// the (source bank, target bank) of the packets of this round, in global bank ids
+    packets: Vec<(usize, usize)>,
+}
+
+impl HierarchicalNetwork {
+    pub fn new(config: HierarchicalNetworkConfig, channels: usize, banks: usize) -> Self {
+        Self {
+            channels,
+            banks,
+            config,
+            packets: vec![],
+        }
+    }
+
+    /// send a packet from the source bank to the target bank in this round
+    pub fn add(&mut self, source_bank: usize, target_bank: usize) {
+        self.packets.push((source_bank, target_bank));
+    }
+
+    fn bus(&self, bank: usize) -> usize {
+        bank
+    }
+
+    fn bank_network(&self, channel: usize) -> usize {
+        self.channels * self.banks + channel
+    }
+
+    fn tsv(&self, channel: usize) -> usize {
+        self.channels * self.banks + self.channels + channel
+    }
+
+    fn base_layer(&self) -> usize {
+        self.channels * self.banks + self.channels * 2
+    }
+
+    /// the links the packet goes through
+    fn path(&self, source: usize, target: usize) -> Vec<usize> {
+        let (source_channel, target_channel) = (source / self.banks, target / self.banks);
+        if source == target {
+            vec![self.bus(source)]
+        } else if source_channel == target_channel {
+            vec![
+                self.bus(source),
+                self.bank_network(source_channel),
+                self.bus(target),
+            ]
+        } else {
+            vec![
+                self.bus(source),
+                self.bank_network(source_channel),
+                self.tsv(source_channel),
+                self.base_layer(),
+                self.tsv(target_channel),
+                self.bank_network(target_channel),
+                self.bus(target),
+            ]
+        }
+    }
+
+    fn links(&self) -> Vec<Link> {
+        let link = |level, config| Link {
+            level,
+            config,
+            queue: VecDeque::new(),
+            in_flight: VecDeque::new(),
+            busy_cycles: 0,
+        };
+        let config = &self.config;
+        let mut links = vec![];
+        links.extend(
+            (0..self.channels * self.banks)
+                .map(|_| link(NetworkLevel::SubarrayBus, config.subarray_bus)),
+        );
+        links.extend(
+            (0..self.channels).map(|_| link(NetworkLevel::BankNetwork, config.bank_network)),
+        );
+        links.extend((0..self.channels).map(|_| link(NetworkLevel::Tsv, config.tsv)));
+        links.push(link(NetworkLevel::BaseLayer, config.base_layer));
+        links
+    }
+
+    /// ## rust function
+    /// ## Author: Jiangqiu Shen
+    /// ## Date: 2023-06-18
+    /// Description: deliver the packets of this round and clear them, return the cycles and the congestion of each
+    /// level. return an error with the queued links when no link serves a packet for `watchdog_cycles` cycles
+    pub fn run_round(&mut self, watchdog_cycles: usize) -> eyre::Result<HierarchyReport> {
+        let packets = std::mem::take(&mut self.packets);
+        let paths = packets
+            .iter()
+            .map(|&(source, target)| self.path(source, target))
+            .collect::<Vec<_>>();
+        let mut hops = vec![0; paths.len()];
+        let mut links = self.links();
+        let mut report = HierarchyReport {
+            packets: packets.len(),
+            ..Default::default()
+        };
+        let level_index = |level: NetworkLevel| level as usize;
+
+        for (packet, path) in paths.iter().enumerate() {
+            links[path[0]].queue.push_back((packet, 0));
+        }
+        let mut remaining = paths.len();
+        let mut cycle = 0;
+        let mut idle_cycles = 0;
+        while remaining > 0 {
+            // the packets arrive at their next links
+            for link_id in 0..links.len() {
+                while let Some(&(packet, ready)) = links[link_id].in_flight.front() {
+                    if ready > cycle {
+                        break;
+                    }
+                    links[link_id].in_flight.pop_front();
+                    hops[packet] += 1;
+                    match paths[packet].get(hops[packet]) {
+                        Some(&next) => links[next].queue.push_back((packet, cycle)),
+                        None => {
+                            remaining -= 1;
+                            report.cycles = cycle;
+                        }
+                    }
+                }
+            }
+            // each link serves its queue, the packets on the way are still making progress
+            let mut progress = links.iter().any(|link| !link.in_flight.is_empty());
+            for link in links.iter_mut() {
+                let level = &mut report.levels[level_index(link.level)];
+                level.max_queue = level.max_queue.max(link.queue.len());
+                let served = link.config.bandwidth.min(link.queue.len());
+                if served > 0 {
+                    link.busy_cycles += 1;
+                    progress = true;
+                }
+                for (packet, arrived) in link.queue.drain(..served) {
+                    level.packets += 1;
+                    level.queueing_delay += cycle - arrived;
+                    link.in_flight
+                        .push_back((packet, cycle + link.config.latency.max(1)));
+                }
+            }
+            idle_cycles = if progress { 0 } else { idle_cycles + 1 };
+            if idle_cycles >= watchdog_cycles {
+                let queued = links
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, link)| !link.queue.is_empty())
+                    .map(|(id, link)| {
+                        format!(
+                            "link {} {:?}: {} queued, {:?}",
+                            id,
+                            link.level,
+                            link.queue.len(),
+                            link.config
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                bail!(
+                    "the hierarchical network made no progress for {} cycles with {} packets remaining\n{}",
+                    idle_cycles,
+                    remaining,
+                    queued.join("\n")
+                );
+            }
+            cycle += 1;
+        }
+        for link in &links {
+            let level = &mut report.levels[level_index(link.level)];
+            level.max_busy_cycles = level.max_busy_cycles.max(link.busy_cycles);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::interconnect::DEFAULT_WATCHDOG_CYCLES;
+
+    #[test]
+    fn test_hierarchical_network() -> eyre::Result<()> {
+        // 2 channels, 4 banks each
+        let mut network = HierarchicalNetwork::new(Default::default(), 2, 4);
+        assert!(network
+            .run_round(DEFAULT_WATCHDOG_CYCLES)?
+            .levels
+            .iter()
+            .all(|l| l.packets == 0));
+
+        network.add(1, 1);
+        let report = network.run_round(DEFAULT_WATCHDOG_CYCLES)?;
+        assert_eq!(report.cycles, 1);
+        assert_eq!(report.levels[0].packets, 1);
+
+        // bus, bank network, bus
+        network.add(0, 3);
+        assert_eq!(network.run_round(DEFAULT_WATCHDOG_CYCLES)?.cycles, 3);
+
+        // all levels, the tsv and the bank network are taken twice
+        network.add(0, 5);
+        let report = network.run_round(DEFAULT_WATCHDOG_CYCLES)?;
+        assert_eq!(report.cycles, 7);
+        let packets = report.levels.iter().map(|l| l.packets).collect::<Vec<_>>();
+        assert_eq!(packets, vec![2, 2, 2, 1]);
+        assert_eq!(report.levels[2].queueing_delay, 0);
+
+        // 4 banks send to the other channel, they queue at the bank network
+        for bank in 0..4 {
+            network.add(bank, 4);
+        }
+        let report = network.run_round(DEFAULT_WATCHDOG_CYCLES)?;
+        assert_eq!(report.levels[1].max_queue, 4);
+        assert_eq!(report.levels[1].max_busy_cycles, 4);
+        // waited 0, 1, 2, 3 cycles in the first bank network, the others are pipelined
+        assert_eq!(report.levels[1].queueing_delay, 6);
+        assert_eq!(report.levels[1].mean_queueing_delay(), 6. / 8.);
+        // the last packet leaves the bank network at cycle 4 and takes 5 more links
+        assert_eq!(report.cycles, 10);
+        // the target subarray bus
+        assert_eq!(report.levels[0].packets, 8);
+
+        // the wider and slower links
+        let config = HierarchicalNetworkConfig {
+            bank_network: LinkConfig {
+                bandwidth: 4,
+                latency: 3,
+            },
+            ..Default::default()
+        };
+        let mut network = HierarchicalNetwork::new(config, 2, 4);
+        for bank in 0..4 {
+            network.add(bank, (bank + 1) % 4);
+        }
+        let mut total = network.run_round(DEFAULT_WATCHDOG_CYCLES)?;
+        assert_eq!(total.levels[1].queueing_delay, 0);
+        assert_eq!(total.cycles, 5);
+        total.add(&total.clone());
+        assert_eq!(total.cycles, 10);
+        assert_eq!(total.packets, 8);
+
+        // the slow links are not stalled
+        let config = HierarchicalNetworkConfig {
+            bank_network: LinkConfig {
+                bandwidth: 1,
+                latency: 50,
+            },
+            ..Default::default()
+        };
+        let mut network = HierarchicalNetwork::new(config, 2, 4);
+        network.add(0, 1);
+        assert_eq!(network.run_round(10)?.cycles, 52);
+
+        // a link that serves nothing stalls the network
+        let config = HierarchicalNetworkConfig {
+            tsv: LinkConfig {
+                bandwidth: 0,
+                latency: 1,
+            },
+            ..Default::default()
+        };
+        let mut network = HierarchicalNetwork::new(config, 2, 4);
+        network.add(0, 5);
+        let error = network.run_round(10).unwrap_err().to_string();
+        assert!(error.contains("no progress for 10 cycles"), "{}", error);
+        assert!(error.contains("Tsv: 1 queued"), "{}", error);
+        Ok(())
+    }
+}
@@ -0,0 +1,706 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-16
+//! Description: the common interface of the interconnect simulators.
+//! a packet is injected into a port with backpressure, moves one step each cycle and is ejected from its
+//! destination port. the analyses build the network from [`InterconnectType`] in the config, so the topology can be
+//! changed without touching the analysis.
+use eyre::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    crossbare_simulator::CrossBareSimulator,
+    crossbare_simulator_no_conflic::CrossBareSimulatorNoConflict,
+    mesh_simulator::{LinkStats, MeshSimulator},
+    ring_simulator::{RingConfig, RingSimulator},
+    CrossBarPacket, Direction, IcntPacket,
+};
+
+/// the counters of a network since it's created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IcntStats {
+    pub cycles: usize,
+    /// the packets accepted by `inject`
+    pub injected: usize,
+    /// the packets rejected by `inject` because of the backpressure
+    pub rejected: usize,
+    pub ejected: usize,
+    /// the times a packet could not move because another packet took its output in the same cycle
+    pub conflicts: usize,
+}
+
+impl IcntStats {
+    /// the packets that are injected but not ejected yet
+    pub fn in_flight(&self) -> usize {
+        self.injected - self.ejected
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-16
+/// Description: a network with `ports()` ports carrying packets of type `T`
+pub trait Interconnect<T> {
+    fn ports(&self) -> usize;
+    /// inject the packet into the port, return the packet back when the port is full
+    fn inject(&mut self, port: usize, packet: T) -> Result<(), T>;
+    /// move the packets one step
+    fn cycle(&mut self);
+    /// take the first packet arrived at the port
+    fn eject(&mut self, port: usize) -> Option<T>;
+    /// the first packet arrived at the port, without taking it
+    fn peek(&self, port: usize) -> Option<&T>;
+    fn stats(&self) -> IcntStats;
+    /// the usage of each link, empty for the networks that do not model the links
+    fn link_stats(&self) -> Vec<LinkStats> {
+        vec![]
+    }
+    /// the buffers and credits of the network, written to the error when the network stalls
+    fn dump_state(&self) -> String;
+}
+
+impl<T, I: Interconnect<T> + ?Sized> Interconnect<T> for Box<I> {
+    fn ports(&self) -> usize {
+        (**self).ports()
+    }
+
+    fn inject(&mut self, port: usize, packet: T) -> Result<(), T> {
+        (**self).inject(port, packet)
+    }
+
+    fn cycle(&mut self) {
+        (**self).cycle()
+    }
+
+    fn eject(&mut self, port: usize) -> Option<T> {
+        (**self).eject(port)
+    }
+
+    fn peek(&self, port: usize) -> Option<&T> {
+        (**self).peek(port)
+    }
+
+    fn stats(&self) -> IcntStats {
+        (**self).stats()
+    }
+
+    fn link_stats(&self) -> Vec<LinkStats> {
+        (**self).link_stats()
+    }
+
+    fn dump_state(&self) -> String {
+        (**self).dump_state()
+    }
+}
+
+impl<T: CrossBarPacket> Interconnect<T> for CrossBareSimulator<T> {
+    fn ports(&self) -> usize {
+        self.ports()
+    }
+
+    fn inject(&mut self, port: usize, packet: T) -> Result<(), T> {
+        self.add(port, packet)
+    }
+
+    fn cycle(&mut self) {
+        self.cycle()
+    }
+
+    fn eject(&mut self, port: usize) -> Option<T> {
+        self.pop(port)
+    }
+
+    fn peek(&self, port: usize) -> Option<&T> {
+        self.front(port)
+    }
+
+    fn stats(&self) -> IcntStats {
+        self.stats
+    }
+
+    fn dump_state(&self) -> String {
+        self.dump_state()
+    }
+}
+
+impl<T: CrossBarPacket> Interconnect<T> for CrossBareSimulatorNoConflict<T> {
+    fn ports(&self) -> usize {
+        self.ports()
+    }
+
+    fn inject(&mut self, port: usize, packet: T) -> Result<(), T> {
+        self.add(port, packet)
+    }
+
+    fn cycle(&mut self) {
+        self.cycle()
+    }
+
+    fn eject(&mut self, port: usize) -> Option<T> {
+        self.pop(port)
+    }
+
+    fn peek(&self, port: usize) -> Option<&T> {
+        self.front(port)
+    }
+
+    fn stats(&self) -> IcntStats {
+        self.stats
+    }
+
+    fn dump_state(&self) -> String {
+        self.dump_state()
+    }
+}
+
+impl<T: IcntPacket> Interconnect<T> for RingSimulator<T> {
+    fn ports(&self) -> usize {
+        self.nodes()
+    }
+
+    fn inject(&mut self, port: usize, packet: T) -> Result<(), T> {
+        self.add(port, packet)
+    }
+
+    fn cycle(&mut self) {
+        self.cycle()
+    }
+
+    fn eject(&mut self, port: usize) -> Option<T> {
+        self.pop(port)
+    }
+
+    fn peek(&self, port: usize) -> Option<&T> {
+        self.front(port)
+    }
+
+    fn stats(&self) -> IcntStats {
+        self.stats
+    }
+
+    fn dump_state(&self) -> String {
+        self.dump_state()
+    }
+}
+
+/// a crossbar packet routed on a ring, it goes the shorter way to its destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingRoute<T> {
+    pub packet: T,
+    direction: Direction,
+}
+
+impl<T: CrossBarPacket> RingRoute<T> {
+    pub fn new(packet: T, nodes: usize) -> Self {
+        let from = packet.get_source();
+        let to = packet.get_dest();
+        let right_distance = (to + nodes - from) % nodes;
+        let left_distance = (from + nodes - to) % nodes;
+        let direction = if right_distance < left_distance {
+            Direction::Right
+        } else {
+            Direction::Left
+        };
+        Self { packet, direction }
+    }
+}
+
+impl<T: CrossBarPacket> IcntPacket for RingRoute<T> {
+    fn get_source(&self) -> usize {
+        self.packet.get_source()
+    }
+
+    fn get_next_hop(&self) -> usize {
+        self.packet.get_dest()
+    }
+
+    fn get_direction(&self) -> Direction {
+        self.direction
+    }
+
+    fn get_size(&self) -> usize {
+        self.packet.get_size()
+    }
+}
+
+/// a ring carrying the crossbar packets, so it can replace a crossbar
+pub struct CrossBarRing<T: CrossBarPacket> {
+    ring: RingSimulator<RingRoute<T>>,
+}
+
+impl<T: CrossBarPacket> CrossBarRing<T> {
+    pub fn new(ports: usize, buffer_size: usize) -> Self {
+        Self::with_config(ports, buffer_size, RingConfig::default())
+    }
+
+    pub fn with_config(ports: usize, buffer_size: usize, config: RingConfig) -> Self {
+        Self {
+            ring: RingSimulator::with_config(ports, buffer_size, config),
+        }
+    }
+}
+
+impl<T: CrossBarPacket> Interconnect<T> for CrossBarRing<T> {
+    fn ports(&self) -> usize {
+        self.ring.nodes()
+    }
+
+    fn inject(&mut self, port: usize, packet: T) -> Result<(), T> {
+        let nodes = self.ring.nodes();
+        self.ring
+            .add(port, RingRoute::new(packet, nodes))
+            .map_err(|route| route.packet)
+    }
+
+    fn cycle(&mut self) {
+        self.ring.cycle()
+    }
+
+    fn eject(&mut self, port: usize) -> Option<T> {
+        self.ring.pop(port).map(|route| route.packet)
+    }
+
+    fn peek(&self, port: usize) -> Option<&T> {
+        self.ring.front(port).map(|route| &route.packet)
+    }
+
+    fn stats(&self) -> IcntStats {
+        self.ring.stats
+    }
+
+    fn dump_state(&self) -> String {
+        self.ring.dump_state()
+    }
+}
+
+/// the topology of a network, set by the `interconnect` field of the config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InterconnectType {
+    /// an output port accepts one packet per cycle
+    #[default]
+    Crossbar,
+    /// an output port accepts all packets arrived in the same cycle
+    CrossbarNoConflict,
+    /// a bidirectional ring, the packets go the shorter way
+    Ring,
+    /// a 2D mesh of the most square shape, with the xy routing and two virtual channels
+    Mesh,
+    /// a 2D torus of the most square shape, with the xy routing and two virtual channels
+    Torus,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-16
+/// Description: build a network of the topology with `ports` ports, each buffer holds `buffer_size` packets
+pub fn build_interconnect<'a, T: CrossBarPacket + 'a>(
+    kind: InterconnectType,
+    ports: usize,
+    buffer_size: usize,
+) -> Box<dyn Interconnect<T> + 'a> {
+    build_interconnect_with_ring(kind, ports, buffer_size, RingConfig::default())
+}
+
+/// same as [`build_interconnect`], the ring uses the links in `ring`
+pub fn build_interconnect_with_ring<'a, T: CrossBarPacket + 'a>(
+    kind: InterconnectType,
+    ports: usize,
+    buffer_size: usize,
+    ring: RingConfig,
+) -> Box<dyn Interconnect<T> + 'a> {
+    match kind {
+        InterconnectType::Crossbar => Box::new(CrossBareSimulator::new(ports, buffer_size)),
+        InterconnectType::CrossbarNoConflict => {
+            Box::new(CrossBareSimulatorNoConflict::new(ports, buffer_size))
+        }
+        InterconnectType::Ring => Box::new(CrossBarRing::with_config(ports, buffer_size, ring)),
+        InterconnectType::Mesh => Box::new(MeshSimulator::square(ports, false, 2, buffer_size)),
+        InterconnectType::Torus => Box::new(MeshSimulator::square(ports, true, 2, buffer_size)),
+    }
+}
+
+/// the default of `network_buffer_size` in the config
+pub const DEFAULT_BUFFER_SIZE: usize = 16;
+
+/// used by serde as the default of `network_buffer_size`
+pub fn default_buffer_size() -> usize {
+    DEFAULT_BUFFER_SIZE
+}
+
+/// the bytes of a word, [`RingConfig::link_width`] is counted in words
+pub const WORD_BYTES: usize = 4;
+
+/// the default of `packet_value_bytes` in the config, an index and a value fill the two words of the default link
+pub const DEFAULT_PACKET_VALUE_BYTES: usize = 4;
+
+/// used by serde as the default of `packet_value_bytes`
+pub fn default_packet_value_bytes() -> usize {
+    DEFAULT_PACKET_VALUE_BYTES
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-18
+/// Description: the words of a packet that carries an index of `index_bytes` and a value of `value_bytes`, this is the
+/// size returned by [`CrossBarPacket::get_size`] and [`IcntPacket::get_size`], the ring splits it by the link width
+pub fn packet_words(index_bytes: usize, value_bytes: usize) -> usize {
+    (index_bytes + value_bytes).div_ceil(WORD_BYTES).max(1)
+}
+
+/// the default of `network_watchdog_cycles` in the config
+pub const DEFAULT_WATCHDOG_CYCLES: usize = 10_000;
+
+/// used by serde as the default of `network_watchdog_cycles`
+pub fn default_watchdog_cycles() -> usize {
+    DEFAULT_WATCHDOG_CYCLES
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-18
+/// Description: detect a network that stops making progress. a small buffer can deadlock the ring credits or keep the
+/// crossbar retrying forever, so the loops driving a network call [`Watchdog::check`] after each cycle, it returns an
+/// error with the state of the network when no packet is ejected for `limit` cycles while packets are in flight
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    limit: usize,
+    last_ejected: usize,
+    idle_cycles: usize,
+}
+
+impl Watchdog {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            last_ejected: 0,
+            idle_cycles: 0,
+        }
+    }
+
+    /// call it after each cycle of the network
+    pub fn check<T>(&mut self, network: &(impl Interconnect<T> + ?Sized)) -> eyre::Result<()> {
+        let stats = network.stats();
+        if stats.in_flight() == 0 || stats.ejected != self.last_ejected {
+            self.last_ejected = stats.ejected;
+            self.idle_cycles = 0;
+            return Ok(());
+        }
+        self.idle_cycles += 1;
+        if self.idle_cycles >= self.limit {
+            bail!(
+                "the network made no progress for {} cycles with {} packets in flight, stats: {:?}\n{}",
+                self.idle_cycles,
+                stats.in_flight(),
+                stats,
+                network.dump_state()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// a packet that only carries its source, destination and size, used to measure the traffic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Packet {
+    pub source: usize,
+    pub dest: usize,
+    /// the payload in words, see [`packet_words`]
+    pub size: usize,
+}
+
+impl CrossBarPacket for Packet {
+    fn get_source(&self) -> usize {
+        self.source
+    }
+
+    fn get_dest(&self) -> usize {
+        self.dest
+    }
+
+    fn get_size(&self) -> usize {
+        self.size
+    }
+}
+
+/// the networks cycled in [`drive_networks`], each network is checked by its own [`Watchdog`]
+pub struct NetworkClock {
+    limit: usize,
+    watchdogs: Vec<Watchdog>,
+    next: usize,
+}
+
+impl NetworkClock {
+    /// cycle the network and check it, the networks should be cycled in the same order in each cycle
+    pub fn cycle<T>(
+        &mut self,
+        network: &mut (impl Interconnect<T> + ?Sized),
+        context: impl FnOnce() -> String,
+    ) -> eyre::Result<()> {
+        network.cycle();
+        if self.next == self.watchdogs.len() {
+            self.watchdogs.push(Watchdog::new(self.limit));
+        }
+        let watchdog = &mut self.watchdogs[self.next];
+        self.next += 1;
+        watchdog.check(&*network).wrap_err_with(context)
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-18
+/// Description: drive the networks until all packets are delivered and return the cycles. `step` runs one cycle: it
+/// injects and ejects the packets, cycles each network by [`NetworkClock::cycle`] and returns the packets still in
+/// flight. a network that makes no progress for `watchdog_cycles` cycles stops the loop with an error
+pub fn drive_networks(
+    watchdog_cycles: usize,
+    mut step: impl FnMut(&mut NetworkClock) -> eyre::Result<usize>,
+) -> eyre::Result<usize> {
+    let mut clock = NetworkClock {
+        limit: watchdog_cycles,
+        watchdogs: vec![],
+        next: 0,
+    };
+    let mut cycles = 0;
+    loop {
+        clock.next = 0;
+        let remaining = step(&mut clock)?;
+        cycles += 1;
+        if remaining == 0 {
+            return Ok(cycles);
+        }
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-17
+/// Description: inject all packets as fast as the network accepts them and return the cycles until all of them are
+/// ejected. the packets of each source are injected in order. return an error when the network makes no progress
+/// for `watchdog_cycles` cycles
+pub fn drain_traffic<T: CrossBarPacket>(
+    network: &mut (impl Interconnect<T> + ?Sized),
+    packets: impl IntoIterator<Item = T>,
+    watchdog_cycles: usize,
+) -> eyre::Result<usize> {
+    let mut pending = (0..network.ports())
+        .map(|_| std::collections::VecDeque::new())
+        .collect::<Vec<_>>();
+    let mut remaining = 0;
+    for packet in packets {
+        pending[packet.get_source()].push_back(packet);
+        remaining += 1;
+    }
+    if remaining == 0 {
+        return Ok(0);
+    }
+    drive_networks(watchdog_cycles, |clock| {
+        for (port, queue) in pending.iter_mut().enumerate() {
+            while let Some(packet) = queue.pop_front() {
+                match network.inject(port, packet) {
+                    Ok(()) => {}
+                    Err(packet) => {
+                        queue.push_front(packet);
+                        break;
+                    }
+                }
+            }
+        }
+        clock.cycle(&mut *network, || {
+            format!("{} packets are not delivered", remaining)
+        })?;
+        for port in 0..network.ports() {
+            while network.eject(port).is_some() {
+                remaining -= 1;
+            }
+        }
+        Ok(remaining)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct TestPacket {
+        source: usize,
+        dest: usize,
+    }
+    impl CrossBarPacket for TestPacket {
+        fn get_source(&self) -> usize {
+            self.source
+        }
+        fn get_dest(&self) -> usize {
+            self.dest
+        }
+    }
+
+    /// send a packet from each port to the next port, return the cycles until all packets arrive
+    fn run_shift(network: &mut dyn Interconnect<TestPacket>, packets: usize) -> usize {
+        let ports = network.ports();
+        let mut pending = (0..ports).map(|_| packets).collect::<Vec<_>>();
+        let mut arrived = 0;
+        let mut cycles = 0;
+        while arrived < ports * packets {
+            for (port, remain) in pending.iter_mut().enumerate() {
+                let packet = TestPacket {
+                    source: port,
+                    dest: (port + 1) % ports,
+                };
+                if *remain > 0 && network.inject(port, packet).is_ok() {
+                    *remain -= 1;
+                }
+            }
+            network.cycle();
+            for port in 0..ports {
+                if let Some(&packet) = network.peek(port) {
+                    assert_eq!(network.eject(port), Some(packet));
+                    assert_eq!(packet.dest, port);
+                    arrived += 1;
+                }
+            }
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn test_interconnect() {
+        for kind in [
+            InterconnectType::Crossbar,
+            InterconnectType::CrossbarNoConflict,
+            InterconnectType::Ring,
+            InterconnectType::Mesh,
+            InterconnectType::Torus,
+        ] {
+            let mut network = build_interconnect::<TestPacket>(kind, 4, 4);
+            assert_eq!(network.ports(), 4);
+            let cycles = run_shift(&mut network, 10);
+            assert!(cycles >= 10, "{:?}: {}", kind, cycles);
+            let stats = network.stats();
+            assert_eq!(stats.injected, 40, "{:?}", kind);
+            assert_eq!(stats.ejected, 40, "{:?}", kind);
+            assert_eq!(stats.in_flight(), 0);
+            assert_eq!(stats.cycles, cycles);
+            // only the mesh and the torus count the busy cycles of each link
+            let has_links = matches!(kind, InterconnectType::Mesh | InterconnectType::Torus);
+            assert_eq!(!network.link_stats().is_empty(), has_links, "{:?}", kind);
+        }
+
+        // all ports send to port 0, the crossbar serves one packet per cycle
+        let mut crossbar = build_interconnect::<TestPacket>(InterconnectType::Crossbar, 4, 1);
+        for port in 0..4 {
+            crossbar
+                .inject(
+                    port,
+                    TestPacket {
+                        source: port,
+                        dest: 0,
+                    },
+                )
+                .unwrap();
+        }
+        assert!(crossbar
+            .inject(0, TestPacket { source: 0, dest: 0 })
+            .is_err());
+        crossbar.cycle();
+        let stats = crossbar.stats();
+        assert_eq!(stats.rejected, 1);
+        assert_eq!(stats.conflicts, 3);
+        assert_eq!(stats.in_flight(), 4);
+    }
+
+    #[test]
+    fn test_watchdog() {
+        for kind in [
+            InterconnectType::Crossbar,
+            InterconnectType::CrossbarNoConflict,
+            InterconnectType::Ring,
+            InterconnectType::Mesh,
+            InterconnectType::Torus,
+        ] {
+            // nobody ejects the packets, the network stalls once the buffers are full
+            let mut network = build_interconnect::<TestPacket>(kind, 4, 1);
+            let mut watchdog = Watchdog::new(10);
+            let mut result = Ok(());
+            for _ in 0..100 {
+                let _ = network.inject(1, TestPacket { source: 1, dest: 0 });
+                network.cycle();
+                result = watchdog.check(&network);
+                if result.is_err() {
+                    break;
+                }
+            }
+            let error = result.unwrap_err().to_string();
+            assert!(error.contains("no progress for 10 cycles"), "{:?}", kind);
+            assert!(error.lines().count() > 4, "{:?}: {}", kind, error);
+        }
+
+        // an idle network is not stalled
+        let mut network = build_interconnect::<TestPacket>(InterconnectType::Crossbar, 4, 1);
+        let mut watchdog = Watchdog::new(10);
+        for _ in 0..100 {
+            network.cycle();
+            watchdog.check(&network).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_drive_networks() {
+        // each network has its own watchdog, only the stalled one is reported
+        let mut busy = build_interconnect::<TestPacket>(InterconnectType::Ring, 4, 1);
+        let mut idle = build_interconnect::<TestPacket>(InterconnectType::Crossbar, 4, 1);
+        let error = drive_networks(10, |clock| {
+            let _ = busy.inject(1, TestPacket { source: 1, dest: 0 });
+            clock.cycle(&mut idle, || "the idle network is stalled".to_string())?;
+            clock.cycle(&mut busy, || "the busy network is stalled".to_string())?;
+            Ok(1)
+        })
+        .unwrap_err();
+        assert_eq!(error.to_string(), "the busy network is stalled");
+
+        let mut network = build_interconnect::<TestPacket>(InterconnectType::Crossbar, 4, 1);
+        network
+            .inject(1, TestPacket { source: 1, dest: 0 })
+            .unwrap();
+        let mut remaining = 1;
+        let cycles = drive_networks(10, |clock| {
+            clock.cycle(&mut network, || "the network is stalled".to_string())?;
+            if network.eject(0).is_some() {
+                remaining -= 1;
+            }
+            Ok(remaining)
+        })
+        .unwrap();
+        assert!(cycles >= 1);
+    }
+
+    #[test]
+    fn test_packet_size() {
+        // an u32 index and an f32 value fit in one flit of the default ring
+        assert_eq!(packet_words(4, DEFAULT_PACKET_VALUE_BYTES), 2);
+        assert_eq!(packet_words(4, 8), 3);
+        assert_eq!(packet_words(0, 0), 1);
+
+        let ring_cycles = |value_bytes| {
+            let size = packet_words(4, value_bytes);
+            let mut ring = build_interconnect_with_ring::<Packet>(
+                InterconnectType::Ring,
+                8,
+                DEFAULT_BUFFER_SIZE,
+                RingConfig::default(),
+            );
+            let packets = (0..8).flat_map(|source| {
+                (0..10).map(move |_| Packet {
+                    source,
+                    dest: (source + 3) % 8,
+                    size,
+                })
+            });
+            drain_traffic(&mut ring, packets, DEFAULT_WATCHDOG_CYCLES).unwrap()
+        };
+        // a larger payload takes more flits on each link
+        assert!(ring_cycles(DEFAULT_PACKET_VALUE_BYTES) < ring_cycles(16));
+    }
+}
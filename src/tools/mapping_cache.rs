@@ -0,0 +1,283 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-09
+//! Description: a persistent cache of the translated graphs and the evil sub graphs of the mappings.
+//! building the sorted permutation and translating the graph is much slower than loading them, and the sweeps
+//! over `remap_cycle` or the jump models reuse the same mapping again and again.
+//!
+//! each entry is a file in the cache dir named by its [`MappingKey`], all numbers are little endian:
+//! - header: magic, graph hash, channels, banks, subarrays, evil threshold, cols, rows, graph cols, nnz, checksum
+//! - mapping kind: length(u64) and utf8 bytes
+//! - translated graph: indptr(rows + 1 u32), indices(nnz u32)
+//! - evil sub graphs(one for each subarray): nnz(u64), indptr(evil threshold + 1 u32), indices(nnz u32)
+//!
+//! the checksum covers all the u32 sections, a broken or mismatched entry is rebuilt.
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use eyre::bail;
+use sprs::{num_kinds::Pattern, CsMatI, CsMatViewI};
+use tracing::{info, warn};
+
+use super::matrix_cache::{checksum, compressed_parts, graph_hash, read_u32s, write_u32s};
+
+const MAGIC: &[u8; 8] = b"SPMMAP01";
+pub const CACHE_EXTENSION: &str = "mapcache";
+
+/// everything that changes the result of a mapping
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappingKey {
+    /// the hash of the original graph, see [`graph_hash`]
+    pub graph_hash: u64,
+    /// the name of the mapping
+    pub kind: String,
+    pub channels: usize,
+    pub banks: usize,
+    /// the subarrays in a bank
+    pub subarrays: usize,
+    pub evil_threshold: usize,
+    /// the columns of a subarray row
+    pub cols: usize,
+}
+
+impl MappingKey {
+    pub fn new(
+        kind: impl Into<String>,
+        graph: CsMatViewI<Pattern, u32>,
+        channels: usize,
+        banks: usize,
+        subarrays: usize,
+        evil_threshold: usize,
+        cols: usize,
+    ) -> eyre::Result<Self> {
+        Ok(Self {
+            graph_hash: graph_hash(graph)?,
+            kind: kind.into(),
+            channels,
+            banks,
+            subarrays,
+            evil_threshold,
+            cols,
+        })
+    }
+
+    /// the subarrays of all channels
+    pub fn total_subarrays(&self) -> usize {
+        self.channels * self.banks * self.subarrays
+    }
+
+    fn file_name(&self) -> String {
+        format!(
+            "{:016x}_{}_{}c{}b{}s_e{}_c{}.{}",
+            self.graph_hash,
+            self.kind,
+            self.channels,
+            self.banks,
+            self.subarrays,
+            self.evil_threshold,
+            self.cols,
+            CACHE_EXTENSION
+        )
+    }
+}
+
+/// the cached part of a mapping
+pub struct CachedMapping {
+    /// the graph after the rows are reordered by the mapping
+    pub translated: CsMatI<Pattern, u32>,
+    /// the evil rows of each subarray
+    pub evil_row_sub_graph: Vec<CsMatI<Pattern, u32>>,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-09
+/// Description: the mapping cache in a dir, the dir is created when the first entry is stored
+#[derive(Debug, Clone)]
+pub struct MappingCache {
+    dir: PathBuf,
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+impl MappingCache {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// the file of the entry
+    pub fn path(&self, key: &MappingKey) -> PathBuf {
+        self.dir.join(key.file_name())
+    }
+
+    /// read the entry, return `None` when it's missing, broken or built for another key
+    pub fn load(&self, key: &MappingKey) -> Option<CachedMapping> {
+        let path = self.path(key);
+        if !path.exists() {
+            return None;
+        }
+        match self.read_entry(&path, key) {
+            Ok(cached) => {
+                info!("mapping loaded from cache {:?}", path);
+                Some(cached)
+            }
+            Err(e) => {
+                warn!("fail to load the mapping cache {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn read_entry(&self, path: &Path, key: &MappingKey) -> eyre::Result<CachedMapping> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("not a mapping cache file");
+        }
+        let mut key_values = [0u64; 6];
+        for value in key_values.iter_mut() {
+            *value = read_u64(&mut reader)?;
+        }
+        let [graph_hash, channels, banks, subarrays, evil_threshold, cols] = key_values;
+        let mut shape_values = [0u64; 4];
+        for value in shape_values.iter_mut() {
+            *value = read_u64(&mut reader)?;
+        }
+        let [rows, graph_cols, nnz, expected_checksum] = shape_values;
+        let kind_len = read_u64(&mut reader)? as usize;
+        let mut kind = vec![0u8; kind_len];
+        reader.read_exact(&mut kind)?;
+        let stored_key = MappingKey {
+            graph_hash,
+            kind: String::from_utf8(kind)?,
+            channels: channels as usize,
+            banks: banks as usize,
+            subarrays: subarrays as usize,
+            evil_threshold: evil_threshold as usize,
+            cols: cols as usize,
+        };
+        if &stored_key != key {
+            bail!("the entry is built for another mapping: {:?}", stored_key);
+        }
+        let (rows, graph_cols, nnz) = (rows as usize, graph_cols as usize, nnz as usize);
+
+        let indptr = read_u32s(&mut reader, rows + 1)?;
+        let indices = read_u32s(&mut reader, nnz)?;
+        let mut sub_graphs = Vec::with_capacity(key.total_subarrays());
+        for _ in 0..key.total_subarrays() {
+            let sub_nnz = read_u64(&mut reader)? as usize;
+            let sub_indptr = read_u32s(&mut reader, key.evil_threshold + 1)?;
+            let sub_indices = read_u32s(&mut reader, sub_nnz)?;
+            sub_graphs.push((sub_indptr, sub_indices));
+        }
+        let sections = [indptr.as_slice(), &indices].into_iter().chain(
+            sub_graphs
+                .iter()
+                .flat_map(|(indptr, indices)| [indptr.as_slice(), indices.as_slice()]),
+        );
+        if checksum(sections) != expected_checksum {
+            bail!("checksum mismatch");
+        }
+        let translated = CsMatI::new((rows, graph_cols), indptr, indices, vec![Pattern; nnz]);
+        let evil_row_sub_graph = sub_graphs
+            .into_iter()
+            .map(|(indptr, indices)| {
+                let nnz = indices.len();
+                CsMatI::new(
+                    (key.evil_threshold, graph_cols),
+                    indptr,
+                    indices,
+                    vec![Pattern; nnz],
+                )
+            })
+            .collect();
+        Ok(CachedMapping {
+            translated,
+            evil_row_sub_graph,
+        })
+    }
+
+    /// write the entry, the mapping is still usable when the write fails, so only a warning is reported
+    pub fn store(
+        &self,
+        key: &MappingKey,
+        translated: CsMatViewI<Pattern, u32>,
+        evil_row_sub_graph: &[CsMatI<Pattern, u32>],
+    ) {
+        let path = self.path(key);
+        let temp_path =
+            path.with_extension(format!("{}.{}.tmp", CACHE_EXTENSION, std::process::id()));
+        let write_result = (|| -> eyre::Result<()> {
+            if evil_row_sub_graph.len() != key.total_subarrays() {
+                bail!(
+                    "expect {} evil sub graphs, got {}",
+                    key.total_subarrays(),
+                    evil_row_sub_graph.len()
+                );
+            }
+            std::fs::create_dir_all(&self.dir)?;
+            let (indptr, indices) = compressed_parts(translated)?;
+            let sub_graphs = evil_row_sub_graph
+                .iter()
+                .map(|sub_graph| compressed_parts(sub_graph.view()))
+                .collect::<eyre::Result<Vec<_>>>()?;
+            let sections = [indptr.as_slice(), &indices].into_iter().chain(
+                sub_graphs
+                    .iter()
+                    .flat_map(|(indptr, indices)| [indptr.as_slice(), indices.as_slice()]),
+            );
+            let checksum = checksum(sections);
+
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            writer.write_all(MAGIC)?;
+            for value in [
+                key.graph_hash,
+                key.channels as u64,
+                key.banks as u64,
+                key.subarrays as u64,
+                key.evil_threshold as u64,
+                key.cols as u64,
+                translated.rows() as u64,
+                translated.cols() as u64,
+                indices.len() as u64,
+                checksum,
+            ] {
+                write_u64(&mut writer, value)?;
+            }
+            write_u64(&mut writer, key.kind.len() as u64)?;
+            writer.write_all(key.kind.as_bytes())?;
+            write_u32s(&mut writer, &indptr)?;
+            write_u32s(&mut writer, &indices)?;
+            for (sub_indptr, sub_indices) in &sub_graphs {
+                write_u64(&mut writer, sub_indices.len() as u64)?;
+                write_u32s(&mut writer, sub_indptr)?;
+                write_u32s(&mut writer, sub_indices)?;
+            }
+            writer.flush()?;
+            drop(writer);
+            std::fs::rename(&temp_path, &path)?;
+            Ok(())
+        })();
+        match write_result {
+            Ok(()) => info!("mapping cache written to {:?}", path),
+            Err(e) => {
+                warn!("fail to write the mapping cache {:?}: {}", path, e);
+                std::fs::remove_file(&temp_path).ok();
+            }
+        }
+    }
+}
@@ -0,0 +1,445 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-09
+//! Description: a binary cache of the graphs, parsing the text format and transposing the large
+//! graphs takes much longer than the simulation of a few rounds.
+//!
+//! the cache is saved next to the graph as `{graph}.csrcache`, or `{graph}.{options}.csrcache` when the graph has
+//! options(see [`graph_reader`]), all numbers are little endian:
+//! - header: magic, rows, cols, nnz, source file length, source mtime(secs, nanos), csr checksum, csc checksum
+//! - csr: indptr(rows + 1 u32), indices(nnz u32)
+//! - csc: indptr(cols + 1 u32), indices(nnz u32)
+//!
+//! the cache is rebuilt when the source file is modified, the cache is truncated or the checksum does not match. the
+//! indptr is u32, so the graphs with more than `u32::MAX` nnz are not supported.
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use eyre::{bail, Context};
+use rayon::prelude::*;
+use sprs::{num_kinds::Pattern, CsMatI, CsMatViewI, TriMatI};
+use tracing::{info, warn};
+
+use super::graph_reader::{self, GraphSource};
+use crate::pim::config_migration::load_config;
+
+const MAGIC: &[u8; 8] = b"SPMCSR01";
+pub const CACHE_EXTENSION: &str = "csrcache";
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+/// the number of u32 decoded at once
+const READ_CHUNK: usize = 16384;
+/// the bytes of the magic and the 8 u64 of the header
+const HEADER_SIZE: u64 = 8 + 8 * 8;
+
+/// the header of the cache file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheHeader {
+    pub rows: usize,
+    pub cols: usize,
+    pub nnz: usize,
+    pub source_len: u64,
+    /// the modified time of the source file since unix epoch
+    pub source_mtime: Duration,
+    pub csr_checksum: u64,
+    pub csc_checksum: u64,
+}
+
+impl CacheHeader {
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        for value in [
+            self.rows as u64,
+            self.cols as u64,
+            self.nnz as u64,
+            self.source_len,
+            self.source_mtime.as_secs(),
+            self.source_mtime.subsec_nanos() as u64,
+            self.csr_checksum,
+            self.csc_checksum,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read_from(reader: &mut impl Read) -> eyre::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("not a matrix cache file");
+        }
+        let mut values = [0u64; 8];
+        for value in values.iter_mut() {
+            let mut bytes = [0u8; 8];
+            reader.read_exact(&mut bytes)?;
+            *value = u64::from_le_bytes(bytes);
+        }
+        let [rows, cols, nnz, source_len, secs, nanos, csr_checksum, csc_checksum] = values;
+        Ok(Self {
+            rows: rows as usize,
+            cols: cols as usize,
+            nnz: nnz as usize,
+            source_len,
+            source_mtime: Duration::new(secs, nanos as u32),
+            csr_checksum,
+            csc_checksum,
+        })
+    }
+
+    /// the size of the cache file described by the header, `None` when a broken header overflows it
+    fn file_size(&self) -> Option<u64> {
+        let u32s = (self.rows as u64)
+            .checked_add(self.cols as u64)?
+            .checked_add(2)?
+            .checked_add((self.nnz as u64).checked_mul(2)?)?;
+        u32s.checked_mul(4)?.checked_add(HEADER_SIZE)
+    }
+}
+
+/// the graph in both csr and csc format
+pub struct CachedMatrix {
+    pub csr: CsMatI<Pattern, u32>,
+    pub csc: CsMatI<Pattern, u32>,
+}
+
+impl CachedMatrix {
+    /// the transpose of the matrix in csr format, no copy is needed
+    pub fn transposed_csr(self) -> CsMatI<Pattern, u32> {
+        self.csc.transpose_into()
+    }
+}
+
+/// the path of the cache of `graph`, `graph` is an entry of `graph_path`. the generated graphs have no cache
+pub fn cache_path(graph: &str) -> eyre::Result<PathBuf> {
+    let source = GraphSource::parse(graph)?;
+    let Some(path) = source.file_path() else {
+        bail!("the generated graph {} has no cache", graph);
+    };
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    let options_tag = source.options_tag();
+    if !options_tag.is_empty() {
+        file_name.push(".");
+        file_name.push(options_tag);
+    }
+    file_name.push(".");
+    file_name.push(CACHE_EXTENSION);
+    Ok(path.with_file_name(file_name))
+}
+
+fn source_stamp(graph: &str) -> eyre::Result<(u64, Duration)> {
+    let source = GraphSource::parse(graph)?;
+    let graph = source
+        .file_path()
+        .ok_or_else(|| eyre::eyre!("the generated graph {} has no file", graph))?;
+    let metadata =
+        std::fs::metadata(graph).wrap_err(format!("fail to read metadata of {:?}", graph))?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok((metadata.len(), mtime))
+}
+
+/// the outer pointers and the inner indices of a compressed matrix, the indptr always starts from 0
+pub(crate) fn compressed_parts(
+    matrix: CsMatViewI<Pattern, u32>,
+) -> eyre::Result<(Vec<u32>, Vec<u32>)> {
+    let mut indptr = Vec::with_capacity(matrix.outer_dims() + 1);
+    let mut indices = Vec::with_capacity(matrix.nnz());
+    indptr.push(0);
+    for vec in matrix.outer_iterator() {
+        indices.extend_from_slice(vec.indices());
+        let Ok(end) = u32::try_from(indices.len()) else {
+            bail!(
+                "the matrix has more than {} nnz, the u32 indptr can not hold it",
+                u32::MAX
+            );
+        };
+        indptr.push(end);
+    }
+    Ok((indptr, indices))
+}
+
+/// the FNV-1a hash of the sections
+pub(crate) fn checksum<'a>(sections: impl IntoIterator<Item = &'a [u32]>) -> u64 {
+    let mut hash = FNV_OFFSET;
+    for value in sections.into_iter().flatten() {
+        for byte in value.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
+}
+
+pub(crate) fn write_u32s(writer: &mut impl Write, data: &[u32]) -> io::Result<()> {
+    for value in data {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_u32s(reader: &mut impl Read, len: usize) -> io::Result<Vec<u32>> {
+    let mut data = Vec::with_capacity(len);
+    let mut buffer = vec![0u8; READ_CHUNK * 4];
+    while data.len() < len {
+        let bytes = &mut buffer[..(len - data.len()).min(READ_CHUNK) * 4];
+        reader.read_exact(bytes)?;
+        data.extend(
+            bytes
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]])),
+        );
+    }
+    Ok(data)
+}
+
+/// parse the graph file and write the cache, the cache is written to a temp file first so the
+/// parallel readers never see a half written cache. the generated graphs are built without the cache
+pub fn build_cache(graph: &str) -> eyre::Result<CachedMatrix> {
+    if GraphSource::parse(graph)?.is_generated() {
+        info!("generating the graph {}", graph);
+        let matrix_tri = graph_reader::read_graph(graph)?;
+        return Ok(CachedMatrix {
+            csr: matrix_tri.to_csr(),
+            csc: matrix_tri.to_csc(),
+        });
+    }
+    info!("building the matrix cache of {}", graph);
+    let (source_len, source_mtime) = source_stamp(graph)?;
+    let matrix_tri = graph_reader::read_graph(graph)?;
+    if u32::try_from(matrix_tri.nnz()).is_err() {
+        bail!(
+            "the graph {} has {} nnz, the u32 indptr can hold at most {}",
+            graph,
+            matrix_tri.nnz(),
+            u32::MAX
+        );
+    }
+    let csr: CsMatI<Pattern, u32> = matrix_tri.to_csr();
+    let csc: CsMatI<Pattern, u32> = matrix_tri.to_csc();
+    drop(matrix_tri);
+
+    let (csr_indptr, csr_indices) = compressed_parts(csr.view())?;
+    let (csc_indptr, csc_indices) = compressed_parts(csc.view())?;
+    let header = CacheHeader {
+        rows: csr.rows(),
+        cols: csr.cols(),
+        nnz: csr.nnz(),
+        source_len,
+        source_mtime,
+        csr_checksum: checksum([csr_indptr.as_slice(), &csr_indices]),
+        csc_checksum: checksum([csc_indptr.as_slice(), &csc_indices]),
+    };
+    let path = cache_path(graph)?;
+    let temp_path = path.with_extension(format!("{}.{}.tmp", CACHE_EXTENSION, std::process::id()));
+    let write_result = (|| -> eyre::Result<()> {
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        header.write_to(&mut writer)?;
+        for section in [&csr_indptr, &csr_indices, &csc_indptr, &csc_indices] {
+            write_u32s(&mut writer, section)?;
+        }
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(&temp_path, &path)?;
+        Ok(())
+    })();
+    match write_result {
+        Ok(()) => info!("matrix cache written to {:?}", path),
+        // the graph dir might be read only, the matrix is still usable
+        Err(e) => {
+            warn!("fail to write the matrix cache {:?}: {}", path, e);
+            std::fs::remove_file(&temp_path).ok();
+        }
+    }
+    Ok(CachedMatrix { csr, csc })
+}
+
+/// open the cache and check that it's still fresh, return `None` when the cache should be rebuilt
+fn open_cache(graph: &str) -> eyre::Result<Option<(CacheHeader, BufReader<File>)>> {
+    if GraphSource::parse(graph)?.is_generated() {
+        return Ok(None);
+    }
+    let path = cache_path(graph)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = File::open(&path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let header = match CacheHeader::read_from(&mut reader) {
+        Ok(header) => header,
+        Err(e) => {
+            warn!("broken matrix cache {:?}: {}", path, e);
+            return Ok(None);
+        }
+    };
+    // a truncated cache or a broken header
+    if header.file_size() != Some(file_len) {
+        warn!(
+            "broken matrix cache {:?}: the header needs {:?} bytes, the file has {} bytes",
+            path,
+            header.file_size(),
+            file_len
+        );
+        return Ok(None);
+    }
+    let (source_len, source_mtime) = source_stamp(graph)?;
+    if header.source_len != source_len || header.source_mtime != source_mtime {
+        info!("the matrix cache {:?} is outdated", path);
+        return Ok(None);
+    }
+    Ok(Some((header, reader)))
+}
+
+/// read the indptr and the indices of a section, `None` when the section can not be read or the checksum does not
+/// match, the cache should be rebuilt then
+fn read_section(
+    reader: &mut impl Read,
+    outer: usize,
+    nnz: usize,
+    expected_checksum: u64,
+) -> Option<(Vec<u32>, Vec<u32>)> {
+    let indptr = read_u32s(reader, outer + 1).ok()?;
+    let indices = read_u32s(reader, nnz).ok()?;
+    if checksum([indptr.as_slice(), &indices]) != expected_checksum {
+        return None;
+    }
+    Some((indptr, indices))
+}
+
+/// read the csr and csc of the graph, the cache is built when it's missing or outdated
+pub fn load_or_build(graph: &str) -> eyre::Result<CachedMatrix> {
+    let Some((header, mut reader)) = open_cache(graph)? else {
+        return build_cache(graph);
+    };
+    let csr = read_section(&mut reader, header.rows, header.nnz, header.csr_checksum);
+    let csc = read_section(&mut reader, header.cols, header.nnz, header.csc_checksum);
+    match (csr, csc) {
+        (Some((csr_indptr, csr_indices)), Some((csc_indptr, csc_indices))) => {
+            let shape = (header.rows, header.cols);
+            Ok(CachedMatrix {
+                csr: CsMatI::new(shape, csr_indptr, csr_indices, vec![Pattern; header.nnz]),
+                csc: CsMatI::new_csc(shape, csc_indptr, csc_indices, vec![Pattern; header.nnz]),
+            })
+        }
+        _ => {
+            warn!("broken matrix cache of {}, rebuild it", graph);
+            build_cache(graph)
+        }
+    }
+}
+
+/// read only the csr of the graph, the csc part of the cache is skipped
+pub fn load_csr_or_build(graph: &str) -> eyre::Result<CsMatI<Pattern, u32>> {
+    let Some((header, mut reader)) = open_cache(graph)? else {
+        return Ok(build_cache(graph)?.csr);
+    };
+    match read_section(&mut reader, header.rows, header.nnz, header.csr_checksum) {
+        Some((indptr, indices)) => Ok(CsMatI::new(
+            (header.rows, header.cols),
+            indptr,
+            indices,
+            vec![Pattern; header.nnz],
+        )),
+        None => {
+            warn!("broken matrix cache of {}, rebuild it", graph);
+            Ok(build_cache(graph)?.csr)
+        }
+    }
+}
+
+/// build the caches of all graphs in `graph_path` of the config, the graphs are converted in parallel
+/// under the memory limit. when `force` is set, the fresh caches are also rebuilt
+pub fn convert_config(config: impl AsRef<Path>, force: bool) -> eyre::Result<()> {
+    let graphs = load_config(config)?.graph_path;
+    graphs.par_iter().try_for_each(|graph| -> eyre::Result<()> {
+        if GraphSource::parse(graph)?.is_generated() {
+            info!("skip the generated graph {}", graph);
+            return Ok(());
+        }
+        if !force && open_cache(graph)?.is_some() {
+            info!("the matrix cache of {} is fresh", graph);
+            return Ok(());
+        }
+        let head = graph_reader::read_graph_head(graph)?;
+        let compressed_size = head.ind_ptr_size() + head.ind_size();
+        let _guard = crate::try_acquire_memory_sections([head.tri_size(), compressed_size * 2])?;
+        build_cache(graph)?;
+        Ok(())
+    })
+}
+
+/// the hash of the structure of the graph, two graphs with the same hash are treated as the same graph
+pub fn graph_hash(csr: CsMatViewI<Pattern, u32>) -> eyre::Result<u64> {
+    let (indptr, indices) = compressed_parts(csr)?;
+    let shape = [csr.rows() as u32, csr.cols() as u32];
+    Ok(checksum([shape.as_slice(), &indptr, &indices]))
+}
+
+/// rebuild the triplet matrix from the csr, used by the mappings that need the triplets
+pub fn csr_to_triplet(csr: CsMatViewI<Pattern, u32>) -> TriMatI<Pattern, u32> {
+    let mut triplet = TriMatI::with_capacity((csr.rows(), csr.cols()), csr.nnz());
+    for (row, vec) in csr.outer_iterator().enumerate() {
+        for &col in vec.indices() {
+            triplet.add_triplet(row, col as usize, Pattern);
+        }
+    }
+    triplet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_round_trip() -> eyre::Result<()> {
+        let dir = std::env::temp_dir().join("spmspm_matrix_cache_test");
+        std::fs::create_dir_all(&dir)?;
+        let graph = dir.join("test.mtx");
+        std::fs::copy("test_mtx/test_large.mtx", &graph)?;
+        let graph = graph.to_str().unwrap();
+        std::fs::remove_file(cache_path(graph)?).ok();
+
+        let built = load_or_build(graph)?;
+        assert!(cache_path(graph)?.exists());
+        let cached = load_or_build(graph)?;
+        assert_eq!(built.csr, cached.csr);
+        assert_eq!(built.csc, cached.csc);
+        assert_eq!(load_csr_or_build(graph)?, built.csr);
+
+        let expected: TriMatI<Pattern, u32> = sprs::io::read_matrix_market(graph)?;
+        assert_eq!(cached.csr, expected.to_csr());
+        assert_eq!(cached.transposed_csr(), expected.transpose_view().to_csr());
+        assert_eq!(csr_to_triplet(built.csr.view()).to_csr(), built.csr);
+
+        // a broken cache is rebuilt
+        let mut bytes = std::fs::read(cache_path(graph)?)?;
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(cache_path(graph)?, &bytes)?;
+        assert_eq!(load_or_build(graph)?.csc, built.csc);
+
+        // a truncated cache is rebuilt instead of failing the read
+        let bytes = std::fs::read(cache_path(graph)?)?;
+        std::fs::write(cache_path(graph)?, &bytes[..bytes.len() / 2])?;
+        assert_eq!(load_or_build(graph)?.csr, built.csr);
+        assert_eq!(std::fs::read(cache_path(graph)?)?.len(), bytes.len());
+        std::fs::write(cache_path(graph)?, &bytes[..bytes.len() - 4])?;
+        assert_eq!(load_csr_or_build(graph)?, built.csr);
+
+        // a broken header is not trusted for the allocation
+        let mut broken = bytes.clone();
+        broken[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(cache_path(graph)?, &broken)?;
+        assert_eq!(load_or_build(graph)?.csc, built.csc);
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
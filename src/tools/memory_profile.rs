@@ -0,0 +1,206 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-14
+//! Description: sample the resident memory of the process while the jobs are running, so the hand-computed
+//! memory estimates of the jobs can be checked against the real usage.
+//! a background thread samples the rss every `MEMORY_SAMPLE_MS` milliseconds(default 200) while there is a running
+//! job, the peak is recorded by all the running jobs. the rss belongs to the whole process, so when several jobs run
+//! together the peak of a job also contains the others and it's not compared with the estimate, see
+//! [`MemoryReport::actual_to_estimated`].
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sysinfo::{ProcessExt, SystemExt};
+use tracing::{info, warn};
+
+/// the estimated and measured memory of a job
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct MemoryReport {
+    pub graph: String,
+    /// the memory acquired from the memory scheduler
+    pub estimated_bytes: u64,
+    /// the rss when the job started
+    pub start_rss_bytes: u64,
+    /// the largest rss sampled while the job is running
+    pub peak_rss_bytes: u64,
+    /// `peak_rss_bytes - start_rss_bytes`, the memory used by this job when it runs alone
+    pub peak_delta_bytes: u64,
+    /// the most jobs running at the same time with this job, including itself
+    pub concurrent_jobs: usize,
+    /// `peak_delta_bytes / estimated_bytes`, `None` when other jobs ran at the same time, their memory is also in
+    /// the rss of the process so the ratio says nothing about this job
+    #[serde(default)]
+    pub actual_to_estimated: Option<f64>,
+    /// how long the job waited in the memory scheduler before it started
+    #[serde(default)]
+    pub waited_secs: f64,
+}
+
+#[derive(Default)]
+struct JobState {
+    peak_rss: AtomicU64,
+    concurrent_jobs: AtomicUsize,
+}
+
+impl JobState {
+    fn record(&self, rss: u64, jobs: usize) {
+        self.peak_rss.fetch_max(rss, Ordering::Relaxed);
+        self.concurrent_jobs.fetch_max(jobs, Ordering::Relaxed);
+    }
+}
+
+struct Sampler {
+    jobs: Mutex<Vec<Arc<JobState>>>,
+}
+
+static SAMPLER: Lazy<Sampler> = Lazy::new(|| {
+    let interval = std::env::var("MEMORY_SAMPLE_MS")
+        .ok()
+        .and_then(|ms| ms.parse::<u64>().ok())
+        .filter(|&ms| ms > 0)
+        .unwrap_or(200);
+    std::thread::Builder::new()
+        .name("memory-sampler".into())
+        .spawn(move || loop {
+            std::thread::sleep(Duration::from_millis(interval));
+            SAMPLER.sample();
+        })
+        .map_err(|e| warn!("fail to start the memory sampler: {}", e))
+        .ok();
+    Sampler {
+        jobs: Mutex::new(vec![]),
+    }
+});
+
+impl Sampler {
+    fn sample(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        // the finished jobs dropped their handle
+        jobs.retain(|job| Arc::strong_count(job) > 1);
+        if jobs.is_empty() {
+            return;
+        }
+        let Some(rss) = current_rss() else {
+            return;
+        };
+        let running = jobs.len();
+        for job in jobs.iter() {
+            job.record(rss, running);
+        }
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-14
+/// Description: the resident memory of the process in bytes, `None` when it's not available
+pub fn current_rss() -> Option<u64> {
+    let pid = sysinfo::get_current_pid().ok()?;
+    let mut system = sysinfo::System::new();
+    system.refresh_process(pid);
+    system.process(pid).map(|process| process.memory())
+}
+
+/// a running job, call [`JobProfile::finish`] to get its report
+pub struct JobProfile {
+    graph: String,
+    estimated_bytes: u64,
+    waited: Duration,
+    start_rss: u64,
+    state: Arc<JobState>,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-14
+/// Description: start sampling for a job of the graph that is estimated to use `estimated_bytes`, the job waited
+/// `waited` for its memory, see [`crate::MemoryGuard::waited`]
+pub fn start_job(graph: &str, estimated_bytes: u64, waited: Duration) -> JobProfile {
+    let start_rss = current_rss().unwrap_or(0);
+    let state = Arc::new(JobState::default());
+    let mut jobs = SAMPLER.jobs.lock().unwrap();
+    jobs.push(state.clone());
+    state.record(start_rss, jobs.len());
+    drop(jobs);
+    JobProfile {
+        graph: graph.to_string(),
+        estimated_bytes,
+        waited,
+        start_rss,
+        state,
+    }
+}
+
+impl JobProfile {
+    /// stop sampling, the job is removed from the sampler at its next sample
+    pub fn finish(self) -> MemoryReport {
+        // the short jobs may finish before the first sample
+        if let Some(rss) = current_rss() {
+            self.state.record(rss, 1);
+        }
+        let peak_rss_bytes = self.state.peak_rss.load(Ordering::Relaxed);
+        let peak_delta_bytes = peak_rss_bytes.saturating_sub(self.start_rss);
+        let concurrent_jobs = self.state.concurrent_jobs.load(Ordering::Relaxed);
+        let report = MemoryReport {
+            graph: self.graph,
+            estimated_bytes: self.estimated_bytes,
+            start_rss_bytes: self.start_rss,
+            peak_rss_bytes,
+            peak_delta_bytes,
+            concurrent_jobs,
+            actual_to_estimated: (concurrent_jobs == 1)
+                .then(|| peak_delta_bytes as f64 / self.estimated_bytes.max(1) as f64),
+            waited_secs: self.waited.as_secs_f64(),
+        };
+        info!(
+            "memory of graph {}: estimated {} bytes, peak delta {} bytes, ratio {:?}, {} concurrent jobs, waited {:.3} secs",
+            report.graph,
+            report.estimated_bytes,
+            report.peak_delta_bytes,
+            report.actual_to_estimated,
+            report.concurrent_jobs,
+            report.waited_secs
+        );
+        if report.concurrent_jobs == 1 && report.peak_delta_bytes > report.estimated_bytes {
+            warn!(
+                "graph {} used {} bytes more than the estimate, the memory guard may be too small",
+                report.graph,
+                report.peak_delta_bytes - report.estimated_bytes
+            );
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_profile() {
+        let profile = start_job("test", 64 * 1024 * 1024, Duration::from_millis(5));
+        let data = vec![1u8; 64 * 1024 * 1024];
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(data.iter().map(|&x| x as usize).sum::<usize>(), data.len());
+        let report = profile.finish();
+        drop(data);
+        assert_eq!(report.graph, "test");
+        assert_eq!(report.waited_secs, 0.005);
+        assert!(report.concurrent_jobs >= 1);
+        assert_eq!(
+            report.actual_to_estimated.is_some(),
+            report.concurrent_jobs == 1
+        );
+        if current_rss().is_some() {
+            assert!(report.peak_rss_bytes >= report.start_rss_bytes);
+            assert!(report.peak_rss_bytes > 0);
+        }
+    }
+}
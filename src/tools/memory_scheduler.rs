@@ -0,0 +1,328 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-13
+//! Description: the admission control of the memory used by the simulations.
+//! each job asks for its memory before loading a graph, the jobs wait in a queue until they reach the head and
+//! their memory fits, so a large job is not starved by the small ones behind it. the scheduler is controlled by
+//! the environment variables:
+//! - `MEMORY_LIMIT`: the memory that can be acquired, like `64GB`, default to the available memory at startup
+//! - `MEMORY_POLICY`: `fifo`(default) or `priority`, the jobs with larger priority go first in `priority` mode,
+//!   the jobs with the same priority are still served in order
+//! - `MEMORY_REMEASURE_SECS`: re-measure the available memory of the system every this many seconds, so the
+//!   memory taken by the other processes on a shared server is respected
+use std::{
+    cmp::Reverse,
+    collections::BTreeSet,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use eyre::bail;
+use once_cell::sync::Lazy;
+use sysinfo::SystemExt;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulePolicy {
+    /// serve the jobs in the order they arrive
+    Fifo,
+    /// serve the jobs with larger priority first
+    Priority,
+}
+
+/// the position of a waiting job, the smallest one is the head of the queue
+type Ticket = (Reverse<i64>, u64);
+
+struct SchedulerState {
+    used: u64,
+    next_ticket: u64,
+    waiting: BTreeSet<Ticket>,
+    /// the memory the jobs of this process can hold, measured as the available memory of the system plus the
+    /// memory reserved at that time, and when it's measured
+    measured: Option<(Instant, u64)>,
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-13
+/// Description: a memory scheduler with a fixed limit, see the module doc
+pub struct MemoryScheduler {
+    limit: u64,
+    policy: SchedulePolicy,
+    remeasure: Option<Duration>,
+    state: Mutex<SchedulerState>,
+    condvar: Condvar,
+}
+
+/// the memory of a job, it's released when the guard is dropped
+#[must_use]
+pub struct MemoryGuard {
+    size: u64,
+    waited: Duration,
+    scheduler: &'static MemoryScheduler,
+}
+
+impl MemoryGuard {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// how long the job waited before the memory is acquired
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+}
+
+impl Drop for MemoryGuard {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        state.used -= self.size;
+        drop(state);
+        self.scheduler.condvar.notify_all();
+    }
+}
+
+fn get_memory_available() -> u64 {
+    let mut sysinfo = sysinfo::System::new();
+    sysinfo.refresh_memory();
+    sysinfo.available_memory()
+}
+
+fn to_gb(size: u64) -> f32 {
+    size as f32 / 1024.0 / 1024.0 / 1024.0
+}
+
+impl MemoryScheduler {
+    pub fn new(limit: u64, policy: SchedulePolicy, remeasure: Option<Duration>) -> Self {
+        Self {
+            limit,
+            policy,
+            remeasure,
+            state: Mutex::new(SchedulerState {
+                used: 0,
+                next_ticket: 0,
+                waiting: BTreeSet::new(),
+                measured: None,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// build the scheduler from `MEMORY_LIMIT`, `MEMORY_POLICY` and `MEMORY_REMEASURE_SECS`
+    pub fn from_env() -> Self {
+        let limit = match std::env::var("MEMORY_LIMIT") {
+            Ok(limit) => parse_size::parse_size(limit).unwrap_or_else(|e| {
+                warn!("failed to parse memory limit: {}", e);
+                get_memory_available()
+            }),
+            Err(_) => get_memory_available(),
+        };
+        let policy = match std::env::var("MEMORY_POLICY").as_deref() {
+            Ok("priority") => SchedulePolicy::Priority,
+            Ok("fifo") | Err(_) => SchedulePolicy::Fifo,
+            Ok(other) => {
+                warn!("unknown memory policy {}, use fifo", other);
+                SchedulePolicy::Fifo
+            }
+        };
+        let remeasure = std::env::var("MEMORY_REMEASURE_SECS")
+            .ok()
+            .and_then(|secs| {
+                secs.parse::<u64>()
+                    .map_err(|e| warn!("failed to parse MEMORY_REMEASURE_SECS: {}", e))
+                    .ok()
+            })
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs);
+        info!(
+            "memory limit: {} bytes, policy: {:?}, remeasure: {:?}",
+            limit, policy, remeasure
+        );
+        Self::new(limit, policy, remeasure)
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// the memory that can be acquired now
+    fn free(&self, state: &mut SchedulerState) -> u64 {
+        let budget = match self.remeasure {
+            Some(interval) => {
+                let budget = match state.measured {
+                    Some((time, budget)) if time.elapsed() < interval => budget,
+                    _ => {
+                        // the reserved memory is already missing from the available memory of the system, add it
+                        // back so it's not subtracted twice
+                        let budget = get_memory_available() + state.used;
+                        state.measured = Some((Instant::now(), budget));
+                        budget
+                    }
+                };
+                self.limit.min(budget)
+            }
+            None => self.limit,
+        };
+        budget.saturating_sub(state.used)
+    }
+
+    /// the number of the jobs waiting for memory
+    pub fn waiting_jobs(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+
+    /// ## rust function
+    /// ## Author: Jiangqiu Shen
+    /// ## Date: 2023-06-13
+    /// Description: wait until all the sections can be acquired together, return an error when they exceed the
+    /// limit and would never fit
+    pub fn acquire(
+        &'static self,
+        sections: Vec<u64>,
+        priority: i64,
+    ) -> eyre::Result<Vec<MemoryGuard>> {
+        if sections.is_empty() {
+            bail!("no memory section is requested");
+        }
+        let total = sections.iter().sum::<u64>();
+        if total > self.limit {
+            bail!(
+                "the job needs {:.3} GB of memory, more than the limit {:.3} GB, set a larger MEMORY_LIMIT or use a smaller graph or hardware",
+                to_gb(total),
+                to_gb(self.limit)
+            );
+        }
+        info!("trying to acquire memory: {} GB", to_gb(total));
+        let start = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let priority = match self.policy {
+            SchedulePolicy::Fifo => 0,
+            SchedulePolicy::Priority => priority,
+        };
+        let ticket = (Reverse(priority), state.next_ticket);
+        state.next_ticket += 1;
+        state.waiting.insert(ticket);
+        loop {
+            if state.waiting.first() == Some(&ticket) && total <= self.free(&mut state) {
+                break;
+            }
+            state = match self.remeasure {
+                Some(interval) => self.condvar.wait_timeout(state, interval).unwrap().0,
+                None => self.condvar.wait(state).unwrap(),
+            };
+        }
+        state.waiting.remove(&ticket);
+        state.used += total;
+        let remaining = self.limit - state.used;
+        drop(state);
+        // the next job may also fit
+        self.condvar.notify_all();
+        let waited = start.elapsed();
+        info!(
+            "memory {} GB acquired after waiting {:?}, {} GB remaining",
+            to_gb(total),
+            waited,
+            to_gb(remaining)
+        );
+        Ok(sections
+            .into_iter()
+            .map(|size| MemoryGuard {
+                size,
+                waited,
+                scheduler: self,
+            })
+            .collect())
+    }
+}
+
+static SCHEDULER: Lazy<MemoryScheduler> = Lazy::new(MemoryScheduler::from_env);
+
+/// the scheduler of the process
+pub fn scheduler() -> &'static MemoryScheduler {
+    &SCHEDULER
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn wait_for_jobs(scheduler: &MemoryScheduler, jobs: usize) {
+        while scheduler.waiting_jobs() < jobs {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// a holds 80, then b asks for 95 and c asks for 10, return the order of b and c
+    fn run_order(policy: SchedulePolicy) -> Vec<&'static str> {
+        let scheduler: &'static MemoryScheduler =
+            Box::leak(Box::new(MemoryScheduler::new(100, policy, None)));
+        let order = Arc::new(Mutex::new(vec![]));
+        let a = scheduler.acquire(vec![80], 0).unwrap();
+        let b = {
+            let order = order.clone();
+            std::thread::spawn(move || {
+                let guard = scheduler.acquire(vec![95], 0).unwrap();
+                order.lock().unwrap().push("b");
+                std::thread::sleep(Duration::from_millis(20));
+                drop(guard);
+            })
+        };
+        wait_for_jobs(scheduler, 1);
+        let c = {
+            let order = order.clone();
+            std::thread::spawn(move || {
+                let _guard = scheduler.acquire(vec![10], 1).unwrap();
+                order.lock().unwrap().push("c");
+            })
+        };
+        // c is admitted at once in priority mode
+        if policy == SchedulePolicy::Fifo {
+            wait_for_jobs(scheduler, 2);
+        } else {
+            while order.lock().unwrap().is_empty() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        drop(a);
+        b.join().unwrap();
+        c.join().unwrap();
+        Arc::try_unwrap(order).unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_memory_scheduler() {
+        let scheduler: &'static MemoryScheduler = Box::leak(Box::new(MemoryScheduler::new(
+            100,
+            SchedulePolicy::Fifo,
+            None,
+        )));
+        assert!(scheduler.acquire(vec![60, 41], 0).is_err());
+        let guards = scheduler.acquire(vec![60, 40], 0).unwrap();
+        assert_eq!(guards.iter().map(MemoryGuard::size).sum::<u64>(), 100);
+        drop(guards);
+        assert_eq!(scheduler.state.lock().unwrap().used, 0);
+
+        assert_eq!(run_order(SchedulePolicy::Fifo), vec!["b", "c"]);
+        assert_eq!(run_order(SchedulePolicy::Priority), vec!["c", "b"]);
+    }
+
+    #[test]
+    fn test_measured_budget() {
+        let scheduler: &'static MemoryScheduler = Box::leak(Box::new(MemoryScheduler::new(
+            100,
+            SchedulePolicy::Fifo,
+            Some(Duration::from_secs(3600)),
+        )));
+        let guard = scheduler.acquire(vec![1], 0).unwrap();
+        let mut state = scheduler.state.lock().unwrap();
+        // the jobs of this process can hold 80 bytes, 1 of them is reserved
+        state.measured = Some((Instant::now(), 80));
+        assert_eq!(scheduler.free(&mut state), 79);
+        drop(state);
+        drop(guard);
+        // the released memory is free again without measuring the system
+        assert_eq!(scheduler.free(&mut scheduler.state.lock().unwrap()), 80);
+    }
+}
@@ -0,0 +1,547 @@
+//! ## rust module
+//! ## Author: Jiangqiu Shen
+//! ## Date: 2023-06-17
+//! Description: the 2D mesh and torus networks.
+//! node `i` is at `(i % width, i / width)`, each router has a local port and four links. the packets are routed in
+//! the x dimension first, then the y dimension. each input port has several virtual channels, a router only sends a
+//! packet when the downstream virtual channel has a credit, the credit is returned one cycle after the packet leaves
+//! the downstream buffer. the torus splits the virtual channels into two classes by the dateline, a packet moves to
+//! the second class after it crosses the wraparound link of the current dimension, so the rings of the torus are
+//! free of deadlock.
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    interconnect::{IcntStats, Interconnect},
+    CrossBarPacket,
+};
+
+/// the ports of a router
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Port {
+    Local,
+    North,
+    South,
+    East,
+    West,
+}
+
+const PORTS: [Port; 5] = [
+    Port::Local,
+    Port::North,
+    Port::South,
+    Port::East,
+    Port::West,
+];
+/// the ports connected to the other routers
+const LINKS: [Port; 4] = [Port::North, Port::South, Port::East, Port::West];
+
+impl Port {
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn opposite(self) -> Port {
+        match self {
+            Port::Local => Port::Local,
+            Port::North => Port::South,
+            Port::South => Port::North,
+            Port::East => Port::West,
+            Port::West => Port::East,
+        }
+    }
+
+    fn is_x(self) -> bool {
+        matches!(self, Port::East | Port::West)
+    }
+}
+
+/// a packet in a router buffer
+struct Flit<T> {
+    packet: T,
+    /// the dateline class in the current dimension
+    class: usize,
+    /// the dimension of the last link, `Some(true)` for x, `None` before the first link
+    dim_x: Option<bool>,
+}
+
+/// the usage of a link since the network is created
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LinkStats {
+    pub from: usize,
+    pub to: usize,
+    /// the cycles the link carried a packet
+    pub busy_cycles: usize,
+    /// `busy_cycles / cycles`
+    pub utilization: f64,
+}
+
+/// add the usage of the same links in a later run to `total`, `cycles` is the cycles of all runs
+pub fn merge_link_stats(total: &mut Vec<LinkStats>, round: &[LinkStats], cycles: usize) {
+    if total.is_empty() {
+        total.extend_from_slice(round);
+    } else {
+        for (link, other) in total.iter_mut().zip(round) {
+            debug_assert_eq!((link.from, link.to), (other.from, other.to));
+            link.busy_cycles += other.busy_cycles;
+        }
+    }
+    for link in total.iter_mut() {
+        link.utilization = link.busy_cycles as f64 / cycles.max(1) as f64;
+    }
+}
+
+/// ## rust function
+/// ## Author: Jiangqiu Shen
+/// ## Date: 2023-06-17
+/// Description: a 2D mesh or torus, see the module doc
+pub struct MeshSimulator<T> {
+    width: usize,
+    height: usize,
+    wrap: bool,
+    vcs: usize,
+    buffer_size: usize,
+    /// the input buffers, indexed by `[node][port][vc]`
+    input: Vec<Vec<Vec<VecDeque<Flit<T>>>>>,
+    /// the free slots of the downstream buffer of each link, indexed by `[node][port][vc]`
+    credits: Vec<Vec<Vec<usize>>>,
+    output: Vec<VecDeque<T>>,
+    /// the round robin pointer of each output port, indexed by `[node][port]`
+    arbiter: Vec<[usize; 5]>,
+    /// the busy cycles of each link, indexed by `[node][port]`
+    link_busy: Vec<[usize; 5]>,
+    stats: IcntStats,
+}
+
+impl<T> MeshSimulator<T> {
+    fn new(width: usize, height: usize, wrap: bool, vcs: usize, buffer_size: usize) -> Self {
+        assert!(width > 0 && height > 0, "the network should not be empty");
+        assert!(vcs > 0, "each port needs at least one virtual channel");
+        assert!(
+            !wrap || vcs >= 2,
+            "the torus needs at least two virtual channels to avoid the deadlock"
+        );
+        let nodes = width * height;
+        let mut mesh = Self {
+            width,
+            height,
+            wrap,
+            vcs,
+            buffer_size,
+            input: (0..nodes)
+                .map(|_| {
+                    (0..PORTS.len())
+                        .map(|_| (0..vcs).map(|_| VecDeque::new()).collect())
+                        .collect()
+                })
+                .collect(),
+            credits: vec![vec![vec![0; vcs]; PORTS.len()]; nodes],
+            output: (0..nodes).map(|_| VecDeque::new()).collect(),
+            arbiter: vec![[0; 5]; nodes],
+            link_busy: vec![[0; 5]; nodes],
+            stats: IcntStats::default(),
+        };
+        for node in 0..nodes {
+            for port in LINKS {
+                if mesh.neighbor(node, port).is_some() {
+                    mesh.credits[node][port.index()] = vec![buffer_size; vcs];
+                }
+            }
+        }
+        mesh
+    }
+
+    /// a mesh of `width` x `height` nodes
+    pub fn mesh(width: usize, height: usize, vcs: usize, buffer_size: usize) -> Self {
+        Self::new(width, height, false, vcs, buffer_size)
+    }
+
+    /// a torus of `width` x `height` nodes, `vcs` should be at least 2
+    pub fn torus(width: usize, height: usize, vcs: usize, buffer_size: usize) -> Self {
+        Self::new(width, height, true, vcs, buffer_size)
+    }
+
+    /// the most square shape with `nodes` nodes, the width is not less than the height
+    pub fn square(nodes: usize, wrap: bool, vcs: usize, buffer_size: usize) -> Self {
+        let height = (1..=nodes)
+            .take_while(|h| h * h <= nodes)
+            .filter(|h| nodes.is_multiple_of(*h))
+            .last()
+            .unwrap_or(1);
+        Self::new(nodes / height, height, wrap, vcs, buffer_size)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn nodes(&self) -> usize {
+        self.width * self.height
+    }
+
+    fn neighbor(&self, node: usize, port: Port) -> Option<usize> {
+        let (x, y) = (node % self.width, node / self.width);
+        let (x, y) = match port {
+            Port::Local => return Some(node),
+            Port::East if x + 1 < self.width => (x + 1, y),
+            Port::East if self.wrap && self.width > 1 => (0, y),
+            Port::West if x > 0 => (x - 1, y),
+            Port::West if self.wrap && self.width > 1 => (self.width - 1, y),
+            Port::South if y + 1 < self.height => (x, y + 1),
+            Port::South if self.wrap && self.height > 1 => (x, 0),
+            Port::North if y > 0 => (x, y - 1),
+            Port::North if self.wrap && self.height > 1 => (x, self.height - 1),
+            _ => return None,
+        };
+        Some(y * self.width + x)
+    }
+
+    /// the link from this node is the wraparound link of the torus
+    fn is_wrap(&self, node: usize, port: Port) -> bool {
+        let (x, y) = (node % self.width, node / self.width);
+        self.wrap
+            && match port {
+                Port::Local => false,
+                Port::East => x + 1 == self.width,
+                Port::West => x == 0,
+                Port::South => y + 1 == self.height,
+                Port::North => y == 0,
+            }
+    }
+
+    /// the dimension order routing, the torus takes the shorter way
+    fn route(&self, node: usize, dest: usize) -> Port {
+        let (x, y) = (node % self.width, node / self.width);
+        let (dest_x, dest_y) = (dest % self.width, dest / self.width);
+        let step = |from: usize, to: usize, size: usize, forward: Port, backward: Port| {
+            if self.wrap {
+                let distance = (to + size - from) % size;
+                if distance <= size - distance {
+                    forward
+                } else {
+                    backward
+                }
+            } else if to > from {
+                forward
+            } else {
+                backward
+            }
+        };
+        if x != dest_x {
+            step(x, dest_x, self.width, Port::East, Port::West)
+        } else if y != dest_y {
+            step(y, dest_y, self.height, Port::South, Port::North)
+        } else {
+            Port::Local
+        }
+    }
+
+    /// the dateline class of the flit after it takes the link
+    fn next_class(&self, node: usize, port: Port, flit: &Flit<T>) -> usize {
+        if self.is_wrap(node, port) {
+            1
+        } else if flit.dim_x == Some(port.is_x()) {
+            flit.class
+        } else {
+            0
+        }
+    }
+
+    /// the downstream virtual channel with a credit
+    fn select_vc(&self, node: usize, port: Port, flit: &Flit<T>) -> Option<usize> {
+        let class = self.next_class(node, port, flit);
+        (0..self.vcs)
+            .filter(|vc| !self.wrap || vc % 2 == class)
+            .find(|&vc| self.credits[node][port.index()][vc] > 0)
+    }
+
+    /// the occupancy of each virtual channel and the credits of each link, used to diagnose a stalled network
+    pub fn dump_state(&self) -> String {
+        (0..self.nodes())
+            .map(|node| {
+                let buffers = PORTS
+                    .iter()
+                    .map(|port| {
+                        let lengths = self.input[node][port.index()]
+                            .iter()
+                            .map(VecDeque::len)
+                            .collect::<Vec<_>>();
+                        format!("{:?} {:?}", port, lengths)
+                    })
+                    .collect::<Vec<_>>();
+                let credits = LINKS
+                    .iter()
+                    .map(|port| format!("{:?} {:?}", port, self.credits[node][port.index()]))
+                    .collect::<Vec<_>>();
+                format!(
+                    "node {} ({}, {}): input [{}], output {}/{}, credits [{}]",
+                    node,
+                    node % self.width,
+                    node / self.width,
+                    buffers.join(", "),
+                    self.output[node].len(),
+                    self.buffer_size,
+                    credits.join(", ")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// the usage of all links
+    pub fn link_stats(&self) -> Vec<LinkStats> {
+        let cycles = self.stats.cycles.max(1) as f64;
+        (0..self.nodes())
+            .flat_map(|node| {
+                LINKS.into_iter().filter_map(move |port| {
+                    self.neighbor(node, port).map(|to| {
+                        let busy_cycles = self.link_busy[node][port.index()];
+                        LinkStats {
+                            from: node,
+                            to,
+                            busy_cycles,
+                            utilization: busy_cycles as f64 / cycles,
+                        }
+                    })
+                })
+            })
+            .collect()
+    }
+}
+
+impl<T: CrossBarPacket> MeshSimulator<T> {
+    /// move the packets one hop, each output port sends at most one packet and each input port is read at most once
+    pub fn cycle(&mut self) {
+        let slots = PORTS.len() * self.vcs;
+        let mut arrivals = vec![];
+        let mut returned = vec![];
+        for node in 0..self.nodes() {
+            let mut input_used = [false; 5];
+            for out in PORTS {
+                let start = self.arbiter[node][out.index()];
+                let mut winner = None;
+                for k in 0..slots {
+                    let slot = (start + k) % slots;
+                    let (in_port, vc) = (slot / self.vcs, slot % self.vcs);
+                    if input_used[in_port] {
+                        continue;
+                    }
+                    let Some(flit) = self.input[node][in_port][vc].front() else {
+                        continue;
+                    };
+                    if self.route(node, flit.packet.get_dest()) != out {
+                        continue;
+                    }
+                    let next_vc = match out {
+                        Port::Local if self.output[node].len() < self.buffer_size => 0,
+                        Port::Local => continue,
+                        _ => match self.select_vc(node, out, flit) {
+                            Some(vc) => vc,
+                            None => continue,
+                        },
+                    };
+                    if winner.is_none() {
+                        winner = Some((slot, in_port, vc, next_vc));
+                    } else {
+                        self.stats.conflicts += 1;
+                    }
+                }
+                let Some((slot, in_port, vc, next_vc)) = winner else {
+                    continue;
+                };
+                input_used[in_port] = true;
+                self.arbiter[node][out.index()] = (slot + 1) % slots;
+                let mut flit = self.input[node][in_port][vc].pop_front().unwrap();
+                let in_port = PORTS[in_port];
+                if in_port != Port::Local {
+                    let upstream = self.neighbor(node, in_port).unwrap();
+                    returned.push((upstream, in_port.opposite(), vc));
+                }
+                if out == Port::Local {
+                    self.output[node].push_back(flit.packet);
+                } else {
+                    flit.class = self.next_class(node, out, &flit);
+                    flit.dim_x = Some(out.is_x());
+                    self.credits[node][out.index()][next_vc] -= 1;
+                    self.link_busy[node][out.index()] += 1;
+                    let next = self.neighbor(node, out).unwrap();
+                    arrivals.push((next, out.opposite(), next_vc, flit));
+                }
+            }
+        }
+        for (node, port, vc, flit) in arrivals {
+            self.input[node][port.index()][vc].push_back(flit);
+        }
+        for (node, port, vc) in returned {
+            self.credits[node][port.index()][vc] += 1;
+        }
+        self.stats.cycles += 1;
+    }
+
+    /// inject the packet into the local port of the node, return it back when all local buffers are full
+    pub fn add(&mut self, node: usize, packet: T) -> Result<(), T> {
+        let buffer_size = self.buffer_size;
+        match self.input[node][Port::Local.index()]
+            .iter_mut()
+            .find(|buffer| buffer.len() < buffer_size)
+        {
+            Some(buffer) => {
+                buffer.push_back(Flit {
+                    packet,
+                    class: 0,
+                    dim_x: None,
+                });
+                self.stats.injected += 1;
+                Ok(())
+            }
+            None => {
+                self.stats.rejected += 1;
+                Err(packet)
+            }
+        }
+    }
+
+    pub fn pop(&mut self, node: usize) -> Option<T> {
+        let packet = self.output[node].pop_front();
+        if packet.is_some() {
+            self.stats.ejected += 1;
+        }
+        packet
+    }
+}
+
+impl<T: CrossBarPacket> Interconnect<T> for MeshSimulator<T> {
+    fn ports(&self) -> usize {
+        self.nodes()
+    }
+
+    fn inject(&mut self, port: usize, packet: T) -> Result<(), T> {
+        self.add(port, packet)
+    }
+
+    fn cycle(&mut self) {
+        self.cycle()
+    }
+
+    fn eject(&mut self, port: usize) -> Option<T> {
+        self.pop(port)
+    }
+
+    fn peek(&self, port: usize) -> Option<&T> {
+        self.output[port].front()
+    }
+
+    fn stats(&self) -> IcntStats {
+        self.stats
+    }
+
+    fn link_stats(&self) -> Vec<LinkStats> {
+        self.link_stats()
+    }
+
+    fn dump_state(&self) -> String {
+        self.dump_state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::interconnect::{drain_traffic, Packet, DEFAULT_WATCHDOG_CYCLES};
+
+    /// the cycles until the single packet arrives
+    fn latency(mesh: &mut MeshSimulator<Packet>, source: usize, dest: usize) -> usize {
+        mesh.add(
+            source,
+            Packet {
+                source,
+                dest,
+                size: 1,
+            },
+        )
+        .unwrap();
+        let mut cycles = 0;
+        loop {
+            mesh.cycle();
+            cycles += 1;
+            if let Some(packet) = mesh.pop(dest) {
+                assert_eq!(
+                    packet,
+                    Packet {
+                        source,
+                        dest,
+                        size: 1,
+                    }
+                );
+                return cycles;
+            }
+            assert!(cycles < 100);
+        }
+    }
+
+    #[test]
+    fn test_mesh_routing() {
+        let mut mesh = MeshSimulator::mesh(4, 4, 1, 4);
+        // 3 hops in x, 3 hops in y and the ejection
+        assert_eq!(latency(&mut mesh, 0, 15), 7);
+        let busy = mesh
+            .link_stats()
+            .iter()
+            .map(|link| link.busy_cycles)
+            .sum::<usize>();
+        assert_eq!(busy, 6);
+        assert_eq!(latency(&mut mesh, 0, 3), 4);
+        assert_eq!(latency(&mut mesh, 5, 5), 1);
+
+        let mut torus = MeshSimulator::torus(4, 4, 2, 4);
+        // through the wraparound links
+        assert_eq!(latency(&mut torus, 0, 3), 2);
+        assert_eq!(latency(&mut torus, 0, 15), 3);
+
+        let square = MeshSimulator::<Packet>::square(64, false, 1, 4);
+        assert_eq!((square.width(), square.height()), (8, 8));
+        let line = MeshSimulator::<Packet>::square(7, true, 2, 4);
+        assert_eq!((line.width(), line.height()), (7, 1));
+    }
+
+    #[test]
+    fn test_mesh_drain() -> eyre::Result<()> {
+        // all nodes send to the others with small buffers, the network should not deadlock
+        let traffic = |nodes: usize| {
+            (0..nodes)
+                .flat_map(|source| {
+                    (0..20).map(move |i| Packet {
+                        source,
+                        dest: (source * 7 + i * 5 + 1) % nodes,
+                        size: 1,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        for wrap in [false, true] {
+            let mut network = MeshSimulator::square(16, wrap, 2, 1);
+            let cycles = drain_traffic(&mut network, traffic(16), DEFAULT_WATCHDOG_CYCLES)?;
+            assert!(cycles >= 20);
+            let stats = Interconnect::stats(&network);
+            assert_eq!(stats.injected, 16 * 20);
+            assert_eq!(stats.ejected, 16 * 20);
+            assert!(network
+                .link_stats()
+                .iter()
+                .all(|link| link.utilization <= 1.0));
+            // all credits are returned
+            assert!(network
+                .credits
+                .iter()
+                .flatten()
+                .flatten()
+                .all(|&credit| credit == 0 || credit == 1));
+        }
+        Ok(())
+    }
+}
@@ -1,21 +1,42 @@
 pub mod file_server;
+pub mod graph_generator;
+pub mod graph_reader;
+pub mod hierarchical_network;
+pub mod interconnect;
 mod flat_interleave;
 mod rayon_play;
 pub use flat_interleave::{FlatInterleave, FlatInterleaveTrait};
+pub mod cancel;
+pub mod checkpoint;
+pub mod chrome_trace;
+pub mod control;
 pub mod crossbare_simulator;
 pub mod crossbare_simulator_no_conflic;
+pub mod dram_trace;
+pub mod mapping_cache;
 pub mod math;
+pub mod memory_profile;
+pub mod memory_scheduler;
+pub mod mesh_simulator;
+pub mod matrix_cache;
 pub mod remapping_translate;
 pub mod ring_simulator;
-pub mod stop_signal;
 pub trait CrossBarPacket {
     fn get_source(&self) -> usize;
     fn get_dest(&self) -> usize;
+    /// the payload in words, only the ring splits a packet into flits by its size
+    fn get_size(&self) -> usize {
+        1
+    }
 }
 pub trait IcntPacket {
     fn get_source(&self) -> usize;
     fn get_next_hop(&self) -> usize;
     fn get_direction(&self) -> Direction;
+    /// the payload in words, see [`ring_simulator::RingConfig`]
+    fn get_size(&self) -> usize {
+        1
+    }
 }
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Direction {
@@ -1,6 +1,8 @@
 use std::collections::VecDeque;
 
-use super::{Direction, IcntPacket};
+use serde::{Deserialize, Serialize};
+
+use super::{interconnect::IcntStats, Direction, IcntPacket};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum SourceId {
@@ -31,6 +33,29 @@ impl SourceId {
     }
 }
 
+/// the links of the ring, the `ring` field of the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RingConfig {
+    /// the words a link carries per cycle, a packet of `n` words is split into `ceil(n / link_width)` flits. the
+    /// default carries an index and data pair per cycle, the same as the old one packet per hop per cycle ring
+    pub link_width: usize,
+    /// the cycles the head of a packet takes to cross a link
+    pub hop_latency: usize,
+}
+
+impl Default for RingConfig {
+    fn default() -> Self {
+        Self {
+            link_width: 2,
+            hop_latency: 1,
+        }
+    }
+}
+
+/// the ring with virtual cut-through flow control: the credits and the buffers are counted in flits, a packet only
+/// moves when the next buffer has room for all of its flits, its head can leave a node before its tail arrives, and
+/// a link is busy for one cycle per flit.
 pub struct RingSimulator<T: IcntPacket> {
     /// the input buffer
     pub input: Vec<VecDeque<T>>,
@@ -39,20 +64,42 @@ pub struct RingSimulator<T: IcntPacket> {
     /// the inner buffer
     left_buffer: Vec<VecDeque<T>>,
     right_buffer: Vec<VecDeque<T>>,
-    /// the temp buffer
-    temp_right_buffer: Vec<VecDeque<T>>,
-    temp_left_buffer: Vec<VecDeque<T>>,
+    /// the temp buffer, with the cycle the packet arrives
+    temp_right_buffer: Vec<VecDeque<(usize, T)>>,
+    temp_left_buffer: Vec<VecDeque<(usize, T)>>,
+    /// the packets whose tail is still arriving at the output, with the cycle it arrives
+    temp_output: Vec<VecDeque<(usize, T)>>,
+    /// the flits in the inner buffer and the temp buffer of each node
+    left_flits: Vec<usize>,
+    right_flits: Vec<usize>,
+    /// the cycle the link leaving the inner buffer of each node is free again
+    left_link_free: Vec<usize>,
+    right_link_free: Vec<usize>,
+    /// the cycle the input port of each node is free again
+    input_free: Vec<usize>,
     creddit_left: Vec<usize>,
     creddit_right: Vec<usize>,
     source_id: SourceId,
     /// the total nodes of the ring
     nodes: usize,
     buffer_capacity: usize,
+    config: RingConfig,
+    pub(super) stats: IcntStats,
 }
 impl<T: IcntPacket> RingSimulator<T> {
     /// create a new ring simulator
     /// with `nodes` nodes
     pub fn new(nodes: usize, buffer_capacity: usize) -> Self {
+        Self::with_config(nodes, buffer_capacity, RingConfig::default())
+    }
+
+    /// create a ring with the link width and latency in `config`, `buffer_capacity` is counted in flits
+    pub fn with_config(nodes: usize, buffer_capacity: usize, config: RingConfig) -> Self {
+        assert!(
+            config.link_width > 0,
+            "the link should carry at least one word"
+        );
+        assert!(config.hop_latency > 0, "a hop takes at least one cycle");
         Self {
             input: (0..nodes)
                 .map(|_| VecDeque::with_capacity(buffer_capacity))
@@ -72,19 +119,35 @@ impl<T: IcntPacket> RingSimulator<T> {
             temp_right_buffer: (0..nodes)
                 .map(|_| VecDeque::with_capacity(buffer_capacity))
                 .collect(),
+            temp_output: (0..nodes).map(|_| VecDeque::new()).collect(),
+            left_flits: vec![0; nodes],
+            right_flits: vec![0; nodes],
+            left_link_free: vec![0; nodes],
+            right_link_free: vec![0; nodes],
+            input_free: vec![0; nodes],
             source_id: SourceId::Input,
             nodes,
             creddit_left: vec![buffer_capacity; nodes],
             creddit_right: vec![buffer_capacity; nodes],
             buffer_capacity,
+            config,
+            stats: IcntStats::default(),
         }
     }
+
+    /// the flits of the packet
+    fn flits(&self, packet: &T) -> usize {
+        packet.get_size().div_ceil(self.config.link_width).max(1)
+    }
+
     /// add a value to the input buffer of a node
     pub fn add(&mut self, node: usize, value: T) -> Result<(), T> {
         if self.input[node].len() == self.buffer_capacity {
+            self.stats.rejected += 1;
             Err(value)
         } else {
             // try to allocate the creddit from source .. target
+            let flits = self.flits(&value);
             let mut source = value.get_source();
             let target = value.get_next_hop();
             let direction = value.get_direction();
@@ -93,8 +156,9 @@ impl<T: IcntPacket> RingSimulator<T> {
                 Direction::Right => &mut self.creddit_right,
             };
             while source != target {
-                if creddit[source] == 0 {
+                if creddit[source] < flits {
                     // no creddit remain, return err
+                    self.stats.rejected += 1;
                     return Err(value);
                 }
                 match direction {
@@ -109,7 +173,7 @@ impl<T: IcntPacket> RingSimulator<T> {
             // allocate the creddit
             let mut source = value.get_source();
             while source != target {
-                creddit[source] -= 1;
+                creddit[source] -= flits;
                 match direction {
                     Direction::Left => {
                         source = (source + self.nodes - 1) % self.nodes;
@@ -120,6 +184,7 @@ impl<T: IcntPacket> RingSimulator<T> {
                 }
             }
             self.input[node].push_back(value);
+            self.stats.injected += 1;
             Ok(())
         }
     }
@@ -131,6 +196,7 @@ impl<T: IcntPacket> RingSimulator<T> {
         match p {
             Some(p) => {
                 //release the creddit
+                let flits = self.flits(&p);
                 let mut source = p.get_source();
                 let target = p.get_next_hop();
                 let direction = p.get_direction();
@@ -139,7 +205,7 @@ impl<T: IcntPacket> RingSimulator<T> {
                     Direction::Right => &mut self.creddit_right,
                 };
                 while source != target {
-                    creddit[source] += 1;
+                    creddit[source] += flits;
                     match direction {
                         Direction::Left => {
                             source = (source + self.nodes - 1) % self.nodes;
@@ -149,6 +215,7 @@ impl<T: IcntPacket> RingSimulator<T> {
                         }
                     }
                 }
+                self.stats.ejected += 1;
                 Some(p)
             }
             None => None,
@@ -159,8 +226,51 @@ impl<T: IcntPacket> RingSimulator<T> {
         self.output[node].front()
     }
 
+    /// the total nodes of the ring
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    /// the occupancy of each buffer and the remaining credits, used to diagnose a stalled network
+    pub fn dump_state(&self) -> String {
+        (0..self.nodes)
+            .map(|node| {
+                format!(
+                    "node {}: input {}/{}, output {}, left {} flits, right {} flits, credit left {}, credit right {}",
+                    node,
+                    self.input[node].len(),
+                    self.buffer_capacity,
+                    self.output[node].len() + self.temp_output[node].len(),
+                    self.left_flits[node],
+                    self.right_flits[node],
+                    self.creddit_left[node],
+                    self.creddit_right[node]
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// the output has room for one more packet
+    fn output_has_room(&self, node: usize) -> bool {
+        self.output[node].len() + self.temp_output[node].len() < self.buffer_capacity
+    }
+
+    /// the head of the packet arrives at the output in this cycle, it can be popped when its tail arrives
+    fn deliver(&mut self, node: usize, packet: T, flits: usize, link_latency: usize) {
+        let now = self.stats.cycles;
+        let ready = now + link_latency + flits - 1;
+        if ready <= now {
+            self.output[node].push_back(packet);
+        } else {
+            self.temp_output[node].push_back((ready, packet));
+        }
+    }
+
     /// cycle the simulator
     pub fn cycle(&mut self) {
+        let now = self.stats.cycles;
+        let hop_latency = self.config.hop_latency;
         // route packets
         // will push the packet to temp buffer first
         for node in 0..self.nodes {
@@ -168,27 +278,39 @@ impl<T: IcntPacket> RingSimulator<T> {
                 match direction {
                     SourceId::Input => {
                         // an input packet can go to left, right or output
+                        if self.input_free[node] > now {
+                            // the last packet is still leaving the input port
+                            continue;
+                        }
                         if let Some(packet) = self.input[node].pop_front() {
+                            let flits = self.flits(&packet);
                             if packet.get_next_hop() == node {
-                                if self.output[node].len() < self.buffer_capacity {
+                                if self.output_has_room(node) {
                                     // tracing::debug!("from input {} to output {}", node, node);
-                                    self.output[node].push_back(packet);
+                                    self.input_free[node] = now + flits;
+                                    self.deliver(node, packet, flits, 0);
                                 } else {
                                     self.input[node].push_front(packet);
                                 }
                             } else {
                                 match packet.get_direction() {
                                     Direction::Left => {
-                                        if self.left_buffer[node].len() < self.buffer_capacity {
-                                            self.temp_left_buffer[node].push_back(packet);
+                                        if self.left_flits[node] + flits <= self.buffer_capacity {
+                                            self.input_free[node] = now + flits;
+                                            self.left_flits[node] += flits;
+                                            self.temp_left_buffer[node]
+                                                .push_back((now + 1, packet));
                                             // tracing::debug!("from input {} to left {}", node, node);
                                         } else {
                                             self.input[node].push_front(packet);
                                         }
                                     }
                                     Direction::Right => {
-                                        if self.right_buffer[node].len() < self.buffer_capacity {
-                                            self.temp_right_buffer[node].push_back(packet);
+                                        if self.right_flits[node] + flits <= self.buffer_capacity {
+                                            self.input_free[node] = now + flits;
+                                            self.right_flits[node] += flits;
+                                            self.temp_right_buffer[node]
+                                                .push_back((now + 1, packet));
                                             // tracing::debug!(
                                             //     "from input {} to right {}",
                                             //     node,
@@ -205,17 +327,27 @@ impl<T: IcntPacket> RingSimulator<T> {
                     }
                     SourceId::Left => {
                         let right_node = (node + 1) % self.nodes;
+                        if self.left_link_free[right_node] > now {
+                            // the link is still sending the last packet
+                            continue;
+                        }
                         if let Some(packet) = self.left_buffer[right_node].pop_front() {
+                            let flits = self.flits(&packet);
                             if packet.get_next_hop() == node {
-                                if self.output[node].len() < self.buffer_capacity {
-                                    self.output[node].push_back(packet);
+                                if self.output_has_room(node) {
+                                    self.left_flits[right_node] -= flits;
+                                    self.left_link_free[right_node] = now + flits;
+                                    self.deliver(node, packet, flits, hop_latency - 1);
                                     // tracing::debug!("from left {} to output {}", right_node, node);
                                 } else {
                                     self.left_buffer[right_node].push_front(packet);
                                 }
-                            } else if self.left_buffer[node].len() < self.buffer_capacity {
+                            } else if self.left_flits[node] + flits <= self.buffer_capacity {
                                 // tracing::debug!("from left {} to left {}", right_node, node);
-                                self.temp_left_buffer[node].push_back(packet);
+                                self.left_flits[right_node] -= flits;
+                                self.left_link_free[right_node] = now + flits;
+                                self.left_flits[node] += flits;
+                                self.temp_left_buffer[node].push_back((now + hop_latency, packet));
                             } else {
                                 self.left_buffer[right_node].push_front(packet);
                             }
@@ -224,16 +356,26 @@ impl<T: IcntPacket> RingSimulator<T> {
                     }
                     SourceId::Right => {
                         let left_node = (node + self.nodes - 1) % self.nodes;
+                        if self.right_link_free[left_node] > now {
+                            // the link is still sending the last packet
+                            continue;
+                        }
                         if let Some(packet) = self.right_buffer[left_node].pop_front() {
+                            let flits = self.flits(&packet);
                             if packet.get_next_hop() == node {
-                                if self.output[node].len() < self.buffer_capacity {
-                                    self.output[node].push_back(packet);
+                                if self.output_has_room(node) {
+                                    self.right_flits[left_node] -= flits;
+                                    self.right_link_free[left_node] = now + flits;
+                                    self.deliver(node, packet, flits, hop_latency - 1);
                                     // tracing::debug!("from right {} to output {}", left_node, node);
                                 } else {
                                     self.right_buffer[left_node].push_front(packet);
                                 }
-                            } else if self.right_buffer[node].len() < self.buffer_capacity {
-                                self.temp_right_buffer[node].push_back(packet);
+                            } else if self.right_flits[node] + flits <= self.buffer_capacity {
+                                self.right_flits[left_node] -= flits;
+                                self.right_link_free[left_node] = now + flits;
+                                self.right_flits[node] += flits;
+                                self.temp_right_buffer[node].push_back((now + hop_latency, packet));
                                 // tracing::debug!("from right {} to right {}", left_node, node);
                             } else {
                                 self.right_buffer[left_node].push_front(packet);
@@ -246,14 +388,33 @@ impl<T: IcntPacket> RingSimulator<T> {
         }
         // finished the routing, move the source to the next
         self.source_id.next();
-        // move the temp buffer to the real buffer
+        self.stats.cycles += 1;
+        // move the arrived packets from the temp buffer to the real buffer
+        let now = self.stats.cycles;
         for node in 0..self.nodes {
-            self.left_buffer[node].append(&mut self.temp_left_buffer[node]);
-            self.right_buffer[node].append(&mut self.temp_right_buffer[node]);
+            move_arrived(
+                &mut self.temp_left_buffer[node],
+                &mut self.left_buffer[node],
+                now,
+            );
+            move_arrived(
+                &mut self.temp_right_buffer[node],
+                &mut self.right_buffer[node],
+                now,
+            );
+            move_arrived(&mut self.temp_output[node], &mut self.output[node], now);
         }
     }
 }
 
+/// move the packets arrived before `now` from the delay line to the buffer
+fn move_arrived<T>(temp: &mut VecDeque<(usize, T)>, buffer: &mut VecDeque<T>, now: usize) {
+    while temp.front().is_some_and(|(ready, _)| *ready <= now) {
+        let (_, packet) = temp.pop_front().unwrap();
+        buffer.push_back(packet);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -440,6 +601,88 @@ mod tests {
         assert_eq!(result, Err(p1));
     }
 
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    struct SizedPacket {
+        next_hop: usize,
+        source: usize,
+        size: usize,
+    }
+    impl IcntPacket for SizedPacket {
+        fn get_source(&self) -> usize {
+            self.source
+        }
+        fn get_next_hop(&self) -> usize {
+            self.next_hop
+        }
+        fn get_direction(&self) -> Direction {
+            Direction::Right
+        }
+        fn get_size(&self) -> usize {
+            self.size
+        }
+    }
+
+    /// send the packets from node 0 to node 1, return the cycles until all of them are popped
+    fn run_sized(config: RingConfig, packets: usize, size: usize) -> usize {
+        let mut simulator = RingSimulator::with_config(4, 64, config);
+        let packet = SizedPacket {
+            next_hop: 1,
+            source: 0,
+            size,
+        };
+        for _ in 0..packets {
+            simulator.add(0, packet).unwrap();
+        }
+        let mut finished = 0;
+        let mut cycles = 0;
+        while finished < packets {
+            simulator.cycle();
+            cycles += 1;
+            while simulator.pop(1).is_some() {
+                finished += 1;
+            }
+        }
+        cycles
+    }
+
+    #[test]
+    fn test_multi_flit() {
+        // 4 words on a 2 words link are 2 flits, a hop takes 3 cycles
+        let config = RingConfig {
+            link_width: 2,
+            hop_latency: 3,
+        };
+        let mut simulator = RingSimulator::with_config(4, 8, config);
+        let packet = SizedPacket {
+            next_hop: 2,
+            source: 0,
+            size: 4,
+        };
+        simulator.add(0, packet).unwrap();
+        // inject, 2 hops of 3 cycles and the tail flit
+        for _ in 0..6 {
+            simulator.cycle();
+            assert_eq!(simulator.pop(2), None);
+        }
+        simulator.cycle();
+        assert_eq!(simulator.pop(2), Some(packet));
+
+        // the credits are counted in flits
+        for _ in 0..4 {
+            simulator.add(0, packet).unwrap();
+        }
+        assert_eq!(simulator.add(0, packet), Err(packet));
+
+        // a link sends one flit per cycle
+        assert_eq!(run_sized(RingConfig::default(), 10, 2), 11);
+        assert_eq!(run_sized(RingConfig::default(), 10, 4), 20);
+        let wide = RingConfig {
+            link_width: 4,
+            hop_latency: 1,
+        };
+        assert_eq!(run_sized(wide, 10, 4), 11);
+    }
+
     #[cfg_attr(miri, ignore)]
     #[test]
     fn test_full_bandwith_long_ring() {